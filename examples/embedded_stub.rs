@@ -0,0 +1,60 @@
+//! Embeds the stub in another application: selects the second DAS device, adds a
+//! `monitor bench <args>` command and serves a single GDB session over a socket the
+//! application accepted itself.
+//!
+//! Build without the command line frontend:
+//! `cargo run --example embedded_stub --no-default-features`
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::net::TcpListener;
+use std::rc::Rc;
+
+use gdbstub::outputln;
+use tricore_gdb_das::{
+    serve, Device, FlashOptions, MonitorOutput, MonitoredConnection, Observers, SessionError,
+    Settings, TricoreTarget,
+};
+
+/// Index of the device in the DAS scan, as listed when connecting
+const DEVICE: usize = 1;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let settings = Settings::default();
+    let keepalive = settings.keepalive;
+    let mut device = Device::connect(DEVICE, &[], &FlashOptions::default(), settings, None)?;
+    let mut target = TricoreTarget::attach(&mut device)?;
+
+    let mut invocations = 0;
+    target.register_monitor_command(
+        "bench",
+        Box::new(
+            move |target: &mut TricoreTarget<'_>, args: &[&str], out: &mut MonitorOutput<'_>| {
+                invocations += 1;
+                outputln!(
+                    out,
+                    "bench call {} on {} with {:?}",
+                    invocations,
+                    target.das_capabilities().server,
+                    args
+                );
+            },
+        ),
+    );
+    target.restart();
+
+    let listener = TcpListener::bind("127.0.0.1:3333")?;
+    println!("Waiting for a GDB connection on 127.0.0.1:3333...");
+    let (stream, addr) = listener.accept()?;
+    println!("Debugger connected from {}", addr);
+
+    let target = Rc::new(RefCell::new(target));
+    let connection = MonitoredConnection::new(stream, keepalive)?;
+    match serve(&target, connection, &mut Observers::disabled()) {
+        Ok(reason) => println!("Session ended: {:?}", reason),
+        Err(SessionError::Connection(e)) => println!("connection error: {}", e),
+        Err(SessionError::Stub(e)) => println!("gdbstub encountered a fatal error: {}", e),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,514 @@
+//! GDB connection wrapper which keeps track of activity to detect dead peers.
+//!
+//! Errors which only mean "try again" are absorbed here rather than ending the session:
+//! `Interrupted` is retried right away, `WouldBlock` means no data for a peek and is
+//! retried until [RETRY_DEADLINE] for reads and writes. Any other error, e.g. a reset or
+//! broken pipe, reaches the event loop as before.
+//!
+//! The wrapper is generic over its [Transport], a TCP stream in the stub. Keepalive and
+//! pending socket errors are TCP matters and left to the transport.
+
+use std::io;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use gdbstub::conn::{Connection, ConnectionExt};
+use socket2::{SockRef, TcpKeepalive};
+
+use crate::metrics::Metrics;
+
+/// Time a read or write keeps retrying a socket which would block
+const RETRY_DEADLINE: Duration = Duration::from_secs(1);
+
+/// Pause before retrying a socket which would block
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Interrupt request of GDB, sent outside of packets
+pub const INTERRUPT: u8 = 0x03;
+
+/// Follows the packet framing of the bytes received from GDB, to tell an interrupt
+/// request from a 0x03 in the binary payload of a packet, e.g. of `X` or `vFlashWrite`
+#[derive(Debug, Default)]
+pub struct PacketFraming {
+    state: FramingState,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum FramingState {
+    /// Between packets, where acks and interrupt requests arrive
+    #[default]
+    Between,
+    Payload,
+    /// Checksum digits still to come
+    Checksum(u8),
+}
+
+impl PacketFraming {
+    /// Whether `byte`, received next, is an interrupt request rather than packet content
+    pub fn is_interrupt(&self, byte: u8) -> bool {
+        self.state == FramingState::Between && byte == INTERRUPT
+    }
+
+    /// Advances over a received byte. A `#` in the payload always ends it, binary data
+    /// escapes it.
+    pub fn feed(&mut self, byte: u8) {
+        self.state = match (self.state, byte) {
+            (FramingState::Between, b'$') => FramingState::Payload,
+            (FramingState::Between, _) => FramingState::Between,
+            (FramingState::Payload, b'#') => FramingState::Checksum(2),
+            (FramingState::Payload, _) => FramingState::Payload,
+            (FramingState::Checksum(1), _) => FramingState::Between,
+            (FramingState::Checksum(left), _) => FramingState::Checksum(left - 1),
+        };
+    }
+}
+
+/// Connection to the owning GDB client which can tell whether the peer is still alive
+pub trait SessionConnection: ConnectionExt<Error = io::Error> {
+    /// Time since the last byte was exchanged or the connection was last probed
+    fn idle_for(&self) -> Duration;
+
+    /// Checks for errors on an idle connection, a healthy one is never reported as error
+    fn probe(&mut self) -> io::Result<()>;
+
+    /// Applies a changed keepalive idle time to the live connection
+    fn set_keepalive(&mut self, keepalive: Duration) -> io::Result<()>;
+}
+
+/// Byte stream to the GDB client under a [MonitoredConnection]
+pub trait Transport: io::Read + io::Write {
+    /// Next byte without consuming it, `WouldBlock` if none has arrived
+    fn peek(&mut self) -> io::Result<Option<u8>>;
+
+    /// Has the peer probed after `keepalive` without traffic, where the transport can
+    fn set_keepalive(&self, keepalive: Duration) -> io::Result<()>;
+
+    /// Error recorded for the transport since the last call, e.g. a failed keepalive probe
+    fn take_error(&self) -> io::Result<Option<io::Error>>;
+
+    fn on_session_start(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TcpStream {
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        ConnectionExt::peek(self)
+    }
+
+    /// Has the kernel probe the peer after `keepalive` without traffic
+    fn set_keepalive(&self, keepalive: Duration) -> io::Result<()> {
+        SockRef::from(self).set_tcp_keepalive(
+            &TcpKeepalive::new()
+                .with_time(keepalive)
+                .with_interval(keepalive),
+        )
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        TcpStream::take_error(self)
+    }
+
+    fn on_session_start(&mut self) -> io::Result<()> {
+        Connection::on_session_start(self)
+    }
+}
+
+/// Connection to the GDB client, over TCP unless embedded otherwise
+pub struct MonitoredConnection<S: Transport = TcpStream> {
+    stream: S,
+    last_activity: Instant,
+    keepalive: Duration,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl MonitoredConnection<TcpStream> {
+    /// Wraps the stream and enables TCP keepalive with the given idle time, so the
+    /// kernel notices half-open connections even when neither side sends data.
+    pub fn new(stream: TcpStream, keepalive: Duration) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Self::with_transport(stream, keepalive)
+    }
+}
+
+impl<S: Transport> MonitoredConnection<S> {
+    /// Wraps any transport, applying the keepalive idle time where the transport has one
+    pub fn with_transport(stream: S, keepalive: Duration) -> io::Result<Self> {
+        stream.set_keepalive(keepalive)?;
+
+        Ok(MonitoredConnection {
+            stream,
+            last_activity: Instant::now(),
+            keepalive,
+            metrics: None,
+        })
+    }
+
+    /// Reports client activity to the service metrics as well
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Absorbs an error worth retrying the read or write for until `deadline`, returns
+    /// any other error
+    fn absorb(&self, e: io::Error, deadline: Instant) -> io::Result<()> {
+        match e.kind() {
+            io::ErrorKind::Interrupted => {}
+            io::ErrorKind::WouldBlock if Instant::now() < deadline => sleep(RETRY_INTERVAL),
+            _ => return Err(e),
+        }
+        self.record_retry();
+        Ok(())
+    }
+
+    fn record_retry(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_connection_retry();
+        }
+    }
+}
+
+impl<S: Transport> SessionConnection for MonitoredConnection<S> {
+    fn idle_for(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Checks for errors the kernel recorded on the socket, e.g. failed keepalive probes,
+    /// then sends an ack. GDB skips acks it doesn't wait for, but the peer of a half-open
+    /// connection never acknowledges the segment, so the kernel records an error for the
+    /// next probe to find.
+    fn probe(&mut self) -> io::Result<()> {
+        if let Some(e) = self.stream.take_error()? {
+            return Err(e);
+        }
+        Connection::write(self, b'+')?;
+        Connection::flush(self)
+    }
+
+    fn set_keepalive(&mut self, keepalive: Duration) -> io::Result<()> {
+        if keepalive != self.keepalive {
+            self.stream.set_keepalive(keepalive)?;
+            self.keepalive = keepalive;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Transport> Connection for MonitoredConnection<S> {
+    type Error = io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        Connection::write_all(self, &[byte])
+    }
+
+    /// Writes in pieces, so a retry never sends what was written already
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Self::Error> {
+        self.last_activity = Instant::now();
+        let deadline = Instant::now() + RETRY_DEADLINE;
+        while !buf.is_empty() {
+            match io::Write::write(&mut self.stream, buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => buf = &buf[written..],
+                Err(e) => self.absorb(e, deadline)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let deadline = Instant::now() + RETRY_DEADLINE;
+        loop {
+            match io::Write::flush(&mut self.stream) {
+                Ok(()) => return Ok(()),
+                Err(e) => self.absorb(e, deadline)?,
+            }
+        }
+    }
+
+    fn on_session_start(&mut self) -> Result<(), Self::Error> {
+        self.stream.on_session_start()
+    }
+}
+
+impl<S: Transport> ConnectionExt for MonitoredConnection<S> {
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        let deadline = Instant::now() + RETRY_DEADLINE;
+        let mut byte = [0];
+        loop {
+            match io::Read::read(&mut self.stream, &mut byte) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => break,
+                Err(e) => self.absorb(e, deadline)?,
+            }
+        }
+        self.last_activity = Instant::now();
+        if let Some(metrics) = &self.metrics {
+            metrics.record_activity();
+        }
+        Ok(byte[0])
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        loop {
+            match self.stream.peek() {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => self.record_retry(),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.record_retry();
+                    return Ok(None);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Errors the connection retries rather than passing on
+    const ABSORBED: &[io::ErrorKind] = &[io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock];
+
+    const FATAL: &[io::ErrorKind] = &[
+        io::ErrorKind::ConnectionReset,
+        io::ErrorKind::ConnectionAborted,
+        io::ErrorKind::ConnectionRefused,
+        io::ErrorKind::BrokenPipe,
+        io::ErrorKind::NotConnected,
+        io::ErrorKind::TimedOut,
+        io::ErrorKind::UnexpectedEof,
+        io::ErrorKind::Other,
+    ];
+
+    /// Transport replaying scripted reads and writes with errors injected in between
+    #[derive(Default)]
+    struct MockTransport {
+        /// Results of successive reads and peeks, a peek only consumes errors
+        incoming: VecDeque<Result<u8, io::ErrorKind>>,
+        /// Results of successive writes, `None` accepts up to two bytes. Writes beyond
+        /// the script are accepted.
+        writes: VecDeque<Option<io::ErrorKind>>,
+        written: Vec<u8>,
+    }
+
+    impl io::Read for MockTransport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.incoming.pop_front() {
+                Some(Ok(byte)) => {
+                    buf[0] = byte;
+                    Ok(1)
+                }
+                Some(Err(kind)) => Err(kind.into()),
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl io::Write for MockTransport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(Some(kind)) = self.writes.pop_front() {
+                return Err(kind.into());
+            }
+            let accepted = buf.len().min(2);
+            self.written.extend_from_slice(&buf[..accepted]);
+            Ok(accepted)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn peek(&mut self) -> io::Result<Option<u8>> {
+            match self.incoming.front().copied() {
+                Some(Ok(byte)) => Ok(Some(byte)),
+                Some(Err(kind)) => {
+                    self.incoming.pop_front();
+                    Err(kind.into())
+                }
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+
+        fn set_keepalive(&self, _keepalive: Duration) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn take_error(&self) -> io::Result<Option<io::Error>> {
+            Ok(None)
+        }
+    }
+
+    fn connection(transport: MockTransport) -> MonitoredConnection<MockTransport> {
+        MonitoredConnection::with_transport(transport, Duration::from_secs(1)).unwrap()
+    }
+
+    #[test]
+    fn absorbed_read_errors_lose_nothing() {
+        for &kind in ABSORBED {
+            let incoming = [
+                Ok(b'a'),
+                Err(kind),
+                Ok(b'b'),
+                Err(kind),
+                Err(kind),
+                Ok(b'c'),
+            ];
+            let mut conn = connection(MockTransport {
+                incoming: incoming.into(),
+                ..Default::default()
+            });
+            let read: Vec<u8> = (0..3).map(|_| conn.read().unwrap()).collect();
+            assert_eq!(read, b"abc", "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn fatal_read_errors_surface_in_order() {
+        for &kind in FATAL {
+            let incoming = [Ok(b'a'), Err(kind), Ok(b'b')];
+            let mut conn = connection(MockTransport {
+                incoming: incoming.into(),
+                ..Default::default()
+            });
+            assert_eq!(conn.read().unwrap(), b'a');
+            assert_eq!(conn.read().unwrap_err().kind(), kind);
+            // The byte after the error is still there, nothing was skipped
+            assert_eq!(conn.read().unwrap(), b'b');
+        }
+    }
+
+    #[test]
+    fn absorbed_write_errors_send_everything_once() {
+        let data = b"$m80000000,4#2d";
+        for &kind in ABSORBED {
+            let writes = [Some(kind), None, Some(kind), Some(kind), None, Some(kind)];
+            let mut conn = connection(MockTransport {
+                writes: writes.into(),
+                ..Default::default()
+            });
+            Connection::write_all(&mut conn, data).unwrap();
+            assert_eq!(conn.stream.written, data, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn fatal_write_errors_surface_without_resending() {
+        let data = b"$m80000000,4#2d";
+        for &kind in FATAL {
+            let mut conn = connection(MockTransport {
+                writes: [None, None, Some(kind)].into(),
+                ..Default::default()
+            });
+            let error = Connection::write_all(&mut conn, data).unwrap_err();
+            assert_eq!(error.kind(), kind);
+            assert_eq!(conn.stream.written, data[..4]);
+        }
+    }
+
+    #[test]
+    fn peeks_report_no_data_and_keep_the_byte() {
+        let mut conn = connection(MockTransport::default());
+        assert_eq!(ConnectionExt::peek(&mut conn).unwrap(), None);
+
+        for &kind in ABSORBED {
+            let mut conn = connection(MockTransport {
+                incoming: [Err(kind), Ok(b'$')].into(),
+                ..Default::default()
+            });
+            let peeked = ConnectionExt::peek(&mut conn).unwrap();
+            if kind == io::ErrorKind::WouldBlock {
+                // No data yet, the next peek finds the byte
+                assert_eq!(peeked, None);
+                assert_eq!(ConnectionExt::peek(&mut conn).unwrap(), Some(b'$'));
+            } else {
+                assert_eq!(peeked, Some(b'$'));
+            }
+            assert_eq!(conn.read().unwrap(), b'$');
+        }
+
+        for &kind in FATAL {
+            let mut conn = connection(MockTransport {
+                incoming: [Err(kind), Ok(b'$')].into(),
+                ..Default::default()
+            });
+            assert_eq!(ConnectionExt::peek(&mut conn).unwrap_err().kind(), kind);
+            assert_eq!(conn.read().unwrap(), b'$');
+        }
+    }
+
+    /// `payload` framed as GDB sends it, with its checksum
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let checksum = payload
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(payload);
+        packet.extend(format!("#{:02x}", checksum).bytes());
+        packet
+    }
+
+    /// Feeds `bytes`, returns the positions taken for interrupt requests
+    fn interrupts(framing: &mut PacketFraming, bytes: &[u8]) -> Vec<usize> {
+        let mut found = Vec::new();
+        for (position, &byte) in bytes.iter().enumerate() {
+            if framing.is_interrupt(byte) {
+                found.push(position);
+            }
+            framing.feed(byte);
+        }
+        found
+    }
+
+    #[test]
+    fn interrupts_are_only_taken_between_packets() {
+        let mut vcont = b"+".to_vec();
+        vcont.extend(packet(b"vCont;c:p1.1;c:p1.2"));
+        for offset in 0..=vcont.len() {
+            let mut bytes = vcont.clone();
+            bytes.insert(offset, INTERRUPT);
+            let mut framing = PacketFraming::default();
+            let between = offset <= 1 || offset == vcont.len();
+            let expected = if between { vec![offset] } else { Vec::new() };
+            assert_eq!(
+                interrupts(&mut framing, &bytes),
+                expected,
+                "0x03 at offset {}",
+                offset
+            );
+            // Whatever it was taken for, the framing is back between packets afterwards
+            assert!(framing.is_interrupt(INTERRUPT), "after offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn binary_payloads_hold_no_interrupts() {
+        // `X` data with a raw 0x03, an escaped `#` (which is `}` 0x03), an escaped `}` and
+        // an escaped `$`
+        let mut payload = b"X80000000,8:".to_vec();
+        payload.extend([0x01, INTERRUPT, b'}', b'#' ^ 0x20, b'}', b'}' ^ 0x20]);
+        payload.extend([b'}', b'$' ^ 0x20, INTERRUPT, 0xff]);
+        let mut bytes = packet(&payload);
+        bytes.push(b'+');
+        bytes.extend(packet(&payload));
+
+        let mut framing = PacketFraming::default();
+        assert_eq!(interrupts(&mut framing, &bytes), Vec::<usize>::new());
+        assert!(framing.is_interrupt(INTERRUPT));
+    }
+
+    #[test]
+    fn acks_and_stray_bytes_stay_between_packets() {
+        let mut framing = PacketFraming::default();
+        assert_eq!(
+            interrupts(&mut framing, &[b'+', b'-', INTERRUPT, b'+', INTERRUPT]),
+            [2, 4]
+        );
+    }
+}
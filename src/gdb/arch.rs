@@ -0,0 +1,117 @@
+//! Core architecture variants and the device tables which depend on them.
+
+use std::fmt;
+
+use log::warn;
+
+use super::chip_communication::DeviceSelection;
+use super::memory_map::{
+    AliasWindow, CoreLocalMap, MemoryRegion, TC2XX_ALIASES, TC2XX_LOCAL_MAP, TC2XX_REGIONS,
+    TC3XX_ALIASES, TC3XX_BMHD_SLOTS, TC3XX_LOCAL_MAP, TC3XX_REGIONS,
+};
+use super::reset_cause::{ResetRegisters, TC2XX_RESET, TC3XX_RESET};
+use super::target_time::{StmRegisters, TC2XX_STM, TC3XX_STM};
+
+/// TriCore core architecture of the attached device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArchVariant {
+    /// TC1.6.1 (TC1.6P/E) cores of the AURIX TC2xx family
+    Tc161,
+    /// TC1.6.2 cores of the AURIX TC3xx family
+    #[default]
+    Tc162,
+}
+
+impl ArchVariant {
+    /// Derives the variant from the device name, `None` if the family is not recognized
+    pub fn detect(device_name: &str) -> Option<Self> {
+        let name = device_name.to_ascii_uppercase();
+        if name.contains("TC2") {
+            Some(ArchVariant::Tc161)
+        } else if name.contains("TC3") {
+            Some(ArchVariant::Tc162)
+        } else {
+            None
+        }
+    }
+
+    /// Variant of a scanned device, falling back to TC1.6.2 for unknown devices
+    pub fn of_device(device: &DeviceSelection) -> Self {
+        let name = format!("{:?}", device.info.acc_hw());
+        ArchVariant::detect(&name).unwrap_or_else(|| {
+            warn!(
+                "Cannot tell the core architecture of {}, assuming {}",
+                name,
+                ArchVariant::default()
+            );
+            ArchVariant::default()
+        })
+    }
+
+    /// Memory layout used for flash checks and address validation
+    pub fn regions(self) -> &'static [MemoryRegion] {
+        match self {
+            ArchVariant::Tc161 => TC2XX_REGIONS,
+            ArchVariant::Tc162 => TC3XX_REGIONS,
+        }
+    }
+
+    /// Memories seen through a cached and a non-cached alias
+    pub fn aliases(self) -> &'static [AliasWindow] {
+        match self {
+            ArchVariant::Tc161 => TC2XX_ALIASES,
+            ArchVariant::Tc162 => TC3XX_ALIASES,
+        }
+    }
+
+    /// Global aliases of the core local scratchpad windows, `None` where they are unknown
+    pub fn local_map(self) -> Option<&'static CoreLocalMap> {
+        match self {
+            ArchVariant::Tc161 => Some(&TC2XX_LOCAL_MAP),
+            ArchVariant::Tc162 => Some(&TC3XX_LOCAL_MAP),
+        }
+    }
+
+    /// UCB boot mode header slots validated before UCB content is programmed.
+    ///
+    /// TC2xx devices keep their boot mode headers in program flash with a different
+    /// layout, they are not validated.
+    pub fn bmhd_slots(self) -> &'static [u32] {
+        match self {
+            ArchVariant::Tc161 => &[],
+            ArchVariant::Tc162 => TC3XX_BMHD_SLOTS,
+        }
+    }
+
+    /// SCU registers recording the cause of the last resets
+    pub fn reset_registers(self) -> &'static ResetRegisters {
+        match self {
+            ArchVariant::Tc161 => &TC2XX_RESET,
+            ArchVariant::Tc162 => &TC3XX_RESET,
+        }
+    }
+
+    /// System timers of the cores, read for the target time of a run
+    pub fn stm_registers(self) -> &'static StmRegisters {
+        match self {
+            ArchVariant::Tc161 => &TC2XX_STM,
+            ArchVariant::Tc162 => &TC3XX_STM,
+        }
+    }
+
+    /// Whether the core state has to be queried before registers read back valid values
+    pub fn query_state_before_register_read(self) -> bool {
+        match self {
+            ArchVariant::Tc161 | ArchVariant::Tc162 => true,
+        }
+    }
+}
+
+impl fmt::Display for ArchVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchVariant::Tc161 => write!(f, "TC1.6.1 (TC2xx)"),
+            ArchVariant::Tc162 => write!(f, "TC1.6.2 (TC3xx)"),
+        }
+    }
+}
@@ -2,24 +2,58 @@ use anyhow::Result;
 use gdbstub::{
     common::Tid,
     target::{
-        ext::base::multithread::{MultiThreadBase, MultiThreadResumeOps},
+        ext::{
+            base::multithread::{MultiThreadBase, MultiThreadResumeOps},
+            base::single_register_access::{SingleRegisterAccess, SingleRegisterAccessOps},
+            thread_extra_info::{ThreadExtraInfo, ThreadExtraInfoOps},
+        },
         TargetError, TargetResult,
     },
 };
 use log::debug;
 
-use super::StaticTricoreTarget;
+use super::registers::{CoreRegisters, RegisterDef, RegisterId, REGISTERS};
+use super::{
+    present_core, read_register, write_register, CpuId, TricoreTarget, TricoreTargetError,
+};
 
-impl MultiThreadBase for StaticTricoreTarget {
-    fn read_registers(
-        &mut self,
-        regs: &mut gdbstub_arch::tricore::reg::TricoreCoreRegs,
-        tid: Tid,
-    ) -> TargetResult<(), Self> {
-        let core = self.get_core(tid)?;
+/// Whether the non-empty range `[start, start + len)` ends within the 32 bit address space
+fn range_fits(start: u32, len: usize) -> bool {
+    u32::try_from(len - 1)
+        .ok()
+        .and_then(|last| start.checked_add(last))
+        .is_some()
+}
+
+impl<'a> MultiThreadBase for TricoreTarget<'a> {
+    fn read_registers(&mut self, regs: &mut CoreRegisters, tid: Tid) -> TargetResult<(), Self> {
+        if self.refuse_busy("register read") {
+            return Err(TargetError::NonFatal);
+        }
+        let index = self.core_index(tid)?;
+        if self.left_running && self.running_cores().contains(&index) {
+            // GDB reads the registers of the stop it was told of on attaching
+            debug!("Core {} is running, its registers are not read", index);
+            return Err(TargetError::NonFatal);
+        }
+        if self.settings.reg_cache {
+            if let Some(cached) = self
+                .slot_at(index)
+                .and_then(|slot| slot.register_cache.as_ref())
+            {
+                *regs = cached.clone();
+                self.stats.register_cache_hits += 1;
+                return Ok(());
+            }
+        }
+
+        // Borrow the core through its field so the statistics can be updated alongside
+        let core = present_core(&self.slots, index)?;
 
         // todo: why is this needed?
-        _ = core.query_state();
+        if self.arch.query_state_before_register_read() {
+            _ = core.query_state();
+        }
 
         let groups = core
             .register_groups()
@@ -29,52 +63,79 @@ impl MultiThreadBase for StaticTricoreTarget {
             .get_group(0)
             .map_err(|_| TargetError::Fatal("Can't read register groups"))?;
 
-        let read_register = |name: &str| -> TargetResult<u32, Self> {
-            group
-                .register(name)
-                .ok_or_else(|| TargetError::Fatal("Could not find {} register"))?
+        // Registers the core lacks are sent to GDB as unavailable rather than failing the read
+        let capabilities = self
+            .slots
+            .get(index)
+            .map(|slot| &slot.capabilities)
+            .ok_or(TricoreTargetError::NotPresent(index))?;
+        *regs = CoreRegisters::for_core(capabilities);
+        let snapshot = CpuId::try_from(index)
+            .ok()
+            .and_then(|cpu_id| self.stop_snapshots.get(&cpu_id));
+        for def in REGISTERS.iter().filter(|def| capabilities.has(def)) {
+            let cached = snapshot.and_then(|snapshot| match def.mcd {
+                "PC" => Some(snapshot.pc),
+                "A10" => Some(snapshot.a10),
+                _ => None,
+            });
+            if let Some(value) = cached {
+                regs.set(def, value);
+                continue;
+            }
+            let value = group
+                .register(def.mcd)
+                .ok_or(TargetError::Fatal("Could not find register"))?
                 .read()
-                .map_err(|_| TargetError::Fatal("Can't read register"))
-        };
-
-        let register_names = [
-            "A10", "A11", "A12", "A13", "A14", "A15", "D8", "D9", "D10", "D11", "D12", "D13",
-            "D14", "D15", "PC", "PCXI", "PSW",
-        ];
-
-        for &name in &register_names {
-            let value = read_register(name)?;
-            match name {
-                "A10" => regs.a10 = value,
-                "A11" => regs.a11 = value,
-                "A12" => regs.a12 = value,
-                "A13" => regs.a13 = value,
-                "A14" => regs.a14 = value,
-                "A15" => regs.a15 = value,
-                "D8" => regs.d8 = value,
-                "D9" => regs.d9 = value,
-                "D10" => regs.d10 = value,
-                "D11" => regs.d11 = value,
-                "D12" => regs.d12 = value,
-                "D13" => regs.d13 = value,
-                "D14" => regs.d14 = value,
-                "D15" => regs.d15 = value,
-                "PC" => regs.pc = value,
-                "PCXI" => regs.pcxi = value,
-                "PSW" => regs.psw = value,
-                _ => unreachable!(),
+                .map_err(|_| TargetError::Fatal("Can't read register"))?;
+            regs.set(def, value);
+        }
+        self.stats.record_register_read();
+        if self.settings.reg_cache {
+            self.stats.register_cache_misses += 1;
+            if let Some(slot) = self.slot_at_mut(index) {
+                slot.register_cache = Some(regs.clone());
             }
         }
 
         Ok(())
     }
 
-    fn write_registers(
-        &mut self,
-        _regs: &gdbstub_arch::tricore::reg::TricoreCoreRegs,
-        _tid: Tid,
-    ) -> TargetResult<(), Self> {
-        todo!()
+    fn write_registers(&mut self, regs: &CoreRegisters, tid: Tid) -> TargetResult<(), Self> {
+        if self.refuse_busy("register write") {
+            return Err(TargetError::NonFatal);
+        }
+        let index = self.core_index(tid)?;
+        if let Ok(cpu_id) = CpuId::try_from(index) {
+            self.stop_snapshots.remove(&cpu_id);
+        }
+        self.invalidate_registers(index);
+
+        let core = self.get_core(tid)?;
+
+        let groups = core
+            .register_groups()
+            .map_err(|_| TargetError::Fatal("Can't read register groups"))?;
+
+        let group = groups
+            .get_group(0)
+            .map_err(|_| TargetError::Fatal("Can't read register groups"))?;
+
+        let capabilities = self
+            .slots
+            .get(index)
+            .map(|slot| &slot.capabilities)
+            .ok_or(TricoreTargetError::NotPresent(index))?;
+        for def in REGISTERS.iter().filter(|def| capabilities.has(def)) {
+            let value = regs.get(def);
+            group
+                .register(def.mcd)
+                .ok_or(TargetError::Fatal("Could not find register"))?
+                .write(value)
+                .map_err(|_| TargetError::NonFatal)?;
+        }
+
+        Ok(())
     }
 
     fn read_addrs(
@@ -83,31 +144,59 @@ impl MultiThreadBase for StaticTricoreTarget {
         data: &mut [u8],
         tid: Tid,
     ) -> TargetResult<usize, Self> {
-        let core = self.get_core(tid)?;
-
-        let bytes = core
-            .read_bytes(start_addr as u64, data.len())
-            .map_err(|_| {
-                debug!(
-                    "Cannot read from requested address range {:0x} - {:0x}",
-                    start_addr,
-                    start_addr + data.len() as u32
-                );
-                TargetError::NonFatal
-            })?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        if self.refuse_busy("memory read") {
+            return Err(TargetError::NonFatal);
+        }
+        if !range_fits(start_addr, data.len()) {
+            debug!(
+                "Refusing read of {} bytes at {:0x} beyond the address space",
+                data.len(),
+                start_addr
+            );
+            return Err(TargetError::NonFatal);
+        }
+        if data.len() > self.settings.max_read {
+            debug!(
+                "Refusing read of {} bytes at {:0x}, limit is {} bytes",
+                data.len(),
+                start_addr,
+                self.settings.max_read
+            );
+            return Err(TargetError::NonFatal);
+        }
 
-        data.copy_from_slice(&bytes);
+        let start_addr = self.translate_local(tid, start_addr, data.len())?;
+        self.read_memory(tid, start_addr, data)?;
 
-        Ok(bytes.len())
+        Ok(data.len())
     }
 
     fn write_addrs(&mut self, start_addr: u32, data: &[u8], tid: Tid) -> TargetResult<(), Self> {
-        let core = self.get_core(tid)?;
-
-        core.write(start_addr as u64, data.to_vec()).map_err(|_| {
-            debug!("Cannot write to addr {:0x} ", start_addr);
-            TargetError::NonFatal
-        })?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.refuse_busy("memory write") {
+            return Err(TargetError::NonFatal);
+        }
+        if !range_fits(start_addr, data.len()) {
+            debug!(
+                "Refusing write of {} bytes at {:0x} beyond the address space",
+                data.len(),
+                start_addr
+            );
+            return Err(TargetError::NonFatal);
+        }
+        if self.refuse_flash_write(start_addr, data.len()) {
+            return Err(TargetError::NonFatal);
+        }
+        let start_addr = self.translate_local(tid, start_addr, data.len())?;
+        if self.coalesce_write(tid, start_addr, data)? {
+            return Ok(());
+        }
+        self.write_memory(tid, start_addr, data)?;
         Ok(())
     }
 
@@ -116,13 +205,121 @@ impl MultiThreadBase for StaticTricoreTarget {
         Some(self)
     }
 
+    #[inline(always)]
+    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<'_, Tid, Self>> {
+        Some(self)
+    }
+
     fn list_active_threads(
         &mut self,
         register_thread: &mut dyn FnMut(Tid),
     ) -> Result<(), Self::Error> {
-        for (index, _) in self.cores.iter().enumerate() {
+        for index in self.thread_order() {
             register_thread(Tid::new(index + 1).unwrap());
         }
         Ok(())
     }
+
+    #[inline(always)]
+    fn support_thread_extra_info(&mut self) -> Option<ThreadExtraInfoOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Reads a register of the table on its own, from the register cache where it holds
+    /// the core's registers
+    pub(crate) fn read_table_register(
+        &mut self,
+        tid: Tid,
+        def: &RegisterDef,
+    ) -> TargetResult<u32, Self> {
+        if self.refuse_busy("register read") {
+            return Err(TargetError::NonFatal);
+        }
+        let index = self.core_index(tid)?;
+        if self.left_running && self.running_cores().contains(&index) {
+            debug!("Core {} is running, its registers are not read", index);
+            return Err(TargetError::NonFatal);
+        }
+        let slot = self
+            .slot_at(index)
+            .ok_or(TricoreTargetError::NotPresent(index))?;
+        if !slot.capabilities.has(def) {
+            debug!("Core {} has no register {}", index, def.name);
+            return Err(TargetError::NonFatal);
+        }
+        if self.settings.reg_cache {
+            if let Some(value) = slot.register_cache.as_ref().map(|cached| cached.get(def)) {
+                self.stats.register_cache_hits += 1;
+                return Ok(value);
+            }
+        }
+        let core = present_core(&self.slots, index)?;
+        if self.arch.query_state_before_register_read() {
+            _ = core.query_state();
+        }
+        read_register(core, def.mcd).ok_or(TargetError::NonFatal)
+    }
+
+    /// Writes a register of the table on its own
+    pub(crate) fn write_table_register(
+        &mut self,
+        tid: Tid,
+        def: &RegisterDef,
+        value: u32,
+    ) -> TargetResult<(), Self> {
+        if self.refuse_busy("register write") {
+            return Err(TargetError::NonFatal);
+        }
+        let index = self.core_index(tid)?;
+        let has = self
+            .slot_at(index)
+            .is_some_and(|slot| slot.capabilities.has(def));
+        if !has {
+            debug!("Core {} has no register {}", index, def.name);
+            return Err(TargetError::NonFatal);
+        }
+        if let Ok(cpu_id) = CpuId::try_from(index) {
+            self.stop_snapshots.remove(&cpu_id);
+        }
+        self.invalidate_registers(index);
+        let core = self.get_core(tid)?;
+        write_register(core, def.mcd, value).ok_or(TargetError::NonFatal)
+    }
+}
+
+impl<'a> SingleRegisterAccess<Tid> for TricoreTarget<'a> {
+    fn read_register(
+        &mut self,
+        tid: Tid,
+        reg_id: RegisterId,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        let def = reg_id.def();
+        let value = self.read_table_register(tid, def)?;
+        let len = def.size.min(buf.len());
+        buf[..len].copy_from_slice(&value.to_le_bytes()[..len]);
+        Ok(len)
+    }
+
+    fn write_register(
+        &mut self,
+        tid: Tid,
+        reg_id: RegisterId,
+        val: &[u8],
+    ) -> TargetResult<(), Self> {
+        let def = reg_id.def();
+        let bytes: [u8; 4] = val.try_into().map_err(|_| TargetError::NonFatal)?;
+        self.write_table_register(tid, def, u32::from_le_bytes(bytes))
+    }
+}
+
+impl<'a> ThreadExtraInfo for TricoreTarget<'a> {
+    fn thread_extra_info(&self, tid: Tid, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let info = format!("CPU{} {}", tid.get() - 1, self.core_state_name(tid));
+        let len = info.len().min(buf.len());
+        buf[..len].copy_from_slice(&info.as_bytes()[..len]);
+        Ok(len)
+    }
 }
@@ -0,0 +1,328 @@
+//! `--boot-args`, arguments for the firmware written to a RAM block after every reset,
+//! before the cores are released.
+//!
+//! The block starts with the magic word `BARG` and is laid out in one of two formats, all
+//! words little endian:
+//!
+//! - `blob`: magic, length in bytes, the argument string as given followed by a NUL
+//!   not counted in the length.
+//! - `argv`: magic, argc, argc pointers to the arguments followed by a null pointer, the
+//!   arguments as NUL terminated strings. The arguments are the whitespace separated
+//!   words of the string, the pointers are absolute addresses within the block.
+
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+use log::{info, warn};
+
+use super::{parse_addr_len, MonitorOutput, TricoreTarget};
+
+/// First word of the block, "BARG" in memory
+const MAGIC: u32 = u32::from_le_bytes(*b"BARG");
+
+/// Reserved RAM block the boot arguments are written to
+#[derive(Debug, Clone, Copy)]
+pub struct BootArgsBlock {
+    pub addr: u32,
+    pub len: u32,
+}
+
+impl BootArgsBlock {
+    /// Parses `<addr>:<len>`, both in decimal or `0x` prefixed hex
+    pub fn parse(text: &str) -> Result<BootArgsBlock, String> {
+        let (addr, len) = parse_addr_len(text)?;
+        if len < 8 {
+            return Err(format!(
+                "boot args block {:?} is shorter than 8 bytes",
+                text
+            ));
+        }
+        Ok(BootArgsBlock { addr, len })
+    }
+}
+
+/// Layout of the boot arguments in the block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BootArgsFormat {
+    /// The string as given, prefixed by its length
+    #[default]
+    Blob,
+    /// argc and a C argv array
+    Argv,
+}
+
+impl BootArgsFormat {
+    pub fn parse(value: &str) -> Result<BootArgsFormat, String> {
+        match value {
+            "blob" => Ok(BootArgsFormat::Blob),
+            "argv" => Ok(BootArgsFormat::Argv),
+            _ => Err(format!("'{}' is none of 'blob' and 'argv'", value)),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            BootArgsFormat::Blob => "blob",
+            BootArgsFormat::Argv => "argv",
+        }
+    }
+}
+
+fn word(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// The block content for `args`, fails if it exceeds the block
+pub(crate) fn serialize(
+    args: &str,
+    format: BootArgsFormat,
+    block: BootArgsBlock,
+) -> Result<Vec<u8>, String> {
+    let mut bytes = MAGIC.to_le_bytes().to_vec();
+    match format {
+        BootArgsFormat::Blob => {
+            bytes.extend_from_slice(&(args.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(args.as_bytes());
+            bytes.push(0);
+        }
+        BootArgsFormat::Argv => {
+            let words: Vec<&str> = args.split_whitespace().collect();
+            bytes.extend_from_slice(&(words.len() as u32).to_le_bytes());
+            let mut string_offset = bytes.len() + 4 * (words.len() + 1);
+            let mut strings = Vec::new();
+            for word in &words {
+                let pointer = block.addr as u64 + string_offset as u64;
+                bytes.extend_from_slice(&(pointer as u32).to_le_bytes());
+                strings.extend_from_slice(word.as_bytes());
+                strings.push(0);
+                string_offset += word.len() + 1;
+            }
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend_from_slice(&strings);
+        }
+    }
+    if bytes.len() > block.len as usize {
+        return Err(format!(
+            "boot args need {} bytes, the block at {:#010x} holds {}",
+            bytes.len(),
+            block.addr,
+            block.len
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Fails with the sizes if the boot arguments don't fit their block, checked at startup
+pub fn check_boot_args(
+    args: &str,
+    format: BootArgsFormat,
+    block: BootArgsBlock,
+) -> Result<(), String> {
+    serialize(args, format, block).map(|_| ())
+}
+
+/// Arguments read back from the block, the whole string for `blob`
+pub(crate) fn decode(
+    bytes: &[u8],
+    format: BootArgsFormat,
+    block: BootArgsBlock,
+) -> Result<Vec<String>, String> {
+    match word(bytes, 0) {
+        Some(MAGIC) => {}
+        Some(other) => return Err(format!("no boot args, magic is {:#010x}", other)),
+        None => return Err("block too short".to_owned()),
+    }
+    let count = word(bytes, 4).ok_or("block too short")? as usize;
+    match format {
+        BootArgsFormat::Blob => {
+            let text = bytes
+                .get(8..8 + count)
+                .ok_or_else(|| format!("length {} exceeds the block", count))?;
+            Ok(vec![String::from_utf8_lossy(text).into_owned()])
+        }
+        BootArgsFormat::Argv => (0..count)
+            .map(|index| {
+                let pointer = word(bytes, 8 + 4 * index)
+                    .ok_or_else(|| format!("argc {} exceeds the block", count))?;
+                let start = pointer
+                    .checked_sub(block.addr)
+                    .map(|offset| offset as usize)
+                    .filter(|&offset| offset < bytes.len())
+                    .ok_or_else(|| {
+                        format!("argv[{}] {:#010x} points outside the block", index, pointer)
+                    })?;
+                let len = bytes[start..]
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .ok_or_else(|| format!("argv[{}] is not terminated", index))?;
+                Ok(String::from_utf8_lossy(&bytes[start..start + len]).into_owned())
+            })
+            .collect(),
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Writes the boot arguments to their block, if any are configured
+    pub(crate) fn write_boot_args(&mut self) -> Result<(), String> {
+        let (Some(args), Some(block)) = (
+            self.settings.boot_args.clone(),
+            self.settings.boot_args_block,
+        ) else {
+            return Ok(());
+        };
+        let bytes = serialize(&args, self.settings.boot_args_format, block)?;
+        self.write_memory(Tid::new(1).unwrap(), block.addr, &bytes)
+            .map_err(|e| e.to_string())?;
+        info!(
+            "Wrote {} bytes of boot args to {:#010x}",
+            bytes.len(),
+            block.addr
+        );
+        Ok(())
+    }
+
+    /// Writes the boot arguments after a reset, a failure is logged
+    pub(crate) fn apply_boot_args(&mut self) {
+        if let Err(e) = self.write_boot_args() {
+            warn!("Cannot write the boot args: {}", e);
+        }
+    }
+
+    /// The boot args block, `None` with a hint if there is none
+    fn boot_args_block(&self, out: &mut MonitorOutput<'_>) -> Option<BootArgsBlock> {
+        if self.settings.boot_args_block.is_none() {
+            outputln!(
+                out,
+                "No boot args block, start with --boot-args-addr <addr>:<len>"
+            );
+        }
+        self.settings.boot_args_block
+    }
+
+    /// `bootargs set <string>`
+    pub(crate) fn boot_args_set(&mut self, words: &[&str], out: &mut MonitorOutput<'_>) {
+        let Some(block) = self.boot_args_block(out) else {
+            return;
+        };
+        let args = words.join(" ");
+        if let Err(e) = serialize(&args, self.settings.boot_args_format, block) {
+            outputln!(out, "{}", e);
+            return;
+        }
+        let previous = self.settings.boot_args.replace(args);
+        match self.write_boot_args() {
+            Ok(()) => outputln!(out, "Boot args written, applied again on every reset"),
+            Err(e) => {
+                self.settings.boot_args = previous;
+                outputln!(out, "Cannot write the boot args: {}", e);
+            }
+        }
+    }
+
+    /// `bootargs show`
+    pub(crate) fn boot_args_show(&mut self, out: &mut MonitorOutput<'_>) {
+        let Some(block) = self.boot_args_block(out) else {
+            return;
+        };
+        let mut bytes = vec![0; block.len as usize];
+        if let Err(e) = self.read_memory(Tid::new(1).unwrap(), block.addr, &mut bytes) {
+            outputln!(out, "Cannot read the boot args block: {}", e);
+            return;
+        }
+        match decode(&bytes, self.settings.boot_args_format, block) {
+            Ok(args) => {
+                for (index, arg) in args.iter().enumerate() {
+                    outputln!(out, "[{}] {}", index, arg);
+                }
+            }
+            Err(e) => outputln!(out, "{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: BootArgsBlock = BootArgsBlock {
+        addr: 0x7000_0000,
+        len: 64,
+    };
+
+    #[test]
+    fn block_is_parsed() {
+        let block = BootArgsBlock::parse("0x70000000:64").unwrap();
+        assert_eq!((block.addr, block.len), (0x7000_0000, 64));
+        let block = BootArgsBlock::parse("1024:0x100").unwrap();
+        assert_eq!((block.addr, block.len), (1024, 0x100));
+        assert!(BootArgsBlock::parse("0x70000000").is_err());
+        assert!(BootArgsBlock::parse("0x70000000:4").is_err());
+        assert!(BootArgsBlock::parse("0xfffffff0:0x20").is_err());
+        assert!(BootArgsBlock::parse("ram:64").is_err());
+    }
+
+    #[test]
+    fn blob_layout() {
+        let bytes = serialize("a=1 b", BootArgsFormat::Blob, BLOCK).unwrap();
+        assert_eq!(&bytes[..4], b"BARG");
+        assert_eq!(&bytes[4..8], &5u32.to_le_bytes());
+        assert_eq!(&bytes[8..], b"a=1 b\0");
+    }
+
+    #[test]
+    fn argv_layout() {
+        let bytes = serialize("a  bc", BootArgsFormat::Argv, BLOCK).unwrap();
+        let words: Vec<u32> = (0..5)
+            .map(|index| word(&bytes, 4 * index).unwrap())
+            .collect();
+        assert_eq!(words, [MAGIC, 2, 0x7000_0014, 0x7000_0016, 0]);
+        assert_eq!(&bytes[20..], b"a\0bc\0");
+    }
+
+    #[test]
+    fn both_formats_decode_what_they_serialize() {
+        let args = "mode=test verbose";
+        let blob = serialize(args, BootArgsFormat::Blob, BLOCK).unwrap();
+        assert_eq!(decode(&blob, BootArgsFormat::Blob, BLOCK).unwrap(), [args]);
+        let argv = serialize(args, BootArgsFormat::Argv, BLOCK).unwrap();
+        assert_eq!(
+            decode(&argv, BootArgsFormat::Argv, BLOCK).unwrap(),
+            ["mode=test", "verbose"]
+        );
+        assert!(serialize("", BootArgsFormat::Argv, BLOCK)
+            .and_then(|bytes| decode(&bytes, BootArgsFormat::Argv, BLOCK))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn args_exceeding_the_block_are_refused() {
+        let small = BootArgsBlock {
+            addr: 0x7000_0000,
+            len: 16,
+        };
+        // 8 bytes of header, 7 characters and the NUL
+        assert!(check_boot_args("1234567", BootArgsFormat::Blob, small).is_ok());
+        let error = check_boot_args("12345678", BootArgsFormat::Blob, small).unwrap_err();
+        assert_eq!(
+            error,
+            "boot args need 17 bytes, the block at 0x70000000 holds 16"
+        );
+        // 8 bytes of header and the null pointer leave 4 bytes for the strings
+        assert!(check_boot_args("a b", BootArgsFormat::Argv, small).is_err());
+    }
+
+    #[test]
+    fn foreign_or_broken_blocks_are_not_decoded() {
+        assert!(decode(&[0; 64], BootArgsFormat::Blob, BLOCK).is_err());
+        assert!(decode(b"BAR", BootArgsFormat::Blob, BLOCK).is_err());
+        let mut bytes = serialize("abc", BootArgsFormat::Blob, BLOCK).unwrap();
+        bytes[4] = 0xff;
+        assert!(decode(&bytes, BootArgsFormat::Blob, BLOCK).is_err());
+        let mut bytes = serialize("abc", BootArgsFormat::Argv, BLOCK).unwrap();
+        bytes[8..12].copy_from_slice(&0x6000_0000u32.to_le_bytes());
+        assert!(decode(&bytes, BootArgsFormat::Argv, BLOCK).is_err());
+        let bytes = serialize("abc", BootArgsFormat::Argv, BLOCK).unwrap();
+        assert!(decode(&bytes[..bytes.len() - 1], BootArgsFormat::Argv, BLOCK).is_err());
+    }
+}
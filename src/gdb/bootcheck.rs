@@ -0,0 +1,223 @@
+//! Boot mode header check for `monitor bootcheck`, also run once after connecting.
+//!
+//! A correctly flashed application still doesn't start when no boot mode header (BMHD)
+//! in the UCB is valid or the valid one starts somewhere else. The startup software
+//! evaluates the headers in slot order and boots from the first valid one.
+
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+
+use super::flash::{read_u32_le, validate_bmhd, BMHD_LEN};
+use super::memory_map::{find_region, MemoryRegion, RegionKind};
+use super::{MonitorOutput, TricoreTarget};
+
+/// Words read at the start address to tell programmed from blank flash
+const STAD_PROBE_WORDS: usize = 4;
+
+/// BMI.PINDIS, the boot mode is taken from BMI.HWCFG instead of the configuration pins
+const BMI_PINDIS: u32 = 1 << 0;
+const BMI_HWCFG_SHIFT: u32 = 1;
+const BMI_HWCFG_MASK: u32 = 0b111 << BMI_HWCFG_SHIFT;
+
+/// Verdict on a single boot mode header slot
+#[derive(Debug, PartialEq, Eq)]
+pub enum BmhdVerdict {
+    /// Header and start address are consistent
+    Valid {
+        stad: u32,
+        bmi: u32,
+    },
+    /// The slot was never programmed
+    Erased,
+    Invalid(String),
+}
+
+/// Blank flash reads as all zeros, or all ones depending on the ECC configuration
+fn is_blank(data: &[u8]) -> bool {
+    data.iter().all(|&byte| byte == 0x00) || data.iter().all(|&byte| byte == 0xFF)
+}
+
+/// Boot mode selected by the header, `None` where the configuration pins decide
+fn boot_mode(bmi: u32) -> Option<&'static str> {
+    if bmi & BMI_PINDIS == 0 {
+        return None;
+    }
+    Some(match (bmi & BMI_HWCFG_MASK) >> BMI_HWCFG_SHIFT {
+        0b111 => "internal start",
+        0b110 => "alternate boot mode",
+        0b100 => "generic bootstrap loader",
+        0b011 => "ASC bootstrap loader",
+        _ => "invalid boot mode",
+    })
+}
+
+/// Validates a header, `read_stad` reads the first words at the start address
+pub fn check_bmhd(
+    header: &[u8],
+    regions: &'static [MemoryRegion],
+    read_stad: impl FnOnce(u32) -> Option<Vec<u8>>,
+) -> BmhdVerdict {
+    if is_blank(header) {
+        return BmhdVerdict::Erased;
+    }
+    if let Err(e) = validate_bmhd(header) {
+        return BmhdVerdict::Invalid(e.to_string());
+    }
+
+    let bmi = read_u32_le(header, 0) & 0xFFFF;
+    let stad = read_u32_le(header, 4);
+    match find_region(regions, stad) {
+        Some(region) if region.kind == RegionKind::ProgramFlash => {}
+        Some(region) => {
+            return BmhdVerdict::Invalid(format!(
+                "STAD {:#010x} is in {}, not in program flash",
+                stad, region.name
+            ))
+        }
+        None => {
+            return BmhdVerdict::Invalid(format!("STAD {:#010x} is outside of any memory", stad))
+        }
+    }
+    match read_stad(stad) {
+        Some(code) if is_blank(&code) => {
+            BmhdVerdict::Invalid(format!("STAD {:#010x} points to erased flash", stad))
+        }
+        Some(_) => BmhdVerdict::Valid { stad, bmi },
+        None => BmhdVerdict::Invalid(format!("Cannot read STAD {:#010x}", stad)),
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Verdict per boot mode header slot followed by the slot the device boots from
+    pub(crate) fn bootcheck_report(&mut self) -> Vec<String> {
+        let slots = self.arch.bmhd_slots();
+        if slots.is_empty() {
+            return vec![format!(
+                "Boot mode headers of {} devices are not checked",
+                self.arch
+            )];
+        }
+
+        let mut lines = Vec::new();
+        let mut boot_slot = None;
+        for (slot, &addr) in slots.iter().enumerate() {
+            let mut header = vec![0; BMHD_LEN];
+            let verdict = match self.read_memory(Tid::new(1).unwrap(), addr, &mut header) {
+                Ok(()) => check_bmhd(&header, self.arch.regions(), |stad| {
+                    let mut code = vec![0; STAD_PROBE_WORDS * 4];
+                    self.read_memory(Tid::new(1).unwrap(), stad, &mut code)
+                        .ok()
+                        .map(|_| code)
+                }),
+                Err(e) => BmhdVerdict::Invalid(format!("Cannot read header: {}", e)),
+            };
+
+            let line = match &verdict {
+                BmhdVerdict::Valid { stad, bmi } => {
+                    if boot_slot.is_none() {
+                        boot_slot = Some((slot, *bmi));
+                    }
+                    format!("valid, STAD {:#010x}, BMI {:#06x}", stad, bmi)
+                }
+                BmhdVerdict::Erased => "erased".to_owned(),
+                BmhdVerdict::Invalid(reason) => format!("invalid: {}", reason),
+            };
+            lines.push(format!("BMHD{} {:#010x} {}", slot, addr, line));
+        }
+
+        lines.push(match boot_slot {
+            Some((slot, bmi)) => match boot_mode(bmi) {
+                Some(mode) => format!("Device boots from BMHD{}: {}", slot, mode),
+                None => format!(
+                    "Device boots from BMHD{}, the configuration pins select the boot mode",
+                    slot
+                ),
+            },
+            None => {
+                "No valid boot mode header, the device will not start the application".to_owned()
+            }
+        });
+        lines
+    }
+
+    pub(crate) fn bootcheck_command(&mut self, out: &mut MonitorOutput<'_>) {
+        for line in self.bootcheck_report() {
+            outputln!(out, "{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gdb::flash::bmhd_crc;
+    use crate::gdb::memory_map::TC3XX_REGIONS;
+
+    /// Unlocked header with boot mode index `bmi` starting the application at `stad`
+    fn header(bmi: u16, stad: u32) -> Vec<u8> {
+        let mut header = vec![0; BMHD_LEN];
+        header[0..4].copy_from_slice(&(0xB359_0000 | bmi as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&stad.to_le_bytes());
+        let crc = bmhd_crc(&header);
+        header[8..12].copy_from_slice(&crc.to_le_bytes());
+        header[12..16].copy_from_slice(&(!crc).to_le_bytes());
+        header[0x1F0..0x1F4].copy_from_slice(&0x4321_1234u32.to_le_bytes());
+        header
+    }
+
+    fn programmed(_: u32) -> Option<Vec<u8>> {
+        Some(vec![0x91, 0x00, 0x00, 0xF8])
+    }
+
+    #[test]
+    fn consistent_header_is_valid() {
+        assert_eq!(
+            check_bmhd(&header(0x00FE, 0xA000_0000), TC3XX_REGIONS, programmed),
+            BmhdVerdict::Valid {
+                stad: 0xA000_0000,
+                bmi: 0x00FE
+            }
+        );
+    }
+
+    #[test]
+    fn blank_slot_is_erased() {
+        assert_eq!(
+            check_bmhd(&[0xFF; BMHD_LEN], TC3XX_REGIONS, programmed),
+            BmhdVerdict::Erased
+        );
+        assert_eq!(
+            check_bmhd(&[0x00; BMHD_LEN], TC3XX_REGIONS, programmed),
+            BmhdVerdict::Erased
+        );
+    }
+
+    #[test]
+    fn start_address_must_hold_code() {
+        let invalid = |header: &[u8], read_stad: fn(u32) -> Option<Vec<u8>>| {
+            matches!(
+                check_bmhd(header, TC3XX_REGIONS, read_stad),
+                BmhdVerdict::Invalid(_)
+            )
+        };
+        assert!(invalid(&header(0x00FE, 0xA000_0000), |_| Some(vec![
+            0xFF;
+            16
+        ])));
+        assert!(invalid(&header(0x00FE, 0xA000_0000), |_| None));
+        assert!(invalid(&header(0x00FE, 0xAF00_0000), programmed));
+        assert!(invalid(&header(0x00FE, 0x1000_0000), programmed));
+
+        let mut corrupt = header(0x00FE, 0xA000_0000);
+        corrupt[4] ^= 0x01;
+        assert!(invalid(&corrupt, programmed));
+    }
+
+    #[test]
+    fn boot_mode_follows_the_header_configuration() {
+        assert_eq!(boot_mode(0x00FE), None);
+        assert_eq!(boot_mode(0x00FF), Some("internal start"));
+        assert_eq!(boot_mode(0x0007), Some("ASC bootstrap loader"));
+        assert_eq!(boot_mode(0x0001), Some("invalid boot mode"));
+    }
+}
@@ -0,0 +1,158 @@
+//! `bp sync` and `bp check`, for when GDB's breakpoints and the stub's triggers no longer
+//! agree, e.g. after resets, reconnects or flash operations.
+//!
+//! `bp sync` drops every trigger the stub owns and lists what it had, GDB inserts its
+//! breakpoints again with the next continue. `bp check` compares the bookkeeping with
+//! the cores and changes nothing. rust_mcd cannot read triggers back from the probe, so
+//! the check covers what the stub knows of the triggers it created. Breakpoints are IP
+//! triggers, there are no patched opcodes to compare with memory.
+
+use gdbstub::outputln;
+
+use super::events::TargetEvent;
+use super::trigger_budget::TriggerOwner;
+use super::{MonitorOutput, TricoreTarget};
+
+impl<'a> TricoreTarget<'a> {
+    /// `bp sync`: removes every breakpoint trigger and forgets the breakpoints
+    pub(crate) fn breakpoint_sync(&mut self, out: &mut MonitorOutput<'_>) {
+        if !self.running_cores().is_empty() {
+            outputln!(
+                out,
+                "Halt the target first, triggers of running cores can't be removed"
+            );
+            return;
+        }
+
+        let mut keys: Vec<u32> = self.breakpoints.keys().copied().collect();
+        keys.sort_unstable();
+        let mut failed = 0;
+        for key in &keys {
+            let Some(mut breakpoint) = self.breakpoints.remove(key) else {
+                continue;
+            };
+            let owner = TriggerOwner::Breakpoint(breakpoint.address);
+            for (index, trigger) in breakpoint.take_triggers() {
+                if !self.remove_core_trigger(index, trigger, owner) {
+                    failed += 1;
+                }
+            }
+            outputln!(
+                out,
+                "{:#010x}{}{}",
+                breakpoint.requested,
+                if breakpoint.from_gdb { " gdb" } else { "" },
+                if breakpoint.from_monitor {
+                    " monitor"
+                } else {
+                    ""
+                }
+            );
+            self.publish(TargetEvent::BreakpointRemoved {
+                addr: breakpoint.address,
+            });
+        }
+        let mut deferred: Vec<u32> = self.deferred.drain().map(|(addr, _)| addr).collect();
+        deferred.sort_unstable();
+        for addr in deferred {
+            outputln!(out, "{:#010x} deferred", addr);
+        }
+        self.pending_breakpoints.clear();
+        self.clear_step_overs();
+        self.flush_triggers();
+
+        if failed > 0 {
+            outputln!(out, "{} triggers could not be removed", failed);
+        }
+        outputln!(
+            out,
+            "Removed {} breakpoints. GDB inserts its breakpoints again on the next continue, \
+             with `set breakpoint always-inserted on` use `delete breakpoints` and set them \
+             again. Set the ones marked monitor again with `monitor b`.",
+            keys.len()
+        );
+    }
+
+    /// `bp check`: reports where the breakpoint bookkeeping doesn't match the cores
+    pub(crate) fn breakpoint_check(&self, out: &mut MonitorOutput<'_>) {
+        let cores = self.all_cores();
+        let mut findings = 0;
+        let mut keys: Vec<&u32> = self.breakpoints.keys().collect();
+        keys.sort();
+        for &key in keys {
+            let breakpoint = &self.breakpoints[&key];
+            let mut report = |finding: String| {
+                outputln!(out, "{:#010x}: {}", key, finding);
+                findings += 1;
+            };
+            if self.breakpoint_key(breakpoint.address) != key {
+                report(format!(
+                    "kept under the wrong key, planted at {:#010x}",
+                    breakpoint.address
+                ));
+            }
+            if breakpoint.stale {
+                report("planted before a reset, waits for GDB to add it again".to_owned());
+            }
+            if !breakpoint.from_gdb && !breakpoint.from_monitor {
+                report("owned by neither GDB nor the monitor".to_owned());
+            }
+            let planted = breakpoint.planted_core_indices();
+            for &index in &planted {
+                if self.core(index).is_none() {
+                    report(format!("trigger on core {}, which is absent", index));
+                } else if !cores.contains(&index) {
+                    report(format!("trigger on core {}, outside the session", index));
+                }
+            }
+            if planted.is_empty() {
+                report("planted on no core".to_owned());
+            } else if breakpoint.from_gdb && planted.len() < cores.len() {
+                report(format!(
+                    "set by GDB but planted on {} of {} cores",
+                    planted.len(),
+                    cores.len()
+                ));
+            }
+        }
+
+        for index in cores {
+            let in_use = self.triggers_in_use(index);
+            if let Some(Some(limit)) = self.das_capabilities.trigger_limits.get(index) {
+                if in_use > *limit {
+                    outputln!(
+                        out,
+                        "core {}: {} triggers in use, the probe reported {}",
+                        index,
+                        in_use,
+                        limit
+                    );
+                    findings += 1;
+                }
+            }
+        }
+        if !self.dirty_triggers.is_empty() {
+            let dirty: Vec<&usize> = self.dirty_triggers.iter().collect();
+            outputln!(
+                out,
+                "Trigger changes of cores {:?} are downloaded with the next resume",
+                dirty
+            );
+        }
+        if !self.pending_breakpoints.is_empty() {
+            outputln!(
+                out,
+                "{} breakpoint changes wait for the next stop",
+                self.pending_breakpoints.len()
+            );
+        }
+
+        outputln!(
+            out,
+            "{} breakpoints checked, {} findings. Triggers can't be read back from the probe, \
+             compare the list with GDB's `info breakpoints`.",
+            self.breakpoints.len(),
+            findings
+        );
+    }
+}
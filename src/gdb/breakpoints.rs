@@ -1,78 +1,998 @@
+use std::ops::Range;
+
 use gdbstub::target::{
     self,
     ext::breakpoints::{Breakpoints, SwBreakpointOps},
     TargetError, TargetResult,
 };
-use log::debug;
-use rust_mcd::core::Trigger;
+use log::{debug, info, warn};
+use rust_mcd::core::{Core, CoreState, Trigger};
+
+use super::events::TargetEvent;
+use super::hit_timing::HitTiming;
+use super::memory_map::{canonical_address, flash_alias};
+use super::trigger_budget::{Refusal, TriggerOwner};
+use super::{read_register, CpuId, TricoreTarget};
+
+/// Bookkeeping of a breakpoint address, planted on any subset of the cores. Kept by the
+/// canonical address, see [TricoreTarget::breakpoint_key].
+pub(crate) struct Breakpoint {
+    /// Address the triggers are planted at, the alias the code runs from
+    pub(crate) address: u32,
+    /// Address last asked for, before it was moved to the alias of the elf
+    pub(crate) requested: u32,
+    /// Trigger per core index
+    slots: CoreTriggers<TriggerId>,
+    /// Breakpoint kind as given by GDB
+    pub(crate) kind: usize,
+    /// Number of hits still to be skipped before the stop is reported
+    pub(crate) ignore_count: u32,
+    /// Number of hits skipped so far
+    pub(crate) skipped: u32,
+    /// The address is not covered by executable code of the loaded elf
+    pub(crate) suspect: bool,
+    /// Removed from every core by the stub on its first reported hit
+    pub(crate) oneshot: bool,
+    /// Planted before a reset, the triggers are planted again when GDB re-adds it
+    pub(crate) stale: bool,
+    /// Set by GDB, kept while the other owner still wants it
+    pub(crate) from_gdb: bool,
+    /// Set by `monitor b`, GDB doesn't know it and a GDB removal leaves it in place
+    pub(crate) from_monitor: bool,
+    /// Hits with their host time, reported or silently resumed
+    pub(crate) timing: HitTiming,
+}
+
+impl Breakpoint {
+    pub(crate) fn new(core_count: usize, kind: usize, address: u32) -> Self {
+        Breakpoint {
+            address,
+            requested: address,
+            slots: CoreTriggers::new(core_count),
+            kind,
+            ignore_count: 0,
+            skipped: 0,
+            suspect: false,
+            oneshot: false,
+            stale: false,
+            from_gdb: false,
+            from_monitor: false,
+            timing: HitTiming::default(),
+        }
+    }
+
+    /// The requested and planted address for `monitor breakpoints` where they differ from
+    /// the canonical address `key`
+    pub(crate) fn aliases(&self, key: u32) -> String {
+        let mut aliases = String::new();
+        if self.requested != key {
+            aliases += &format!(" requested {:#010x}", self.requested);
+        }
+        if self.address != key {
+            aliases += &format!(" planted {:#010x}", self.address);
+        }
+        aliases
+    }
+
+    /// Number of cores the breakpoint is planted on
+    pub(crate) fn planted_cores(&self) -> usize {
+        self.slots.planted()
+    }
+
+    /// Indices of the cores the breakpoint is planted on
+    pub(crate) fn planted_core_indices(&self) -> Vec<usize> {
+        self.slots.planted_indices()
+    }
+
+    /// Takes the trigger of core `index` out, e.g. while the core steps off the address
+    pub(crate) fn take_trigger(&mut self, index: usize) -> Option<TriggerId> {
+        self.slots.take(index)
+    }
+
+    /// Puts a trigger of core `index` planted again back, see [Self::take_trigger]
+    pub(crate) fn restore_trigger(&mut self, index: usize, trigger: TriggerId) {
+        self.slots.put(index, trigger);
+    }
+
+    /// Takes the triggers out of the breakpoint with their core index, for removal
+    pub(crate) fn take_triggers(&mut self) -> Vec<(usize, TriggerId)> {
+        self.slots.take_all()
+    }
+
+    /// Takes the triggers of a breakpoint planted before a reset out, for removal before
+    /// it is planted again. A breakpoint which isn't stale keeps its triggers.
+    pub(crate) fn take_stale_triggers(&mut self) -> Vec<(usize, TriggerId)> {
+        if !self.stale {
+            return Vec::new();
+        }
+        self.stale = false;
+        self.take_triggers()
+    }
+
+    /// Whether the breakpoint is in effect on all of `core_count` cores as it is
+    pub(crate) fn in_effect_on(&self, core_count: usize) -> bool {
+        !self.stale && self.planted_cores() == core_count
+    }
+
+    fn set_owner(&mut self, owner: Owner, owned: bool) {
+        match owner {
+            Owner::Gdb => self.from_gdb = owned,
+            Owner::Monitor => self.from_monitor = owned,
+        }
+    }
+
+    /// Whether an owner other than `owner` still wants the breakpoint
+    fn kept_by_other(&self, owner: Owner) -> bool {
+        match owner {
+            Owner::Gdb => self.from_monitor,
+            Owner::Monitor => self.from_gdb,
+        }
+    }
+
+    /// Consumes one ignored hit, returns whether the hit has to be skipped
+    pub(crate) fn take_ignored_hit(&mut self) -> bool {
+        if self.ignore_count == 0 {
+            return false;
+        }
+        self.ignore_count -= 1;
+        self.skipped += 1;
+        true
+    }
+}
+
+/// Trigger planted on a core, by the ID rust_mcd gave it. A [Trigger] holds a mutable
+/// borrow of its core for as long as it lives, the target keeps the ID instead and lends
+/// the core for the removal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TriggerId(u32);
+
+impl TriggerId {
+    pub(crate) fn of(trigger: &Trigger) -> Self {
+        TriggerId(trigger.id())
+    }
+
+    /// Removes the trigger from `core`, the core it was created on
+    pub(crate) fn remove(self, core: &mut Core) -> bool {
+        core.remove_trigger(self.0).is_ok()
+    }
+}
+
+/// Triggers of a breakpoint by core index, `None` where it isn't planted. Generic over
+/// the trigger, the bookkeeping doesn't depend on the probe.
+pub(crate) struct CoreTriggers<T> {
+    slots: Vec<Option<T>>,
+}
+
+/// Core whose trigger could not be created while planting, with the triggers the same
+/// call planted before, taken out again for release
+pub(crate) struct PlantFailure<T> {
+    pub core: usize,
+    pub rolled_back: Vec<(usize, T)>,
+}
+
+impl<T> CoreTriggers<T> {
+    pub(crate) fn new(core_count: usize) -> Self {
+        CoreTriggers {
+            slots: (0..core_count).map(|_| None).collect(),
+        }
+    }
+
+    pub(crate) fn planted(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    pub(crate) fn planted_indices(&self) -> Vec<usize> {
+        (0..self.slots.len())
+            .filter(|&index| self.slots[index].is_some())
+            .collect()
+    }
+
+    /// Takes the trigger of core `index` out, `None` where it isn't planted
+    pub(crate) fn take(&mut self, index: usize) -> Option<T> {
+        self.slots.get_mut(index).and_then(Option::take)
+    }
+
+    /// Puts a trigger of core `index` back, a trigger planted there before is replaced
+    pub(crate) fn put(&mut self, index: usize, trigger: T) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            *slot = Some(trigger);
+        }
+    }
+
+    pub(crate) fn take_all(&mut self) -> Vec<(usize, T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((index, slot.take()?)))
+            .collect()
+    }
+
+    /// Plants a trigger made by `create` on every core of `cores` without one yet, cores
+    /// out of range are skipped. If a trigger cannot be created, the triggers planted by
+    /// this call are taken out again, the ones planted before are kept.
+    pub(crate) fn plant(
+        &mut self,
+        cores: &[usize],
+        mut create: impl FnMut(usize) -> Option<T>,
+    ) -> Result<(), PlantFailure<T>> {
+        let mut planted = Vec::new();
+        for &index in cores {
+            if self.slots.get(index).map_or(true, Option::is_some) {
+                continue;
+            }
+            match create(index) {
+                Some(trigger) => {
+                    self.slots[index] = Some(trigger);
+                    planted.push(index);
+                }
+                None => {
+                    let rolled_back = planted
+                        .into_iter()
+                        .filter_map(|index| Some((index, self.take(index)?)))
+                        .collect();
+                    return Err(PlantFailure {
+                        core: index,
+                        rolled_back,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Who asked for a breakpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Owner {
+    Gdb,
+    /// `monitor b`
+    Monitor,
+}
+
+/// Breakpoint change, queued when requested while cores were running
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PendingChange {
+    Add {
+        addr: u32,
+        /// Address asked for, `addr` is where it is planted
+        requested: u32,
+        kind: usize,
+        suspect: bool,
+        owner: Owner,
+        /// Single core to plant on, every core for `None`
+        core: Option<usize>,
+    },
+    Remove {
+        addr: u32,
+        owner: Owner,
+    },
+}
+
+impl PendingChange {
+    pub(crate) fn address(self) -> u32 {
+        match self {
+            PendingChange::Add { addr, .. } | PendingChange::Remove { addr, .. } => addr,
+        }
+    }
+}
+
+/// Where a breakpoint requested at `addr` is planted, given the executable ranges of the elf
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Placement {
+    /// The elf has code at the address
+    Exact(u32),
+    /// The elf has code at the other flash alias of the address
+    Alias(u32),
+    /// Neither the address nor an alias of it holds code of the elf
+    Suspect(u32),
+}
+
+impl Placement {
+    pub(crate) fn address(self) -> u32 {
+        match self {
+            Placement::Exact(addr) | Placement::Alias(addr) | Placement::Suspect(addr) => addr,
+        }
+    }
+}
+
+/// Normalizes a breakpoint address to the program flash alias the elf was linked for.
+///
+/// Without an elf there is nothing to check against and the address is kept.
+pub(crate) fn place_breakpoint(addr: u32, exec_ranges: &[Range<u32>]) -> Placement {
+    let covered = |addr: u32| exec_ranges.iter().any(|range| range.contains(&addr));
+
+    if exec_ranges.is_empty() || covered(addr) {
+        return Placement::Exact(addr);
+    }
+    match flash_alias(addr) {
+        Some(alias) if covered(alias) => Placement::Alias(alias),
+        _ => Placement::Suspect(addr),
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Address a breakpoint requested by GDB at `addr` is planted at
+    pub(crate) fn breakpoint_address(&self, addr: u32) -> u32 {
+        place_breakpoint(addr, &self.exec_ranges()).address()
+    }
+
+    /// Key of the breakpoint at `addr` in the breakpoint map, the cached alias of the
+    /// address on memories with two. GDB may remove a breakpoint through the other alias
+    /// of the one it set it at.
+    pub(crate) fn breakpoint_key(&self, addr: u32) -> u32 {
+        canonical_address(self.arch.aliases(), addr)
+    }
+
+    /// Creates an IP trigger at `addr` on a single core for `owner`, if the trigger budget
+    /// of the core allows. It takes effect once the core's triggers are downloaded by
+    /// [Self::flush_triggers].
+    pub(crate) fn create_core_trigger(
+        &mut self,
+        index: usize,
+        addr: u32,
+        owner: TriggerOwner,
+    ) -> Option<TriggerId> {
+        self.core(index)?;
+        if let Err(refusal) = self.acquire_trigger(index, owner) {
+            if owner.internal() {
+                warn!(
+                    "No trigger left on core {} for the {}: {}",
+                    index, owner, refusal
+                );
+            } else {
+                debug!("Core {} refused the {}: {}", index, owner, refusal);
+            }
+            return None;
+        }
+        let Ok(trigger) = self.core_mut(index)?.create_breakpoint(
+            rust_mcd::breakpoint::TriggerType::IP,
+            addr as u64,
+            4,
+        ) else {
+            self.trigger_budget.release(index, owner);
+            return None;
+        };
+        let trigger = TriggerId::of(&trigger);
+        self.dirty_triggers.insert(index);
+        Some(trigger)
+    }
+
+    /// Removes a trigger of core `index` and gives it back to `owner`'s budget, in effect
+    /// once the core's triggers are downloaded
+    pub(crate) fn remove_core_trigger(
+        &mut self,
+        index: usize,
+        trigger: TriggerId,
+        owner: TriggerOwner,
+    ) -> bool {
+        self.dirty_triggers.insert(index);
+        self.trigger_budget.release(index, owner);
+        self.core_mut(index)
+            .is_some_and(|core| trigger.remove(core))
+    }
+
+    /// Downloads the trigger list of every core whose triggers changed since, once per
+    /// core however many breakpoints changed. Called before cores are started.
+    pub(crate) fn flush_triggers(&mut self) {
+        for index in std::mem::take(&mut self.dirty_triggers) {
+            if let Some(core) = self.core_mut(index) {
+                core.download_triggers();
+                self.stats.record_trigger_download();
+            }
+        }
+    }
+
+    /// Plants the breakpoint at `addr` on `cores`, cores already having it are left alone.
+    ///
+    /// If a trigger cannot be created, the triggers created by this call are removed again
+    /// so no partially planted state is left behind.
+    pub(crate) fn plant_breakpoint(
+        &mut self,
+        addr: u32,
+        cores: &[usize],
+        kind: usize,
+    ) -> Result<(), &'static str> {
+        let core_count = self.core_count();
+        let key = self.breakpoint_key(addr);
+        let mut breakpoint = self
+            .breakpoints
+            .remove(&key)
+            .unwrap_or_else(|| Breakpoint::new(core_count, kind, addr));
+        let owner = TriggerOwner::Breakpoint(breakpoint.address);
+        if breakpoint.stale {
+            debug!("Planting breakpoint at {:#010x} again after a reset", addr);
+        }
+        for (index, trigger) in breakpoint.take_stale_triggers() {
+            self.remove_core_trigger(index, trigger, owner);
+        }
 
-use super::StaticTricoreTarget;
+        let address = breakpoint.address;
+        let planted = breakpoint.slots.plant(cores, |index| {
+            self.create_core_trigger(index, address, owner)
+        });
 
-impl Breakpoints for StaticTricoreTarget {
-    // there are several kinds of breakpoints - this target uses software breakpoints
+        let result = match planted {
+            Ok(()) => Ok(()),
+            Err(failure) => {
+                debug!(
+                    "Can't set breakpoint at {:#010x} on core {}",
+                    addr, failure.core
+                );
+                self.hint_breakpoint_limit(failure.core);
+                for (index, trigger) in failure.rolled_back {
+                    self.remove_core_trigger(index, trigger, owner);
+                }
+                Err("Can't set breakpoint, no trigger left")
+            }
+        };
+        if breakpoint.planted_cores() > 0 {
+            self.breakpoints.insert(key, breakpoint);
+        }
+        result
+    }
+
+    /// Triggers in use on core `index`, by any owner
+    pub(crate) fn triggers_in_use(&self, index: usize) -> usize {
+        self.trigger_budget.in_use(index)
+    }
+
+    /// Explains a breakpoint core `index` refused. The hint on GDB's breakpoint limit is
+    /// given once.
+    fn hint_breakpoint_limit(&mut self, index: usize) {
+        let in_use = self.triggers_in_use(index);
+        let reserved = self.settings.reserved_triggers;
+        let limit = self
+            .trigger_limit(index, TriggerOwner::Breakpoint(0))
+            .filter(|&limit| in_use + reserved >= limit);
+        let Some(limit) = limit else {
+            warn!(
+                "Core {} refused a breakpoint with {} triggers in use",
+                index, in_use
+            );
+            return;
+        };
+        warn!(
+            "Core {} ran out of breakpoints, {}",
+            index,
+            Refusal::Budget {
+                in_use,
+                limit,
+                reserved
+            }
+        );
+        if self.breakpoint_limit_hint_shown {
+            return;
+        }
+        self.breakpoint_limit_hint_shown = true;
+        if let Some(limit) = self.das_capabilities.gdb_breakpoint_limit(reserved) {
+            warn!(
+                "Use `set remote hardware-breakpoint-limit {}` so GDB refuses further \
+                 breakpoints right away",
+                limit
+            );
+        }
+    }
+
+    /// Removes the breakpoint at `addr` from `cores`, the entry goes with its last core
+    pub(crate) fn unplant_breakpoint(
+        &mut self,
+        addr: u32,
+        cores: &[usize],
+    ) -> Result<(), &'static str> {
+        let key = self.breakpoint_key(addr);
+        let Some(mut breakpoint) = self.breakpoints.remove(&key) else {
+            return Ok(());
+        };
+
+        let mut result = Ok(());
+        for &index in cores {
+            let Some(trigger) = breakpoint.slots.take(index) else {
+                continue;
+            };
+            if self.remove_core_trigger(
+                index,
+                trigger,
+                TriggerOwner::Breakpoint(breakpoint.address),
+            ) {
+                debug!(
+                    "Removed breakpoint at addr {:#010x} on core {}",
+                    addr, index
+                );
+            } else {
+                result = Err("Failed to remove trigger");
+            }
+        }
+
+        if breakpoint.planted_cores() > 0 {
+            self.breakpoints.insert(key, breakpoint);
+        }
+        result
+    }
+
+    /// Removes the one-shot breakpoints the stopped cores sit on from every core, before
+    /// the stop is reported. Cores which hit the same one-shot breakpoint in one poll are
+    /// all reported, the breakpoint is removed once.
+    pub(crate) fn remove_oneshot_hits(&mut self, stopped: &[CpuId]) {
+        let mut hits: Vec<u32> = stopped
+            .iter()
+            .filter_map(|cpu_id| match self.stop_snapshots.get(cpu_id) {
+                Some(snapshot) => Some(snapshot.pc),
+                None => read_register(self.core(usize::from(*cpu_id))?, "PC"),
+            })
+            .map(|pc| self.breakpoint_key(pc))
+            .filter(|key| self.breakpoints.get(key).is_some_and(|bp| bp.oneshot))
+            .collect();
+        hits.sort_unstable();
+        hits.dedup();
+
+        let cores = self.all_cores();
+        for addr in hits {
+            match self.unplant_breakpoint(addr, &cores) {
+                Ok(()) => info!(
+                    "Removed one-shot breakpoint at {:#010x} after its hit",
+                    addr
+                ),
+                Err(e) => warn!("Cannot remove one-shot breakpoint at {:#010x}: {}", addr, e),
+            }
+            self.publish(TargetEvent::BreakpointRemoved { addr });
+        }
+    }
+
+    /// Whether a change of the breakpoint at `addr`, or its other alias, waits for the
+    /// next stop
+    pub(crate) fn has_pending_change(&self, addr: u32) -> bool {
+        let key = self.breakpoint_key(addr);
+        self.pending_breakpoints
+            .iter()
+            .any(|change| self.breakpoint_key(change.address()) == key)
+    }
+
+    /// Whether the breakpoint at `addr` is in effect on every present core as it is
+    fn is_fully_planted(&self, addr: u32) -> bool {
+        let key = self.breakpoint_key(addr);
+        self.breakpoints
+            .get(&key)
+            .is_some_and(|breakpoint| breakpoint.in_effect_on(self.all_cores().len()))
+            && !self.has_pending_change(addr)
+    }
+
+    /// Indices of the present cores which are executing
+    pub(crate) fn running_cores(&self) -> Vec<usize> {
+        self.all_cores()
+            .into_iter()
+            .filter(|&index| {
+                self.core(index).is_some_and(|core| {
+                    matches!(
+                        core.query_state().map(|info| info.state),
+                        Ok(CoreState::Running)
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Applies a breakpoint change right away while the target is halted.
+    ///
+    /// Downloading triggers to a running core can stall it, so while cores run the change
+    /// is queued for the next stop. With `live-bp` the running cores are halted for the
+    /// change and resumed, the halt is not reported.
+    pub(crate) fn change_breakpoint(&mut self, change: PendingChange) -> Result<(), &'static str> {
+        let running = self.running_cores();
+        if running.is_empty() {
+            return self.apply_breakpoint_change(change);
+        }
+        if !self.settings.live_bp {
+            debug!("Queueing {:?} until the target stops", change);
+            self.pending_breakpoints.push(change);
+            return Ok(());
+        }
+
+        // Cores which stopped on their own are left alone, their stop is reported by the
+        // next poll
+        for &index in &running {
+            if let Some(core) = self.core_mut(index) {
+                _ = core.stop();
+            }
+        }
+        let result = self.apply_breakpoint_change(change);
+        self.flush_triggers();
+        for (position, index) in self.resume_sequence(&running).into_iter().enumerate() {
+            self.stagger_start(position);
+            if !self.core_mut(index).is_some_and(|core| core.run().is_ok()) {
+                warn!("Cannot resume core {} after changing breakpoints", index);
+            }
+        }
+        result
+    }
+
+    fn apply_breakpoint_change(&mut self, change: PendingChange) -> Result<(), &'static str> {
+        let cores = self.all_cores();
+        match change {
+            PendingChange::Add {
+                addr,
+                requested,
+                kind,
+                suspect,
+                owner,
+                core,
+            } => {
+                let cores = core.map_or(cores, |index| vec![index]);
+                self.plant_breakpoint(addr, &cores, kind)?;
+                let key = self.breakpoint_key(addr);
+                if let Some(breakpoint) = self.breakpoints.get_mut(&key) {
+                    breakpoint.requested = requested;
+                    breakpoint.suspect = suspect;
+                    breakpoint.set_owner(owner, true);
+                }
+                self.publish(TargetEvent::BreakpointAdded { addr });
+            }
+            PendingChange::Remove { addr, owner } => {
+                let key = self.breakpoint_key(addr);
+                let Some(breakpoint) = self.breakpoints.get_mut(&key) else {
+                    return Ok(());
+                };
+                breakpoint.set_owner(owner, false);
+                if breakpoint.kept_by_other(owner) {
+                    debug!(
+                        "Breakpoint at {:#010x} stays, it was set by the other side as well",
+                        addr
+                    );
+                    return Ok(());
+                }
+                self.unplant_breakpoint(addr, &cores)?;
+                self.publish(TargetEvent::BreakpointRemoved { addr });
+            }
+        }
+        Ok(())
+    }
+
+    /// Plants the breakpoints set with `monitor b` again after a reset, GDB doesn't know
+    /// them and won't re-add them
+    pub(crate) fn replant_monitor_breakpoints(&mut self) {
+        let monitor_owned: Vec<(u32, usize, Vec<usize>)> = self
+            .breakpoints
+            .values()
+            .filter(|breakpoint| breakpoint.from_monitor)
+            .map(|breakpoint| {
+                (
+                    breakpoint.address,
+                    breakpoint.kind,
+                    breakpoint.planted_core_indices(),
+                )
+            })
+            .collect();
+        for (addr, kind, cores) in monitor_owned {
+            if let Err(e) = self.plant_breakpoint(addr, &cores, kind) {
+                warn!("Cannot plant breakpoint at {:#010x} again: {}", addr, e);
+            }
+        }
+    }
+
+    /// Applies the changes queued while running in their order. Called with all cores
+    /// halted before the stop is reported, so they are in effect for the next resume.
+    pub(crate) fn apply_pending_breakpoints(&mut self) {
+        for change in std::mem::take(&mut self.pending_breakpoints) {
+            match self.apply_breakpoint_change(change) {
+                Ok(()) => debug!("Applied queued {:?}", change),
+                Err(e) => warn!("Cannot apply queued {:?}: {}", change, e),
+            }
+        }
+    }
+
+    /// Indices of all cores which are present, only the selected one with `--single-core`
+    pub(crate) fn all_cores(&self) -> Vec<usize> {
+        self.slots()
+            .filter(|(index, slot)| slot.core.is_some() && self.in_session(*index))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+impl<'a> Breakpoints for TricoreTarget<'a> {
+    // GDB's software breakpoints are served by IP triggers, memory is never patched. A
+    // core resumed from a breakpoint address steps off it first, see `step_off`.
     #[inline(always)]
     fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
         Some(self)
     }
 }
 
-impl target::ext::breakpoints::SwBreakpoint for StaticTricoreTarget {
+impl<'a> target::ext::breakpoints::SwBreakpoint for TricoreTarget<'a> {
     fn add_sw_breakpoint(
         &mut self,
         addr: u32,
         //todo: refer type from gdbstub_arch
-        _kind: usize,
+        kind: usize,
     ) -> TargetResult<bool, Self> {
-        //this is strange
-        let core_count = self.system.core_count();
+        debug!("add_sw_breakpoint invoked at address: {:#010x}", addr);
 
-        debug!("add_sw_breakpoint invoked at address: {:#01x}", addr);
-
-        let mut triggers = <Vec<Trigger>>::new();
-
-        for idx in 0..core_count {
-            let static_core: &'static mut rust_mcd::core::Core<'static> =
-                unsafe { std::mem::transmute(&mut self.cores[idx]) };
+        let placement = place_breakpoint(addr, &self.exec_ranges());
+        match placement {
+            Placement::Exact(_) => {}
+            Placement::Alias(alias) => self.notify(
+                None,
+                format_args!(
+                    "Breakpoint at {:#010x} moved to {:#010x}, the alias the elf code runs at",
+                    addr, alias
+                ),
+            ),
+            Placement::Suspect(_) => self.notify(
+                None,
+                format_args!(
+                    "Breakpoint at {:#010x} is outside of the elf code, is the flashed image up \
+                     to date?",
+                    addr
+                ),
+            ),
+        }
+        let requested = addr;
+        let addr = placement.address();
 
-            let trig = static_core.create_breakpoint(
-                rust_mcd::breakpoint::TriggerType::IP,
-                addr as u64,
-                4,
+        if let Some(guard) = self.overlay_guard(addr) {
+            debug!(
+                "Deferring breakpoint at {:#010x} until its overlay is copied",
+                addr
             );
+            self.defer_breakpoint(addr, guard);
+            return Ok(true);
+        }
 
-            match trig {
-                Ok(trigger) => {
-                    self.cores[idx].download_triggers();
-                    triggers.push(trigger);
-                }
-                Err(_) => {
-                    debug!("Can't set breakpoint at address: {:#01x}", addr);
-                    return Err(TargetError::Fatal(stringify!(
-                        "Can't set breakpoint at address: {:#01x}",
-                        addr
-                    )));
-                }
+        // GDB re-sends its breakpoints on some events, a breakpoint in effect is kept as
+        // it is rather than taking another trigger per core. Its ignore count starts over
+        // as for any breakpoint added.
+        if self.is_fully_planted(addr) {
+            debug!("Breakpoint at {:#010x} is already planted", addr);
+            let key = self.breakpoint_key(addr);
+            if let Some(breakpoint) = self.breakpoints.get_mut(&key) {
+                breakpoint.requested = requested;
+                breakpoint.kind = kind;
+                breakpoint.from_gdb = true;
+                breakpoint.ignore_count = 0;
+                breakpoint.skipped = 0;
             }
+            return Ok(true);
         }
-        self.breakpoints.insert(addr, triggers);
+
+        // A breakpoint which doesn't fit is refused, GDB reports it and the session goes on
+        self.change_breakpoint(PendingChange::Add {
+            addr,
+            requested,
+            kind,
+            suspect: matches!(placement, Placement::Suspect(_)),
+            owner: Owner::Gdb,
+            core: None,
+        })
+        .map_err(|_| TargetError::NonFatal)?;
 
         Ok(true)
     }
 
+    /// Removing a breakpoint which isn't set succeeds, as the remote protocol asks Z and z
+    /// packets to be idempotent. GDB may send a removal again, e.g. after a lost reply.
     fn remove_sw_breakpoint(
         &mut self,
         addr: u32,
         //todo: refere type from gdbstub_arch
         _kind: usize,
     ) -> TargetResult<bool, Self> {
-        if let Some(triggers) = self.breakpoints.remove(&addr) {
-            for trigger in triggers {
-                match trigger.remove() {
-                    Ok(_) => debug!("Removed breakpoint at addr {:#01x}", addr),
-                    Err(_) => return Err(TargetError::Fatal("Failed to remove trigger")),
-                }
-            }
+        let addr = self.breakpoint_address(addr);
+        if self.deferred.remove(&addr).is_some() {
+            debug!("Dropped deferred breakpoint at addr {:#010x}", addr);
+            return Ok(true);
+        }
+        if !self.breakpoints.contains_key(&self.breakpoint_key(addr))
+            && !self.has_pending_change(addr)
+        {
+            debug!("No breakpoint at {:#010x} to remove", addr);
+            return Ok(true);
         }
+        self.change_breakpoint(PendingChange::Remove {
+            addr,
+            owner: Owner::Gdb,
+        })
+        .map_err(TargetError::Fatal)?;
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Trigger stand-in, numbered in creation order
+    fn counter() -> impl FnMut(usize) -> Option<u32> {
+        let mut next = 0;
+        move |_| {
+            next += 1;
+            Some(next)
+        }
+    }
+
+    #[test]
+    fn add_twice_keeps_the_triggers() {
+        let mut triggers = CoreTriggers::new(3);
+        let mut create = counter();
+        assert!(triggers.plant(&[0, 2], &mut create).is_ok());
+        assert!(triggers.plant(&[0, 2], &mut create).is_ok());
+        assert_eq!(triggers.planted_indices(), vec![0, 2]);
+        assert_eq!(triggers.take_all(), vec![(0, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn add_extends_to_further_cores() {
+        let mut triggers = CoreTriggers::new(3);
+        let mut create = counter();
+        assert!(triggers.plant(&[0], &mut create).is_ok());
+        assert!(triggers.plant(&[0, 1, 2], &mut create).is_ok());
+        assert_eq!(triggers.take_all(), vec![(0, 1), (1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn remove_partial_keeps_the_other_cores() {
+        let mut triggers = CoreTriggers::new(3);
+        assert!(triggers.plant(&[0, 1, 2], counter()).is_ok());
+        assert_eq!(triggers.take(1), Some(2));
+        assert_eq!(triggers.take(1), None);
+        assert_eq!(triggers.planted(), 2);
+        assert_eq!(triggers.planted_indices(), vec![0, 2]);
+    }
+
+    #[test]
+    fn step_off_puts_the_trigger_back() {
+        let mut triggers = CoreTriggers::new(2);
+        assert!(triggers.plant(&[0, 1], counter()).is_ok());
+        for replanted in [7, 8] {
+            assert!(triggers.take(1).is_some());
+            assert_eq!(triggers.planted_indices(), vec![0]);
+            triggers.put(1, replanted);
+            assert_eq!(triggers.planted_indices(), vec![0, 1]);
+        }
+        triggers.put(5, 9);
+        assert_eq!(triggers.take_all(), vec![(0, 1), (1, 8)]);
+    }
+
+    #[test]
+    fn remove_all_leaves_nothing_planted() {
+        let mut triggers = CoreTriggers::new(3);
+        assert!(triggers.plant(&[0, 1], counter()).is_ok());
+        assert_eq!(triggers.take(0), Some(1));
+        assert_eq!(triggers.take(1), Some(2));
+        assert_eq!(triggers.planted(), 0);
+        assert!(triggers.take_all().is_empty());
+    }
+
+    #[test]
+    fn failed_add_rolls_back_only_its_own_triggers() {
+        let mut triggers = CoreTriggers::new(4);
+        assert!(triggers.plant(&[0], counter()).is_ok());
+
+        let mut created = 10;
+        let failure = triggers
+            .plant(&[0, 1, 2, 3], |index| {
+                (index != 3).then(|| {
+                    created += 1;
+                    created
+                })
+            })
+            .unwrap_err();
+        assert_eq!(failure.core, 3);
+        assert_eq!(failure.rolled_back, vec![(1, 11), (2, 12)]);
+        assert_eq!(triggers.planted_indices(), vec![0]);
+    }
+
+    #[test]
+    fn cores_out_of_range_are_skipped() {
+        let mut triggers = CoreTriggers::new(2);
+        assert!(triggers.plant(&[1, 5], counter()).is_ok());
+        assert_eq!(triggers.planted_indices(), vec![1]);
+        assert_eq!(triggers.take(5), None);
+    }
+
+    #[test]
+    fn breakpoint_follows_the_alias_the_elf_was_linked_for() {
+        let code = [0x8000_0100..0x8000_0200];
+        assert_eq!(
+            place_breakpoint(0x8000_0104, &code),
+            Placement::Exact(0x8000_0104)
+        );
+        assert_eq!(
+            place_breakpoint(0xA000_0104, &code),
+            Placement::Alias(0x8000_0104)
+        );
+        assert_eq!(
+            place_breakpoint(0x8000_0204, &code),
+            Placement::Suspect(0x8000_0204)
+        );
+        assert_eq!(
+            place_breakpoint(0x7000_0000, &code),
+            Placement::Suspect(0x7000_0000)
+        );
+    }
+
+    #[test]
+    fn breakpoint_without_elf_is_kept() {
+        assert_eq!(
+            place_breakpoint(0xA000_0104, &[]),
+            Placement::Exact(0xA000_0104)
+        );
+    }
+
+    /// Breakpoint planted on `cores`, its triggers numbered from `first`
+    fn planted(core_count: usize, cores: &[usize], first: u32) -> Breakpoint {
+        let mut breakpoint = Breakpoint::new(core_count, 4, 0x8000_0100);
+        let mut next = first;
+        assert!(breakpoint
+            .slots
+            .plant(cores, |_| {
+                next += 1;
+                Some(TriggerId(next - 1))
+            })
+            .is_ok());
+        breakpoint
+    }
+
+    #[test]
+    fn add_add_remove_uses_one_trigger_per_core() {
+        let mut breakpoint = planted(3, &[0, 1, 2], 1);
+        let mut created = 0;
+        assert!(breakpoint
+            .slots
+            .plant(&[0, 1, 2], |_| {
+                created += 1;
+                Some(TriggerId(created))
+            })
+            .is_ok());
+        assert_eq!(created, 0);
+        assert!(breakpoint.in_effect_on(3));
+        assert!(breakpoint.take_stale_triggers().is_empty());
+
+        let removed = breakpoint.take_triggers();
+        assert_eq!(
+            removed,
+            vec![(0, TriggerId(1)), (1, TriggerId(2)), (2, TriggerId(3))]
+        );
+        assert_eq!(breakpoint.planted_cores(), 0);
+    }
+
+    #[test]
+    fn add_reset_add_replaces_the_stale_triggers() {
+        let mut breakpoint = planted(2, &[0, 1], 1);
+        breakpoint.stale = true;
+        assert!(!breakpoint.in_effect_on(2));
+
+        let stale = breakpoint.take_stale_triggers();
+        assert_eq!(stale, vec![(0, TriggerId(1)), (1, TriggerId(2))]);
+        assert!(!breakpoint.stale);
+        assert!(breakpoint
+            .slots
+            .plant(&[0, 1], |index| Some(TriggerId(10 + index as u32)))
+            .is_ok());
+        assert!(breakpoint.in_effect_on(2));
+        assert_eq!(
+            breakpoint.take_triggers(),
+            vec![(0, TriggerId(10)), (1, TriggerId(11))]
+        );
+    }
+
+    #[test]
+    fn remove_nonexistent_takes_nothing() {
+        let mut breakpoint = planted(3, &[1], 1);
+        assert_eq!(breakpoint.take_trigger(0), None);
+        assert_eq!(breakpoint.take_trigger(7), None);
+        assert_eq!(breakpoint.take_triggers(), vec![(1, TriggerId(1))]);
+        assert!(breakpoint.take_triggers().is_empty());
+    }
+
+    #[test]
+    fn aliases_show_where_they_differ_from_the_key() {
+        let mut breakpoint = Breakpoint::new(1, 4, 0x8000_0100);
+        assert_eq!(breakpoint.aliases(0x8000_0100), "");
+        breakpoint.requested = 0xA000_0100;
+        assert_eq!(breakpoint.aliases(0x8000_0100), " requested 0xa0000100");
+        breakpoint.address = 0xA000_0100;
+        assert_eq!(
+            breakpoint.aliases(0x8000_0100),
+            " requested 0xa0000100 planted 0xa0000100"
+        );
+    }
+}
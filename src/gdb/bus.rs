@@ -0,0 +1,90 @@
+//! Sized accesses for `monitor rd8|rd16|rd32` and `monitor wr8|wr16|wr32`.
+//!
+//! Each access is a single MCD transaction of exactly the requested width at an address
+//! aligned to it, unlike GDB reads which may be split or merged. rust_mcd takes no access
+//! size hint, so the probe decides on the bus access: some probes read peripheral space
+//! with 32 bit accesses whatever the width is.
+
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+use log::info;
+
+use super::traits::TricoreTargetError;
+use super::{parse_u32, MonitorOutput, TricoreTarget};
+
+fn check_aligned(addr: u32, width: usize) -> Result<(), TricoreTargetError> {
+    if !matches!(width, 1 | 2 | 4) {
+        return Err(TricoreTargetError::MemoryAccess(format!(
+            "unsupported access width of {} bytes",
+            width
+        )));
+    }
+    if addr as usize % width != 0 {
+        return Err(TricoreTargetError::MemoryAccess(format!(
+            "{:#010x} is not {} byte aligned",
+            addr, width
+        )));
+    }
+    Ok(())
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Reads `width` bytes at `addr` in a single transaction
+    pub(crate) fn read_sized(
+        &mut self,
+        addr: u32,
+        width: usize,
+    ) -> Result<u32, TricoreTargetError> {
+        check_aligned(addr, width)?;
+        let mut bytes = [0u8; 4];
+        self.read_memory(Tid::new(1).unwrap(), addr, &mut bytes[..width])?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Writes the low `width` bytes of `value` at `addr` in a single transaction
+    pub(crate) fn write_sized(
+        &mut self,
+        addr: u32,
+        width: usize,
+        value: u32,
+    ) -> Result<(), TricoreTargetError> {
+        check_aligned(addr, width)?;
+        self.write_memory(Tid::new(1).unwrap(), addr, &value.to_le_bytes()[..width])
+    }
+
+    pub(crate) fn bus_read(&mut self, addr: &str, width: usize, out: &mut MonitorOutput<'_>) {
+        let Some(addr) = parse_u32(addr) else {
+            outputln!(out, "usage: rd{} <addr>", width * 8);
+            return;
+        };
+        match self.read_sized(addr, width) {
+            Ok(value) => {
+                info!("rd{} {:#010x} = {:#x}", width * 8, addr, value);
+                outputln!(out, "{:#010x}: {:#0w$x}", addr, value, w = width * 2 + 2);
+            }
+            Err(e) => outputln!(out, "{}", e),
+        }
+    }
+
+    pub(crate) fn bus_write(
+        &mut self,
+        addr: &str,
+        value: &str,
+        width: usize,
+        out: &mut MonitorOutput<'_>,
+    ) {
+        let (Some(addr), Some(value)) = (parse_u32(addr), parse_u32(value)) else {
+            outputln!(out, "usage: wr{} <addr> <value>", width * 8);
+            return;
+        };
+        if width < 4 && value >> (width * 8) != 0 {
+            outputln!(out, "{:#x} does not fit {} bits", value, width * 8);
+            return;
+        }
+        info!("wr{} {:#010x} = {:#x}", width * 8, addr, value);
+        match self.write_sized(addr, width, value) {
+            Ok(()) => outputln!(out, "{:#010x}: {:#0w$x}", addr, value, w = width * 2 + 2),
+            Err(e) => outputln!(out, "{}", e),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Operation gate for flash programming while a GDB client is attached.
+//!
+//! Interleaving other MCD transactions with a flash sequence corrupts the programming
+//! session on the probe. While an operation holds the gate, memory and register accesses
+//! and resume requests of the client are refused, and so are monitor commands other than
+//! `monitor flash abort`.
+
+use std::fmt;
+
+use log::warn;
+
+use super::TricoreTarget;
+
+/// Flash, erase or verify operation holding the gate
+#[derive(Debug, Clone)]
+pub struct BusyReason {
+    pub operation: &'static str,
+    /// Progress of the operation, 0 to 100
+    pub percent: u8,
+    /// `monitor flash abort` was given, the operation stops at its next progress update
+    pub abort_requested: bool,
+}
+
+impl fmt::Display for BusyReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in progress, {}% done", self.operation, self.percent)
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Runs `operation` with the gate held, the gate is released however it returns
+    pub fn run_busy<T>(&mut self, operation: &'static str, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.busy = Some(BusyReason {
+            operation,
+            percent: 0,
+            abort_requested: false,
+        });
+        let result = f(self);
+        self.busy = None;
+        result
+    }
+
+    /// Records the progress of the operation holding the gate. Returns `false` once the
+    /// operation was asked to abort.
+    pub fn busy_progress(&mut self, percent: u8) -> bool {
+        match &mut self.busy {
+            Some(busy) => {
+                busy.percent = percent.min(100);
+                !busy.abort_requested
+            }
+            None => true,
+        }
+    }
+
+    /// Operation holding the gate, if any
+    pub fn busy(&self) -> Option<&BusyReason> {
+        self.busy.as_ref()
+    }
+
+    /// Whether a client request has to be refused, logs why
+    pub(crate) fn refuse_busy(&self, request: &str) -> bool {
+        match &self.busy {
+            Some(busy) => {
+                warn!("Refusing {}: {}", request, busy);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Cache maintenance for `monitor cache`, so reads through the debug port see what a
+//! core wrote into its data cache.
+//!
+//! The enable state is read from the core's CSFRs. Writing back and invalidating the
+//! data cache takes the CACHEI instructions, which run as an injected snippet looping
+//! over every line of the cache.
+
+use gdbstub::outputln;
+
+use super::arch::ArchVariant;
+use super::inject::SNIPPET_TIMEOUT;
+use super::memory_map::csfr_address;
+use super::{parse_u32, MonitorOutput, TricoreTarget};
+
+/// CSFR offsets of the cache control registers
+const PCON0: u32 = 0x920C;
+const DCON0: u32 = 0x9040;
+/// PCON0.PCBYP and DCON0.DCBYP, the cache is bypassed
+const CON0_BYPASS: u32 = 1 << 1;
+
+/// Data cache line size of TC1.6.1 and TC1.6.2 cores
+const LINE: u32 = 32;
+
+/// BO format op2 of the post-increment CACHEI variants
+const CACHEI_W: u32 = 0x0B;
+const CACHEI_WI: u32 = 0x0F;
+
+impl ArchVariant {
+    /// Data cache size in bytes of the performance cores
+    fn data_cache_size(self) -> u32 {
+        match self {
+            ArchVariant::Tc161 => 8 * 1024,
+            ArchVariant::Tc162 => 16 * 1024,
+        }
+    }
+}
+
+/// `cachei.<op> [a15+]LINE; loop a14, 0; dsync`, stopping after the dsync.
+/// Returns the code and the offset it stops at.
+fn cachei_loop(op2: u32) -> (Vec<u8>, u32) {
+    let cachei: u32 = 0x89 | (15 << 12) | ((LINE & 0x3F) << 16) | (op2 << 22) | ((LINE >> 6) << 28);
+    // 16 bit LOOP, disp4 of 0b1110 jumps back 4 bytes to the cachei
+    let loop_a14: u16 = 0xFC | (0b1110 << 8) | (14 << 12);
+    let dsync: u32 = 0x0480_000D;
+
+    let mut code = Vec::new();
+    code.extend_from_slice(&cachei.to_le_bytes());
+    code.extend_from_slice(&loop_a14.to_le_bytes());
+    code.extend_from_slice(&dsync.to_le_bytes());
+    let end = code.len() as u32;
+    // 16 bit NOP the stop breakpoint sits on
+    code.extend_from_slice(&[0x00, 0x00]);
+    (code, end)
+}
+
+impl<'a> TricoreTarget<'a> {
+    fn cache_core(&self, core: Option<&str>, out: &mut MonitorOutput<'_>) -> Option<usize> {
+        let index = match core.map(parse_u32) {
+            None => 0,
+            Some(Some(index)) => index as usize,
+            Some(None) => {
+                outputln!(out, "usage: cache status|flush|invalidate [core]");
+                return None;
+            }
+        };
+        if self.core(index).is_none() {
+            outputln!(out, "No core {}", index);
+            return None;
+        }
+        Some(index)
+    }
+
+    pub(crate) fn cache_status(&mut self, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let Some(index) = self.cache_core(core, out) else {
+            return;
+        };
+        for (name, offset) in [("program cache", PCON0), ("data cache", DCON0)] {
+            match self.read_sized(csfr_address(index, offset), 4) {
+                Ok(con0) if con0 & CON0_BYPASS != 0 => {
+                    outputln!(out, "{:<20} bypassed", name)
+                }
+                Ok(_) => outputln!(out, "{:<20} enabled", name),
+                Err(e) => outputln!(out, "{:<20} {}", name, e),
+            }
+        }
+    }
+
+    /// Writes back the data cache of a halted core, `invalidate` drops the lines as well
+    pub(crate) fn cache_maintain(
+        &mut self,
+        core: Option<&str>,
+        invalidate: bool,
+        out: &mut MonitorOutput<'_>,
+    ) {
+        let Some(index) = self.cache_core(core, out) else {
+            return;
+        };
+        let (code, end) = cachei_loop(if invalidate { CACHEI_WI } else { CACHEI_W });
+        let lines = self.arch.data_cache_size() / LINE;
+        let registers = [("A15", 0), ("A14", lines - 1)];
+        match self.run_snippet(index, &code, end, &registers, SNIPPET_TIMEOUT) {
+            Ok(()) if invalidate => {
+                outputln!(
+                    out,
+                    "Data cache of core {} written back and invalidated",
+                    index
+                )
+            }
+            Ok(()) => outputln!(out, "Data cache of core {} written back", index),
+            Err(e) => outputln!(out, "{}", e),
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Resources of the DAS server and probe, determined once after connecting.
+//!
+//! rust_mcd does not report trigger resources, so the number of IP and data triggers of a
+//! core is found by creating triggers of the type until the probe refuses one.
+
+use log::{info, warn};
+
+use super::breakpoints::TriggerId;
+use super::trigger_budget::TriggerOwner;
+use super::watch_range::WatchAccess;
+use super::{ProbeIdentity, TricoreTarget};
+
+/// Upper bound of triggers created per core while probing
+const MAX_PROBED_TRIGGERS: usize = 32;
+
+/// Address of the triggers created while probing, never executed
+const PROBE_ADDRESS: u32 = 0;
+
+/// What the connected DAS server and probe support
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    /// Server description as reported by the scan
+    pub server: String,
+    /// Access hardware the device is connected through
+    pub probe: Option<ProbeIdentity>,
+    /// IP triggers available per core, by physical index. `None` where the core is absent
+    /// or the probe accepted [MAX_PROBED_TRIGGERS] triggers without refusing one.
+    pub trigger_limits: Vec<Option<usize>>,
+    /// Data triggers available per core, as [Self::trigger_limits]
+    pub data_trigger_limits: Vec<Option<usize>>,
+    /// Data triggers for hardware watchpoints
+    pub watchpoints: bool,
+    /// Several memory accesses per MCD transaction
+    pub batching: bool,
+    /// Switching the target power through the access hardware
+    pub power_control: bool,
+}
+
+impl Capabilities {
+    /// Breakpoint limit common to all cores, the smallest known one
+    pub fn breakpoint_limit(&self) -> Option<usize> {
+        self.trigger_limits.iter().flatten().copied().min()
+    }
+
+    /// Limit to suggest for `set remote hardware-breakpoint-limit`, the breakpoint limit
+    /// less the triggers reserved for the stub's own use
+    pub fn gdb_breakpoint_limit(&self, reserved: usize) -> Option<usize> {
+        self.breakpoint_limit()
+            .map(|limit| limit.saturating_sub(reserved))
+    }
+
+    /// IP or data trigger limit of core `index`, `None` where unknown
+    pub fn trigger_limit(&self, index: usize, data: bool) -> Option<usize> {
+        let limits = if data {
+            &self.data_trigger_limits
+        } else {
+            &self.trigger_limits
+        };
+        limits.get(index).copied().flatten()
+    }
+
+    /// All capabilities with their value, in display order
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        let limits = |limits: &[Option<usize>]| {
+            limits
+                .iter()
+                .map(|limit| match limit {
+                    Some(limit) => limit.to_string(),
+                    None => "?".to_owned(),
+                })
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        vec![
+            ("das server", self.server.clone()),
+            (
+                "probe",
+                self.probe
+                    .as_ref()
+                    .map_or("unknown".to_owned(), |probe| probe.name.clone()),
+            ),
+            (
+                "probe serial",
+                self.probe
+                    .as_ref()
+                    .and_then(|probe| probe.serial.clone())
+                    .unwrap_or("none".to_owned()),
+            ),
+            ("triggers per core", limits(&self.trigger_limits)),
+            ("data triggers per core", limits(&self.data_trigger_limits)),
+            ("watchpoints", supported(self.watchpoints)),
+            ("batching", supported(self.batching)),
+            ("power control", supported(self.power_control)),
+        ]
+    }
+}
+
+fn supported(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_owned()
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Counts the IP and data triggers each present core accepts. The limits are only
+    /// stored at the end, so the trigger budget doesn't interfere with the counting.
+    pub(crate) fn probe_trigger_limits(&mut self) {
+        let mut limits = vec![None; self.core_count()];
+        let mut data_limits = vec![None; self.core_count()];
+        for index in self.all_cores() {
+            limits[index] = self.count_triggers(index, |target| {
+                target.create_core_trigger(index, PROBE_ADDRESS, TriggerOwner::Probe)
+            });
+            data_limits[index] = self.count_triggers(index, |target| {
+                target.create_data_trigger(
+                    index,
+                    PROBE_ADDRESS,
+                    4,
+                    WatchAccess::Write,
+                    TriggerOwner::Probe,
+                )
+            });
+        }
+
+        match limits.iter().flatten().min() {
+            Some(limit) => info!("Probe provides {} breakpoints per core", limit),
+            None => warn!("Cannot tell the number of breakpoints the probe provides"),
+        }
+        self.das_capabilities.trigger_limits = limits;
+        self.das_capabilities.data_trigger_limits = data_limits;
+    }
+
+    /// Creates triggers on core `index` until it refuses one, then removes them again
+    fn count_triggers(
+        &mut self,
+        index: usize,
+        mut create: impl FnMut(&mut Self) -> Option<TriggerId>,
+    ) -> Option<usize> {
+        let mut triggers = Vec::new();
+        let mut limit = None;
+        while triggers.len() < MAX_PROBED_TRIGGERS {
+            match create(self) {
+                Some(trigger) => triggers.push(trigger),
+                None => {
+                    limit = Some(triggers.len());
+                    break;
+                }
+            }
+        }
+        for trigger in triggers {
+            self.remove_core_trigger(index, trigger, TriggerOwner::Probe);
+        }
+        self.flush_triggers();
+        limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server whose probe refused no trigger while probing, e.g. an older DAS release
+    fn old_server() -> Capabilities {
+        Capabilities {
+            server: "DAS v6.0".to_owned(),
+            probe: None,
+            trigger_limits: vec![None, None, None],
+            data_trigger_limits: vec![None, None, None],
+            ..Default::default()
+        }
+    }
+
+    /// A server reporting per-core limits, core 1 absent
+    fn new_server() -> Capabilities {
+        Capabilities {
+            server: "DAS v8.1".to_owned(),
+            probe: Some(ProbeIdentity {
+                name: "miniWiggler".to_owned(),
+                serial: Some("1234".to_owned()),
+                port: 0,
+            }),
+            trigger_limits: vec![Some(8), None, Some(6)],
+            data_trigger_limits: vec![Some(2), None, Some(2)],
+            watchpoints: true,
+            batching: true,
+            power_control: false,
+        }
+    }
+
+    fn entry(capabilities: &Capabilities, key: &str) -> String {
+        capabilities
+            .entries()
+            .into_iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+            .unwrap()
+    }
+
+    #[test]
+    fn old_server_has_no_breakpoint_limit() {
+        let capabilities = old_server();
+        assert_eq!(capabilities.breakpoint_limit(), None);
+        assert_eq!(capabilities.gdb_breakpoint_limit(1), None);
+        assert_eq!(capabilities.trigger_limit(0, false), None);
+        assert_eq!(capabilities.trigger_limit(0, true), None);
+    }
+
+    #[test]
+    fn new_server_limit_is_the_smallest_known() {
+        let capabilities = new_server();
+        assert_eq!(capabilities.breakpoint_limit(), Some(6));
+        assert_eq!(capabilities.gdb_breakpoint_limit(2), Some(4));
+        assert_eq!(capabilities.gdb_breakpoint_limit(10), Some(0));
+        assert_eq!(capabilities.trigger_limit(0, false), Some(8));
+        assert_eq!(capabilities.trigger_limit(1, false), None);
+        assert_eq!(capabilities.trigger_limit(2, true), Some(2));
+        assert_eq!(capabilities.trigger_limit(3, false), None);
+    }
+
+    #[test]
+    fn entries_show_unknown_values() {
+        let capabilities = old_server();
+        assert_eq!(entry(&capabilities, "das server"), "DAS v6.0");
+        assert_eq!(entry(&capabilities, "probe"), "unknown");
+        assert_eq!(entry(&capabilities, "probe serial"), "none");
+        assert_eq!(entry(&capabilities, "triggers per core"), "? ? ?");
+        assert_eq!(entry(&capabilities, "watchpoints"), "no");
+        assert_eq!(entry(&capabilities, "batching"), "no");
+    }
+
+    #[test]
+    fn entries_show_reported_values() {
+        let capabilities = new_server();
+        assert_eq!(entry(&capabilities, "probe"), "miniWiggler");
+        assert_eq!(entry(&capabilities, "probe serial"), "1234");
+        assert_eq!(entry(&capabilities, "triggers per core"), "8 ? 6");
+        assert_eq!(entry(&capabilities, "data triggers per core"), "2 ? 2");
+        assert_eq!(entry(&capabilities, "watchpoints"), "yes");
+        assert_eq!(entry(&capabilities, "power control"), "no");
+    }
+}
@@ -1,13 +1,21 @@
 use anyhow::{bail, Context};
 use rust_mcd::connection::{Scan, ServerInfo};
 use rust_mcd::system::System;
-use std::fs;
-use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::gdb::arch::ArchVariant;
 use crate::gdb::das;
-use crate::gdb::elf::elf_to_hex;
-use crate::gdb::flash::AurixFlasherUpload;
+use crate::gdb::elf::{elf_to_hex, ElfImage};
+use crate::gdb::elf_set::check_overlaps;
+use crate::gdb::flash::{
+    check_elf, check_project_change, elf_id_block, exclude_from_hex, hex_extent, merge_hex,
+    parse_build_identity, programs_ucb, AurixFlasherUpload, FlashOptions, IdBlock,
+};
+use crate::gdb::flash_error::{FlashError, FlashLocation, FlashProgress};
+use crate::gdb::memory_map::{find_region, program_flash_size, RegionKind};
+
+/// Bytes read back per transaction while verifying
+const VERIFY_CHUNK: usize = 4096;
 
 #[derive(Debug, Clone, Copy)]
 pub struct DeviceSelection {
@@ -15,6 +23,75 @@ pub struct DeviceSelection {
     pub info: ServerInfo,
 }
 
+/// Markers the access hardware description puts before the probe serial
+const SERIAL_MARKERS: [&str; 4] = ["serial number", "serial", "s/n", "sn"];
+
+/// Access hardware of a scanned device, as far as its description tells
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeIdentity {
+    pub name: String,
+    /// `None` where the description holds no serial, e.g. for some OCDS over USB devices
+    pub serial: Option<String>,
+    /// Index of the device in the DAS scan
+    pub port: usize,
+}
+
+impl ProbeIdentity {
+    pub(crate) fn of(device: &DeviceSelection) -> Self {
+        Self::parse(&format!("{:?}", device.info.acc_hw()), device.udas_port)
+    }
+
+    /// Splits an access hardware description, e.g. `miniWiggler V3 (SN: 1234ABCD)`, into
+    /// the probe name and its serial
+    pub fn parse(acc_hw: &str, port: usize) -> Self {
+        let text = acc_hw.trim().trim_matches('"').trim();
+        let lower = text.to_ascii_lowercase();
+        let is_word = |c: char| c.is_ascii_alphanumeric();
+
+        for marker in SERIAL_MARKERS {
+            for (start, _) in lower.match_indices(marker) {
+                let end = start + marker.len();
+                if lower[..start].chars().next_back().is_some_and(is_word)
+                    || lower[end..].chars().next().is_some_and(is_word)
+                {
+                    continue;
+                }
+                let value = text[end..]
+                    .trim_start_matches(|c: char| c == ':' || c == '=' || c == '#' || c == ' ');
+                let serial: String = value
+                    .chars()
+                    .take_while(|&c| is_word(c) || c == '-' || c == '_')
+                    .collect();
+                if serial.is_empty() {
+                    continue;
+                }
+                let name = text[..start]
+                    .trim_end_matches(|c: char| " ([,;-".contains(c))
+                    .to_owned();
+                return ProbeIdentity {
+                    name,
+                    serial: Some(serial),
+                    port,
+                };
+            }
+        }
+        ProbeIdentity {
+            name: text.to_owned(),
+            serial: None,
+            port,
+        }
+    }
+}
+
+impl std::fmt::Display for ProbeIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.serial {
+            Some(serial) => write!(f, "{} (serial {})", self.name, serial),
+            None => write!(f, "{} (no serial)", self.name),
+        }
+    }
+}
+
 pub struct ChipCommunication {
     device: Option<DeviceSelection>,
     scan_result: Option<Scan>,
@@ -58,27 +135,158 @@ impl ChipCommunication {
         })
     }
 
-    fn flash_hex(&mut self, ihex: String) -> anyhow::Result<()> {
+    fn flash_hex(&mut self, ihex: String, arch: ArchVariant) -> anyhow::Result<()> {
         let device = self
             .get_selected_device()
             .context("Failed to identify target device for AurixFlasher.")?;
+        let udas_port = device.udas_port;
 
-        let mut upload = AurixFlasherUpload::start(ihex, device.udas_port)
-            .context("Failed to run AurixFlasher.")?;
+        let started = Instant::now();
+        let extent = hex_extent(&ihex)?.context("The image holds no data")?;
+        let location = FlashLocation::of(arch.regions(), extent.start, extent.end);
+        let progress = FlashProgress {
+            completed: 0,
+            total: extent.bytes,
+        };
+        let mut upload =
+            AurixFlasherUpload::start(ihex, udas_port).map_err(|e| FlashError::Start {
+                location,
+                progress,
+                cause: format!("{:#}", e),
+            })?;
 
-        upload.wait();
+        upload.wait().map_err(|e| FlashError::Program {
+            location,
+            progress,
+            cause: format!("{:#}", e),
+        })?;
+        log::info!("Flashed image in {:.1?}", started.elapsed());
 
         anyhow::Ok(())
     }
 
-    /// Behaves like [Chip::flash_hex], but the binary is specified as a path to an elf
-    /// file instead of provided as Intel hex in memory.
-    pub fn flash_elf(&mut self, elf_file: &Path) -> anyhow::Result<()> {
-        log::info!("Converting elf {} to hex file", elf_file.display());
-        let elf_data = fs::read(elf_file).context("Cannot load elf file")?;
-        let ihex = elf_to_hex(&elf_data).context("Cannot convert elf to hex file")?;
+    /// Behaves like [Chip::flash_hex], but the binary is given as parsed elf files instead
+    /// of Intel hex in memory. Several elfs, e.g. the programs of different cores, are
+    /// programmed together and must agree where they overlap.
+    ///
+    /// UCB content is stripped from the image unless [FlashOptions::allow_ucb] is set.
+    pub fn flash_elf(
+        &mut self,
+        elfs: &[&ElfImage],
+        arch: ArchVariant,
+        options: &FlashOptions,
+    ) -> anyhow::Result<()> {
+        check_overlaps(elfs).context("Elf files conflict")?;
+
+        let program_flash = self
+            .get_selected_device()
+            .ok()
+            .and_then(|device| program_flash_size(&format!("{:?}", device.info.acc_hw())));
+        let mut images = Vec::new();
+        let mut ucb = false;
+        for &elf in elfs {
+            log::info!("Converting elf {} to hex file", elf.path.display());
+            let checks = check_elf(elf, arch, program_flash, options)
+                .context("Elf pre-flight check failed")?;
+            ucb |= programs_ucb(&checks);
+            if let Some(block) = options.id_block {
+                self.check_build_identity(elf, block, options.force_project_change)?;
+            }
+            let mut ihex = elf_to_hex(elf.data()).context("Cannot convert elf to hex file")?;
+            if !options.allow_ucb {
+                for region in arch.regions().iter().filter(|r| r.kind == RegionKind::Ucb) {
+                    ihex = exclude_from_hex(&ihex, region).context("Cannot strip UCB content")?;
+                }
+            }
+            images.push(ihex);
+        }
+        let ihex = merge_hex(&images).context("Cannot merge hex files")?;
         log::info!("Flashing hex file");
-        self.flash_hex(ihex)
+        self.flash_hex(ihex, arch)?;
+
+        if ucb {
+            log::warn!(
+                "UCB content was programmed, a power-on reset is required for it to take effect"
+            );
+        }
+        anyhow::Ok(())
+    }
+
+    /// Reads the flash content the elf programmed back and compares it, returns the first
+    /// differing address of every segment which does not match
+    pub fn verify_elf(
+        &mut self,
+        elf: &ElfImage,
+        arch: ArchVariant,
+        options: &FlashOptions,
+    ) -> anyhow::Result<Vec<u32>> {
+        let system = self.get_system()?;
+        let core = system.get_core(0)?;
+
+        let mut mismatches = Vec::new();
+        for (segment, data) in elf.segment_contents() {
+            let programmed = find_region(arch.regions(), segment.address).is_some_and(|region| {
+                region.is_flash() && (region.kind != RegionKind::Ucb || options.allow_ucb)
+            });
+            if !programmed {
+                continue;
+            }
+            for (index, expected) in data.chunks(VERIFY_CHUNK).enumerate() {
+                let addr = segment.address as u64 + (index * VERIFY_CHUNK) as u64;
+                let actual = core
+                    .read_bytes(addr, expected.len())
+                    .with_context(|| format!("Cannot read back {:#010x}", addr))?;
+                if let Some(offset) = actual.iter().zip(expected).position(|(a, e)| a != e) {
+                    mismatches.push((addr + offset as u64) as u32);
+                    break;
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Compares the build identification block of the device against the one of the elf,
+    /// fails with [ProjectChange](crate::gdb::flash::ProjectChange) if the projects differ
+    fn check_build_identity(
+        &mut self,
+        elf: &ElfImage,
+        block: IdBlock,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let Some(image_block) = elf_id_block(elf, block) else {
+            log::warn!(
+                "Elf has no id block at {:#010x}, project is not checked",
+                block.addr
+            );
+            return Ok(());
+        };
+        let image = parse_build_identity(&image_block).context("Invalid id block in elf")?;
+
+        let system = self.get_system()?;
+        let device_block = system
+            .get_core(0)?
+            .read_bytes(block.addr as u64, block.len as usize)
+            .context("Cannot read id block from device")?;
+        let device = match parse_build_identity(&device_block) {
+            Ok(device) => device,
+            Err(e) => {
+                log::warn!(
+                    "Unrecognized id block on device, project is not checked: {}",
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        log::info!("Device: {}", device);
+        log::info!("Elf:    {}", image);
+        match check_project_change(device, image) {
+            Err(change) if force => {
+                log::warn!("Flashing another project: {}", change.image);
+                Ok(())
+            }
+            result => Ok(result?),
+        }
     }
 
     /// Returns the selected device.
@@ -126,3 +334,58 @@ impl ChipCommunication {
         self.get_selected_device()?.info.connect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(name: &str, serial: Option<&str>, port: usize) -> ProbeIdentity {
+        ProbeIdentity {
+            name: name.to_owned(),
+            serial: serial.map(str::to_owned),
+            port,
+        }
+    }
+
+    #[test]
+    fn miniwiggler_serial_is_split_off() {
+        assert_eq!(
+            ProbeIdentity::parse("\"miniWiggler V3 (SN: 1234ABCD)\"", 0),
+            probe("miniWiggler V3", Some("1234ABCD"), 0)
+        );
+        assert_eq!(
+            ProbeIdentity::parse("DAS JDS miniWiggler, Serial Number: MW-0042_a", 2),
+            probe("DAS JDS miniWiggler", Some("MW-0042_a"), 2)
+        );
+    }
+
+    #[test]
+    fn ocds_over_usb_serial_is_split_off() {
+        assert_eq!(
+            ProbeIdentity::parse("UDAS OCDS over USB s/n=A1B2C3", 1),
+            probe("UDAS OCDS over USB", Some("A1B2C3"), 1)
+        );
+    }
+
+    #[test]
+    fn missing_serial_keeps_the_whole_name() {
+        assert_eq!(
+            ProbeIdentity::parse("UDAS OCDS over USB", 1),
+            probe("UDAS OCDS over USB", None, 1)
+        );
+        assert_eq!(
+            ProbeIdentity::parse("\"Snapper serial:\"", 0),
+            probe("Snapper serial:", None, 0)
+        );
+        assert_eq!(ProbeIdentity::parse("snapshot probe", 3).serial, None);
+    }
+
+    #[test]
+    fn identity_names_its_serial() {
+        assert_eq!(
+            probe("miniWiggler V3", Some("1234ABCD"), 0).to_string(),
+            "miniWiggler V3 (serial 1234ABCD)"
+        );
+        assert_eq!(probe("UDAS", None, 0).to_string(), "UDAS (no serial)");
+    }
+}
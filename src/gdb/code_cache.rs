@@ -0,0 +1,129 @@
+//! Read cache of program flash, for GDB's disassembly and backtraces which read the same
+//! code over and over.
+//!
+//! Reads entirely inside a program flash region are served from 4 KiB blocks, a missing
+//! block is read in full. Blocks are kept by the cached alias of their address and the
+//! least recently used block goes first once `code-cache-size` is reached. Program flash
+//! only changes through the stub: the cache is dropped when an elf is loaded, when GDB
+//! writes flash with `allow-flash-writes` and on every reset, in case a boot loader
+//! reprogrammed it. Data flash, which firmware commonly writes, RAM and peripherals are
+//! never cached.
+
+use std::collections::{HashMap, VecDeque};
+
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+
+use super::memory_map::{canonical_address, find_region, RegionKind};
+use super::traits::TricoreTargetError;
+use super::{MonitorOutput, TricoreTarget};
+
+/// Unit the cache is filled in
+const BLOCK: usize = 4096;
+
+#[derive(Debug, Default)]
+pub(crate) struct CodeCache {
+    blocks: HashMap<u32, Vec<u8>>,
+    /// Block addresses, least recently used first
+    lru: VecDeque<u32>,
+    hits: u64,
+    misses: u64,
+    /// Bytes served from the cache instead of the probe
+    bytes_saved: u64,
+}
+
+impl CodeCache {
+    pub fn invalidate(&mut self) {
+        self.blocks.clear();
+        self.lru.clear();
+    }
+
+    fn touch(&mut self, block: u32) {
+        if let Some(position) = self.lru.iter().position(|&used| used == block) {
+            self.lru.remove(position);
+        }
+        self.lru.push_back(block);
+    }
+
+    fn insert(&mut self, block: u32, bytes: Vec<u8>, capacity: usize) {
+        while self.blocks.len() >= capacity {
+            let Some(oldest) = self.lru.pop_front() else {
+                break;
+            };
+            self.blocks.remove(&oldest);
+        }
+        self.blocks.insert(block, bytes);
+        self.touch(block);
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Serves a read of program flash from the cache, filling missing blocks. Returns
+    /// false for reads which are not cached.
+    pub(crate) fn read_cached(
+        &mut self,
+        tid: Tid,
+        start_addr: u32,
+        data: &mut [u8],
+    ) -> Result<bool, TricoreTargetError> {
+        let capacity = self.settings.code_cache_size / BLOCK;
+        if capacity == 0 {
+            return Ok(false);
+        }
+        let start = canonical_address(self.arch.aliases(), start_addr);
+        let cacheable = find_region(self.arch.regions(), start).is_some_and(|region| {
+            region.kind == RegionKind::ProgramFlash
+                && region.contains_range(start, data.len() as u64)
+        });
+        if !cacheable {
+            return Ok(false);
+        }
+
+        let mut offset = 0;
+        while offset < data.len() {
+            let addr = start + offset as u32;
+            let block = addr & !(BLOCK as u32 - 1);
+            let in_block = (addr - block) as usize;
+            let len = (BLOCK - in_block).min(data.len() - offset);
+            if self.code_cache.blocks.contains_key(&block) {
+                self.code_cache.hits += 1;
+                self.code_cache.bytes_saved += len as u64;
+                self.code_cache.touch(block);
+            } else {
+                self.code_cache.misses += 1;
+                let mut bytes = vec![0; BLOCK];
+                self.read_through(tid, block, &mut bytes)?;
+                self.code_cache.insert(block, bytes, capacity);
+            }
+            data[offset..offset + len]
+                .copy_from_slice(&self.code_cache.blocks[&block][in_block..in_block + len]);
+            offset += len;
+        }
+        Ok(true)
+    }
+
+    /// `cache-stats`
+    pub(crate) fn print_cache_stats(&self, out: &mut MonitorOutput<'_>) {
+        let cache = &self.code_cache;
+        let lookups = cache.hits + cache.misses;
+        outputln!(
+            out,
+            "{:<20} {}/{} blocks of {} bytes",
+            "blocks",
+            cache.blocks.len(),
+            self.settings.code_cache_size / BLOCK,
+            BLOCK
+        );
+        outputln!(out, "{:<20} {}", "hits", cache.hits);
+        outputln!(out, "{:<20} {}", "misses", cache.misses);
+        if lookups > 0 {
+            outputln!(
+                out,
+                "{:<20} {:.1}%",
+                "hit rate",
+                cache.hits as f64 * 100.0 / lookups as f64
+            );
+        }
+        outputln!(out, "{:<20} {}", "bytes saved", cache.bytes_saved);
+    }
+}
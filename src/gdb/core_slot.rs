@@ -0,0 +1,166 @@
+//! Per-core state of the target, one slot per physical core index.
+//!
+//! Everything tracked per core lives in a [CoreSlot], so a core's handle, resume action,
+//! register cache and capabilities cannot get out of step with each other. Slots are
+//! reached through the bounds-checked accessors below, never by indexing.
+
+use log::warn;
+use rust_mcd::core::Core;
+
+use super::registers::{CoreCapabilities, CoreRegisters};
+use super::{CpuId, ResumeAction, TricoreTarget, TricoreTargetError};
+
+/// A core of the device, present or not
+pub(crate) struct CoreSlot<'a> {
+    /// `None` where the core could not be initialized, e.g. a fused off core
+    pub core: Option<Core<'a>>,
+    /// Why the core could not be initialized
+    pub absent: Option<String>,
+    /// Resume action to be used upon a continue request
+    pub resume_action: ResumeAction,
+    /// Registers available on the core
+    pub capabilities: CoreCapabilities,
+    /// Register set read while halted, served again until the core runs
+    pub register_cache: Option<CoreRegisters>,
+}
+
+impl<'a> CoreSlot<'a> {
+    pub fn present(core: Core<'a>, capabilities: CoreCapabilities) -> Self {
+        CoreSlot {
+            core: Some(core),
+            absent: None,
+            resume_action: ResumeAction::Unchanged,
+            capabilities,
+            register_cache: None,
+        }
+    }
+
+    pub fn absent(reason: String) -> Self {
+        CoreSlot {
+            core: None,
+            absent: Some(reason),
+            resume_action: ResumeAction::Unchanged,
+            capabilities: CoreCapabilities::default(),
+            register_cache: None,
+        }
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Number of core slots, absent cores included
+    pub(crate) fn core_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn slot(&self, cpu_id: CpuId) -> Option<&CoreSlot<'a>> {
+        self.slots.get(usize::from(cpu_id))
+    }
+
+    pub(crate) fn slot_mut(&mut self, cpu_id: CpuId) -> Option<&mut CoreSlot<'a>> {
+        self.slots.get_mut(usize::from(cpu_id))
+    }
+
+    /// Slot at a physical index, `None` beyond the last core
+    pub(crate) fn slot_at(&self, index: usize) -> Option<&CoreSlot<'a>> {
+        self.slots.get(index)
+    }
+
+    pub(crate) fn slot_at_mut(&mut self, index: usize) -> Option<&mut CoreSlot<'a>> {
+        self.slots.get_mut(index)
+    }
+
+    /// Every slot with its physical index
+    pub(crate) fn slots(&self) -> impl Iterator<Item = (usize, &CoreSlot<'a>)> {
+        self.slots.iter().enumerate()
+    }
+
+    pub(crate) fn slots_mut(&mut self) -> impl Iterator<Item = (usize, &mut CoreSlot<'a>)> {
+        self.slots.iter_mut().enumerate()
+    }
+
+    /// Every present core
+    pub(crate) fn present_cores_mut(&mut self) -> impl Iterator<Item = &mut Core<'a>> {
+        self.slots.iter_mut().filter_map(|slot| slot.core.as_mut())
+    }
+
+    /// Forgets the cached registers of every core, e.g. once they run
+    pub(crate) fn clear_register_cache(&mut self) {
+        for slot in &mut self.slots {
+            slot.register_cache = None;
+        }
+    }
+
+    /// Forgets the cached registers of the core at `index`, e.g. after a register write
+    pub(crate) fn invalidate_registers(&mut self, index: usize) {
+        if let Some(slot) = self.slots.get_mut(index) {
+            slot.register_cache = None;
+        }
+    }
+}
+
+/// The core at `index`, failing for cores which could not be initialized
+pub(crate) fn present_core<'s, 'a>(
+    slots: &'s [CoreSlot<'a>],
+    index: usize,
+) -> Result<&'s Core<'a>, TricoreTargetError> {
+    slots
+        .get(index)
+        .and_then(|slot| slot.core.as_ref())
+        .ok_or(TricoreTargetError::NotPresent(index))
+}
+
+/// Initializes the cores `0..count` with `init`, one result per physical index.
+///
+/// A core that cannot be initialized (e.g. fused off) is skipped with a warning and keeps
+/// its place, so the remaining cores stay at their physical index. Fails only if no core
+/// could be initialized.
+pub(crate) fn init_cores<C>(
+    count: usize,
+    mut init: impl FnMut(usize) -> Result<C, String>,
+) -> Result<Vec<Result<C, String>>, String> {
+    let cores: Vec<_> = (0..count)
+        .map(|index| init(index).inspect_err(|e| warn!("Skipping core {}: {}", index, e)))
+        .collect();
+    if cores.iter().all(Result::is_err) {
+        return Err(format!("None of the {} cores could be initialized", count));
+    }
+    Ok(cores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device whose cores listed in `fused_off` fail to initialize
+    fn device(fused_off: &'static [usize]) -> impl FnMut(usize) -> Result<usize, String> {
+        move |index| {
+            if fused_off.contains(&index) {
+                Err(format!("CPU{} not available", index))
+            } else {
+                Ok(index)
+            }
+        }
+    }
+
+    #[test]
+    fn hole_in_the_middle_keeps_the_physical_indices() {
+        let cores = init_cores(6, device(&[3])).unwrap();
+        assert_eq!(cores.len(), 6);
+        assert_eq!(cores[2], Ok(2));
+        assert_eq!(cores[3], Err("CPU3 not available".to_owned()));
+        assert_eq!(cores[4], Ok(4));
+    }
+
+    #[test]
+    fn hole_at_index_zero_keeps_the_physical_indices() {
+        let cores = init_cores(3, device(&[0])).unwrap();
+        assert!(cores[0].is_err());
+        assert_eq!(cores[1..], [Ok(1), Ok(2)]);
+    }
+
+    #[test]
+    fn startup_fails_without_any_core() {
+        assert!(init_cores(2, device(&[0, 1])).is_err());
+        assert!(init_cores(0, device(&[])).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Context save area (CSA) decoding for `monitor csa info`.
+//!
+//! TriCore saves upper and lower contexts into 16 word CSAs which are chained through link
+//! words. Free CSAs form the list headed by FCX, the contexts of the current call chain the
+//! list headed by PCXI. Running out of free CSAs raises an FCD trap.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use gdbstub::outputln;
+
+use super::memory_map::{find_region, RegionKind};
+use super::{cpuid_to_tid, parse_u32, read_register, CpuId, MonitorOutput, TricoreTarget};
+
+/// Size of a context save area in bytes
+const CSA_SIZE: usize = 64;
+
+/// Upper bound of entries visited in a single list walk
+const MAX_WALK: usize = 1024;
+
+/// Segment and offset bits of a link word
+const LINK_MASK: u32 = 0x000F_FFFF;
+
+/// PCXI.UL, set when the previous context is an upper context
+const PCXI_UL: u32 = 1 << 20;
+
+/// Converts the segment (bits 19..16) and offset (bits 15..0) of a link word into the
+/// address of the CSA it points to.
+pub(crate) fn csa_address(link: u32) -> u32 {
+    (((link >> 16) & 0xF) << 28) | ((link & 0xFFFF) << 6)
+}
+
+/// Why a list walk ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WalkEnd {
+    /// Reached a null link, the regular end of a list
+    Null,
+    /// The link points to a CSA already visited
+    Loop(u32),
+    /// More than [MAX_WALK] entries were visited
+    TooLong,
+    /// The link points outside of the device RAM
+    OutsideRam(u32),
+    /// The CSA could not be read from the target
+    ReadFailed(u32),
+}
+
+impl fmt::Display for WalkEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalkEnd::Null => write!(f, "end of list"),
+            WalkEnd::Loop(addr) => write!(f, "loop back to {:#010x}", addr),
+            WalkEnd::TooLong => write!(f, "more than {} entries", MAX_WALK),
+            WalkEnd::OutsideRam(addr) => write!(f, "link to {:#010x} outside of RAM", addr),
+            WalkEnd::ReadFailed(addr) => write!(f, "cannot read CSA at {:#010x}", addr),
+        }
+    }
+}
+
+/// A context save area read from the target
+struct Csa {
+    address: u32,
+    words: [u32; CSA_SIZE / 4],
+}
+
+impl Csa {
+    /// The link word stored in the first word of every CSA
+    fn link(&self) -> u32 {
+        self.words[0]
+    }
+
+    /// Return address, saved in the fourth word of upper and the second word of lower contexts
+    fn a11(&self, upper: bool) -> u32 {
+        if upper {
+            self.words[3]
+        } else {
+            self.words[1]
+        }
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Follows the list starting at `link`, calling `visit` for each CSA
+    fn walk_csa_list(
+        &mut self,
+        cpu_id: CpuId,
+        mut link: u32,
+        mut visit: impl FnMut(&Csa),
+    ) -> (usize, WalkEnd) {
+        let tid = cpuid_to_tid(cpu_id);
+        let mut visited = HashSet::new();
+
+        loop {
+            if link & LINK_MASK == 0 {
+                return (visited.len(), WalkEnd::Null);
+            }
+            if visited.len() >= MAX_WALK {
+                return (visited.len(), WalkEnd::TooLong);
+            }
+
+            let address = csa_address(link);
+            let in_ram = find_region(self.arch.regions(), address)
+                .is_some_and(|region| region.kind == RegionKind::Ram);
+            if !in_ram {
+                return (visited.len(), WalkEnd::OutsideRam(address));
+            }
+            if !visited.insert(address) {
+                return (visited.len() - 1, WalkEnd::Loop(address));
+            }
+
+            let mut bytes = [0u8; CSA_SIZE];
+            if self.read_memory(tid, address, &mut bytes).is_err() {
+                return (visited.len() - 1, WalkEnd::ReadFailed(address));
+            }
+            let mut words = [0u32; CSA_SIZE / 4];
+            for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+                *word = u32::from_le_bytes(chunk.try_into().unwrap());
+            }
+
+            let csa = Csa { address, words };
+            visit(&csa);
+            link = csa.link();
+        }
+    }
+
+    /// Prints the free and previous context lists of a core
+    pub(crate) fn csa_info(&mut self, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let index = match core.map(parse_u32) {
+            None => 0,
+            Some(Some(index)) => index as usize,
+            Some(None) => {
+                outputln!(out, "usage: csa info [core]");
+                return;
+            }
+        };
+        let (Ok(cpu_id), Some(core)) = (CpuId::try_from(index), self.core(index)) else {
+            outputln!(out, "No core {}", index);
+            return;
+        };
+
+        let (Some(fcx), Some(lcx), Some(pcxi)) = (
+            read_register(core, "FCX"),
+            read_register(core, "LCX"),
+            read_register(core, "PCXI"),
+        ) else {
+            outputln!(out, "Cannot read the context registers of core {}", index);
+            return;
+        };
+
+        outputln!(out, "FCX  {:#010x} -> {:#010x}", fcx, csa_address(fcx));
+        outputln!(out, "LCX  {:#010x} -> {:#010x}", lcx, csa_address(lcx));
+        outputln!(out, "PCXI {:#010x} -> {:#010x}", pcxi, csa_address(pcxi));
+
+        let (free, free_end) = self.walk_csa_list(cpu_id, fcx, |_| {});
+        outputln!(out, "Free CSAs: {} ({})", free, free_end);
+
+        outputln!(out, "Previous contexts:");
+        let mut upper = pcxi & PCXI_UL != 0;
+        let mut lines = Vec::new();
+        let (depth, chain_end) = self.walk_csa_list(cpu_id, pcxi, |csa| {
+            lines.push(format!(
+                "  {:#010x} {} A11 {:#010x} link {:#010x}",
+                csa.address,
+                if upper { "upper" } else { "lower" },
+                csa.a11(upper),
+                csa.link()
+            ));
+            upper = csa.link() & PCXI_UL != 0;
+        });
+        for line in lines {
+            outputln!(out, "{}", line);
+        }
+        outputln!(out, "Call context depth: {} ({})", depth, chain_end);
+
+        if fcx & LINK_MASK == lcx & LINK_MASK {
+            outputln!(out, "FCX == LCX, free CSAs are about to run out");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_segment_and_offset_form_the_address() {
+        // EA = segment in bits 31..28, offset in bits 21..6, as the architecture manual
+        // gives it for FCX, LCX and PCXI
+        assert_eq!(csa_address(0x0007_0000), 0x7000_0000);
+        assert_eq!(csa_address(0x0007_0001), 0x7000_0040);
+        assert_eq!(csa_address(0x0009_0100), 0x9000_4000);
+        assert_eq!(csa_address(0x000D_0280), 0xD000_A000);
+        assert_eq!(csa_address(0x000B_FFFF), 0xB03F_FFC0);
+        assert_eq!(csa_address(0), 0);
+    }
+
+    #[test]
+    fn pcxi_bits_above_the_link_are_ignored() {
+        // PCPN, PIE and UL share the register with the link of PCXI
+        let link = 0x0007_0123;
+        assert_eq!(csa_address(0xFFE0_0000 | link), csa_address(link));
+        assert_eq!(csa_address(PCXI_UL | link), 0x7000_48C0);
+        assert_eq!(csa_address(LINK_MASK), 0xF03F_FFC0);
+    }
+
+    #[test]
+    fn consecutive_offsets_are_one_csa_apart() {
+        for link in [0x0007_0000, 0x0007_0010, 0x000D_7FFE] {
+            assert_eq!(csa_address(link + 1) - csa_address(link), CSA_SIZE as u32);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! Minimal TriCore instruction and status register decoding.
+
+/// Length in bytes of the instruction starting with `first`, 32 bit opcodes have bit 0 set
+pub(crate) fn instruction_len(first: u8) -> u32 {
+    if first & 1 == 0 {
+        2
+    } else {
+        4
+    }
+}
+
+/// Length of the instruction in `code` if it is a call, `None` for any other instruction.
+///
+/// Covers CALL (both formats), CALLA, CALLI and the fast call variants FCALL, FCALLA and
+/// FCALLI, all of which return to the instruction following them.
+pub(crate) fn call_len(code: &[u8]) -> Option<u32> {
+    let &op1 = code.first()?;
+    let len = instruction_len(op1);
+    let is_call = match len {
+        2 => op1 == 0x5C,
+        _ => {
+            let word = u32::from_le_bytes(code.get(..4)?.try_into().ok()?);
+            let op2 = (word >> 20) & 0xFF;
+            matches!(op1, 0x6D | 0xED | 0x61 | 0xE1) || (op1 == 0x2D && matches!(op2, 0x00 | 0x01))
+        }
+    };
+    is_call.then_some(len)
+}
+
+/// Target of the conditional jump or loop instruction in `code` at `pc`, `None` for any
+/// other instruction. Execution continues either there or at the next instruction.
+///
+/// Covers the 16 bit forms JZ, JNZ, JEQ, JNE, JLTZ, JLEZ, JGTZ, JGEZ, JZ.A, JNZ.A, JZ.T,
+/// JNZ.T and LOOP, and the 32 bit BRC, BRR and BRN jumps, LOOP and LOOPU.
+pub(crate) fn conditional_jump_target(pc: u32, code: &[u8]) -> Option<u32> {
+    let &op1 = code.first()?;
+    if instruction_len(op1) == 2 {
+        let half = u16::from_le_bytes(code.get(..2)?.try_into().ok()?) as u32;
+        let disp8 = (half >> 8) as u8 as i8 as i32;
+        let disp4 = ((half >> 8) & 0xF) as i32;
+        let halfwords = match op1 {
+            // SB format, JZ and JNZ on D15
+            0x6E | 0xEE => disp8,
+            // SBC, SBR and SBRN formats with a forward displacement
+            0x1E | 0x5E | 0x3E | 0x7E | 0x0E | 0x8E | 0x4E | 0xCE | 0x76 | 0xF6 | 0xBC | 0x7C
+            | 0x2E | 0xAE => disp4,
+            // TC1.6 variants of JEQ and JNE reaching 16 halfwords further
+            0x9E | 0xDE | 0xBE | 0xFE => disp4 + 16,
+            // LOOP, always backwards
+            0xFC => disp4 - 16,
+            _ => return None,
+        };
+        return Some(pc.wrapping_add_signed(halfwords * 2));
+    }
+
+    let word = u32::from_le_bytes(code.get(..4)?.try_into().ok()?);
+    match op1 {
+        // BRC and BRR jumps, JZ.T/JNZ.T (BRN), LOOP and LOOPU
+        0xDF | 0x5F | 0x7D | 0xFF | 0x7F | 0xBF | 0x3F | 0x9F | 0x1F | 0xBD | 0x6F | 0xFD => {
+            let disp15 = ((word >> 16) & 0x7FFF) as i32;
+            // Sign extended from 15 bits
+            let halfwords = (disp15 << 17) >> 17;
+            Some(pc.wrapping_add_signed(halfwords * 2))
+        }
+        _ => None,
+    }
+}
+
+/// Whether the instruction in `code` writes ICR.IE: ENABLE, DISABLE, RESTORE, BISR, RFE,
+/// RFM and MTCR to ICR, in all their formats
+pub(crate) fn writes_icr(code: &[u8]) -> bool {
+    let Some(&op1) = code.first() else {
+        return false;
+    };
+    if instruction_len(op1) == 2 {
+        // BISR (SC) and RFE (SR)
+        return op1 == 0xE0 || (op1 == 0x00 && code.get(1).map(|high| high >> 4) == Some(0x8));
+    }
+
+    let Some(word) = code
+        .get(..4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    else {
+        return false;
+    };
+    match op1 {
+        // SYS format: RFM, RFE, ENABLE, DISABLE, RESTORE and DISABLE D[a]
+        0x0D => matches!((word >> 22) & 0x3F, 0x05 | 0x07 | 0x0C..=0x0F),
+        // BISR (RC)
+        0xAD => (word >> 21) & 0x7F == 0x00,
+        // MTCR (RLC) with the CSFR offset of ICR
+        0xCD => (word >> 12) & 0xFFFF == ICR_OFFSET,
+        _ => false,
+    }
+}
+
+/// CSFR offset of ICR as encoded in MTCR
+const ICR_OFFSET: u32 = 0xFE2C;
+
+/// PSW.CDC, the call depth counter and its mode in bits 6..0
+const PSW_CDC_MASK: u32 = 0x7F;
+const PSW_CDE: u32 = 1 << 7;
+const PSW_GW: u32 = 1 << 8;
+const PSW_IS: u32 = 1 << 9;
+const PSW_IO_SHIFT: u32 = 10;
+const PSW_S: u32 = 1 << 14;
+
+const IO_MODES: [&str; 4] = ["User-0", "User-1", "Supervisor", "reserved"];
+
+/// Arithmetic status flags in the upper PSW bits
+const PSW_STATUS_FLAGS: &[(u32, &str)] =
+    &[(31, "C"), (30, "V"), (29, "SV"), (28, "AV"), (27, "SAV")];
+
+/// Mode and state of the call depth counter encoded in PSW.CDC.
+///
+/// The leading ones of the field select the counter width: `0cccccc` is a 6 bit counter,
+/// `10ccccc` a 5 bit one down to `111110c`. `1111110` traps on every call and return,
+/// `1111111` disables call depth counting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CallDepth {
+    /// Counter of `width` bits, a call with the counter at all ones raises the CDO trap
+    Counter {
+        width: u32,
+        depth: u32,
+    },
+    Trace,
+    Disabled,
+}
+
+impl CallDepth {
+    pub(crate) fn from_psw(psw: u32) -> Self {
+        let cdc = psw & PSW_CDC_MASK;
+        match cdc {
+            0x7F => CallDepth::Disabled,
+            0x7E => CallDepth::Trace,
+            _ => {
+                let width = 6 - (cdc << 25).leading_ones();
+                CallDepth::Counter {
+                    width,
+                    depth: cdc & ((1 << width) - 1),
+                }
+            }
+        }
+    }
+
+    /// Calls left before the counter overflows, `None` if the mode has no counter
+    pub(crate) fn headroom(self) -> Option<u32> {
+        match self {
+            CallDepth::Counter { width, depth } => Some((1 << width) - 1 - depth),
+            CallDepth::Trace | CallDepth::Disabled => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CallDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallDepth::Counter { width, depth } => write!(
+                f,
+                "depth {} of {} ({} bit counter)",
+                depth,
+                (1 << width) - 1,
+                width
+            ),
+            CallDepth::Trace => write!(f, "trace, every call and return traps"),
+            CallDepth::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+/// Whether call depth counting is enabled in PSW.CDE
+pub(crate) fn call_depth_enabled(psw: u32) -> bool {
+    psw & PSW_CDE != 0
+}
+
+/// PSW decoded into labeled fields, in register order
+pub(crate) fn psw_fields(psw: u32) -> Vec<(&'static str, String)> {
+    let on_off = |bit: u32| if psw & bit != 0 { "on" } else { "off" }.to_owned();
+    // PRS[1:0] in bits 13..12, TC1.6.2 adds PRS[2] in bit 15
+    let prs = ((psw >> 12) & 0b11) | ((psw >> 13) & 0b100);
+    let flags: Vec<&str> = PSW_STATUS_FLAGS
+        .iter()
+        .filter(|(bit, _)| psw & (1 << bit) != 0)
+        .map(|&(_, name)| name)
+        .collect();
+
+    vec![
+        ("CDC", CallDepth::from_psw(psw).to_string()),
+        ("CDE", on_off(PSW_CDE)),
+        ("GW", on_off(PSW_GW)),
+        (
+            "IS",
+            if psw & PSW_IS != 0 {
+                "interrupt stack"
+            } else {
+                "user stack"
+            }
+            .to_owned(),
+        ),
+        (
+            "IO",
+            IO_MODES[((psw >> PSW_IO_SHIFT) & 0b11) as usize].to_owned(),
+        ),
+        ("PRS", prs.to_string()),
+        ("S", on_off(PSW_S)),
+        (
+            "flags",
+            if flags.is_empty() {
+                "none".to_owned()
+            } else {
+                flags.join(" ")
+            },
+        ),
+    ]
+}
+
+/// Bytes of the trap vector table at BTV, eight classes of 32 bytes each
+pub(crate) const TRAP_TABLE_LEN: u32 = 8 * 32;
+
+const TRAP_CLASSES: [&str; 8] = [
+    "MMU",
+    "internal protection",
+    "instruction error",
+    "context management",
+    "system bus and peripheral error",
+    "assertion",
+    "system call",
+    "non-maskable interrupt",
+];
+
+/// Name of a trap class
+pub(crate) fn trap_class_name(class: u8) -> &'static str {
+    TRAP_CLASSES
+        .get(class as usize)
+        .copied()
+        .unwrap_or("unknown")
+}
+
+/// Trap class whose vector `pc` lies in, with its name
+pub(crate) fn trap_class(btv: u32, pc: u32) -> Option<(u32, &'static str)> {
+    let offset = pc
+        .checked_sub(btv)
+        .filter(|&offset| offset < TRAP_TABLE_LEN)?;
+    let class = offset / 32;
+    Some((class, TRAP_CLASSES[class as usize]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions_writing_icr_are_recognized() {
+        for code in [
+            // enable, disable, disable %d2, restore %d2
+            0x0300_000D_u32,
+            0x0340_000D,
+            0x03C0_020D,
+            0x0380_020D,
+            // rfe, rfm, bisr 0x10
+            0x01C0_000D,
+            0x0140_000D,
+            0x0001_00AD,
+            // mtcr icr, %d3 and mtcr 0xfe2c, %d0
+            0x0FE2_C3CD,
+            0x0FE2_C0CD,
+        ] {
+            assert!(writes_icr(&code.to_le_bytes()), "{:#010x}", code);
+        }
+        // rfe and bisr 0x10, 16 bit
+        assert!(writes_icr(&[0x00, 0x80]));
+        assert!(writes_icr(&[0xE0, 0x10]));
+    }
+
+    #[test]
+    fn other_instructions_leave_icr() {
+        for code in [
+            // isync, dsync, syscall 0x10, mtcr psw, %d3, mfcr %d3, icr
+            0x04C0_000D_u32,
+            0x0480_000D,
+            0x0081_00AD,
+            0x0FE0_43CD,
+            0x0FE2_C34D,
+            // call
+            0x0000_006D,
+        ] {
+            assert!(!writes_icr(&code.to_le_bytes()), "{:#010x}", code);
+        }
+        // ret, nop and debug, 16 bit
+        assert!(!writes_icr(&[0x00, 0x90]));
+        assert!(!writes_icr(&[0x00, 0x00]));
+        assert!(!writes_icr(&[0x00, 0xA0]));
+        assert!(!writes_icr(&[0x0D]));
+        assert!(!writes_icr(&[]));
+    }
+
+    #[test]
+    fn call_depth_counter_width_follows_the_leading_ones() {
+        assert_eq!(
+            CallDepth::from_psw(0x0000_0005),
+            CallDepth::Counter { width: 6, depth: 5 }
+        );
+        assert_eq!(
+            CallDepth::from_psw(0x0000_005F),
+            CallDepth::Counter {
+                width: 5,
+                depth: 31
+            }
+        );
+        assert_eq!(
+            CallDepth::from_psw(0x0000_007D),
+            CallDepth::Counter { width: 1, depth: 1 }
+        );
+        assert_eq!(CallDepth::from_psw(0x0000_007E), CallDepth::Trace);
+        assert_eq!(CallDepth::from_psw(0xFFFF_FF7F), CallDepth::Disabled);
+    }
+
+    #[test]
+    fn headroom_counts_the_calls_left() {
+        assert_eq!(CallDepth::from_psw(0x0000_0000).headroom(), Some(63));
+        assert_eq!(CallDepth::from_psw(0x0000_003E).headroom(), Some(1));
+        assert_eq!(CallDepth::from_psw(0x0000_005F).headroom(), Some(0));
+        assert_eq!(CallDepth::Trace.headroom(), None);
+        assert_eq!(CallDepth::Disabled.headroom(), None);
+    }
+
+    #[test]
+    fn psw_is_decoded_into_fields() {
+        // CDE, GW, interrupt stack, supervisor, PRS 5, C and SAV flags
+        let psw = 0x8800_0000 | 1 << 15 | 1 << 12 | 0b10 << 10 | 1 << 9 | 1 << 8 | 1 << 7 | 3;
+        let fields = psw_fields(psw);
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert!(call_depth_enabled(psw));
+        assert_eq!(field("CDC"), Some("depth 3 of 63 (6 bit counter)"));
+        assert_eq!(field("CDE"), Some("on"));
+        assert_eq!(field("GW"), Some("on"));
+        assert_eq!(field("IS"), Some("interrupt stack"));
+        assert_eq!(field("IO"), Some("Supervisor"));
+        assert_eq!(field("PRS"), Some("5"));
+        assert_eq!(field("S"), Some("off"));
+        assert_eq!(field("flags"), Some("C SAV"));
+    }
+}
@@ -0,0 +1,220 @@
+//! `monitor dump` and `monitor restore`, target memory to and from host files.
+//!
+//! Both stream [READ_CHUNK_SIZE] bytes at a time, so memory use doesn't grow with the
+//! length. `dump ... --gz` compresses the file on the fly into a gzip stream, `restore`
+//! recognizes gzip files by their magic bytes and decompresses them the same way. Both
+//! print their throughput, to judge whether compression pays off on a slow share.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::{Duration, Instant};
+
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+
+use super::elf_set::parse_core;
+use super::{parse_u32, thread_of, MonitorOutput, TricoreTarget, READ_CHUNK_SIZE};
+
+/// First bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn throughput(bytes: u64, elapsed: Duration) -> String {
+    format!(
+        "{:.2} s, {:.1} KiB/s",
+        elapsed.as_secs_f64(),
+        bytes as f64 / 1024.0 / elapsed.as_secs_f64().max(0.001)
+    )
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// `dump <addr> <len> <file> [--gz] [cpuN]`, the core reads core local addresses and
+    /// defaults to the first
+    pub(crate) fn dump_memory(
+        &mut self,
+        addr: &str,
+        len: &str,
+        path: &str,
+        options: &[&str],
+        out: &mut MonitorOutput<'_>,
+    ) {
+        let (Some(addr), Some(len)) = (parse_u32(addr), parse_u32(len)) else {
+            outputln!(out, "usage: dump <addr> <len> <file> [--gz] [cpuN]");
+            return;
+        };
+        let mut gz = false;
+        let mut tid = Tid::new(1).unwrap();
+        for option in options {
+            match (*option, parse_core(option)) {
+                ("--gz", _) => gz = true,
+                (_, Some(core)) => tid = thread_of(&self.settings, core),
+                _ => {
+                    outputln!(out, "Unknown option '{}'", option);
+                    out.fail();
+                    return;
+                }
+            }
+        }
+
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(e) => {
+                outputln!(out, "Cannot create {}: {}", path, e);
+                return;
+            }
+        };
+
+        let started = Instant::now();
+        let result = if gz {
+            let mut encoder = GzEncoder::new(file, Compression::fast());
+            self.dump_to(tid, addr, len, &mut encoder, path)
+                .and_then(|()| {
+                    encoder
+                        .finish()
+                        .map(drop)
+                        .map_err(|e| format!("Cannot write {}: {}", path, e))
+                })
+        } else {
+            let mut file = file;
+            self.dump_to(tid, addr, len, &mut file, path)
+        };
+        if let Err(e) = result {
+            outputln!(out, "{}", e);
+            return;
+        }
+
+        let elapsed = started.elapsed();
+        outputln!(
+            out,
+            "Dumped {} bytes from {:#010x} to {} in {}",
+            len,
+            addr,
+            path,
+            throughput(len as u64, elapsed)
+        );
+        if gz {
+            if let Ok(metadata) = std::fs::metadata(path) {
+                outputln!(
+                    out,
+                    "Compressed to {} bytes, {:.1}% of the memory read",
+                    metadata.len(),
+                    metadata.len() as f64 * 100.0 / (len as f64).max(1.0)
+                );
+            }
+        }
+    }
+
+    /// Streams `len` bytes of memory at `addr` into `sink` one chunk at a time, through
+    /// the chunk buffer of the session
+    fn dump_to(
+        &mut self,
+        tid: Tid,
+        addr: u32,
+        len: u32,
+        sink: &mut dyn Write,
+        path: &str,
+    ) -> Result<(), String> {
+        let mut buffer = std::mem::take(&mut self.read_buffer);
+        let result = self.dump_through(&mut buffer, tid, addr, len, sink, path);
+        self.read_buffer = buffer;
+        result
+    }
+
+    fn dump_through(
+        &mut self,
+        buffer: &mut [u8],
+        tid: Tid,
+        addr: u32,
+        len: u32,
+        sink: &mut dyn Write,
+        path: &str,
+    ) -> Result<(), String> {
+        let mut offset = 0u32;
+        while offset < len {
+            let chunk_len = ((len - offset) as usize).min(buffer.len());
+            let chunk = &mut buffer[..chunk_len];
+            let chunk_addr = addr.wrapping_add(offset);
+            let global = self
+                .translate_local(tid, chunk_addr, chunk_len)
+                .map_err(|e| format!("Dump stopped at {:#010x}: {}", chunk_addr, e))?;
+            self.read_memory(tid, global, chunk)
+                .map_err(|e| format!("Dump stopped at {:#010x}: {}", chunk_addr, e))?;
+            sink.write_all(chunk)
+                .map_err(|e| format!("Cannot write {}: {}", path, e))?;
+            offset += chunk_len as u32;
+        }
+        Ok(())
+    }
+
+    /// `restore <file> <addr>`, gzip files are decompressed on the fly
+    pub(crate) fn restore_memory(&mut self, path: &str, addr: &str, out: &mut MonitorOutput<'_>) {
+        let Some(addr) = parse_u32(addr) else {
+            outputln!(out, "usage: restore <file> <addr>");
+            return;
+        };
+        let mut reader = match File::open(path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) => {
+                outputln!(out, "Cannot open {}: {}", path, e);
+                return;
+            }
+        };
+        let gz = match reader.fill_buf() {
+            Ok(head) => head.starts_with(&GZIP_MAGIC),
+            Err(e) => {
+                outputln!(out, "Cannot read {}: {}", path, e);
+                return;
+            }
+        };
+        let mut source: Box<dyn Read> = if gz {
+            Box::new(MultiGzDecoder::new(reader))
+        } else {
+            Box::new(reader)
+        };
+
+        let tid = Tid::new(1).unwrap();
+        let started = Instant::now();
+        let mut buffer = Vec::with_capacity(READ_CHUNK_SIZE);
+        let mut written = 0u64;
+        loop {
+            buffer.clear();
+            if let Err(e) = source
+                .by_ref()
+                .take(READ_CHUNK_SIZE as u64)
+                .read_to_end(&mut buffer)
+            {
+                outputln!(out, "Cannot read {}: {}", path, e);
+                return;
+            }
+            if buffer.is_empty() {
+                break;
+            }
+            let chunk_addr = addr.wrapping_add(written as u32);
+            if self.refuse_flash_write(chunk_addr, buffer.len()) {
+                outputln!(
+                    out,
+                    "Restore stopped at {:#010x}, it is in flash",
+                    chunk_addr
+                );
+                return;
+            }
+            if let Err(e) = self.write_memory(tid, chunk_addr, &buffer) {
+                outputln!(out, "Restore stopped at {:#010x}: {}", chunk_addr, e);
+                return;
+            }
+            written += buffer.len() as u64;
+        }
+
+        outputln!(
+            out,
+            "Restored {} bytes{} from {} to {:#010x} in {}",
+            written,
+            if gz { " of gzip input" } else { "" },
+            path,
+            addr,
+            throughput(written, started.elapsed())
+        );
+    }
+}
@@ -1,10 +1,24 @@
 //! Hosts utilities to work with elf files.
 
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::Context;
+use elf::abi::{EM_TRICORE, PF_X, PT_LOAD, STT_FUNC};
+use elf::endian::AnyEndian;
+use elf::ElfBytes;
+use log::debug;
+use memmap2::Mmap;
 use tempfile::TempDir;
 
+use super::semihost::SEMIHOST_SYMBOL;
+
 /// Interprets the given data as a hex file and returns it in Intel hex format.
 ///
 /// This function relies on the gnu utility 'objcopy' to be installed on the system.
@@ -54,3 +68,413 @@ pub fn elf_to_hex(data: &[u8]) -> anyhow::Result<String> {
     drop(temporary_directory);
     Ok(hex_file)
 }
+
+/// Reasons an elf is unfit to be programmed into a TriCore device
+#[derive(Debug)]
+pub enum ElfCheckError {
+    /// The file does not start with the elf magic
+    NotElf,
+    /// The elf headers cannot be parsed
+    Malformed(String),
+    /// The elf was built for another machine, e.g. the host
+    WrongMachine(u16),
+    /// The elf has neither content to program nor debug info
+    NoLoadSegments,
+}
+
+/// What an elf can be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfContent {
+    /// Loadable content without debug info
+    Bootable,
+    /// Debug info only, e.g. split off with `objcopy --only-keep-debug`
+    SymbolsOnly,
+    /// Loadable content and debug info
+    Both,
+}
+
+impl ElfContent {
+    /// Whether there is anything to program
+    pub fn is_bootable(self) -> bool {
+        self != ElfContent::SymbolsOnly
+    }
+}
+
+/// Tells bootable elfs from symbol files by their loadable content and `.debug_info`
+pub fn classify_elf(data: &[u8]) -> Result<ElfContent, ElfCheckError> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data)
+        .map_err(|e| ElfCheckError::Malformed(e.to_string()))?;
+    let loadable = !load_segments(data)
+        .map_err(|e| ElfCheckError::Malformed(format!("{:#}", e)))?
+        .is_empty();
+    let debug_info = file
+        .section_header_by_name(".debug_info")
+        .map_err(|e| ElfCheckError::Malformed(e.to_string()))?
+        .is_some();
+
+    match (loadable, debug_info) {
+        (true, false) => Ok(ElfContent::Bootable),
+        (false, true) => Ok(ElfContent::SymbolsOnly),
+        (true, true) => Ok(ElfContent::Both),
+        (false, false) => Err(ElfCheckError::NoLoadSegments),
+    }
+}
+
+/// Checks magic, machine type and content of an elf before the probe is touched
+pub fn validate_tricore_elf(data: &[u8]) -> Result<ElfContent, ElfCheckError> {
+    if !data.starts_with(b"\x7fELF") {
+        return Err(ElfCheckError::NotElf);
+    }
+
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data)
+        .map_err(|e| ElfCheckError::Malformed(e.to_string()))?;
+    if file.ehdr.e_machine != EM_TRICORE {
+        return Err(ElfCheckError::WrongMachine(file.ehdr.e_machine));
+    }
+
+    classify_elf(data)
+}
+
+/// A loadable segment of an elf file, addressed by where it has to be placed on the device
+#[derive(Debug, Clone, Copy)]
+pub struct LoadSegment {
+    /// Load (physical) address of the segment
+    pub address: u32,
+    /// Number of bytes stored in the file, i.e. what has to be programmed
+    pub file_size: u64,
+    /// Offset of the segment content within the elf file
+    pub file_offset: u64,
+}
+
+impl LoadSegment {
+    /// Returns the segment content from the elf data it was parsed from
+    pub fn data<'d>(&self, elf_data: &'d [u8]) -> Option<&'d [u8]> {
+        let start = usize::try_from(self.file_offset).ok()?;
+        let len = usize::try_from(self.file_size).ok()?;
+        elf_data.get(start..start.checked_add(len)?)
+    }
+}
+
+/// Returns all PT_LOAD segments of the elf which carry content.
+///
+/// Segments without file content (e.g. `.bss`) are skipped as nothing is programmed for them.
+pub fn load_segments(data: &[u8]) -> anyhow::Result<Vec<LoadSegment>> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data).context("Cannot parse elf file")?;
+
+    let segments = file
+        .segments()
+        .context("Elf file does not contain a program header table")?;
+
+    Ok(segments
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD && header.p_filesz > 0)
+        .map(|header| LoadSegment {
+            address: header.p_paddr as u32,
+            file_size: header.p_filesz,
+            file_offset: header.p_offset,
+        })
+        .collect())
+}
+
+/// GNU build ID note type
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Returns the GNU build ID of the elf, `None` if it was linked without one
+pub fn build_id(data: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data).context("Cannot parse elf file")?;
+    let Some(header) = file
+        .section_header_by_name(".note.gnu.build-id")
+        .context("Cannot read section headers")?
+    else {
+        return Ok(None);
+    };
+    let (note, _) = file
+        .section_data(&header)
+        .context("Cannot read build ID note")?;
+
+    // namesz, descsz and type, followed by the 4 byte aligned name and the descriptor
+    let word = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            note.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let (Some(name_size), Some(desc_size), Some(NT_GNU_BUILD_ID)) = (word(0), word(4), word(8))
+    else {
+        return Ok(None);
+    };
+    let desc_start = 12 + ((name_size as usize + 3) & !3);
+    Ok(note
+        .get(desc_start..desc_start + desc_size as usize)
+        .map(<[u8]>::to_vec))
+}
+
+/// Content of an elf file, mapped into memory where the file system allows it
+pub enum ElfData {
+    Mapped(Mmap),
+    /// Read into memory, for files which cannot be mapped
+    Read(Vec<u8>),
+}
+
+impl ElfData {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        // SAFETY: the mapping is only read. An elf rewritten in place while mapped shows
+        // mixed content, as a file changing halfway through reading it would; linkers
+        // replace the file instead.
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => Ok(ElfData::Mapped(map)),
+            Err(e) => {
+                debug!("Cannot map {:?}, reading it instead: {}", path, e);
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                Ok(ElfData::Read(data))
+            }
+        }
+    }
+}
+
+impl Deref for ElfData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ElfData::Mapped(map) => map,
+            ElfData::Read(data) => data,
+        }
+    }
+}
+
+impl fmt::Debug for ElfData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfData::Mapped(map) => write!(f, "Mapped({} bytes)", map.len()),
+            ElfData::Read(data) => write!(f, "Read({} bytes)", data.len()),
+        }
+    }
+}
+
+/// The elf of the session, parsed once and shared by everything depending on its addresses
+/// or content. Segment content and symbols are borrowed from the file content, which is
+/// mapped rather than copied.
+#[derive(Debug)]
+pub struct ElfImage {
+    /// Absolute path of the elf
+    pub path: PathBuf,
+    pub build_id: Option<Vec<u8>>,
+    pub entry: u32,
+    pub segments: Vec<LoadSegment>,
+    /// Executable address ranges, used to sanity check breakpoints
+    pub exec_ranges: Vec<Range<u32>>,
+    /// Address of the semihosting entry point, if the program has one
+    pub semihost_call: Option<u32>,
+    /// When the elf was parsed
+    pub loaded_at: SystemTime,
+    /// Content of the file
+    data: Arc<ElfData>,
+    /// File holding the content of [Self::segments], the elf itself, or for a symbol file
+    /// the programmed elf whose segments it took over
+    segment_data: Arc<ElfData>,
+}
+
+impl ElfImage {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data =
+            Arc::new(ElfData::open(path).with_context(|| format!("Cannot read {:?}", path))?);
+        let file = ElfBytes::<AnyEndian>::minimal_parse(&data).context("Cannot parse elf file")?;
+        let segments = load_segments(&data)?;
+        if let Some(segment) = segments
+            .iter()
+            .find(|segment| segment.data(&data).is_none())
+        {
+            anyhow::bail!("Segment at {:#010x} exceeds {:?}", segment.address, path);
+        }
+        Ok(ElfImage {
+            path: std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            build_id: build_id(&data)?,
+            entry: file.ehdr.e_entry as u32,
+            segments,
+            exec_ranges: exec_ranges(&data)?,
+            semihost_call: symbol_address(&data, SEMIHOST_SYMBOL)?,
+            loaded_at: SystemTime::now(),
+            segment_data: data.clone(),
+            data,
+        })
+    }
+
+    /// Content of the elf file, e.g. to look up symbols
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The loadable segments with their content, borrowed from the file
+    pub fn segment_contents(&self) -> impl Iterator<Item = (&LoadSegment, &[u8])> {
+        self.segments
+            .iter()
+            .filter_map(|segment| Some((segment, segment.data(&self.segment_data)?)))
+    }
+
+    /// Takes over the segments of the programmed elf, for a symbol file without content
+    pub fn use_segments_of(&mut self, programmed: &ElfImage) {
+        self.segments = programmed.segments.clone();
+        self.segment_data = programmed.segment_data.clone();
+    }
+
+    /// Build ID as hex string, as printed by `readelf -n`
+    pub fn build_id_hex(&self) -> Option<String> {
+        self.build_id
+            .as_ref()
+            .map(|id| id.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+}
+
+/// Returns the address of the named symbol, `None` if the elf has no such symbol
+pub fn symbol_address(data: &[u8], name: &str) -> anyhow::Result<Option<u32>> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data).context("Cannot parse elf file")?;
+    let Some((symbols, strings)) = file.symbol_table().context("Cannot read symbol table")? else {
+        return Ok(None);
+    };
+    Ok(symbols
+        .iter()
+        .find(|symbol| strings.get(symbol.st_name as usize).ok() == Some(name))
+        .map(|symbol| symbol.st_value as u32))
+}
+
+/// A function symbol matching a name looked up with [find_functions]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSymbol {
+    /// Demangled name, for C++ functions with the parameter list
+    pub name: String,
+    pub address: u32,
+}
+
+/// Readable name of a symbol, C++ names are demangled
+fn demangle(name: &str) -> String {
+    cpp_demangle::Symbol::new(name)
+        .ok()
+        .and_then(|symbol| symbol.demangle(&Default::default()).ok())
+        .unwrap_or_else(|| name.to_owned())
+}
+
+/// Function symbols named `name`, which is compared with the mangled name, the demangled
+/// name and the demangled name without its parameter list. Several functions may match,
+/// e.g. static functions of different translation units or C++ overloads.
+pub fn find_functions(data: &[u8], name: &str) -> anyhow::Result<Vec<FunctionSymbol>> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data).context("Cannot parse elf file")?;
+    let Some((symbols, strings)) = file.symbol_table().context("Cannot read symbol table")? else {
+        return Ok(Vec::new());
+    };
+    let mut found: Vec<FunctionSymbol> = Vec::new();
+    for symbol in symbols
+        .iter()
+        .filter(|symbol| symbol.st_symtype() == STT_FUNC)
+    {
+        let Ok(raw) = strings.get(symbol.st_name as usize) else {
+            continue;
+        };
+        let demangled = demangle(raw);
+        let bare = demangled.split('(').next().unwrap_or(&demangled);
+        if raw != name && demangled != name && bare != name {
+            continue;
+        }
+        let candidate = FunctionSymbol {
+            name: demangled,
+            address: symbol.st_value as u32,
+        };
+        if !found.contains(&candidate) {
+            found.push(candidate);
+        }
+    }
+    Ok(found)
+}
+
+/// Returns the address ranges of the executable PT_LOAD segments, where code runs from
+pub fn exec_ranges(data: &[u8]) -> anyhow::Result<Vec<Range<u32>>> {
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data).context("Cannot parse elf file")?;
+
+    let segments = file
+        .segments()
+        .context("Elf file does not contain a program header table")?;
+
+    Ok(segments
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD && header.p_flags & PF_X != 0)
+        .map(|header| {
+            let start = header.p_vaddr as u32;
+            start..start.saturating_add(header.p_memsz as u32)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const SHSTRTAB: &[u8] = b"\0.shstrtab\0.debug_info\0";
+
+    /// Little endian TriCore ELF32 with a single load segment, holding four bytes if
+    /// `loadable`, and a `.debug_info` section if `debug_info`
+    pub(crate) fn synthetic_elf(loadable: bool, debug_info: bool) -> Vec<u8> {
+        let words = |elf: &mut Vec<u8>, words: &[u32]| {
+            for word in words {
+                elf.extend(word.to_le_bytes());
+            }
+        };
+        let section_count: u16 = if debug_info { 3 } else { 2 };
+
+        let mut elf = b"\x7fELF\x01\x01\x01".to_vec();
+        elf.resize(16, 0);
+        elf.extend(2u16.to_le_bytes());
+        elf.extend(EM_TRICORE.to_le_bytes());
+        // e_version, e_entry, e_phoff, e_shoff, e_flags
+        words(&mut elf, &[1, 0x8000_0000, 52, 112, 0]);
+        for half in [52, 32, 1, 40, section_count, 1] {
+            elf.extend(u16::to_le_bytes(half));
+        }
+        // PT_LOAD: offset, vaddr, paddr, filesz, memsz, flags, align
+        let file_size = if loadable { 4 } else { 0 };
+        words(
+            &mut elf,
+            &[PT_LOAD, 84, 0x8000_0000, 0x8000_0000, file_size, 4, PF_X, 4],
+        );
+        elf.extend([0x00, 0x00, 0x00, 0x00]);
+        elf.extend(SHSTRTAB);
+        elf.resize(112, 0);
+        // name, type, flags, addr, offset, size, link, info, addralign, entsize
+        words(&mut elf, &[0; 10]);
+        words(
+            &mut elf,
+            &[1, 3, 0, 0, 88, SHSTRTAB.len() as u32, 0, 0, 1, 0],
+        );
+        if debug_info {
+            words(&mut elf, &[11, 1, 0, 0, 84, 4, 0, 0, 1, 0]);
+        }
+        elf
+    }
+
+    #[test]
+    fn content_is_classified() {
+        let classify = |loadable, debug_info| classify_elf(&synthetic_elf(loadable, debug_info));
+        assert!(matches!(classify(true, false), Ok(ElfContent::Bootable)));
+        assert!(matches!(classify(false, true), Ok(ElfContent::SymbolsOnly)));
+        assert!(matches!(classify(true, true), Ok(ElfContent::Both)));
+        assert!(matches!(
+            classify(false, false),
+            Err(ElfCheckError::NoLoadSegments)
+        ));
+    }
+
+    #[test]
+    fn only_symbol_files_are_not_bootable() {
+        assert!(ElfContent::Bootable.is_bootable());
+        assert!(ElfContent::Both.is_bootable());
+        assert!(!ElfContent::SymbolsOnly.is_bootable());
+    }
+
+    #[test]
+    fn symbol_file_passes_the_startup_check() {
+        assert!(matches!(
+            validate_tricore_elf(&synthetic_elf(false, true)),
+            Ok(ElfContent::SymbolsOnly)
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+//! Elf files of an AMP system with a separate program per core.
+//!
+//! `--elf_file cpu2:dsp.elf` assigns an elf to a single core, a plain `--elf_file app.elf`
+//! applies to every core without an elf of its own. All of them are programmed together,
+//! the symbol and address consuming features use the elf of the core concerned.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+
+use super::elf::{ElfImage, LoadSegment};
+use super::CpuId;
+
+/// Elf given on the command line, optionally restricted to one core
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSpec {
+    /// `None` for the elf shared by all cores
+    pub core: Option<CpuId>,
+    pub path: PathBuf,
+}
+
+impl ElfSpec {
+    /// Elf shared by all cores
+    pub fn shared(path: impl Into<PathBuf>) -> Self {
+        ElfSpec {
+            core: None,
+            path: path.into(),
+        }
+    }
+}
+
+/// Parses `cpu<N>` as used in `cpu2:dsp.elf`
+pub(crate) fn parse_core(text: &str) -> Option<CpuId> {
+    let index = text
+        .strip_prefix("cpu")
+        .or_else(|| text.strip_prefix("CPU"))?;
+    CpuId::try_from(index.parse::<usize>().ok()?).ok()
+}
+
+impl FromStr for ElfSpec {
+    type Err = String;
+
+    /// `[cpu<N>:]<path>`, a prefix other than `cpu<N>` is part of the path, e.g. a drive
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let spec = match text.split_once(':') {
+            Some((prefix, path)) => match parse_core(prefix) {
+                Some(core) => ElfSpec {
+                    core: Some(core),
+                    path: PathBuf::from(path),
+                },
+                None if prefix.starts_with("cpu") || prefix.starts_with("CPU") => {
+                    return Err(format!("Unknown core '{}', expected cpu0 to cpu5", prefix))
+                }
+                None => ElfSpec::shared(text),
+            },
+            None => ElfSpec::shared(text),
+        };
+        if spec.path.as_os_str().is_empty() {
+            return Err(format!("No elf file given in '{}'", text));
+        }
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for ElfSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.core {
+            Some(core) => write!(f, "{:?}: {}", core, self.path.display()),
+            None => write!(f, "all cores: {}", self.path.display()),
+        }
+    }
+}
+
+/// Checks no core, and not the shared elf either, is given more than one elf
+pub fn check_assignments(specs: &[ElfSpec]) -> Result<(), String> {
+    for (index, spec) in specs.iter().enumerate() {
+        if let Some(other) = specs[..index].iter().find(|other| other.core == spec.core) {
+            return Err(match spec.core {
+                Some(core) => format!(
+                    "{:?} is given both {:?} and {:?}",
+                    core, other.path, spec.path
+                ),
+                None => format!(
+                    "Both {:?} and {:?} are given for all cores, assign them with cpu<N>:",
+                    other.path, spec.path
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks the elfs don't give different content for the same address, where they
+/// overlap at all
+pub fn check_overlaps(elfs: &[&ElfImage]) -> anyhow::Result<()> {
+    let mut segments = Vec::new();
+    for elf in elfs {
+        for (segment, content) in elf.segment_contents() {
+            segments.push((elf.path.as_path(), segment.address as u64, content));
+        }
+    }
+    check_segment_overlaps(&segments)
+}
+
+/// Checks segments given as elf path, load address and content against the segments of
+/// the other elfs
+fn check_segment_overlaps(segments: &[(&Path, u64, &[u8])]) -> anyhow::Result<()> {
+    for (index, &(path, start, content)) in segments.iter().enumerate() {
+        for &(other_path, other_start, other_content) in &segments[index + 1..] {
+            if path == other_path {
+                continue;
+            }
+            let overlap_start = start.max(other_start);
+            let overlap_end =
+                (start + content.len() as u64).min(other_start + other_content.len() as u64);
+            if overlap_start >= overlap_end {
+                continue;
+            }
+            let ours = &content[(overlap_start - start) as usize..(overlap_end - start) as usize];
+            let theirs = &other_content
+                [(overlap_start - other_start) as usize..(overlap_end - other_start) as usize];
+            if let Some(offset) = ours.iter().zip(theirs).position(|(a, b)| a != b) {
+                bail!(
+                    "{:?} and {:?} program different content at {:#010x}",
+                    path,
+                    other_path,
+                    overlap_start + offset as u64
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The parsed elfs of the session
+#[derive(Default)]
+pub(crate) struct ElfSet {
+    /// Elf of the cores without one of their own
+    shared: Option<ElfImage>,
+    per_core: HashMap<CpuId, ElfImage>,
+}
+
+impl ElfSet {
+    /// Parses the elf of every spec
+    pub fn load(specs: &[ElfSpec]) -> anyhow::Result<Self> {
+        let mut elfs = ElfSet::default();
+        for spec in specs {
+            let image = ElfImage::load(&spec.path)
+                .with_context(|| format!("Cannot load {:?}", spec.path))?;
+            elfs.insert(spec.core, image);
+        }
+        Ok(elfs)
+    }
+
+    pub fn insert(&mut self, core: Option<CpuId>, image: ElfImage) {
+        match core {
+            Some(core) => {
+                self.per_core.insert(core, image);
+            }
+            None => self.shared = Some(image),
+        }
+    }
+
+    /// Elf assigned to exactly this core, or the shared one for `None`
+    pub fn assigned(&self, core: Option<CpuId>) -> Option<&ElfImage> {
+        match core {
+            Some(core) => self.per_core.get(&core),
+            None => self.shared.as_ref(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.is_none() && self.per_core.is_empty()
+    }
+
+    /// Elf the core runs, its own or the shared one
+    pub fn for_core(&self, core: CpuId) -> Option<&ElfImage> {
+        self.per_core.get(&core).or(self.shared.as_ref())
+    }
+
+    /// The shared elf, or the one of the lowest core, for requests not tied to a core
+    pub fn primary(&self) -> Option<&ElfImage> {
+        self.shared.as_ref().or_else(|| {
+            (0..)
+                .map_while(|index| CpuId::try_from(index).ok())
+                .find_map(|core| self.per_core.get(&core))
+        })
+    }
+
+    /// Every elf with the core it is assigned to, the shared one first
+    pub fn assignments(&self) -> Vec<(Option<CpuId>, &ElfImage)> {
+        let mut assignments: Vec<(Option<CpuId>, &ElfImage)> = self
+            .per_core
+            .iter()
+            .map(|(core, image)| (Some(*core), image))
+            .collect();
+        assignments.sort_by_key(|(core, _)| core.map(usize::from));
+        if let Some(shared) = &self.shared {
+            assignments.insert(0, (None, shared));
+        }
+        assignments
+    }
+
+    /// Every distinct elf, an elf assigned to several cores listed once
+    pub fn distinct(&self) -> Vec<&ElfImage> {
+        let mut images: Vec<&ElfImage> = Vec::new();
+        for (_, image) in self.assignments() {
+            if !images.iter().any(|other| other.path == image.path) {
+                images.push(image);
+            }
+        }
+        images
+    }
+
+    /// Loadable segments of every elf, an elf assigned to several cores counted once
+    pub fn segments(&self) -> Vec<LoadSegment> {
+        self.distinct()
+            .into_iter()
+            .flat_map(|image| image.segments.iter().copied())
+            .collect()
+    }
+
+    /// Executable ranges of every elf. Breakpoints are planted on all cores, so an
+    /// address is code if any of the programs has code there.
+    pub fn exec_ranges(&self) -> Vec<Range<u32>> {
+        self.assignments()
+            .into_iter()
+            .flat_map(|(_, image)| image.exec_ranges.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::elf::tests::synthetic_elf;
+    use super::*;
+
+    #[test]
+    fn core_prefix_assigns_the_elf() {
+        assert_eq!(
+            "cpu2:dsp.elf".parse(),
+            Ok(ElfSpec {
+                core: Some(CpuId::Cpu2),
+                path: PathBuf::from("dsp.elf")
+            })
+        );
+        assert_eq!(
+            "CPU0:app0.elf".parse::<ElfSpec>().map(|spec| spec.core),
+            Ok(Some(CpuId::Cpu0))
+        );
+        assert_eq!("app.elf".parse(), Ok(ElfSpec::shared("app.elf")));
+    }
+
+    #[test]
+    fn other_prefixes_are_part_of_the_path() {
+        assert_eq!(
+            r"C:\build\app.elf".parse(),
+            Ok(ElfSpec::shared(r"C:\build\app.elf"))
+        );
+    }
+
+    #[test]
+    fn bad_core_specs_are_refused() {
+        assert!("cpu6:app.elf".parse::<ElfSpec>().is_err());
+        assert!("cpux:app.elf".parse::<ElfSpec>().is_err());
+        assert!("cpu1:".parse::<ElfSpec>().is_err());
+        assert!("".parse::<ElfSpec>().is_err());
+    }
+
+    #[test]
+    fn a_core_takes_one_elf() {
+        let specs = |texts: &[&str]| -> Vec<ElfSpec> {
+            texts.iter().map(|text| text.parse().unwrap()).collect()
+        };
+        assert!(check_assignments(&specs(&["app.elf", "cpu2:dsp.elf"])).is_ok());
+        assert!(check_assignments(&specs(&["cpu2:a.elf", "cpu2:b.elf"])).is_err());
+        assert!(check_assignments(&specs(&["a.elf", "b.elf"])).is_err());
+    }
+
+    #[test]
+    fn overlaps_must_agree() {
+        let app = Path::new("app.elf");
+        let dsp = Path::new("dsp.elf");
+        let same: &[u8] = &[1, 2, 3, 4];
+        assert!(check_segment_overlaps(&[
+            (app, 0x8000_0000, same),
+            (dsp, 0x8000_0002, &same[2..])
+        ])
+        .is_ok());
+        assert!(
+            check_segment_overlaps(&[(app, 0x8000_0000, same), (dsp, 0x8000_0004, same)]).is_ok()
+        );
+
+        let error =
+            check_segment_overlaps(&[(app, 0x8000_0000, same), (dsp, 0x8000_0001, &[2, 9])])
+                .unwrap_err();
+        assert!(error.to_string().contains("0x80000002"));
+    }
+
+    #[test]
+    fn segments_of_one_elf_are_not_compared() {
+        let app = Path::new("app.elf");
+        assert!(check_segment_overlaps(&[(app, 0x100, &[1]), (app, 0x100, &[2])]).is_ok());
+    }
+
+    #[test]
+    fn consumers_share_the_mapped_segments() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &synthetic_elf(true, false)).unwrap();
+        let elfs = ElfSet::load(&[ElfSpec::shared(file.path())]).unwrap();
+
+        // Programming walks the distinct elfs, the session looks up the elf of a core
+        let flashed = elfs.distinct()[0].segment_contents().next().unwrap().1;
+        let image = elfs.for_core(CpuId::Cpu3).unwrap();
+        let session = image.segment_contents().next().unwrap().1;
+        assert_eq!(flashed, session);
+        assert_eq!(flashed.as_ptr(), session.as_ptr());
+
+        // Borrowed from the file content, not copied out of it
+        let file_range = image.data().as_ptr_range();
+        assert!(file_range.contains(&flashed.as_ptr()));
+
+        // A symbol file takes over the segments of the programmed elf without a copy
+        let mut symbols = ElfImage::load(file.path()).unwrap();
+        symbols.use_segments_of(image);
+        let symbol_segment = symbols.segment_contents().next().unwrap().1;
+        assert_eq!(symbol_segment.as_ptr(), flashed.as_ptr());
+    }
+}
@@ -0,0 +1,299 @@
+//! Broadcasts target events as JSON lines to external tooling.
+//!
+//! Subscribers connect to a TCP port and only ever receive data. Each subscriber has a
+//! bounded queue drained by its own writer thread, a slow consumer therefore loses
+//! events instead of stalling the debug session.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, info, warn};
+
+use super::flash_error::FlashFailure;
+use super::CpuId;
+
+/// Number of events buffered per subscriber before events are dropped
+const SUBSCRIBER_QUEUE_LEN: usize = 256;
+
+/// Significant state changes of the target
+#[derive(Debug, Clone, Copy)]
+pub enum TargetEvent {
+    Halted {
+        cpu: CpuId,
+        pc: Option<u32>,
+        cause: &'static str,
+    },
+    Resumed {
+        cpu: CpuId,
+    },
+    BreakpointAdded {
+        addr: u32,
+    },
+    BreakpointRemoved {
+        addr: u32,
+    },
+    FlashStarted,
+    FlashFinished {
+        success: bool,
+        /// Where programming failed and how far it got, where known
+        failure: Option<FlashFailure>,
+    },
+    /// The device holds another project and the flash was not attempted
+    FlashRefused,
+    /// The programmed content was read back and compared with the elf
+    VerifyFinished {
+        success: bool,
+    },
+    Reset,
+    /// The halted device stopped answering the liveness check
+    DeviceLost,
+    /// The device answers the liveness check again after it was lost
+    DeviceRecovered,
+    /// The GDB port is open, sent before the device is initialized with `--listen-early`
+    Listening {
+        port: u16,
+    },
+}
+
+impl TargetEvent {
+    fn to_json(self) -> String {
+        match self {
+            TargetEvent::Halted { cpu, pc, cause } => {
+                let pc = pc
+                    .map(|pc| format!("\"{:#010x}\"", pc))
+                    .unwrap_or("null".to_owned());
+                format!(
+                    r#"{{"event":"halted","core":"{:?}","pc":{},"cause":"{}"}}"#,
+                    cpu, pc, cause
+                )
+            }
+            TargetEvent::Resumed { cpu } => {
+                format!(r#"{{"event":"resumed","core":"{:?}"}}"#, cpu)
+            }
+            TargetEvent::BreakpointAdded { addr } => {
+                format!(r#"{{"event":"breakpoint_added","addr":"{:#010x}"}}"#, addr)
+            }
+            TargetEvent::BreakpointRemoved { addr } => {
+                format!(
+                    r#"{{"event":"breakpoint_removed","addr":"{:#010x}"}}"#,
+                    addr
+                )
+            }
+            TargetEvent::FlashStarted => r#"{"event":"flash_started"}"#.to_owned(),
+            TargetEvent::FlashFinished { success, failure } => match failure {
+                Some(failure) => format!(
+                    r#"{{"event":"flash_finished","success":{},"failure":{}}}"#,
+                    success,
+                    failure_json(failure)
+                ),
+                None => format!(r#"{{"event":"flash_finished","success":{}}}"#, success),
+            },
+            TargetEvent::FlashRefused => {
+                r#"{"event":"flash_refused","reason":"project_change"}"#.to_owned()
+            }
+            TargetEvent::VerifyFinished { success } => {
+                format!(r#"{{"event":"verify_finished","success":{}}}"#, success)
+            }
+            TargetEvent::Reset => r#"{"event":"reset"}"#.to_owned(),
+            TargetEvent::DeviceLost => r#"{"event":"device_lost"}"#.to_owned(),
+            TargetEvent::DeviceRecovered => r#"{"event":"device_recovered"}"#.to_owned(),
+            TargetEvent::Listening { port } => {
+                format!(r#"{{"event":"listening","port":{}}}"#, port)
+            }
+        }
+    }
+}
+
+/// The fields of a flash failure as a JSON object
+fn failure_json(failure: FlashFailure) -> String {
+    let location = failure.location;
+    format!(
+        r#"{{"phase":"{}","bank":"{}","sector":{},"start":"{:#010x}","end":"{:#010x}","completed":{},"total":{}}}"#,
+        failure.phase.name(),
+        location.bank,
+        location
+            .sector
+            .map_or("null".to_owned(), |sector| sector.to_string()),
+        location.start,
+        location.end,
+        failure.progress.completed,
+        failure.progress.total
+    )
+}
+
+impl TargetEvent {
+    /// Core the event concerns, `None` for device wide events
+    pub fn core(&self) -> Option<CpuId> {
+        match *self {
+            TargetEvent::Halted { cpu, .. } | TargetEvent::Resumed { cpu } => Some(cpu),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TargetEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = |success: bool| if success { "succeeded" } else { "failed" };
+        match *self {
+            TargetEvent::Halted { pc, cause, .. } => match pc {
+                Some(pc) => write!(f, "Halted at {:#010x} ({})", pc, cause),
+                None => write!(f, "Halted ({})", cause),
+            },
+            TargetEvent::Resumed { .. } => write!(f, "Resumed"),
+            TargetEvent::BreakpointAdded { addr } => {
+                write!(f, "Breakpoint added at {:#010x}", addr)
+            }
+            TargetEvent::BreakpointRemoved { addr } => {
+                write!(f, "Breakpoint removed at {:#010x}", addr)
+            }
+            TargetEvent::FlashStarted => write!(f, "Flash started"),
+            TargetEvent::FlashFinished {
+                failure: Some(failure),
+                ..
+            } => write!(f, "Flash failed: {}", failure),
+            TargetEvent::FlashFinished { success, .. } => write!(f, "Flash {}", outcome(success)),
+            TargetEvent::FlashRefused => {
+                write!(f, "Flash refused, the device holds another project")
+            }
+            TargetEvent::VerifyFinished { success } => write!(f, "Verify {}", outcome(success)),
+            TargetEvent::Reset => write!(f, "Reset"),
+            TargetEvent::DeviceLost => write!(f, "Device lost"),
+            TargetEvent::DeviceRecovered => write!(f, "Device answers again"),
+            TargetEvent::Listening { port } => write!(f, "Listening for GDB on port {}", port),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    subscribers: Vec<SyncSender<String>>,
+    /// Last known state per core, sent to subscribers joining mid-session
+    core_states: HashMap<CpuId, &'static str>,
+    /// Serial of the probe in use, added to every event
+    probe: Option<String>,
+    /// ID of the stub process, added to every event
+    session: Option<String>,
+}
+
+impl Shared {
+    fn snapshot(&self) -> String {
+        let mut cores: Vec<_> = self.core_states.iter().collect();
+        cores.sort_by_key(|(cpu, _)| usize::from(**cpu));
+        let cores: Vec<String> = cores
+            .iter()
+            .map(|(cpu, state)| format!(r#""{:?}":"{}""#, cpu, state))
+            .collect();
+        self.tagged(format!(
+            r#"{{"event":"snapshot","cores":{{{}}}}}"#,
+            cores.join(",")
+        ))
+    }
+
+    /// Adds the session ID and probe serial to a JSON event, so logs of several rigs tell
+    /// the processes and probes apart
+    fn tagged(&self, line: String) -> String {
+        let line = match &self.probe {
+            Some(serial) => format!(
+                r#"{{"probe":"{}",{}"#,
+                serial.replace('\\', "\\\\").replace('"', "\\\""),
+                &line[1..]
+            ),
+            None => line,
+        };
+        match &self.session {
+            Some(session) => format!(r#"{{"session":"{}",{}"#, session, &line[1..]),
+            None => line,
+        }
+    }
+}
+
+/// Publishes [TargetEvent]s to every connected subscriber
+pub struct EventBroadcaster {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl EventBroadcaster {
+    /// Starts accepting subscribers on the given address in a background thread
+    pub fn bind(sockaddr: &str) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(sockaddr)?;
+        info!("Publishing target events on {}", sockaddr);
+
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let accept_shared = Arc::clone(&shared);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => subscribe(&accept_shared, stream),
+                    Err(e) => warn!("Failed to accept event subscriber: {}", e),
+                }
+            }
+        });
+
+        Ok(EventBroadcaster { shared })
+    }
+
+    /// Sets the serial of the probe in use, `None` if it has none
+    pub fn set_probe(&self, serial: Option<String>) {
+        self.shared.lock().unwrap().probe = serial;
+    }
+
+    /// Sets the session ID of the stub process
+    pub fn set_session(&self, session: String) {
+        self.shared.lock().unwrap().session = Some(session);
+    }
+
+    /// Queues the event for every subscriber without blocking
+    pub fn publish(&self, event: TargetEvent) {
+        let mut shared = self.shared.lock().unwrap();
+
+        match event {
+            TargetEvent::Halted { cpu, .. } => {
+                shared.core_states.insert(cpu, "halted");
+            }
+            TargetEvent::Resumed { cpu } => {
+                shared.core_states.insert(cpu, "running");
+            }
+            _ => {}
+        }
+
+        let line = shared.tagged(event.to_json());
+        shared
+            .subscribers
+            .retain(|subscriber| match subscriber.try_send(line.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    debug!("Event subscriber too slow, dropping event");
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+fn subscribe(shared: &Arc<Mutex<Shared>>, mut stream: TcpStream) {
+    let (sender, receiver) = sync_channel::<String>(SUBSCRIBER_QUEUE_LEN);
+
+    {
+        let mut shared = shared.lock().unwrap();
+        // Cannot fail, the queue is empty and the receiver is alive
+        _ = sender.try_send(shared.snapshot());
+        shared.subscribers.push(sender);
+    }
+
+    if let Ok(addr) = stream.peer_addr() {
+        info!("Event subscriber connected from {}", addr);
+    }
+
+    std::thread::spawn(move || {
+        for line in receiver {
+            if writeln!(stream, "{}", line).is_err() {
+                // Dropping the receiver unregisters the subscriber on the next publish
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,176 @@
+use std::time::UNIX_EPOCH;
+
+use gdbstub::common::Pid;
+use gdbstub::outputln;
+use gdbstub::target::{ext::exec_file::ExecFile, TargetResult};
+use log::{info, warn};
+
+use super::elf::ElfImage;
+use super::elf_set::ElfSpec;
+use super::{parse_u32, CpuId, MonitorOutput, TricoreTarget};
+
+impl<'a> TricoreTarget<'a> {
+    /// Replaces the elf of a core, or the shared one, without programming it. `spec` is
+    /// `[cpu<N>:]<path>` as for `--elf_file`.
+    pub(crate) fn elf_load(&mut self, spec: &str, out: &mut MonitorOutput<'_>) {
+        let spec = match spec.parse::<ElfSpec>() {
+            Ok(spec) => spec,
+            Err(e) => {
+                outputln!(out, "{}", e);
+                return;
+            }
+        };
+        let image = match ElfImage::load(&spec.path) {
+            Ok(image) => image,
+            Err(e) => {
+                outputln!(out, "Cannot load {}: {:#}", spec.path.display(), e);
+                return;
+            }
+        };
+
+        let programmed = image
+            .build_id
+            .as_ref()
+            .is_some_and(|id| self.flashed_build_ids.contains(id));
+        if !self.flashed_build_ids.is_empty() && !programmed {
+            warn!(
+                "Build ID of {} differs from the programmed elfs",
+                spec.path.display()
+            );
+            outputln!(
+                out,
+                "Warning: the build ID differs from the elfs programmed at startup, the device \
+                 still runs the old code"
+            );
+        }
+        if !self.breakpoints.is_empty() {
+            outputln!(
+                out,
+                "{} planted breakpoints keep their addresses",
+                self.breakpoints.len()
+            );
+        }
+
+        outputln!(
+            out,
+            "Loaded {}",
+            ElfSpec {
+                core: spec.core,
+                path: image.path.clone(),
+            }
+        );
+        self.elfs.insert(spec.core, image);
+        // The new elf may come with a programmed flash the cache doesn't know of
+        self.code_cache.invalidate();
+    }
+
+    /// Makes a debug elf the symbol source of a core, or of all cores, in place of the
+    /// programmed elf
+    pub fn load_symbols(&mut self, spec: &ElfSpec) -> anyhow::Result<()> {
+        let mut image = ElfImage::load(&spec.path)?;
+        // A debug elf has no content, the flash usage stays the one of the programmed elf
+        if image.segments.is_empty() {
+            if let Some(programmed) = self.elfs.assigned(spec.core) {
+                image.use_segments_of(programmed);
+            }
+        }
+        info!(
+            "Symbols of {}",
+            ElfSpec {
+                core: spec.core,
+                path: image.path.clone(),
+            }
+        );
+        self.elfs.insert(spec.core, image);
+        Ok(())
+    }
+
+    pub(crate) fn elf_info(&self, out: &mut MonitorOutput<'_>) {
+        let assignments = self.elfs.assignments();
+        if assignments.is_empty() {
+            outputln!(out, "No elf provided");
+            return;
+        }
+
+        for (core, elf) in assignments {
+            let loaded_at = elf
+                .loaded_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            let cores = core.map_or("all cores".to_owned(), |core| format!("{:?}", core));
+            outputln!(out, "{:<20} {}", "cores", cores);
+            outputln!(out, "{:<20} {}", "path", elf.path.display());
+            outputln!(
+                out,
+                "{:<20} {}",
+                "build id",
+                elf.build_id_hex().unwrap_or("none".to_owned())
+            );
+            outputln!(out, "{:<20} {:#010x}", "entry", elf.entry);
+            outputln!(out, "{:<20} {} (unix time)", "parsed at", loaded_at);
+            for segment in &elf.segments {
+                outputln!(
+                    out,
+                    "segment {:#010x}..{:#010x} ({} bytes)",
+                    segment.address,
+                    segment.address as u64 + segment.file_size,
+                    segment.file_size
+                );
+            }
+        }
+    }
+
+    /// Elf of every core with one, or of the given core
+    pub(crate) fn exec_file_command(&self, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let Some(core) = core else {
+            let assignments = self.elfs.assignments();
+            if assignments.is_empty() {
+                outputln!(out, "No elf provided");
+            }
+            for (core, elf) in assignments {
+                outputln!(
+                    out,
+                    "{}",
+                    ElfSpec {
+                        core,
+                        path: elf.path.clone(),
+                    }
+                );
+            }
+            return;
+        };
+
+        let Some(cpu) = parse_u32(core).and_then(|index| CpuId::try_from(index as usize).ok())
+        else {
+            outputln!(out, "usage: exec-file [core]");
+            return;
+        };
+        match self.elfs.for_core(cpu) {
+            Some(elf) => outputln!(out, "{}", elf.path.display()),
+            None => outputln!(out, "No elf provided for {:?}", cpu),
+        }
+    }
+}
+
+impl<'a> ExecFile for TricoreTarget<'a> {
+    /// Reports the elf the stub was started with, so GDB can load its symbols
+    fn get_exec_file(
+        &self,
+        _pid: Option<Pid>,
+        offset: u64,
+        length: usize,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        // GDB asks for a single program, the shared one or the one of the lowest core
+        let Some(elf) = self.elfs.primary() else {
+            return Ok(0);
+        };
+
+        let path = elf.path.to_string_lossy();
+        let path = path.as_bytes();
+        let start = (offset as usize).min(path.len());
+        let len = length.min(buf.len()).min(path.len() - start);
+        buf[..len].copy_from_slice(&path[start..start + len]);
+        Ok(len)
+    }
+}
@@ -1,4 +1,4 @@
-use super::StaticTricoreTarget;
+use super::{DisconnectAction, KillAction, TricoreTarget};
 use gdbstub::{
     common::Pid,
     target::{
@@ -7,26 +7,42 @@ use gdbstub::{
         TargetResult,
     },
 };
+use log::{debug, info, warn};
 
-impl target::ext::extended_mode::ExtendedMode for StaticTricoreTarget {
+impl<'a> target::ext::extended_mode::ExtendedMode for TricoreTarget<'a> {
     fn kill(&mut self, pid: Option<Pid>) -> TargetResult<ShouldTerminate, Self> {
-        eprintln!("GDB sent a kill request for pid {:?}", pid);
+        info!("GDB sent a kill request for pid {:?}", pid);
+        match self.settings.kill_action {
+            KillAction::None => info!("Kill action is none, device left untouched"),
+            KillAction::Reset => {
+                self.reset_session_state();
+                self.restart();
+                info!("Device reset, breakpoints and cached state dropped");
+            }
+            KillAction::PowerCycle => {
+                // Rejected at startup where the access hardware cannot switch power
+                self.reset_session_state();
+                self.restart();
+                warn!("Power cycle unavailable, device reset instead");
+            }
+        }
+        // The stub keeps serving the session, GDB may run the target again
         Ok(ShouldTerminate::No)
     }
 
     fn restart(&mut self) -> Result<(), Self::Error> {
-        eprintln!("GDB sent a restart request");
+        info!("GDB sent a restart request");
         self.restart();
         Ok(())
     }
 
     fn attach(&mut self, pid: Pid) -> TargetResult<(), Self> {
-        eprintln!("GDB attached to a process with PID {}", pid);
+        info!("GDB attached to a process with PID {}", pid);
         Ok(())
     }
 
     fn run(&mut self, filename: Option<&[u8]>, args: Args<'_, '_>) -> TargetResult<Pid, Self> {
-        eprintln!(
+        info!(
             "GDB tried to run a new process with filename {:?}, and args {:?}",
             filename, args
         );
@@ -36,10 +52,67 @@ impl target::ext::extended_mode::ExtendedMode for StaticTricoreTarget {
     }
 
     fn query_if_attached(&mut self, pid: Pid) -> TargetResult<AttachKind, Self> {
-        eprintln!(
+        debug!(
             "GDB queried if it was attached to a process with PID {}",
             pid
         );
         Ok(AttachKind::Attach)
     }
 }
+
+impl<'a> TricoreTarget<'a> {
+    /// Leaves the device as `on-disconnect` asks, once GDB is gone. Triggers are only
+    /// removed from halted cores, running ones are halted for it.
+    pub fn apply_disconnect_action(&mut self) {
+        self.flush_writes_noted();
+        match self.settings.on_disconnect {
+            DisconnectAction::Run => {
+                info!("GDB is gone, removing all triggers and running the target");
+                self.halt();
+                self.reset_session_state();
+                self.run_all();
+            }
+            DisconnectAction::Halt => {
+                info!("GDB is gone, halting the target");
+                self.interrupt();
+            }
+            DisconnectAction::Detach => {
+                info!("GDB is gone, removing what the session set up");
+                let running = self.running_cores();
+                for &index in &running {
+                    if let Some(core) = self.core_mut(index) {
+                        _ = core.stop();
+                    }
+                }
+                self.reset_session_state();
+                self.flush_triggers();
+                for (position, index) in self.resume_sequence(&running).into_iter().enumerate() {
+                    self.stagger_start(position);
+                    if !self.core_mut(index).is_some_and(|core| core.run().is_ok()) {
+                        warn!("Cannot resume core {} after removing its triggers", index);
+                    }
+                }
+                self.resume_halted_on_connect();
+            }
+        }
+    }
+
+    /// Drops what the session set up on the device, as if GDB had just attached
+    fn reset_session_state(&mut self) {
+        let cores = self.all_cores();
+        let planted: Vec<u32> = self.breakpoints.keys().copied().collect();
+        for addr in planted {
+            if let Err(e) = self.unplant_breakpoint(addr, &cores) {
+                warn!("Cannot remove breakpoint at {:#010x}: {}", addr, e);
+            }
+        }
+        self.remove_range_watches();
+        self.deferred.clear();
+        self.pending_breakpoints.clear();
+        self.clear_step_overs();
+        self.saved_irq_enable.clear();
+        self.pending_events.clear();
+        self.stop_snapshots.clear();
+        self.clear_register_cache();
+    }
+}
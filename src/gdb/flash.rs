@@ -1,9 +1,18 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::process::{Child, Command};
+use std::thread::sleep;
+use std::time::Duration;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use tempfile::TempDir;
 
+use crate::gdb::arch::ArchVariant;
+use crate::gdb::elf::{ElfImage, LoadSegment};
+use crate::gdb::flash_error::{FlashError, FlashLocation, FlashPhase, FlashProgress};
+use crate::gdb::memory_map::{find_region, to_cached_alias, MemoryRegion, RegionKind};
+use crate::gdb::parse_addr_len;
+
 /// Models an upload of a binary with AurixFlasher.
 pub struct AurixFlasherUpload {
     spawned: Child,
@@ -60,12 +69,948 @@ impl AurixFlasherUpload {
     /// This can happen when the flash layout is broken or when another debugger
     /// is already attached. The problem can only really be debugged with the GUI
     /// or solved by implementing reading the logs from Memtool.
-    pub fn wait(&mut self) {
-        let output = self
-            .spawned
-            .wait()
-            .expect("AurixFlasher did not exit with success");
-        assert!(output.success());
-        log::info!("AurixFlasher terminated successfully");
+    pub fn wait(&mut self) -> anyhow::Result<()> {
+        loop {
+            if let Some(result) = self.try_finish() {
+                log::info!("AurixFlasher terminated");
+                return result;
+            }
+            sleep(UPLOAD_POLL_INTERVAL);
+        }
+    }
+
+    /// Checks whether the upload finished without blocking, `None` while it is still running
+    pub fn try_finish(&mut self) -> Option<anyhow::Result<()>> {
+        match self.spawned.try_wait() {
+            Ok(None) => None,
+            Ok(Some(status)) if status.success() => Some(Ok(())),
+            Ok(Some(status)) => Some(Err(anyhow::anyhow!("AurixFlasher failed with {}", status))),
+            Err(e) => Some(Err(e).context("Cannot query AurixFlasher state")),
+        }
+    }
+}
+
+/// Interval in which a running upload is polled
+const UPLOAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Address span and size of the data of an Intel hex file
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HexExtent {
+    pub start: u32,
+    /// Exclusive end of the highest data record
+    pub end: u32,
+    /// Data bytes in total
+    pub bytes: u64,
+}
+
+/// Extent of the data of an Intel hex file, `None` if it holds no data
+pub(crate) fn hex_extent(ihex: &str) -> anyhow::Result<Option<HexExtent>> {
+    let mut extent: Option<HexExtent> = None;
+    let mut base: u32 = 0;
+    for line in ihex.lines().filter(|line| !line.trim().is_empty()) {
+        let (addr, record_type, data) = decode_record(line.trim())?;
+        match record_type {
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            0x00 if !data.is_empty() => {
+                let start = base.wrapping_add(addr as u32);
+                let end = start.saturating_add(data.len() as u32);
+                let extent = extent.get_or_insert(HexExtent {
+                    start,
+                    end,
+                    bytes: 0,
+                });
+                extent.start = extent.start.min(start);
+                extent.end = extent.end.max(end);
+                extent.bytes += data.len() as u64;
+            }
+            _ => {}
+        }
+    }
+    Ok(extent)
+}
+
+/// Result of mapping a single elf segment onto the device memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Segment fits into a flash bank
+    Ok,
+    /// Segment targets RAM and is loaded rather than programmed
+    Ram,
+    /// Segment targets the UCBs and is excluded from programming
+    SkippedUcb,
+    /// Segment is (partially) outside of any known region
+    Outside,
+    /// Segment lies in flash of its bank the derivative doesn't have
+    BankFull,
+    /// Segment gives other content than another segment for the same addresses
+    Overlap,
+}
+
+impl Verdict {
+    fn is_accepted(&self) -> bool {
+        matches!(self, Verdict::Ok | Verdict::Ram | Verdict::SkippedUcb)
+    }
+}
+
+/// Per segment outcome of [preflight]
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentCheck {
+    pub segment: LoadSegment,
+    pub region: Option<&'static MemoryRegion>,
+    pub verdict: Verdict,
+}
+
+/// Bytes of `bank` present on a derivative with `program_flash` bytes of program flash,
+/// which fills the program flash banks in address order
+fn bank_capacity(regions: &[MemoryRegion], bank: &MemoryRegion, program_flash: Option<u32>) -> u64 {
+    let Some(program_flash) = program_flash.filter(|_| bank.kind == RegionKind::ProgramFlash)
+    else {
+        return bank.len as u64;
+    };
+    let before: u64 = regions
+        .iter()
+        .filter(|region| region.kind == RegionKind::ProgramFlash && region.start < bank.start)
+        .map(|region| region.len as u64)
+        .sum();
+    (program_flash as u64)
+        .saturating_sub(before)
+        .min(bank.len as u64)
+}
+
+/// Whether two segments, given with their content, program different bytes to the same
+/// address
+fn conflicting(
+    (a, a_content): (&LoadSegment, &[u8]),
+    (b, b_content): (&LoadSegment, &[u8]),
+) -> bool {
+    let a_start = to_cached_alias(a.address) as u64;
+    let b_start = to_cached_alias(b.address) as u64;
+    let start = a_start.max(b_start);
+    let end = (a_start + a_content.len() as u64).min(b_start + b_content.len() as u64);
+    start < end
+        && a_content[(start - a_start) as usize..(end - a_start) as usize]
+            != b_content[(start - b_start) as usize..(end - b_start) as usize]
+}
+
+/// Maps every segment, given with its content, onto `regions` without touching the
+/// device. Flash banks are checked against the capacity of a derivative with
+/// `program_flash` bytes of program flash, against the whole table where it is unknown.
+pub fn preflight(
+    segments: &[(&LoadSegment, &[u8])],
+    regions: &'static [MemoryRegion],
+    allow_ucb: bool,
+    program_flash: Option<u32>,
+) -> Vec<SegmentCheck> {
+    let mut checks: Vec<SegmentCheck> = segments
+        .iter()
+        .map(|&(segment, _)| {
+            let start = to_cached_alias(segment.address);
+            let region = find_region(regions, segment.address);
+            let verdict = match region {
+                Some(region) if !region.contains_range(start, segment.file_size) => {
+                    Verdict::Outside
+                }
+                Some(region) => match region.kind {
+                    RegionKind::Ucb if allow_ucb => Verdict::Ok,
+                    RegionKind::Ucb => Verdict::SkippedUcb,
+                    RegionKind::Ram => Verdict::Ram,
+                    RegionKind::ProgramFlash | RegionKind::DataFlash
+                        if (start - region.start) as u64 + segment.file_size
+                            > bank_capacity(regions, region, program_flash) =>
+                    {
+                        Verdict::BankFull
+                    }
+                    RegionKind::ProgramFlash | RegionKind::DataFlash => Verdict::Ok,
+                },
+                None => Verdict::Outside,
+            };
+            SegmentCheck {
+                segment: *segment,
+                region,
+                verdict,
+            }
+        })
+        .collect();
+
+    // Segments may overlap where they agree
+    for (index, &segment) in segments.iter().enumerate() {
+        for (other_index, &other) in segments.iter().enumerate().skip(index + 1) {
+            if conflicting(segment, other) {
+                checks[index].verdict = Verdict::Overlap;
+                checks[other_index].verdict = Verdict::Overlap;
+            }
+        }
+    }
+
+    checks
+}
+
+/// Logs a table of address range -> target bank -> verdict
+pub fn print_preflight(checks: &[SegmentCheck]) {
+    log::info!("{:<25} {:<10} Verdict", "Segment", "Bank");
+    for check in checks {
+        let start = check.segment.address as u64;
+        log::info!(
+            "{:<25} {:<10} {:?}",
+            format!("{:#010x}..{:#010x}", start, start + check.segment.file_size),
+            check.region.map(|region| region.name).unwrap_or("-"),
+            check.verdict
+        );
+    }
+}
+
+/// Validates that the elf fits the device flash layout, no device access is performed.
+/// `program_flash` is the program flash of the derivative, if it is known.
+///
+/// UCB content is only accepted when explicitly allowed and every boot mode header in
+/// the image validates. Returns the per segment verdicts.
+pub fn check_elf(
+    elf: &ElfImage,
+    arch: ArchVariant,
+    program_flash: Option<u32>,
+    options: &FlashOptions,
+) -> anyhow::Result<Vec<SegmentCheck>> {
+    let segments: Vec<(&LoadSegment, &[u8])> = elf.segment_contents().collect();
+    let checks = preflight(&segments, arch.regions(), options.allow_ucb, program_flash);
+    print_preflight(&checks);
+
+    let rejected = checks
+        .iter()
+        .filter(|check| !check.verdict.is_accepted())
+        .count();
+    if rejected > 0 {
+        bail!("{} segment(s) do not fit the device flash layout", rejected);
+    }
+
+    let skipped: Vec<&SegmentCheck> = checks
+        .iter()
+        .filter(|check| check.verdict == Verdict::SkippedUcb)
+        .collect();
+    if !skipped.is_empty() {
+        log::warn!("UCB content is NOT programmed, pass --allow-ucb to program it:");
+        for check in skipped {
+            log::warn!(
+                "  skipped {:#010x} ({} bytes)",
+                check.segment.address,
+                check.segment.file_size
+            );
+        }
+    }
+
+    if options.allow_ucb && programs_ucb(&checks) {
+        let mut validated = 0;
+        for &slot in arch.bmhd_slots() {
+            let Some(header) = elf.segment_contents().find_map(|(segment, data)| {
+                let offset = slot.checked_sub(segment.address)? as usize;
+                data.get(offset..offset + BMHD_LEN)
+            }) else {
+                continue;
+            };
+            validate_bmhd(header).with_context(|| format!("Invalid BMHD at {:#010x}", slot))?;
+            log::info!("BMHD at {:#010x} is valid", slot);
+            validated += 1;
+        }
+        if arch.bmhd_slots().is_empty() {
+            log::warn!(
+                "The boot mode headers of {} devices are not validated, check the UCB \
+                 content of the image before it is programmed",
+                arch
+            );
+        } else if validated == 0 {
+            bail!(
+                "--allow-ucb programs UCB content, but the image holds no complete boot mode \
+                 header to validate at {}",
+                arch.bmhd_slots()
+                    .iter()
+                    .map(|slot| format!("{:#010x}", slot))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+
+    Ok(checks)
+}
+
+/// Bytes of the image programmed into a flash bank, by touched sector
+#[derive(Debug, Clone)]
+pub struct BankUsage {
+    pub bank: &'static MemoryRegion,
+    /// Start address and bytes used of every sector the image touches, in address order
+    pub sectors: BTreeMap<u32, u64>,
+}
+
+impl BankUsage {
+    pub fn used(&self) -> u64 {
+        self.sectors.values().sum()
+    }
+}
+
+/// Bins the segments into the sectors of the program and data flash banks.
+///
+/// A segment straddling sector boundaries counts towards every sector it touches with
+/// the bytes inside that sector. Segments outside of the banks are ignored.
+pub fn flash_usage(segments: &[LoadSegment], regions: &'static [MemoryRegion]) -> Vec<BankUsage> {
+    let mut banks: Vec<BankUsage> = Vec::new();
+    for segment in segments.iter().filter(|segment| segment.file_size > 0) {
+        let start = to_cached_alias(segment.address) as u64;
+        let end = start + segment.file_size;
+        let Some(bank) = find_region(regions, segment.address)
+            .filter(|bank| matches!(bank.kind, RegionKind::ProgramFlash | RegionKind::DataFlash))
+        else {
+            continue;
+        };
+        let sector_size = bank.sector_size().expect("Flash banks have sectors") as u64;
+
+        let usage = match banks.iter().position(|usage| usage.bank.name == bank.name) {
+            Some(index) => &mut banks[index],
+            None => {
+                banks.push(BankUsage {
+                    bank,
+                    sectors: BTreeMap::new(),
+                });
+                banks.last_mut().unwrap()
+            }
+        };
+
+        let end = end.min(bank.end());
+        let mut pos = start;
+        while pos < end {
+            let sector = bank.start as u64 + (pos - bank.start as u64) / sector_size * sector_size;
+            let chunk_end = (sector + sector_size).min(end);
+            *usage.sectors.entry(sector as u32).or_default() += chunk_end - pos;
+            pos = chunk_end;
+        }
+    }
+    banks.sort_by_key(|usage| usage.bank.start);
+    banks
+}
+
+fn percent(used: u64, size: u64) -> f64 {
+    used as f64 * 100.0 / size as f64
+}
+
+/// Report lines of the flash usage, sectors filled above `warn_percent` are flagged
+pub fn flash_usage_report(banks: &[BankUsage], warn_percent: u32) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<24} {:>10} {:>10} {:>7}",
+        "Sector", "Used", "Free", "Fill"
+    )];
+    for usage in banks {
+        let sector_size = usage.bank.sector_size().unwrap_or(usage.bank.len) as u64;
+        for (&sector, &used) in &usage.sectors {
+            let fill = percent(used, sector_size);
+            lines.push(format!(
+                "{:<24} {:>10} {:>10} {:>6.1}%{}",
+                format!("{} {:#010x}", usage.bank.name, sector),
+                used,
+                sector_size.saturating_sub(used),
+                fill,
+                if fill > warn_percent as f64 {
+                    " FULL"
+                } else {
+                    ""
+                }
+            ));
+        }
+        let used = usage.used();
+        let size = usage.bank.len as u64;
+        lines.push(format!(
+            "{:<24} {:>10} {:>10} {:>6.1}%",
+            format!("{} total", usage.bank.name),
+            used,
+            size.saturating_sub(used),
+            percent(used, size)
+        ));
+    }
+    lines
+}
+
+/// Whether any of the accepted segments will program the UCBs
+pub fn programs_ucb(checks: &[SegmentCheck]) -> bool {
+    checks.iter().any(|check| {
+        check.verdict == Verdict::Ok && check.region.is_some_and(|r| r.kind == RegionKind::Ucb)
+    })
+}
+
+/// Length of a boot mode header slot
+pub(crate) const BMHD_LEN: usize = 0x200;
+/// Identifier stored in the upper half word of the first BMHD word
+const BMHD_ID: u16 = 0xB359;
+/// Offset of the confirmation word within the BMHD
+const BMHD_CONFIRMATION_OFFSET: usize = 0x1F0;
+/// Confirmation code of a BMHD which can still be reprogrammed
+const BMHD_CONFIRMATION_UNLOCKED: u32 = 0x4321_1234;
+/// Confirmation code of a permanently locked BMHD
+const BMHD_CONFIRMATION_CONFIRMED: u32 = 0x57B5_327F;
+
+pub(crate) fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// CRC-32 as used by the AURIX boot ROM (IEEE 802.3, reflected)
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// CRC of a boot mode header as the boot ROM computes it: [crc32] over the first two
+/// words (BMI/BMHDID and STAD), each taken most significant byte first
+pub(crate) fn bmhd_crc(header: &[u8]) -> u32 {
+    let words: Vec<u8> = header[..8]
+        .chunks(4)
+        .flat_map(|word| word.iter().rev().copied())
+        .collect();
+    crc32(&words)
+}
+
+/// Checks identifier, CRC and confirmation code of a boot mode header.
+///
+/// The CRC is stored followed by its bitwise inverse, see [bmhd_crc].
+pub fn validate_bmhd(header: &[u8]) -> anyhow::Result<()> {
+    if header.len() < BMHD_LEN {
+        bail!("BMHD too short: {} bytes", header.len());
+    }
+
+    let bmi_bmhdid = read_u32_le(header, 0);
+    if (bmi_bmhdid >> 16) as u16 != BMHD_ID {
+        bail!(
+            "BMHDID is {:#06x}, expected {:#06x}",
+            bmi_bmhdid >> 16,
+            BMHD_ID
+        );
+    }
+
+    let crc = read_u32_le(header, 0x8);
+    let crc_n = read_u32_le(header, 0xC);
+    let expected = bmhd_crc(header);
+    if crc != expected {
+        bail!("CRCBMHD is {:#010x}, expected {:#010x}", crc, expected);
+    }
+    if crc_n != !expected {
+        bail!("CRCBMHD_N is {:#010x}, expected {:#010x}", crc_n, !expected);
+    }
+
+    match read_u32_le(header, BMHD_CONFIRMATION_OFFSET) {
+        BMHD_CONFIRMATION_UNLOCKED | BMHD_CONFIRMATION_CONFIRMED => Ok(()),
+        other => bail!("Invalid confirmation code {:#010x}", other),
+    }
+}
+
+/// Splits an Intel hex record into address, record type and payload
+fn decode_record(line: &str) -> anyhow::Result<(u16, u8, Vec<u8>)> {
+    let hex = line
+        .strip_prefix(':')
+        .with_context(|| format!("Malformed hex record '{}'", line))?;
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("?"), 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .with_context(|| format!("Malformed hex record '{}'", line))?;
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        bail!("Malformed hex record '{}'", line);
+    }
+    let addr = u16::from_be_bytes([bytes[1], bytes[2]]);
+    Ok((addr, bytes[3], bytes[4..bytes.len() - 1].to_vec()))
+}
+
+fn encode_record(addr: u16, record_type: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(addr.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    bytes.push(checksum);
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}", hex)
+}
+
+/// Removes all data within `excluded` from an Intel hex file
+pub fn exclude_from_hex(ihex: &str, excluded: &MemoryRegion) -> anyhow::Result<String> {
+    filter_hex(ihex, |addr| !excluded.contains(addr))
+}
+
+/// Joins Intel hex files into one, keeping a single end of file record at the end. Each
+/// file sets its own extended address before its data, so the records stay valid.
+pub fn merge_hex(images: &[String]) -> anyhow::Result<String> {
+    let mut merged = String::new();
+    for image in images {
+        for line in image.lines().filter(|line| !line.trim().is_empty()) {
+            if decode_record(line.trim())?.1 == 0x01 {
+                continue;
+            }
+            merged.push_str(line.trim());
+            merged.push('\n');
+        }
+    }
+    merged.push_str(&encode_record(0, 0x01, &[]));
+    merged.push('\n');
+    Ok(merged)
+}
+
+/// Keeps only the data at addresses for which `keep` holds
+fn filter_hex(ihex: &str, keep: impl Fn(u32) -> bool) -> anyhow::Result<String> {
+    let mut filtered = String::with_capacity(ihex.len());
+    let mut base: u32 = 0;
+
+    for line in ihex.lines().filter(|line| !line.trim().is_empty()) {
+        let (addr, record_type, data) = decode_record(line.trim())?;
+        match record_type {
+            // extended segment / linear address
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            0x00 => {
+                // Emit the runs of kept bytes as separate records
+                let mut run_start = None;
+                for i in 0..=data.len() {
+                    let kept = i < data.len() && keep(base.wrapping_add(addr as u32 + i as u32));
+                    match (kept, run_start) {
+                        (true, None) => run_start = Some(i),
+                        (false, Some(start)) => {
+                            filtered.push_str(&encode_record(
+                                addr.wrapping_add(start as u16),
+                                0x00,
+                                &data[start..i],
+                            ));
+                            filtered.push('\n');
+                            run_start = None;
+                        }
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+        filtered.push_str(line.trim());
+        filtered.push('\n');
+    }
+
+    Ok(filtered)
+}
+
+/// Location of the build identification block images embed at a fixed address
+#[derive(Debug, Clone, Copy)]
+pub struct IdBlock {
+    pub addr: u32,
+    pub len: u32,
+}
+
+impl IdBlock {
+    /// Parses `<addr>:<len>`, both in decimal or `0x` prefixed hex
+    pub fn parse(text: &str) -> Result<IdBlock, String> {
+        let (addr, len) = parse_addr_len(text)?;
+        Ok(IdBlock { addr, len })
+    }
+}
+
+/// Content of a build identification block.
+///
+/// The block holds NUL terminated ASCII strings, the project name followed by the build
+/// ID, the remainder is padding of NUL or erased bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildIdentity {
+    /// The block is erased, nothing was programmed yet
+    Erased,
+    Project {
+        name: String,
+        build: String,
+    },
+}
+
+impl std::fmt::Display for BuildIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildIdentity::Erased => write!(f, "erased"),
+            BuildIdentity::Project { name, build } if build.is_empty() => {
+                write!(f, "project {:?}", name)
+            }
+            BuildIdentity::Project { name, build } => {
+                write!(f, "project {:?}, build {:?}", name, build)
+            }
+        }
+    }
+}
+
+pub fn parse_build_identity(block: &[u8]) -> anyhow::Result<BuildIdentity> {
+    if block.iter().all(|&b| b == 0xFF) {
+        return Ok(BuildIdentity::Erased);
+    }
+
+    let mut fields = block.split(|&b| b == 0 || b == 0xFF);
+    let mut field = || -> anyhow::Result<String> {
+        let bytes = fields.next().unwrap_or_default();
+        if !bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            bail!("id block contains non-printable characters");
+        }
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    };
+    let name = field()?;
+    let build = field()?;
+    if name.is_empty() {
+        bail!("id block has no project name");
+    }
+    Ok(BuildIdentity::Project { name, build })
+}
+
+/// Returns the id block content of the elf, `None` if no segment covers it completely
+pub fn elf_id_block(elf: &ElfImage, block: IdBlock) -> Option<Vec<u8>> {
+    let end = block.addr as u64 + block.len as u64;
+    elf.segment_contents().find_map(|(segment, data)| {
+        let segment_end = segment.address as u64 + segment.file_size;
+        if segment.address > block.addr || segment_end < end {
+            return None;
+        }
+        let offset = (block.addr - segment.address) as usize;
+        Some(data[offset..offset + block.len as usize].to_vec())
+    })
+}
+
+/// Refusal to program an image of another project than the one on the device
+#[derive(Debug)]
+pub struct ProjectChange {
+    pub device: BuildIdentity,
+    pub image: BuildIdentity,
+}
+
+impl std::fmt::Display for ProjectChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "device holds {}, elf is {}; pass --force-project-change to flash anyway",
+            self.device, self.image
+        )
+    }
+}
+
+impl std::error::Error for ProjectChange {}
+
+/// Compares the identity on the device against the one of the new image.
+///
+/// Only a change of the project name is refused, a new build of the same project and a
+/// blank device are fine.
+pub fn check_project_change(
+    device: BuildIdentity,
+    image: BuildIdentity,
+) -> Result<(), ProjectChange> {
+    match (&device, &image) {
+        (
+            BuildIdentity::Project {
+                name: on_device, ..
+            },
+            BuildIdentity::Project { name: in_image, .. },
+        ) if on_device != in_image => Err(ProjectChange { device, image }),
+        _ => Ok(()),
+    }
+}
+
+/// Options controlling what the flash path is allowed to program
+#[derive(Debug, Clone, Default)]
+pub struct FlashOptions {
+    /// Program UCB content (boot mode headers, protection configuration)
+    pub allow_ucb: bool,
+    /// Build identification block compared before an image is programmed
+    pub id_block: Option<IdBlock>,
+    /// Program an image even if the device holds another project
+    pub force_project_change: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gdb::memory_map::TC3XX_REGIONS;
+
+    /// First 16 bytes of the unlocked BMHD0 of the user manual: internal start from
+    /// 0xA0000000, BMI 0x00FE, CRCBMHD 0x31795570 and its inverse
+    const MANUAL_BMHD: [u8; 16] = [
+        0xFE, 0x00, 0x59, 0xB3, 0x00, 0x00, 0x00, 0xA0, 0x70, 0x55, 0x79, 0x31, 0x8F, 0xAA, 0x86,
+        0xCE,
+    ];
+
+    /// The header of the manual with the unlocked confirmation code
+    fn bmhd() -> Vec<u8> {
+        let mut header = vec![0; BMHD_LEN];
+        header[..16].copy_from_slice(&MANUAL_BMHD);
+        header[BMHD_CONFIRMATION_OFFSET..BMHD_CONFIRMATION_OFFSET + 4]
+            .copy_from_slice(&[0x34, 0x12, 0x21, 0x43]);
+        header
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn bmhd_crc_matches_the_manual() {
+        assert_eq!(bmhd_crc(&MANUAL_BMHD), 0x3179_5570);
+    }
+
+    #[test]
+    fn valid_bmhd_is_accepted() {
+        assert!(validate_bmhd(&bmhd()).is_ok());
+        let mut confirmed = bmhd();
+        confirmed[BMHD_CONFIRMATION_OFFSET..BMHD_CONFIRMATION_OFFSET + 4]
+            .copy_from_slice(&[0x7F, 0x32, 0xB5, 0x57]);
+        assert!(validate_bmhd(&confirmed).is_ok());
+    }
+
+    #[test]
+    fn broken_bmhd_is_refused() {
+        assert!(validate_bmhd(&bmhd()[..BMHD_LEN - 1]).is_err());
+
+        let mut header = bmhd();
+        header[3] ^= 0xFF;
+        assert!(validate_bmhd(&header).is_err());
+
+        let mut header = bmhd();
+        header[4] ^= 0x01;
+        assert!(validate_bmhd(&header).is_err());
+
+        let mut header = bmhd();
+        header[8] ^= 0x01;
+        assert!(validate_bmhd(&header).is_err());
+
+        let mut header = bmhd();
+        header[12] ^= 0x01;
+        assert!(validate_bmhd(&header).is_err());
+
+        let mut header = bmhd();
+        header[BMHD_CONFIRMATION_OFFSET] = 0;
+        assert!(validate_bmhd(&header).is_err());
+    }
+
+    #[test]
+    fn hex_record_round_trips() {
+        let record = encode_record(0x1234, 0x00, &[0xDE, 0xAD]);
+        assert_eq!(record, ":02123400DEAD2D");
+        assert_eq!(
+            decode_record(&record).unwrap(),
+            (0x1234, 0x00, vec![0xDE, 0xAD])
+        );
+        assert!(decode_record("021234").is_err());
+        assert!(decode_record(":02123400DE3F").is_err());
+    }
+
+    #[test]
+    fn excluded_region_is_cut_out_of_the_hex() {
+        let ihex = [
+            encode_record(0, 0x04, &[0xAF, 0x40]),
+            encode_record(0x01FE, 0x00, &[1, 2, 3, 4]),
+            encode_record(0, 0x01, &[]),
+        ]
+        .join("\n");
+        let ucb = MemoryRegion {
+            name: "UCB",
+            start: 0xAF40_0200,
+            len: 0x200,
+            kind: RegionKind::Ucb,
+        };
+        let kept = exclude_from_hex(&ihex, &ucb).unwrap();
+        assert_eq!(
+            kept.lines().collect::<Vec<_>>(),
+            vec![
+                encode_record(0, 0x04, &[0xAF, 0x40]),
+                encode_record(0x01FE, 0x00, &[1, 2]),
+                encode_record(0, 0x01, &[]),
+            ]
+        );
+    }
+
+    fn project(name: &str, build: &str) -> BuildIdentity {
+        BuildIdentity::Project {
+            name: name.to_owned(),
+            build: build.to_owned(),
+        }
+    }
+
+    #[test]
+    fn id_block_option_is_parsed() {
+        let block = IdBlock::parse("0x80000020:64").unwrap();
+        assert_eq!((block.addr, block.len), (0x8000_0020, 64));
+        assert!(IdBlock::parse("0x80000020").is_err());
+        assert!(IdBlock::parse("0x80000020:0").is_err());
+        assert!(IdBlock::parse("0xFFFFFFF0:0x20").is_err());
+    }
+
+    #[test]
+    fn identity_is_read_from_the_block() {
+        let mut block = b"brake_ecu\0v1.4.2\0".to_vec();
+        block.resize(32, 0xFF);
+        assert_eq!(
+            parse_build_identity(&block).unwrap(),
+            project("brake_ecu", "v1.4.2")
+        );
+        assert_eq!(
+            parse_build_identity(b"brake_ecu\0\0\0").unwrap(),
+            project("brake_ecu", "")
+        );
+        assert_eq!(
+            parse_build_identity(&[0xFF; 32]).unwrap(),
+            BuildIdentity::Erased
+        );
+    }
+
+    #[test]
+    fn garbage_block_is_refused() {
+        assert!(parse_build_identity(b"\0v1.4.2\0").is_err());
+        assert!(parse_build_identity(b"brake\x01ecu\0").is_err());
+    }
+
+    #[test]
+    fn only_a_project_change_is_refused() {
+        let device = project("brake_ecu", "v1.4.2");
+        assert!(check_project_change(device.clone(), project("brake_ecu", "v1.5.0")).is_ok());
+        assert!(check_project_change(BuildIdentity::Erased, device.clone()).is_ok());
+        let refusal = check_project_change(device, project("door_ecu", "v1.4.2")).unwrap_err();
+        assert_eq!(refusal.image, project("door_ecu", "v1.4.2"));
+    }
+
+    fn segment(address: u32, file_size: u64) -> LoadSegment {
+        LoadSegment {
+            address,
+            file_size,
+            file_offset: 0,
+        }
+    }
+
+    #[test]
+    fn usage_is_binned_by_sector() {
+        let segments = [
+            // straddles the first two 16 KiB sectors of PF0
+            segment(0x8000_3F00, 0x200),
+            // non-cached alias of the same bank
+            segment(0xA000_8000, 0x10),
+            segment(0xAF00_0000, 0x1000),
+            // scratchpad, not flash
+            segment(0x7000_0000, 0x100),
+        ];
+        let banks = flash_usage(&segments, TC3XX_REGIONS);
+        assert_eq!(banks.len(), 2);
+        assert_eq!(banks[0].bank.name, "PF0");
+        assert_eq!(
+            banks[0].sectors.iter().collect::<Vec<_>>(),
+            vec![
+                (&0x8000_0000, &0x100),
+                (&0x8000_4000, &0x100),
+                (&0x8000_8000, &0x10)
+            ]
+        );
+        assert_eq!(banks[0].used(), 0x210);
+        assert_eq!(banks[1].bank.name, "DF0");
+        assert_eq!(banks[1].used(), 0x1000);
+    }
+
+    #[test]
+    fn full_sectors_are_flagged() {
+        let banks = flash_usage(&[segment(0xAF00_0000, 0x1000)], TC3XX_REGIONS);
+        let report = flash_usage_report(&banks, 90);
+        assert_eq!(report.len(), 3);
+        assert!(report[1].starts_with("DF0 0xaf000000"));
+        assert!(report[1].ends_with("100.0% FULL"));
+        assert!(!report[2].ends_with("FULL"));
+    }
+
+    /// Verdicts of `segments`, each filled with its given byte
+    fn verdicts(segments: &[(u32, u64, u8)], program_flash: Option<u32>) -> Vec<Verdict> {
+        let contents: Vec<(LoadSegment, Vec<u8>)> = segments
+            .iter()
+            .map(|&(address, len, fill)| (segment(address, len), vec![fill; len as usize]))
+            .collect();
+        let segments: Vec<(&LoadSegment, &[u8])> = contents
+            .iter()
+            .map(|(segment, content)| (segment, content.as_slice()))
+            .collect();
+        preflight(&segments, TC3XX_REGIONS, false, program_flash)
+            .iter()
+            .map(|check| check.verdict)
+            .collect()
+    }
+
+    #[test]
+    fn segments_are_mapped_onto_their_region() {
+        assert_eq!(
+            verdicts(
+                &[
+                    (0x8000_0000, 0x100, 1),
+                    (0xA030_0000, 0x100, 1),
+                    (0x7000_0000, 0x100, 1),
+                    (0xAF40_0000, 0x200, 1),
+                    (0x802F_FF00, 0x200, 1),
+                    (0x2000_0000, 0x10, 1),
+                ],
+                None
+            ),
+            [
+                Verdict::Ok,
+                Verdict::Ok,
+                Verdict::Ram,
+                Verdict::SkippedUcb,
+                Verdict::Outside,
+                Verdict::Outside,
+            ]
+        );
+    }
+
+    #[test]
+    fn banks_beyond_the_derivative_are_full() {
+        // 6 MiB fill PF0 and PF1, 4 MiB PF0 and the first MiB of PF1
+        let segments = [
+            (0x8030_0000, 0x100, 1),
+            (0x8060_0000, 0x100, 1),
+            (0x803F_FF00, 0x200, 1),
+        ];
+        assert_eq!(
+            verdicts(&segments, Some(6 << 20)),
+            [Verdict::Ok, Verdict::BankFull, Verdict::Ok]
+        );
+        assert_eq!(
+            verdicts(&segments, Some(4 << 20)),
+            [Verdict::Ok, Verdict::BankFull, Verdict::BankFull]
+        );
+        assert_eq!(
+            verdicts(&segments, None),
+            [Verdict::Ok, Verdict::Ok, Verdict::Ok]
+        );
+        // Data flash is not part of the program flash size
+        assert_eq!(
+            verdicts(&[(0xAF00_0000, 0x100, 1)], Some(1 << 20)),
+            [Verdict::Ok]
+        );
+    }
+
+    #[test]
+    fn overlapping_segments_must_agree() {
+        assert_eq!(
+            verdicts(&[(0x8000_0000, 0x100, 1), (0x8000_0080, 0x100, 1)], None),
+            [Verdict::Ok, Verdict::Ok]
+        );
+        // Through the other alias of the same flash
+        assert_eq!(
+            verdicts(
+                &[
+                    (0x8000_0000, 0x100, 1),
+                    (0xA000_00FF, 0x100, 2),
+                    (0x8000_0100, 0x100, 2),
+                ],
+                None
+            ),
+            [Verdict::Overlap, Verdict::Overlap, Verdict::Ok]
+        );
     }
 }
@@ -0,0 +1,255 @@
+//! Typed flash failures, naming where programming failed and how far it got.
+//!
+//! AurixFlasher erases and programs a whole image in one run and reports no more than its
+//! exit status, so a programming failure is located by the bank and address range of the
+//! image handed to it. The sector is named where the failure concerns a single address,
+//! as for a verify mismatch. Callers convert to anyhow at the boundary and find the
+//! error again with `downcast_ref`.
+
+use std::fmt;
+
+use super::memory_map::{find_region, to_cached_alias, MemoryRegion};
+
+/// Step of the flash sequence which failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashPhase {
+    /// Handing the image to AurixFlasher
+    Start,
+    /// Erasing and programming by AurixFlasher
+    Program,
+}
+
+impl FlashPhase {
+    pub fn name(self) -> &'static str {
+        match self {
+            FlashPhase::Start => "start",
+            FlashPhase::Program => "program",
+        }
+    }
+}
+
+/// Bank, sector and address range `[start, end)` a failure concerns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashLocation {
+    /// Bank name, `image` for an image spanning more than flash
+    pub bank: &'static str,
+    /// Sector index within the bank, where the range lies in a single sector
+    pub sector: Option<u32>,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl FlashLocation {
+    /// Location of `[start, end)` among `regions`, named after the region holding `start`
+    pub fn of(regions: &'static [MemoryRegion], start: u32, end: u32) -> FlashLocation {
+        let region = find_region(regions, start).filter(|region| region.is_flash());
+        let sector = region.and_then(|region| {
+            let size = region.sector_size()?;
+            let sector = |addr| (to_cached_alias(addr) - region.start) / size;
+            let first = sector(start);
+            (first == sector(end.saturating_sub(1).max(start))).then_some(first)
+        });
+        FlashLocation {
+            bank: region.map_or("image", |region| region.name),
+            sector,
+            start,
+            end,
+        }
+    }
+}
+
+impl fmt::Display for FlashLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.bank)?;
+        if let Some(sector) = self.sector {
+            write!(f, " sector {}", sector)?;
+        }
+        write!(f, " [{:#010x}, {:#010x})", self.start, self.end)
+    }
+}
+
+/// Image bytes programmed successfully of the whole image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashProgress {
+    pub completed: u64,
+    pub total: u64,
+}
+
+impl fmt::Display for FlashProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} bytes programmed", self.completed, self.total)
+    }
+}
+
+/// Where and how far, as carried by the flash events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashFailure {
+    pub phase: FlashPhase,
+    pub location: FlashLocation,
+    pub progress: FlashProgress,
+}
+
+impl fmt::Display for FlashFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed in {}, {}",
+            self.phase.name(),
+            self.location,
+            self.progress
+        )
+    }
+}
+
+/// Failure of flash programming
+#[derive(Debug)]
+pub enum FlashError {
+    /// AurixFlasher could not be started for the image
+    Start {
+        location: FlashLocation,
+        progress: FlashProgress,
+        cause: String,
+    },
+    /// AurixFlasher failed on the image
+    Program {
+        location: FlashLocation,
+        progress: FlashProgress,
+        cause: String,
+    },
+}
+
+impl FlashError {
+    pub fn failure(&self) -> FlashFailure {
+        let (phase, location, progress) = match self {
+            FlashError::Start {
+                location, progress, ..
+            } => (FlashPhase::Start, location, progress),
+            FlashError::Program {
+                location, progress, ..
+            } => (FlashPhase::Program, location, progress),
+        };
+        FlashFailure {
+            phase,
+            location: *location,
+            progress: *progress,
+        }
+    }
+}
+
+impl fmt::Display for FlashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlashError::Start { cause, .. } | FlashError::Program { cause, .. } => {
+                write!(f, "{}: {}", self.failure(), cause)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlashError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gdb::memory_map::TC3XX_REGIONS;
+
+    fn location(start: u32, end: u32) -> FlashLocation {
+        FlashLocation::of(TC3XX_REGIONS, start, end)
+    }
+
+    #[test]
+    fn range_within_a_sector_names_it() {
+        let within = location(0x8000_C000, 0x8000_C100);
+        assert_eq!((within.bank, within.sector), ("PF0", Some(3)));
+        assert_eq!(within.to_string(), "PF0 sector 3 [0x8000c000, 0x8000c100)");
+        // The end is exclusive
+        assert_eq!(location(0x8000_4000, 0x8000_8000).sector, Some(1));
+        assert_eq!(location(0xAF00_2000, 0xAF00_2010).sector, Some(2));
+        assert_eq!(location(0xAF40_0200, 0xAF40_0204).sector, Some(1));
+    }
+
+    #[test]
+    fn uncached_alias_names_the_same_sector() {
+        let uncached = location(0xA000_C000, 0xA000_C100);
+        assert_eq!((uncached.bank, uncached.sector), ("PF0", Some(3)));
+        assert_eq!(uncached.start, 0xA000_C000);
+    }
+
+    #[test]
+    fn range_across_sectors_names_the_bank() {
+        let across = location(0x8030_0000, 0x8031_0000);
+        assert_eq!((across.bank, across.sector), ("PF1", None));
+        assert_eq!(across.to_string(), "PF1 [0x80300000, 0x80310000)");
+    }
+
+    #[test]
+    fn range_outside_flash_is_the_image() {
+        let ram = location(0x7000_0000, 0x7000_1000);
+        assert_eq!((ram.bank, ram.sector), ("image", None));
+        assert_eq!(location(0x2000_0000, 0x2000_0010).bank, "image");
+    }
+
+    #[test]
+    fn every_variant_renders_its_location() {
+        let location = location(0xAF00_1000, 0xAF00_1100);
+        let progress = FlashProgress {
+            completed: 4096,
+            total: 8192,
+        };
+        let errors = [
+            (
+                FlashPhase::Start,
+                FlashError::Start {
+                    location,
+                    progress,
+                    cause: "AurixFlasher not found".to_owned(),
+                },
+            ),
+            (
+                FlashPhase::Program,
+                FlashError::Program {
+                    location,
+                    progress,
+                    cause: "exit status 1".to_owned(),
+                },
+            ),
+        ];
+        for (phase, error) in errors {
+            assert_eq!(
+                error.failure(),
+                FlashFailure {
+                    phase,
+                    location,
+                    progress
+                }
+            );
+            let message = error.to_string();
+            for field in [
+                phase.name(),
+                "DF0",
+                "sector 1",
+                "[0xaf001000, 0xaf001100)",
+                "4096 of 8192 bytes programmed",
+            ] {
+                assert!(message.contains(field), "{:?} lacks {:?}", message, field);
+            }
+        }
+    }
+
+    #[test]
+    fn message_reads_well() {
+        let error = FlashError::Program {
+            location: location(0xAF00_1000, 0xAF00_1100),
+            progress: FlashProgress {
+                completed: 4096,
+                total: 8192,
+            },
+            cause: "exit status 1".to_owned(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "program failed in DF0 sector 1 [0xaf001000, 0xaf001100), 4096 of 8192 bytes \
+             programmed: exit status 1"
+        );
+    }
+}
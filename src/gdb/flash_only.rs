@@ -0,0 +1,151 @@
+//! `--flash-only`: programs and verifies the device without serving a debug session,
+//! for provisioning where a GDB server must not be started.
+
+use std::fmt;
+use std::thread::sleep;
+use std::time::Duration;
+
+use log::{info, warn};
+use rust_mcd::reset::ResetClass;
+
+use super::elf_set::{ElfSet, ElfSpec};
+use super::events::{EventBroadcaster, TargetEvent};
+use super::flash::{FlashOptions, ProjectChange};
+use super::flash_error::FlashLocation;
+use super::{connect_device, flash_device, CpuId, DeviceChoice};
+
+/// Failure of a flash-only run, each with its own process exit code
+#[derive(Debug)]
+pub enum FlashOnlyError {
+    /// No device found or the connection failed
+    Connect(anyhow::Error),
+    /// The device holds another project, nothing was programmed
+    Refused(anyhow::Error),
+    /// The pre-flight check or programming failed
+    Program(anyhow::Error),
+    /// The content read back differs from the elf or cannot be read
+    Verify(String),
+    /// The device was programmed but could not be started
+    Run(anyhow::Error),
+}
+
+impl FlashOnlyError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FlashOnlyError::Connect(_) => 20,
+            FlashOnlyError::Program(_) => 21,
+            FlashOnlyError::Verify(_) => 22,
+            FlashOnlyError::Refused(_) => 23,
+            FlashOnlyError::Run(_) => 24,
+        }
+    }
+}
+
+impl fmt::Display for FlashOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlashOnlyError::Connect(e) => write!(f, "Cannot connect to the device: {:#}", e),
+            FlashOnlyError::Refused(e) => write!(f, "Flash refused: {:#}", e),
+            FlashOnlyError::Program(e) => write!(f, "Programming failed: {:#}", e),
+            FlashOnlyError::Verify(e) => write!(f, "Verify failed: {}", e),
+            FlashOnlyError::Run(e) => write!(f, "Cannot start the device: {:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for FlashOnlyError {}
+
+/// Connects, programs and verifies the elfs, then optionally resets and runs the device
+pub fn flash_only(
+    device: &DeviceChoice,
+    program_elfs: &[ElfSpec],
+    options: &FlashOptions,
+    run_after_load: bool,
+    events: Option<&EventBroadcaster>,
+) -> Result<(), FlashOnlyError> {
+    let publish = |event: TargetEvent| {
+        if let Some(events) = events {
+            events.publish(event)
+        }
+    };
+
+    let elfs = ElfSet::load(program_elfs).map_err(FlashOnlyError::Program)?;
+    let elfs = elfs.distinct();
+    let paths: Vec<_> = elfs.iter().map(|elf| &elf.path).collect();
+    let mut device = connect_device(device).map_err(FlashOnlyError::Connect)?;
+    if let Some(events) = events {
+        events.set_probe(device.probe.serial.clone());
+    }
+    flash_device(&mut device, &elfs, options, &publish).map_err(|e| {
+        if e.downcast_ref::<ProjectChange>().is_some() {
+            FlashOnlyError::Refused(e)
+        } else {
+            FlashOnlyError::Program(e)
+        }
+    })?;
+
+    // Same settle time as before a debug session claims the cores
+    sleep(Duration::from_secs(2));
+
+    let verified: anyhow::Result<Vec<u32>> = elfs.iter().try_fold(Vec::new(), |mut all, elf| {
+        all.extend(
+            device
+                .command_server
+                .verify_elf(elf, device.arch, options)?,
+        );
+        Ok(all)
+    });
+    publish(TargetEvent::VerifyFinished {
+        success: verified
+            .as_ref()
+            .is_ok_and(|mismatches| mismatches.is_empty()),
+    });
+    match verified {
+        Ok(mismatches) if mismatches.is_empty() => info!("Verified {:?}", paths),
+        Ok(mismatches) => {
+            let addrs: Vec<String> = mismatches
+                .iter()
+                .map(|&addr| {
+                    let location = FlashLocation::of(device.arch.regions(), addr, addr + 1);
+                    match location.sector {
+                        Some(sector) => {
+                            format!("{:#010x} ({} sector {})", addr, location.bank, sector)
+                        }
+                        None => format!("{:#010x}", addr),
+                    }
+                })
+                .collect();
+            return Err(FlashOnlyError::Verify(format!(
+                "content differs at {}",
+                addrs.join(", ")
+            )));
+        }
+        Err(e) => return Err(FlashOnlyError::Verify(format!("{:#}", e))),
+    }
+
+    if run_after_load {
+        let system = device
+            .command_server
+            .get_system()
+            .map_err(FlashOnlyError::Run)?;
+        publish(TargetEvent::Reset);
+        for index in 0..system.core_count() {
+            let started = system.get_core(index).and_then(|mut core| {
+                let system_reset = ResetClass::construct_reset_class(&core, 0);
+                core.reset(system_reset, true)?;
+                core.run()?;
+                Ok(())
+            });
+            match started {
+                Ok(()) => {
+                    let cpu = CpuId::try_from(index).expect("Unexpected core index");
+                    publish(TargetEvent::Resumed { cpu });
+                }
+                Err(e) => warn!("Cannot run core {}: {}", index, e),
+            }
+        }
+        info!("Target running");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! FPU state decoding for `monitor fpu`.
+//!
+//! The TriCore FPU computes in the data registers, its state lives in PSW: the rounding
+//! mode in PSW.RM and the accumulated exception flags in the upper PSW bits, which double
+//! as the integer status flags. Which exceptions trap is configured in FPU_TRAP_CON.
+
+use gdbstub::outputln;
+use rust_mcd::core::CoreState;
+
+use super::{parse_u32, read_register, write_register, MonitorOutput, TricoreTarget};
+
+/// PSW.RM, the rounding mode in bits 25..24
+const PSW_RM_SHIFT: u32 = 24;
+const PSW_RM_MASK: u32 = 0b11 << PSW_RM_SHIFT;
+
+const ROUNDING_MODES: [&str; 4] = ["nearest", "+inf", "-inf", "zero"];
+
+/// Accumulated exception flags in PSW
+const PSW_FLAGS: &[(u32, &str)] = &[
+    (31, "FS (some exception)"),
+    (30, "FI (invalid operation)"),
+    (29, "FV (overflow)"),
+    (28, "FZ (divide by zero)"),
+    (27, "FU (underflow)"),
+    (26, "FX (inexact)"),
+];
+
+/// Trap enables in FPU_TRAP_CON
+const TRAP_ENABLES: &[(u32, &str)] = &[
+    (22, "FIE (invalid operation)"),
+    (21, "FVE (overflow)"),
+    (20, "FZE (divide by zero)"),
+    (19, "FUE (underflow)"),
+    (18, "FXE (inexact)"),
+];
+
+fn set_bits(value: u32, bits: &[(u32, &'static str)]) -> Vec<&'static str> {
+    bits.iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|&(_, name)| name)
+        .collect()
+}
+
+fn list_or_none(names: Vec<&str>) -> String {
+    if names.is_empty() {
+        "none".to_owned()
+    } else {
+        names.join(", ")
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Index of an FPU core given on the command line, core 0 by default
+    fn fpu_core(&self, core: Option<&str>, out: &mut MonitorOutput<'_>) -> Option<usize> {
+        let index = match core.map(parse_u32) {
+            None => 0,
+            Some(Some(index)) => index as usize,
+            Some(None) => {
+                outputln!(out, "usage: fpu [core] | fpu rm <core> <mode>");
+                return None;
+            }
+        };
+        if self.core(index).is_none() {
+            outputln!(out, "No core {}", index);
+            return None;
+        }
+        if !self
+            .slot_at(index)
+            .is_some_and(|slot| slot.capabilities.fpu)
+        {
+            outputln!(out, "Core {} has no FPU", index);
+            return None;
+        }
+        Some(index)
+    }
+
+    pub(crate) fn fpu_info(&mut self, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let Some(index) = self.fpu_core(core, out) else {
+            return;
+        };
+        let core = self.core(index).expect("Checked by fpu_core");
+        let (Some(psw), Some(trap_con)) = (
+            read_register(core, "PSW"),
+            read_register(core, "FPU_TRAP_CON"),
+        ) else {
+            outputln!(out, "Cannot read the FPU state of core {}", index);
+            return;
+        };
+
+        let rm = (psw & PSW_RM_MASK) >> PSW_RM_SHIFT;
+        outputln!(
+            out,
+            "{:<20} {}",
+            "rounding mode",
+            ROUNDING_MODES[rm as usize]
+        );
+        outputln!(
+            out,
+            "{:<20} {}",
+            "exception flags",
+            list_or_none(set_bits(psw, PSW_FLAGS))
+        );
+        outputln!(
+            out,
+            "{:<20} {}",
+            "trapping",
+            list_or_none(set_bits(trap_con, TRAP_ENABLES))
+        );
+    }
+
+    /// Changes PSW.RM of a halted core
+    pub(crate) fn fpu_set_rounding(&mut self, core: &str, mode: &str, out: &mut MonitorOutput<'_>) {
+        let Some(index) = self.fpu_core(Some(core), out) else {
+            return;
+        };
+        let Some(rm) = ROUNDING_MODES.iter().position(|&name| name == mode) else {
+            outputln!(out, "Rounding mode is one of {}", ROUNDING_MODES.join(", "));
+            return;
+        };
+
+        let core = self.core(index).expect("Checked by fpu_core");
+        if !matches!(
+            core.query_state().map(|info| info.state),
+            Ok(CoreState::Debug)
+        ) {
+            outputln!(out, "Core {} must be halted", index);
+            return;
+        }
+        let written = read_register(core, "PSW").and_then(|psw| {
+            let psw = (psw & !PSW_RM_MASK) | ((rm as u32) << PSW_RM_SHIFT);
+            write_register(core, "PSW", psw)
+        });
+        self.invalidate_registers(index);
+        match written {
+            Some(()) => outputln!(out, "Rounding mode of core {} is {}", index, mode),
+            None => outputln!(out, "Cannot write PSW of core {}", index),
+        }
+    }
+}
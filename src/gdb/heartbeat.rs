@@ -0,0 +1,102 @@
+//! Liveness check of the device while GDB sits at a halted target, so a power loss or a
+//! dropped probe is reported when it happens rather than with the next request.
+//!
+//! Every `heartbeat` seconds without a flash operation holding the gate and with all cores
+//! halted, the CORE_ID register of the first core is read and compared with the value read
+//! first. A failed or differing read is retried once right away. A second failure marks
+//! the device as lost: a warning on the console, `device_lost` to event subscribers and
+//! the device reported disconnected by the metrics. Once it answers again the register
+//! cache is dropped, as the device likely went through a power cycle, and the recovery is
+//! reported the same way. The stub is single threaded, the check never interleaves with
+//! another MCD transaction.
+
+use std::time::Instant;
+
+use super::events::TargetEvent;
+use super::memory_map::csfr_address;
+use super::TricoreTarget;
+
+/// Offset of CORE_ID in the CSFR space of a core
+const CORE_ID: u32 = 0xFE1C;
+
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    last: Instant,
+    /// CORE_ID read by the first check
+    expected: Option<u32>,
+    lost: bool,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            last: Instant::now(),
+            expected: None,
+            lost: false,
+        }
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Checks the device is still there if the `heartbeat` interval passed. Called while
+    /// waiting for GDB with the target halted.
+    pub fn heartbeat(&mut self) {
+        let interval = self.settings.heartbeat;
+        if interval.is_zero() || self.heartbeat.last.elapsed() < interval || self.busy.is_some() {
+            return;
+        }
+        self.heartbeat.last = Instant::now();
+        let Some(&index) = self.all_cores().first() else {
+            return;
+        };
+        if !self.running_cores().is_empty() {
+            return;
+        }
+
+        let alive = self.beat(index) || self.beat(index);
+        if alive == !self.heartbeat.lost {
+            return;
+        }
+        self.heartbeat.lost = !alive;
+        if alive {
+            self.clear_register_cache();
+            self.code_cache.invalidate();
+            self.console(
+                None,
+                format_args!("The device answers again, it may have been power cycled"),
+            );
+            if let Some(metrics) = &self.metrics {
+                metrics.record_device_connected(format!(
+                    "{} {}",
+                    self.das_capabilities.server, self.arch
+                ));
+            }
+            self.publish(TargetEvent::DeviceRecovered);
+        } else {
+            self.console(
+                None,
+                format_args!(
+                    "!!! The device stopped answering, check its power supply and the probe \
+                     connection !!!"
+                ),
+            );
+            if let Some(metrics) = &self.metrics {
+                metrics.set_device_lost();
+            }
+            self.publish(TargetEvent::DeviceLost);
+        }
+    }
+
+    /// A single read of CORE_ID of core `index`, false if it fails or changed
+    fn beat(&mut self, index: usize) -> bool {
+        self.stats.heartbeats += 1;
+        let alive = match self.read_sized(csfr_address(index, CORE_ID), 4) {
+            Ok(core_id) => *self.heartbeat.expected.get_or_insert(core_id) == core_id,
+            Err(_) => false,
+        };
+        if !alive {
+            self.stats.heartbeat_failures += 1;
+        }
+        alive
+    }
+}
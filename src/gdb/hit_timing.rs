@@ -0,0 +1,195 @@
+//! Host side timing of breakpoint and range watch hits, e.g. to check that a 10 ms task
+//! really runs every 10 ms.
+//!
+//! A hit is timestamped when the run loop notices the stop. Intervals between reported
+//! stops include the time the target spent halted in GDB, only hits resumed silently,
+//! e.g. through `bp ignore`, give the period of the code itself within the poll latency.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use gdbstub::outputln;
+
+use super::{parse_u32, MonitorOutput, TricoreTarget};
+
+/// Hits kept per breakpoint or watch
+const HIT_HISTORY: usize = 32;
+
+/// Printed next to every timing
+pub(crate) const CAVEAT: &str =
+    "Host timestamps: intervals include the poll latency and the time spent halted";
+
+/// Hits of a single breakpoint or watch
+#[derive(Debug, Default, Clone)]
+pub(crate) struct HitTiming {
+    /// Hits since the breakpoint was set or the timing reset
+    count: u64,
+    /// Last [HIT_HISTORY] hits, oldest first
+    hits: VecDeque<Instant>,
+}
+
+/// Shortest, mean and longest interval of the kept hits
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IntervalSummary {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+}
+
+impl HitTiming {
+    pub fn record(&mut self, at: Instant) {
+        self.count += 1;
+        if self.hits.len() == HIT_HISTORY {
+            self.hits.pop_front();
+        }
+        self.hits.push_back(at);
+    }
+
+    pub fn reset(&mut self) {
+        *self = HitTiming::default();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Intervals between consecutive kept hits, oldest first
+    fn intervals(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.hits
+            .iter()
+            .zip(self.hits.iter().skip(1))
+            .map(|(earlier, later)| later.duration_since(*earlier))
+    }
+
+    pub fn last_interval(&self) -> Option<Duration> {
+        self.intervals().last()
+    }
+
+    pub fn summary(&self) -> Option<IntervalSummary> {
+        let intervals: Vec<Duration> = self.intervals().collect();
+        if intervals.is_empty() {
+            return None;
+        }
+        let total: Duration = intervals.iter().sum();
+        Some(IntervalSummary {
+            min: *intervals.iter().min()?,
+            avg: total / intervals.len() as u32,
+            max: *intervals.iter().max()?,
+        })
+    }
+
+    /// Columns of `monitor breakpoints` and `wp list`, empty before the first hit
+    pub fn columns(&self) -> String {
+        if self.count == 0 {
+            return String::new();
+        }
+        let mut columns = format!(" hits: {}", self.count);
+        if let (Some(last), Some(summary)) = (self.last_interval(), self.summary()) {
+            columns += &format!(
+                " last: {} min/avg/max: {}/{}/{}",
+                format_ms(last),
+                format_ms(summary.min),
+                format_ms(summary.avg),
+                format_ms(summary.max)
+            );
+        }
+        columns
+    }
+
+    /// Prints the kept hits, each with its age and the interval to the hit before
+    fn print_history(&self, out: &mut MonitorOutput<'_>) {
+        if self.hits.is_empty() {
+            outputln!(out, "No hits recorded");
+            return;
+        }
+        let now = Instant::now();
+        let first = self.count - self.hits.len() as u64 + 1;
+        let mut previous: Option<Instant> = None;
+        for (number, &hit) in (first..).zip(&self.hits) {
+            let interval = previous.map_or("-".to_owned(), |previous| {
+                format_ms(hit.duration_since(previous))
+            });
+            outputln!(
+                out,
+                "{:>6} {:>14} ago  interval {}",
+                number,
+                format_ms(now.duration_since(hit)),
+                interval
+            );
+            previous = Some(hit);
+        }
+        if let Some(summary) = self.summary() {
+            outputln!(
+                out,
+                "min/avg/max: {}/{}/{}",
+                format_ms(summary.min),
+                format_ms(summary.avg),
+                format_ms(summary.max)
+            );
+        }
+    }
+}
+
+fn format_ms(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Adds the caveat to the breakpoint listing once a breakpoint was hit
+    pub(crate) fn print_timing_caveat(&self, out: &mut MonitorOutput<'_>) {
+        if self
+            .breakpoints
+            .values()
+            .any(|breakpoint| breakpoint.timing.count() > 0)
+        {
+            outputln!(out, "{}", CAVEAT);
+        }
+    }
+
+    /// `bp timing reset [addr]`
+    pub(crate) fn timing_reset(&mut self, addr: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let Some(addr) = addr else {
+            for breakpoint in self.breakpoints.values_mut() {
+                breakpoint.timing.reset();
+            }
+            self.reset_range_watch_timing();
+            outputln!(out, "Hit timing of every breakpoint and watch reset");
+            return;
+        };
+        let Some(addr) = parse_u32(addr) else {
+            outputln!(out, "usage: bp timing reset [addr]");
+            return;
+        };
+        let addr = self.breakpoint_address(addr);
+        let key = self.breakpoint_key(addr);
+        match self.breakpoints.get_mut(&key) {
+            Some(breakpoint) => {
+                breakpoint.timing.reset();
+                outputln!(out, "Hit timing of {:#010x} reset", addr);
+            }
+            None => outputln!(out, "No breakpoint at {:#010x}", addr),
+        }
+    }
+
+    /// `bp timing <addr>`
+    pub(crate) fn timing_show(&mut self, addr: &str, out: &mut MonitorOutput<'_>) {
+        let Some(addr) = parse_u32(addr) else {
+            outputln!(out, "usage: bp timing <addr>");
+            return;
+        };
+        let addr = self.breakpoint_address(addr);
+        let Some(breakpoint) = self.breakpoints.get(&self.breakpoint_key(addr)) else {
+            outputln!(out, "No breakpoint at {:#010x}", addr);
+            return;
+        };
+        outputln!(
+            out,
+            "{:#010x}: {} hits, the last {} kept",
+            addr,
+            breakpoint.timing.count(),
+            breakpoint.timing.hits.len()
+        );
+        breakpoint.timing.print_history(out);
+        outputln!(out, "{}", CAVEAT);
+    }
+}
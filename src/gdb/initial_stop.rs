@@ -0,0 +1,89 @@
+//! The stop GDB is told of when it attaches, before it ever resumed the target.
+//!
+//! gdbstub answers the first `?` on its own with `T05` for the first thread the target
+//! lists and expedites no registers, GDB reads them with `g` right after. So the core and
+//! cause of the startup stop are recorded here and the core is listed first until GDB
+//! resumes the target for the first time: after a reset or `--halt-on-connect` the first
+//! core halted, after `--break-at` the boot core. SIGTRAP is what GDB expects for all
+//! three; the stop is logged with its cause and PC when the session starts, so a GDB view
+//! which disagrees can be checked against it.
+
+use std::fmt;
+
+use log::info;
+
+use super::{read_register, TricoreTarget};
+
+/// How the cores came to be halted before GDB attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StartupCause {
+    /// Held at the reset vector after the device was reset
+    Reset,
+    /// Halted where it was by `--halt-on-connect`
+    HaltOnConnect,
+    /// Reached the `--break-at` location at the address
+    BreakAt(u32),
+    /// Halted after `--break-at` stopped, timed out or was aborted elsewhere
+    BreakAtMissed,
+}
+
+impl fmt::Display for StartupCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartupCause::Reset => f.write_str("reset"),
+            StartupCause::HaltOnConnect => f.write_str("halted on connect"),
+            StartupCause::BreakAt(addr) => write!(f, "--break-at {:#010x} reached", addr),
+            StartupCause::BreakAtMissed => f.write_str("--break-at not reached"),
+        }
+    }
+}
+
+/// Core GDB is told stopped first, by physical index, and why
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InitialStop {
+    pub core: usize,
+    pub cause: StartupCause,
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Records the startup stop of core `index`, replacing an earlier one
+    pub(crate) fn set_initial_stop(&mut self, index: usize, cause: StartupCause) {
+        self.initial_stop = Some(InitialStop { core: index, cause });
+    }
+
+    /// Records the startup stop for the first core listed to GDB
+    pub(crate) fn set_initial_stop_first(&mut self, cause: StartupCause) {
+        if let Some(&index) = self.all_cores().first() {
+            self.set_initial_stop(index, cause);
+        }
+    }
+
+    /// The cores in the order they are listed to GDB, the core of the startup stop first
+    pub(crate) fn thread_order(&self) -> Vec<usize> {
+        let mut cores = self.all_cores();
+        if let Some(stop) = self.initial_stop {
+            if let Some(position) = cores.iter().position(|&index| index == stop.core) {
+                cores[..=position].rotate_right(1);
+            }
+        }
+        cores
+    }
+
+    /// Logs the stop GDB is about to be told of, called as a session starts
+    pub fn log_initial_stop(&self) {
+        if self.left_running {
+            info!("The target keeps running, continue in GDB and interrupt it to halt it");
+        }
+        let Some(stop) = self.initial_stop else {
+            return;
+        };
+        let pc = self
+            .core(stop.core)
+            .and_then(|core| read_register(core, "PC"))
+            .map_or("an unknown PC".to_owned(), |pc| format!("{:#010x}", pc));
+        info!(
+            "Reporting CPU{} stopped to GDB ({}) at {}",
+            stop.core, stop.cause, pc
+        );
+    }
+}
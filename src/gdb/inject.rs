@@ -0,0 +1,146 @@
+//! Runs a short code snippet on a halted core, for operations only instructions can
+//! perform, e.g. cache maintenance.
+//!
+//! The snippet is placed at the end of the core's program scratchpad with interrupts
+//! masked and stops on a breakpoint at its end. Everything it changes is put back
+//! afterwards: the scratchpad bytes, PC, ICR and the registers it was started with.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use rust_mcd::core::CoreState;
+
+use super::trigger_budget::TriggerOwner;
+use super::{read_register, write_register, TricoreTarget, ICR_IE};
+
+/// Longest a snippet may run before the core is halted
+pub(crate) const SNIPPET_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl<'a> TricoreTarget<'a> {
+    /// Address the snippet is placed at, the end of the core's program scratchpad
+    fn snippet_address(&self, index: usize, len: usize) -> Option<u32> {
+        let name = format!("PSPR{}", index);
+        let region = self
+            .arch
+            .regions()
+            .iter()
+            .find(|region| region.name == name)?;
+        let len = (len as u32 + 7) & !7;
+        (len <= region.len).then(|| region.start + region.len - len)
+    }
+
+    /// Runs `code` on the halted core `index` until it reaches `end`, an offset into
+    /// `code`, with `registers` set beforehand. Fails without running anything if the
+    /// state cannot be saved, and halts the core after `timeout`.
+    pub(crate) fn run_snippet(
+        &mut self,
+        index: usize,
+        code: &[u8],
+        end: u32,
+        registers: &[(&'static str, u32)],
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let core = self
+            .core(index)
+            .ok_or_else(|| format!("No core {}", index))?;
+        if !matches!(
+            core.query_state().map(|info| info.state),
+            Ok(CoreState::Debug)
+        ) {
+            return Err(format!("Core {} must be halted", index));
+        }
+        let addr = self
+            .snippet_address(index, code.len())
+            .ok_or_else(|| format!("No program scratchpad known for core {}", index))?;
+
+        let core = self.core(index).expect("Checked above");
+        let saved_code = core
+            .read_bytes(addr as u64, code.len())
+            .map_err(|_| format!("Cannot save the scratchpad at {:#010x}", addr))?;
+        let mut saved = Vec::new();
+        for name in ["PC", "ICR"]
+            .into_iter()
+            .chain(registers.iter().map(|&(name, _)| name))
+        {
+            let value = read_register(core, name)
+                .ok_or_else(|| format!("Cannot save {} of core {}", name, index))?;
+            saved.push((name, value));
+        }
+        let mut setup = registers.to_vec();
+        setup.push(("ICR", saved[1].1 & !ICR_IE));
+        setup.push(("PC", addr));
+
+        self.invalidate_registers(index);
+        let result = self.start_snippet(index, addr, code, end, &setup, timeout);
+
+        let core = self.core(index).expect("Checked above");
+        let mut restored = core.write(addr as u64, saved_code).is_ok();
+        for &(name, value) in saved.iter().rev() {
+            restored &= write_register(core, name, value).is_some();
+        }
+        self.invalidate_registers(index);
+        if !restored {
+            warn!(
+                "Core {} was not completely restored after running a snippet",
+                index
+            );
+        }
+        result
+    }
+
+    /// Places and runs the snippet, `setup` holds the register values to start it with
+    fn start_snippet(
+        &mut self,
+        index: usize,
+        addr: u32,
+        code: &[u8],
+        end: u32,
+        setup: &[(&'static str, u32)],
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let core = self.core(index).expect("Checked by run_snippet");
+        core.write(addr as u64, code.to_vec())
+            .map_err(|_| format!("Cannot place the snippet at {:#010x}", addr))?;
+        let prepared = setup
+            .iter()
+            .try_for_each(|&(name, value)| write_register(core, name, value));
+        if prepared.is_none() {
+            return Err(format!("Cannot prepare the registers of core {}", index));
+        }
+
+        let end_addr = addr + end;
+        let trigger = self
+            .create_core_trigger(index, end_addr, TriggerOwner::Snippet)
+            .ok_or_else(|| format!("No breakpoint available on core {}", index))?;
+        self.flush_triggers();
+        debug!("Running snippet at {:#010x} on core {}", addr, index);
+        let started = Instant::now();
+        let core = self.core_mut(index).expect("Checked by run_snippet");
+        let mut completed = core.run().is_ok();
+        while completed
+            && !matches!(
+                core.query_state().map(|info| info.state),
+                Ok(CoreState::Debug)
+            )
+        {
+            if started.elapsed() > timeout {
+                _ = core.stop();
+                completed = false;
+                break;
+            }
+            sleep(Duration::from_millis(1));
+        }
+        self.remove_core_trigger(index, trigger, TriggerOwner::Snippet);
+
+        let core = self.core(index).expect("Checked by run_snippet");
+        if completed && read_register(core, "PC") == Some(end_addr) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Snippet on core {} did not complete within {:?}, the core was halted",
+                index, timeout
+            ))
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Detection of a marginal debug interface, e.g. once the firmware switched its PLL and
+//! the probe clock no longer suits the device.
+//!
+//! Failed memory transactions are counted in a sliding window. A burst of them is
+//! reported once per run of the target, with a hint on the probe clock when it follows a
+//! run, during which the firmware may have changed its clocks. rust_mcd offers no control
+//! of the debug interface frequency, so the stub cannot downshift on its own: the user is
+//! told to lower it in the DAS server configuration.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use gdbstub::outputln;
+use log::warn;
+
+use super::{MonitorOutput, TricoreTarget};
+
+/// Span errors are counted over
+const ERROR_WINDOW: Duration = Duration::from_secs(2);
+/// Errors within [ERROR_WINDOW] which count as a burst
+const ERROR_BURST: usize = 8;
+/// Time after the target was resumed in which a burst is blamed on a clock change
+const CLOCK_CHANGE_WINDOW: Duration = Duration::from_secs(30);
+
+/// Failed MCD transactions within a sliding window
+#[derive(Debug)]
+pub(crate) struct ErrorWindow {
+    errors: VecDeque<Instant>,
+    window: Duration,
+    burst: usize,
+}
+
+impl ErrorWindow {
+    pub fn new(window: Duration, burst: usize) -> Self {
+        ErrorWindow {
+            errors: VecDeque::with_capacity(burst),
+            window,
+            burst,
+        }
+    }
+
+    /// Counts an error, returns whether the window now holds a burst
+    pub fn record(&mut self, at: Instant) -> bool {
+        while self
+            .errors
+            .front()
+            .is_some_and(|&error| at.duration_since(error) > self.window)
+        {
+            self.errors.pop_front();
+        }
+        if self.errors.len() == self.burst {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(at);
+        self.errors.len() >= self.burst
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+}
+
+/// Error bursts of the debug interface
+#[derive(Debug)]
+pub(crate) struct LinkHealth {
+    window: ErrorWindow,
+    /// When the target was last resumed
+    last_run: Option<Instant>,
+    /// A burst was reported since the target last ran
+    reported: bool,
+    /// Bursts detected in the session
+    pub bursts: u64,
+    /// Failed transactions in the session
+    pub errors: u64,
+}
+
+impl Default for LinkHealth {
+    fn default() -> Self {
+        LinkHealth {
+            window: ErrorWindow::new(ERROR_WINDOW, ERROR_BURST),
+            last_run: None,
+            reported: false,
+            bursts: 0,
+            errors: 0,
+        }
+    }
+}
+
+impl LinkHealth {
+    pub fn note_run(&mut self) {
+        self.last_run = Some(Instant::now());
+        self.reported = false;
+    }
+
+    /// Counts a failed transaction and warns once per run on a burst
+    pub fn record_error(&mut self) {
+        let now = Instant::now();
+        self.errors += 1;
+        if !self.window.record(now) || self.reported {
+            return;
+        }
+        self.reported = true;
+        self.bursts += 1;
+        let after_run = self
+            .last_run
+            .is_some_and(|run| now.duration_since(run) < CLOCK_CHANGE_WINDOW);
+        warn!(
+            "!!! {} MCD transactions failed within {} s{}. Lower the JTAG/DAP frequency \
+             in the DAS server configuration and reconnect, the stub cannot change it !!!",
+            self.window.len(),
+            ERROR_WINDOW.as_secs(),
+            if after_run {
+                " right after the target ran, the firmware likely changed its clocks"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// `dasclock show`
+    pub(crate) fn das_clock_show(&self, out: &mut MonitorOutput<'_>) {
+        outputln!(
+            out,
+            "The debug interface frequency is set by the DAS server, rust_mcd can't read it"
+        );
+        outputln!(
+            out,
+            "{} failed transactions, {} bursts",
+            self.link_health.errors,
+            self.link_health.bursts
+        );
+    }
+
+    /// `dasclock set <khz>`
+    pub(crate) fn das_clock_set(&self, khz: &str, out: &mut MonitorOutput<'_>) {
+        if khz.parse::<u32>().is_err() {
+            outputln!(out, "usage: dasclock set <khz>");
+            return;
+        }
+        outputln!(
+            out,
+            "rust_mcd can't change the debug interface frequency, set {} kHz in the DAS \
+             server configuration and reconnect",
+            khz
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn burst_is_detected_within_the_window() {
+        let start = Instant::now();
+        let mut window = ErrorWindow::new(2 * SECOND, 3);
+        assert!(!window.record(start));
+        assert!(!window.record(start + SECOND));
+        assert!(window.record(start + 2 * SECOND));
+        assert_eq!(window.len(), 3);
+    }
+
+    #[test]
+    fn errors_slide_out_of_the_window() {
+        let start = Instant::now();
+        let mut window = ErrorWindow::new(2 * SECOND, 3);
+        window.record(start);
+        window.record(start + SECOND);
+        // The first error is older than the window by now
+        assert!(!window.record(start + 3 * SECOND));
+        assert_eq!(window.len(), 2);
+        assert!(!window.record(start + 10 * SECOND));
+        assert_eq!(window.len(), 1);
+    }
+
+    #[test]
+    fn window_holds_at_most_a_burst() {
+        let start = Instant::now();
+        let mut window = ErrorWindow::new(2 * SECOND, 3);
+        for _ in 0..10 {
+            window.record(start);
+        }
+        assert_eq!(window.len(), 3);
+    }
+
+    #[test]
+    fn burst_is_reported_once_per_run() {
+        let mut health = LinkHealth::default();
+        for _ in 0..3 * ERROR_BURST {
+            health.record_error();
+        }
+        assert_eq!(health.bursts, 1);
+        assert_eq!(health.errors, 3 * ERROR_BURST as u64);
+        health.note_run();
+        health.record_error();
+        assert_eq!(health.bursts, 2);
+    }
+}
@@ -0,0 +1,25 @@
+//! Core a log record concerns, for loggers which show it alongside the message.
+//!
+//! Set while the stub handles a single core, e.g. a stop it reports or a console line
+//! about that core, and read back through [current_core] by the logger.
+
+use std::cell::Cell;
+
+use super::CpuId;
+
+thread_local! {
+    static CORE: Cell<Option<CpuId>> = const { Cell::new(None) };
+}
+
+/// Core the records logged by this thread currently concern
+pub fn current_core() -> Option<CpuId> {
+    CORE.with(Cell::get)
+}
+
+/// Runs `f` with `core` as the context of its records, restoring the previous one after
+pub(crate) fn with_core<T>(core: Option<CpuId>, f: impl FnOnce() -> T) -> T {
+    let previous = CORE.with(|context| context.replace(core));
+    let result = f();
+    CORE.with(|context| context.set(previous));
+    result
+}
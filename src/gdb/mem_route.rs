@@ -0,0 +1,178 @@
+//! Routing of memory accesses to the core they are issued through.
+//!
+//! MCD issues every memory access through a core. On some derivatives accesses to system
+//! memories, e.g. LMU or EMEM, fail sporadically through some halted cores but not
+//! through CPU0, while GDB asks through whichever thread it has selected. With
+//! `mem-route auto` the address decides instead: scratchpads and CSFRs go through the
+//! core owning them, everything else through the designated core `mem-route-core`.
+//! rust_mcd exposes no system level access port, so the designated core stands in for it.
+
+use gdbstub::common::Tid;
+use log::trace;
+
+use super::memory_map::{self, CoreLocalMap, MemoryRegion};
+use super::settings::MemRoute;
+use super::traits::TricoreTargetError;
+use super::TricoreTarget;
+
+/// What an address belongs to, as far as routing is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AddressClass {
+    /// Scratchpad or CSFR of the core at this index, by its global address
+    CoreLocal(usize),
+    /// LMU, DLMU, EMEM and other RAM shared by the cores
+    SystemRam,
+    Flash,
+    Peripheral,
+    /// Nothing known lives there
+    Unknown,
+}
+
+/// Path an access takes, counted by `monitor mcd-stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Route {
+    /// The core of the thread GDB asked for
+    Tid,
+    /// The core owning the address
+    Owner(usize),
+    /// The designated core of system accesses
+    Designated,
+}
+
+/// Classifies a global address, core local window addresses are translated beforehand
+pub(crate) fn classify(
+    regions: &'static [MemoryRegion],
+    local_map: Option<&CoreLocalMap>,
+    addr: u32,
+) -> AddressClass {
+    if let Some(index) = memory_map::csfr_cores(addr, 1).next() {
+        return AddressClass::CoreLocal(index);
+    }
+    let segment = addr >> 28;
+    if let Some(index) = local_map.and_then(|map| {
+        map.core_segments
+            .iter()
+            .position(|&core_segment| core_segment == segment)
+    }) {
+        return AddressClass::CoreLocal(index);
+    }
+    if let Some(region) = memory_map::find_region(regions, addr) {
+        return if region.is_flash() {
+            AddressClass::Flash
+        } else {
+            AddressClass::SystemRam
+        };
+    }
+    match segment {
+        // Cached and non-cached segments of the shared memories, EMEM included
+        0x9 | 0xB => AddressClass::SystemRam,
+        0xF => AddressClass::Peripheral,
+        _ => AddressClass::Unknown,
+    }
+}
+
+/// Route of an access to an address of `class` under `policy`
+pub(crate) fn decide(policy: MemRoute, class: AddressClass) -> Route {
+    match (policy, class) {
+        (MemRoute::Tid, _) => Route::Tid,
+        (MemRoute::Core0, _) => Route::Designated,
+        (MemRoute::Auto, AddressClass::CoreLocal(index)) => Route::Owner(index),
+        (MemRoute::Auto, AddressClass::Unknown) => Route::Tid,
+        (
+            MemRoute::Auto,
+            AddressClass::SystemRam | AddressClass::Flash | AddressClass::Peripheral,
+        ) => Route::Designated,
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Index of the core an access at `addr` requested through `tid` is issued through.
+    /// A route to an absent core falls back to the core of `tid`.
+    pub(crate) fn route_memory(
+        &mut self,
+        tid: Tid,
+        addr: u32,
+    ) -> Result<usize, TricoreTargetError> {
+        let requesting = self.core_index(tid)?;
+        let class = classify(self.arch.regions(), self.arch.local_map(), addr);
+        let route = match decide(self.settings.mem_route, class) {
+            Route::Owner(index) if self.core(index).is_none() => Route::Tid,
+            Route::Designated if self.core(self.settings.mem_route_core).is_none() => Route::Tid,
+            route => route,
+        };
+        let index = match route {
+            Route::Tid => requesting,
+            Route::Owner(index) => index,
+            Route::Designated => self.settings.mem_route_core,
+        };
+        if index != requesting {
+            trace!(
+                "{:#010x} ({:?}) through core {} instead of core {}",
+                addr,
+                class,
+                index,
+                requesting
+            );
+        }
+        self.stats.record_route(route);
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gdb::memory_map::{TC3XX_LOCAL_MAP, TC3XX_REGIONS};
+
+    fn tc3xx(addr: u32) -> AddressClass {
+        classify(TC3XX_REGIONS, Some(&TC3XX_LOCAL_MAP), addr)
+    }
+
+    #[test]
+    fn addresses_are_classified() {
+        assert_eq!(tc3xx(0x7000_0100), AddressClass::CoreLocal(0));
+        assert_eq!(tc3xx(0x6010_0000), AddressClass::CoreLocal(1));
+        assert_eq!(tc3xx(0x1000_0000), AddressClass::CoreLocal(5));
+        assert_eq!(tc3xx(0xF883_FE1C), AddressClass::CoreLocal(1));
+        assert_eq!(tc3xx(0x9040_0000), AddressClass::SystemRam);
+        assert_eq!(tc3xx(0xB000_0010), AddressClass::SystemRam);
+        assert_eq!(tc3xx(0x8000_0000), AddressClass::Flash);
+        assert_eq!(tc3xx(0xA000_0000), AddressClass::Flash);
+        assert_eq!(tc3xx(0xAF40_0000), AddressClass::Flash);
+        assert_eq!(tc3xx(0xF003_6050), AddressClass::Peripheral);
+        assert_eq!(tc3xx(0x2000_0000), AddressClass::Unknown);
+    }
+
+    #[test]
+    fn segments_without_local_map_are_not_core_local() {
+        assert_eq!(
+            classify(TC3XX_REGIONS, None, 0x2000_0000),
+            AddressClass::Unknown
+        );
+        assert_eq!(classify(&[], None, 0x9080_0000), AddressClass::SystemRam);
+    }
+
+    #[test]
+    fn routing_decision_matrix() {
+        let classes = [
+            AddressClass::CoreLocal(2),
+            AddressClass::SystemRam,
+            AddressClass::Flash,
+            AddressClass::Peripheral,
+            AddressClass::Unknown,
+        ];
+        let routes = |policy| classes.map(|class| decide(policy, class));
+        assert_eq!(
+            routes(MemRoute::Auto),
+            [
+                Route::Owner(2),
+                Route::Designated,
+                Route::Designated,
+                Route::Designated,
+                Route::Tid
+            ]
+        );
+        assert_eq!(routes(MemRoute::Tid), [Route::Tid; 5]);
+        assert_eq!(routes(MemRoute::Core0), [Route::Designated; 5]);
+    }
+}
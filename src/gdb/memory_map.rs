@@ -0,0 +1,499 @@
+//! Device memory layout used to sanity check addresses before they reach the probe.
+
+use std::fmt;
+
+/// Kind of memory a region is backed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Program flash bank
+    ProgramFlash,
+    /// Data flash bank
+    DataFlash,
+    /// User configuration blocks, which also host the boot mode headers
+    Ucb,
+    /// Scratchpad or shared RAM
+    Ram,
+}
+
+/// A contiguous range of the device address space
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub name: &'static str,
+    pub start: u32,
+    pub len: u32,
+    pub kind: RegionKind,
+}
+
+impl MemoryRegion {
+    /// Exclusive end address, computed in u64 so regions touching the top of memory don't overflow
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.len as u64
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.start && (addr as u64) < self.end()
+    }
+
+    /// Whether `[start, start + len)` lies completely inside the region
+    pub fn contains_range(&self, start: u32, len: u64) -> bool {
+        self.contains(start) && start as u64 + len <= self.end()
+    }
+
+    /// Logical sector size of flash regions, the erase granularity.
+    ///
+    /// TC2xx program flash mixes sector sizes, it is binned by its smallest logical sector.
+    pub fn sector_size(&self) -> Option<u32> {
+        match self.kind {
+            RegionKind::ProgramFlash => Some(16 * KIB),
+            RegionKind::DataFlash => Some(4 * KIB),
+            RegionKind::Ucb => Some(512),
+            RegionKind::Ram => None,
+        }
+    }
+
+    pub fn is_flash(&self) -> bool {
+        matches!(
+            self.kind,
+            RegionKind::ProgramFlash | RegionKind::DataFlash | RegionKind::Ucb
+        )
+    }
+}
+
+const fn region(name: &'static str, start: u32, len: u32, kind: RegionKind) -> MemoryRegion {
+    MemoryRegion {
+        name,
+        start,
+        len,
+        kind,
+    }
+}
+
+const KIB: u32 = 1024;
+const MIB: u32 = 1024 * KIB;
+
+/// Memory layout of the TC39x devices, program flash listed by its cached alias
+pub const TC3XX_REGIONS: &[MemoryRegion] = &[
+    region("PF0", 0x8000_0000, 3 * MIB, RegionKind::ProgramFlash),
+    region("PF1", 0x8030_0000, 3 * MIB, RegionKind::ProgramFlash),
+    region("PF2", 0x8060_0000, 3 * MIB, RegionKind::ProgramFlash),
+    region("PF3", 0x8090_0000, 3 * MIB, RegionKind::ProgramFlash),
+    region("PF4", 0x80C0_0000, 3 * MIB, RegionKind::ProgramFlash),
+    region("PF5", 0x80F0_0000, MIB, RegionKind::ProgramFlash),
+    region("DF0", 0xAF00_0000, MIB, RegionKind::DataFlash),
+    region("UCB", 0xAF40_0000, 24 * KIB, RegionKind::Ucb),
+    region("DF1", 0xAFC0_0000, 128 * KIB, RegionKind::DataFlash),
+    region("DSPR5", 0x1000_0000, 96 * KIB, RegionKind::Ram),
+    region("PSPR5", 0x1010_0000, 64 * KIB, RegionKind::Ram),
+    region("DSPR4", 0x3000_0000, 96 * KIB, RegionKind::Ram),
+    region("PSPR4", 0x3010_0000, 64 * KIB, RegionKind::Ram),
+    region("DSPR3", 0x4000_0000, 96 * KIB, RegionKind::Ram),
+    region("PSPR3", 0x4010_0000, 64 * KIB, RegionKind::Ram),
+    region("DSPR2", 0x5000_0000, 96 * KIB, RegionKind::Ram),
+    region("PSPR2", 0x5010_0000, 64 * KIB, RegionKind::Ram),
+    region("DSPR1", 0x6000_0000, 240 * KIB, RegionKind::Ram),
+    region("PSPR1", 0x6010_0000, 64 * KIB, RegionKind::Ram),
+    region("DSPR0", 0x7000_0000, 240 * KIB, RegionKind::Ram),
+    region("PSPR0", 0x7010_0000, 64 * KIB, RegionKind::Ram),
+    region("DLMU", 0x9000_0000, 384 * KIB, RegionKind::Ram),
+    region("LMU", 0x9040_0000, 768 * KIB, RegionKind::Ram),
+    region("DLMU_NC", 0xB000_0000, 384 * KIB, RegionKind::Ram),
+    region("LMU_NC", 0xB040_0000, 768 * KIB, RegionKind::Ram),
+    region("PSPR_LOCAL", 0xC000_0000, 64 * KIB, RegionKind::Ram),
+    region("DSPR_LOCAL", 0xD000_0000, 240 * KIB, RegionKind::Ram),
+];
+
+/// Memory layout of the TC27x devices, program flash listed by its cached alias
+pub const TC2XX_REGIONS: &[MemoryRegion] = &[
+    region("PF0", 0x8000_0000, 2 * MIB, RegionKind::ProgramFlash),
+    region("PF1", 0x8020_0000, 2 * MIB, RegionKind::ProgramFlash),
+    region("DF0", 0xAF00_0000, 384 * KIB, RegionKind::DataFlash),
+    region("UCB", 0xAF10_0000, 24 * KIB, RegionKind::Ucb),
+    region("DF1", 0xAF11_0000, 64 * KIB, RegionKind::DataFlash),
+    region("DSPR2", 0x5000_0000, 120 * KIB, RegionKind::Ram),
+    region("PSPR2", 0x5010_0000, 32 * KIB, RegionKind::Ram),
+    region("DSPR1", 0x6000_0000, 120 * KIB, RegionKind::Ram),
+    region("PSPR1", 0x6010_0000, 32 * KIB, RegionKind::Ram),
+    region("DSPR0", 0x7000_0000, 112 * KIB, RegionKind::Ram),
+    region("PSPR0", 0x7010_0000, 24 * KIB, RegionKind::Ram),
+    region("LMU", 0x9000_0000, 32 * KIB, RegionKind::Ram),
+    region("LMU_NC", 0xB000_0000, 32 * KIB, RegionKind::Ram),
+    region("PSPR_LOCAL", 0xC000_0000, 32 * KIB, RegionKind::Ram),
+    region("DSPR_LOCAL", 0xD000_0000, 120 * KIB, RegionKind::Ram),
+];
+
+/// Program flash of the AURIX families by the prefix of their device names, the size of
+/// the largest derivative of each family
+const PROGRAM_FLASH_SIZES: &[(&str, u32)] = &[
+    ("TC39", 16 * MIB),
+    ("TC38", 10 * MIB),
+    ("TC37", 6 * MIB),
+    ("TC36", 4 * MIB),
+    ("TC35", 4 * MIB),
+    ("TC33", 2 * MIB),
+    ("TC29", 8 * MIB),
+    ("TC27", 4 * MIB),
+    ("TC26", 2 * MIB + 512 * KIB),
+    ("TC23", 2 * MIB),
+    ("TC22", MIB),
+    ("TC21", 512 * KIB),
+];
+
+/// Program flash of the derivative named in `device_name`, `None` for unknown families
+pub fn program_flash_size(device_name: &str) -> Option<u32> {
+    let name = device_name.to_ascii_uppercase();
+    PROGRAM_FLASH_SIZES
+        .iter()
+        .find(|(family, _)| name.contains(family))
+        .map(|&(_, size)| size)
+}
+
+/// Core local alias window of a scratchpad, each core sees its own scratchpad through it
+#[derive(Debug, Clone, Copy)]
+pub struct LocalWindow {
+    pub name: &'static str,
+    pub start: u32,
+    pub len: u32,
+    /// Offset of the scratchpad in the segment of its core
+    pub segment_offset: u32,
+}
+
+/// Translation of core local addresses into the global alias of a given core
+#[derive(Debug)]
+pub struct CoreLocalMap {
+    pub windows: &'static [LocalWindow],
+    /// Segment holding the global alias of each core's scratchpads, by core index
+    pub core_segments: &'static [u32],
+}
+
+/// Why a range touching a local window has no global alias
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalMapError {
+    /// The range is partly inside and partly outside of a local window
+    Straddles(&'static str),
+    /// The map has no scratchpad segment for the core
+    UnknownCore(usize),
+}
+
+impl fmt::Display for LocalMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalMapError::Straddles(window) => write!(f, "range crosses the end of {}", window),
+            LocalMapError::UnknownCore(index) => write!(f, "core {} has no local window", index),
+        }
+    }
+}
+
+impl CoreLocalMap {
+    /// Global alias of the local range `[addr, addr + len)` as seen by core `index`,
+    /// `None` for a range outside of the local windows. A range only partly inside a
+    /// window has no single alias and is refused, as is a local range of an unknown core.
+    pub fn to_global(
+        &self,
+        index: usize,
+        addr: u32,
+        len: usize,
+    ) -> Result<Option<u32>, LocalMapError> {
+        let start = addr as u64;
+        let end = start + len.max(1) as u64;
+        let Some(window) = self.windows.iter().find(|window| {
+            start < window.start as u64 + window.len as u64 && end > window.start as u64
+        }) else {
+            return Ok(None);
+        };
+        if start < window.start as u64 || end > window.start as u64 + window.len as u64 {
+            return Err(LocalMapError::Straddles(window.name));
+        }
+        let segment = *self
+            .core_segments
+            .get(index)
+            .ok_or(LocalMapError::UnknownCore(index))?;
+        Ok(Some(
+            (segment << 28) + window.segment_offset + (addr - window.start),
+        ))
+    }
+}
+
+const LOCAL_WINDOWS: &[LocalWindow] = &[
+    LocalWindow {
+        name: "DSPR_LOCAL",
+        start: 0xD000_0000,
+        len: MIB,
+        segment_offset: 0,
+    },
+    LocalWindow {
+        name: "PSPR_LOCAL",
+        start: 0xC000_0000,
+        len: MIB,
+        segment_offset: 0x0010_0000,
+    },
+];
+
+/// TC39x scratchpads, CPU5 breaks the stride of one segment per core
+pub const TC3XX_LOCAL_MAP: CoreLocalMap = CoreLocalMap {
+    windows: LOCAL_WINDOWS,
+    core_segments: &[0x7, 0x6, 0x5, 0x4, 0x3, 0x1],
+};
+
+/// TC27x scratchpads
+pub const TC2XX_LOCAL_MAP: CoreLocalMap = CoreLocalMap {
+    windows: LOCAL_WINDOWS,
+    core_segments: &[0x7, 0x6, 0x5],
+};
+
+/// Original boot mode header slots in the TC3xx UCB, each 512 bytes long
+pub const TC3XX_BMHD_SLOTS: &[u32] = &[0xAF40_0000, 0xAF40_0200, 0xAF40_0400, 0xAF40_0600];
+
+/// Memory visible through a cached and a non-cached segment
+#[derive(Debug, Clone, Copy)]
+pub struct AliasWindow {
+    pub cached: u32,
+    pub non_cached: u32,
+    pub len: u32,
+}
+
+impl AliasWindow {
+    fn in_cached(&self, addr: u32) -> bool {
+        addr >= self.cached && addr - self.cached < self.len
+    }
+
+    fn in_non_cached(&self, addr: u32) -> bool {
+        addr >= self.non_cached && addr - self.non_cached < self.len
+    }
+
+    /// Cached alias of an address in either segment of the window
+    fn to_cached(self, addr: u32) -> Option<u32> {
+        if self.in_non_cached(addr) {
+            Some(addr - self.non_cached + self.cached)
+        } else if self.in_cached(addr) {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
+    /// The other alias of an address in either segment of the window
+    fn other(&self, addr: u32) -> Option<u32> {
+        if self.in_non_cached(addr) {
+            Some(addr - self.non_cached + self.cached)
+        } else if self.in_cached(addr) {
+            Some(addr - self.cached + self.non_cached)
+        } else {
+            None
+        }
+    }
+}
+
+const fn alias(cached: u32, non_cached: u32, len: u32) -> AliasWindow {
+    AliasWindow {
+        cached,
+        non_cached,
+        len,
+    }
+}
+
+/// Program flash is visible both cached (segment 8) and non-cached (segment A)
+const PFLASH_ALIAS: AliasWindow = alias(0x8000_0000, 0xA000_0000, 16 * MIB);
+
+/// Memories of the TC39x devices with a cached and a non-cached alias. The scratchpads
+/// have none.
+pub const TC3XX_ALIASES: &[AliasWindow] = &[
+    PFLASH_ALIAS,
+    alias(0x9000_0000, 0xB000_0000, 384 * KIB),
+    alias(0x9040_0000, 0xB040_0000, 768 * KIB),
+];
+
+/// Memories of the TC27x devices with a cached and a non-cached alias
+pub const TC2XX_ALIASES: &[AliasWindow] =
+    &[PFLASH_ALIAS, alias(0x9000_0000, 0xB000_0000, 32 * KIB)];
+
+/// Address a byte is known by whichever alias it was given in: its cached alias where
+/// `aliases` list one, the address itself otherwise
+pub fn canonical_address(aliases: &[AliasWindow], addr: u32) -> u32 {
+    aliases
+        .iter()
+        .find_map(|window| window.to_cached(addr))
+        .unwrap_or(addr)
+}
+
+/// Maps a non-cached program flash address onto its cached alias, other addresses are kept
+pub fn to_cached_alias(addr: u32) -> u32 {
+    canonical_address(&[PFLASH_ALIAS], addr)
+}
+
+/// Maps an address in either program flash segment onto the other alias of the same byte
+pub fn flash_alias(addr: u32) -> Option<u32> {
+    PFLASH_ALIAS.other(addr)
+}
+
+/// Core special function registers of CPUx at `CSFR_BASE + x * CSFR_STRIDE`, TC2xx and TC3xx
+const CSFR_BASE: u32 = 0xF881_0000;
+const CSFR_STRIDE: u32 = 0x2_0000;
+const CSFR_LEN: u32 = 64 * KIB;
+const MAX_CORES: u32 = 6;
+
+/// Global address of the CSFR at `offset` of core `index`
+pub fn csfr_address(index: usize, offset: u32) -> u32 {
+    CSFR_BASE + index as u32 * CSFR_STRIDE + offset
+}
+
+/// Indices of the cores whose CSFR space the range `[start, start + len)` touches
+pub fn csfr_cores(start: u32, len: usize) -> impl Iterator<Item = usize> {
+    let end = start as u64 + len as u64;
+    (0..MAX_CORES)
+        .filter(move |index| {
+            let base = (CSFR_BASE + index * CSFR_STRIDE) as u64;
+            (start as u64) < base + CSFR_LEN as u64 && base < end
+        })
+        .map(|index| index as usize)
+}
+
+/// First flash region touched by the range `[start, start + len)`, checked at both ends
+pub fn flash_region_in(
+    regions: &'static [MemoryRegion],
+    start: u32,
+    len: usize,
+) -> Option<&'static MemoryRegion> {
+    let last = start.wrapping_add(len.saturating_sub(1) as u32);
+    [start, last]
+        .into_iter()
+        .filter_map(|addr| find_region(regions, addr))
+        .find(|region| region.is_flash())
+}
+
+/// Looks up the region containing `addr`, program flash may be given by either alias
+pub fn find_region(regions: &'static [MemoryRegion], addr: u32) -> Option<&'static MemoryRegion> {
+    let addr = to_cached_alias(addr);
+    regions.iter().find(|region| region.contains(addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn both_aliases_name_the_cached_address() {
+        assert_eq!(canonical_address(TC3XX_ALIASES, 0xA000_0100), 0x8000_0100);
+        assert_eq!(canonical_address(TC3XX_ALIASES, 0x8000_0100), 0x8000_0100);
+        assert_eq!(canonical_address(TC3XX_ALIASES, 0xB040_0010), 0x9040_0010);
+        assert_eq!(canonical_address(TC2XX_ALIASES, 0xB000_0010), 0x9000_0010);
+    }
+
+    #[test]
+    fn memory_without_alias_is_kept() {
+        // DSPR of CPU0 and the TC27x LMU beyond its aliased window
+        assert_eq!(canonical_address(TC3XX_ALIASES, 0x7000_0100), 0x7000_0100);
+        assert_eq!(canonical_address(TC2XX_ALIASES, 0xB000_8000), 0xB000_8000);
+        assert_eq!(canonical_address(&[], 0xA000_0100), 0xA000_0100);
+    }
+
+    #[test]
+    fn removal_finds_the_breakpoint_through_either_alias() {
+        for (added, removed) in [(0x8000_0100, 0xA000_0100), (0xA000_0100, 0x8000_0100)] {
+            let mut breakpoints = HashMap::new();
+            breakpoints.insert(canonical_address(TC3XX_ALIASES, added), added);
+            assert_eq!(
+                breakpoints.remove(&canonical_address(TC3XX_ALIASES, removed)),
+                Some(added)
+            );
+        }
+
+        let mut breakpoints = HashMap::new();
+        breakpoints.insert(canonical_address(&[], 0x8000_0100), 0x8000_0100u32);
+        assert_eq!(
+            breakpoints.remove(&canonical_address(&[], 0xA000_0100)),
+            None
+        );
+    }
+
+    #[test]
+    fn flash_alias_swaps_the_segment() {
+        assert_eq!(flash_alias(0x8000_0100), Some(0xA000_0100));
+        assert_eq!(flash_alias(0xA000_0100), Some(0x8000_0100));
+        assert_eq!(flash_alias(0x7000_0100), None);
+        assert_eq!(to_cached_alias(0xA0FF_FFFF), 0x80FF_FFFF);
+    }
+
+    #[test]
+    fn program_flash_is_sized_by_family() {
+        assert_eq!(program_flash_size("TC397XP"), Some(16 * MIB));
+        assert_eq!(program_flash_size("tc375tp"), Some(6 * MIB));
+        assert_eq!(program_flash_size("TC277TF"), Some(4 * MIB));
+        assert_eq!(program_flash_size("miniWiggler"), None);
+    }
+
+    #[test]
+    fn local_windows_map_to_each_cores_scratchpads() {
+        for map in [&TC3XX_LOCAL_MAP, &TC2XX_LOCAL_MAP] {
+            for (index, &segment) in map.core_segments.iter().enumerate() {
+                for window in map.windows {
+                    let global = (segment << 28) + window.segment_offset;
+                    let last = window.start + (window.len - 1);
+                    assert_eq!(map.to_global(index, window.start, 1), Ok(Some(global)));
+                    assert_eq!(
+                        map.to_global(index, last, 1),
+                        Ok(Some(global + window.len - 1))
+                    );
+                    assert_eq!(
+                        map.to_global(index, window.start, window.len as usize),
+                        Ok(Some(global))
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tc3xx_cpu5_uses_segment_one() {
+        assert_eq!(
+            TC3XX_LOCAL_MAP.to_global(5, 0xD000_0010, 4),
+            Ok(Some(0x1000_0010))
+        );
+        assert_eq!(
+            TC3XX_LOCAL_MAP.to_global(5, 0xC000_0010, 4),
+            Ok(Some(0x1010_0010))
+        );
+    }
+
+    #[test]
+    fn ranges_straddling_a_local_window_are_refused() {
+        let map = &TC3XX_LOCAL_MAP;
+        // Leaving the end of the window
+        assert_eq!(
+            map.to_global(0, 0xD00F_FFFE, 4),
+            Err(LocalMapError::Straddles("DSPR_LOCAL"))
+        );
+        // Entering the window from below
+        assert_eq!(
+            map.to_global(0, 0xCFFF_FFFE, 4),
+            Err(LocalMapError::Straddles("DSPR_LOCAL"))
+        );
+        assert_eq!(
+            map.to_global(0, 0xC00F_FFFF, 2),
+            Err(LocalMapError::Straddles("PSPR_LOCAL"))
+        );
+    }
+
+    #[test]
+    fn ranges_outside_the_local_windows_stay_untranslated() {
+        let map = &TC3XX_LOCAL_MAP;
+        assert_eq!(map.to_global(0, 0x7000_0000, 16), Ok(None));
+        assert_eq!(map.to_global(0, 0xD010_0000, 4), Ok(None));
+        assert_eq!(map.to_global(0, 0xCFFF_FFFC, 4), Ok(None));
+        // No local window, the core does not matter
+        assert_eq!(map.to_global(9, 0x7000_0000, 4), Ok(None));
+    }
+
+    #[test]
+    fn local_ranges_of_unknown_cores_are_refused() {
+        assert_eq!(
+            TC3XX_LOCAL_MAP.to_global(6, 0xD000_0000, 4),
+            Err(LocalMapError::UnknownCore(6))
+        );
+        assert_eq!(
+            TC2XX_LOCAL_MAP.to_global(3, 0xC000_0000, 4),
+            Err(LocalMapError::UnknownCore(3))
+        );
+    }
+}
@@ -1,45 +1,259 @@
+use crate::metrics::Metrics;
 use crate::DynResult;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use gdbstub::common::Tid;
 use gdbstub::target;
 use gdbstub::target::ext::breakpoints::BreakpointsOps;
 
+use arch::ArchVariant;
+pub use boot_args::{check_boot_args, BootArgsBlock, BootArgsFormat};
+use breakpoints::{Breakpoint, TriggerId};
+pub use busy::BusyReason;
+pub use capabilities::Capabilities;
+use core_slot::{init_cores, present_core, CoreSlot};
+use elf::ElfImage;
+pub use elf::{classify_elf, validate_tricore_elf, ElfCheckError, ElfContent, ElfData};
+pub use elf_set::{check_assignments, ElfSpec};
+use events::{EventBroadcaster, TargetEvent};
+pub use flash::{FlashOptions, IdBlock};
+pub use flash_error::{FlashError, FlashFailure, FlashLocation, FlashPhase, FlashProgress};
+pub use flash_only::{flash_only, FlashOnlyError};
+use initial_stop::StartupCause;
+pub use log_context::current_core;
+pub use monitor::{MonitorCommand, MonitorOutput};
+pub(crate) use registers::read_target_xml;
+pub use registers::{CoreRegisters, RegisterId, TricoreArch};
+pub use run_to::RunToOutcome;
+pub use settings::{ConnectMode, DisconnectAction, KillAction, MemRoute, OtherCores, Settings};
+pub use sfr::PeripheralMap;
+
 use chip_communication::DeviceSelection;
+pub use chip_communication::ProbeIdentity;
 use gdbstub::target::Target;
-use gdbstub_arch::tricore::TricoreV1_6;
-use log::debug;
-use rust_mcd::core::{Core, CoreState, Trigger};
+use log::{debug, info, trace, warn};
+use rust_mcd::core::{Core, CoreState};
 use rust_mcd::reset::ResetClass;
-use std::collections::HashMap;
-use traits::TricoreTargetError;
+use stats::McdStats;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+pub use traits::TricoreTargetError;
 
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+mod arch;
 mod base;
+mod boot_args;
+mod bootcheck;
+mod bp_sync;
 mod breakpoints;
+mod bus;
+mod busy;
+mod cache;
+mod capabilities;
 mod chip_communication;
+mod code_cache;
+mod core_slot;
+mod csa;
 mod das;
+mod decode;
+mod dump;
 mod elf;
+mod elf_set;
+pub mod events;
+mod exec_file;
 mod extended_mode;
 mod flash;
+mod flash_error;
+mod flash_only;
+mod fpu;
+mod heartbeat;
+mod hit_timing;
+mod initial_stop;
+mod inject;
+mod link_health;
+mod log_context;
+mod mem_route;
+mod memory_map;
 mod monitor;
+mod monitor_registry;
+mod overlay;
+mod pause;
+mod poll_schedule;
+mod reg_groups;
+mod registers;
+mod reset_cause;
 mod resume;
+mod run_to;
+mod script;
+mod search;
+mod semihost;
+mod session_log;
+mod settings;
+mod sfr;
+mod single_core;
+mod soft_step;
+mod state_log;
+mod stats;
+mod step_off;
+mod symbol_bp;
+mod target_time;
 mod traits;
+mod trap_loop;
 pub mod tricore;
+mod trigger_budget;
+mod watch_range;
+mod write_coalesce;
+mod write_journal;
 
 fn pretty_print_devices(devices: &[DeviceSelection]) {
     if devices.is_empty() {
-        println!("No devices available");
+        warn!("No devices available");
         return;
     }
-    println!("Found {} devices:", devices.len());
-    for (index, scanned_device) in devices.iter().enumerate() {
-        println!("Device {index}: {:?}", scanned_device.info.acc_hw())
+    info!("Found {} devices:", devices.len());
+    info!("  {:<6} {:<16} {}", "port", "serial", "probe");
+    for device in devices {
+        let probe = ProbeIdentity::of(device);
+        info!(
+            "  {:<6} {:<16} {}",
+            probe.port,
+            probe.serial.as_deref().unwrap_or("-"),
+            probe.name
+        );
+    }
+}
+
+/// Device to connect to among the ones found by the DAS scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceChoice {
+    /// Position in the scan, which depends on the USB enumeration order
+    Index(usize),
+    /// The probe with exactly this serial
+    Serial(String),
+}
+
+impl From<usize> for DeviceChoice {
+    fn from(index: usize) -> Self {
+        DeviceChoice::Index(index)
     }
 }
 
+/// Interrupt enable bit of the ICR register
+const ICR_IE: u32 = 1 << 15;
+
+/// Time a core is given to halt with `--halt-on-connect` before it is skipped
+const CONNECT_HALT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Notes kept for the GDB console until a monitor command shows them, older ones are dropped
+const MAX_GDB_NOTES: usize = 32;
+
+/// Maximum number of bytes requested from the probe in a single read transaction
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Fills `data` from `start_addr` with one `read` of at most [READ_CHUNK_SIZE] bytes per
+/// chunk, `read` returns the bytes of a chunk in a buffer of its own
+fn read_in_chunks<E>(
+    start_addr: u32,
+    data: &mut [u8],
+    stats: &mut McdStats,
+    mut read: impl FnMut(u32, usize) -> Result<Vec<u8>, E>,
+) -> Result<(), E> {
+    for (index, chunk) in data.chunks_mut(READ_CHUNK_SIZE).enumerate() {
+        let chunk_addr = start_addr.wrapping_add((index * READ_CHUNK_SIZE) as u32);
+        let bytes = read(chunk_addr, chunk.len())?;
+        chunk.copy_from_slice(&bytes);
+        stats.record_read(bytes.len());
+    }
+    Ok(())
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number
+pub(crate) fn parse_u32(value: &str) -> Option<u32> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Parses `<addr>:<len>`, both numbers as [parse_u32] takes them. The range must not be
+/// empty and must end within the address space.
+pub(crate) fn parse_addr_len(text: &str) -> Result<(u32, u32), String> {
+    let (addr, len) = text
+        .split_once(':')
+        .ok_or_else(|| format!("expected <addr>:<len>, got {:?}", text))?;
+    match (parse_u32(addr), parse_u32(len)) {
+        (Some(addr), Some(len)) if len > 0 && addr.checked_add(len - 1).is_some() => {
+            Ok((addr, len))
+        }
+        _ => Err(format!("invalid <addr>:<len> {:?}", text)),
+    }
+}
+
+/// Levenshtein distance of two strings, to suggest a close match for a mistyped name
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Validates the elfs against the device flash layout and each other without connecting
+/// to the device
+pub fn check_elf_files(program_elfs: &[&Path], options: &FlashOptions) -> anyhow::Result<()> {
+    let mut images = Vec::new();
+    for program_elf in program_elfs {
+        images.push(
+            ElfImage::load(program_elf)
+                .with_context(|| format!("Cannot load {:?}", program_elf))?,
+        );
+    }
+    let images: Vec<&ElfImage> = images.iter().collect();
+    let elfs = bootable_elfs(&images);
+    for elf in &elfs {
+        // Without a device the derivative is unknown, check against the default layout
+        flash::check_elf(elf, ArchVariant::default(), None, options)
+            .with_context(|| format!("{:?}", elf.path))?;
+    }
+    elf_set::check_overlaps(&elfs)
+}
+
+/// The elfs with content to program, symbol files are left out. An elf which cannot be
+/// classified is kept, programming it reports the error.
+fn bootable_elfs<'e>(elfs: &[&'e ElfImage]) -> Vec<&'e ElfImage> {
+    elfs.iter()
+        .copied()
+        .filter(|elf| {
+            if classify_elf(elf.data()).ok() == Some(ElfContent::SymbolsOnly) {
+                info!(
+                    "{:?} has no loadable content, it is used for symbols only",
+                    elf.path
+                );
+                return false;
+            }
+            true
+        })
+        .collect()
+}
+
 /// Actions for resuming a core
 #[derive(Debug, Copy, Clone)]
 pub(crate) enum ResumeAction {
@@ -61,6 +275,15 @@ pub enum CpuId {
     Cpu5,
 }
 
+/// Thread of a core as GDB knows it, the only thread is 1 with `--single-core`
+pub fn thread_of(settings: &Settings, id: CpuId) -> Tid {
+    if settings.single_core.is_some() {
+        Tid::new(1).unwrap()
+    } else {
+        cpuid_to_tid(id)
+    }
+}
+
 pub fn cpuid_to_tid(id: CpuId) -> Tid {
     match id {
         CpuId::Cpu0 => Tid::new(1).unwrap(),
@@ -116,132 +339,1126 @@ impl From<CpuId> for usize {
 }
 
 pub struct TricoreTarget<'a> {
-    pub(crate) breakpoints: HashMap<u32, Vec<Trigger<'a>>>,
+    pub(crate) breakpoints: HashMap<u32, Breakpoint>,
+    /// Breakpoint changes requested while running, applied in order at the next stop
+    pub(crate) pending_breakpoints: Vec<breakpoints::PendingChange>,
+    /// Breakpoints waiting for their guard before the trigger is planted
+    pub(crate) deferred: HashMap<u32, overlay::Guard>,
+    /// RAM regions the application copies code into at runtime
+    pub(crate) overlays: Vec<overlay::Overlay>,
+    /// Last poll of the guards of deferred breakpoints
+    pub(crate) last_guard_poll: Option<Instant>,
+    /// Connection to the device, owned by the [Device] the cores borrow from
     #[warn(dead_code)]
-    pub(crate) system: rust_mcd::system::System,
-    pub(crate) cores: Vec<Core<'a>>,
-    /// Resume action to be used upon a continue request
-    resume_actions: Vec<ResumeAction>,
+    pub(crate) system: &'a rust_mcd::system::System,
+    /// Per-core state by physical index, see [core_slot]
+    slots: Vec<CoreSlot<'a>>,
+    /// Core architecture of the device, selects the device tables
+    pub(crate) arch: ArchVariant,
+    /// Resources of the DAS server and probe
+    pub(crate) das_capabilities: capabilities::Capabilities,
+    /// Stops observed on other cores while reporting a stop, delivered before resuming again
+    pending_events: VecDeque<(tricore::Event, CpuId)>,
+    /// Optional publisher of state changes for external tooling
+    events: Option<EventBroadcaster>,
+    /// Monitor output and events appended to a file, see `--session-log`
+    session_log: Option<session_log::SessionLog>,
+    pub(crate) settings: Settings,
+    /// The elfs of the session, from the command line or `monitor elf load`
+    pub(crate) elfs: elf_set::ElfSet,
+    /// Build IDs of the elfs programmed at startup
+    pub(crate) flashed_build_ids: Vec<Vec<u8>>,
+    pub(crate) stats: McdStats,
+    /// Chunk buffer of `monitor dump`, allocated once for the session
+    read_buffer: Vec<u8>,
+    /// Owners of the triggers in use per core, see `monitor triggers`
+    pub(crate) trigger_budget: trigger_budget::TriggerBudget,
+    /// GDB writes acknowledged and kept for merging, see `write-coalesce`
+    pending_write: Option<write_coalesce::PendingWrite>,
+    /// Liveness check while halted, see `heartbeat`
+    heartbeat: heartbeat::Heartbeat,
+    /// Core and cause of the stop GDB is told of on attaching, until the first resume
+    initial_stop: Option<initial_stop::InitialStop>,
+    /// The cores run since [TricoreTarget::run_all] and GDB attached without halting them,
+    /// until GDB resumes or interrupts the target
+    left_running: bool,
+    /// Bursts of failed MCD transactions, see `monitor dasclock`
+    pub(crate) link_health: link_health::LinkHealth,
+    /// Program flash blocks read before, see `monitor cache-stats`
+    pub(crate) code_cache: code_cache::CodeCache,
+    /// Interrupt enable state of cores currently stepping with interrupts masked
+    saved_irq_enable: HashMap<CpuId, bool>,
+    /// Return address and one-shot trigger of cores stepping over a call
+    step_overs: HashMap<CpuId, (u32, TriggerId)>,
+    /// Possible next instructions and their one-shot triggers of cores soft stepping
+    soft_steps: HashMap<CpuId, Vec<(u32, TriggerId)>>,
+    /// External halt requests, polled while running
+    pause: pause::PauseTriggers,
+    /// PC and A10 read right when a core stopped, valid until the next resume
+    pub(crate) stop_snapshots: HashMap<CpuId, StopSnapshot>,
+    /// Peripheral registers loaded with `--svd`
+    pub(crate) peripherals: Option<PeripheralMap>,
+    /// Notes for the GDB console not shown yet, see [TricoreTarget::notify]
+    pub(crate) gdb_notes: VecDeque<String>,
+    /// Whether the hint on refused flash writes was given already
+    flash_write_hint_shown: bool,
+    /// Whether the hint on the breakpoint limit was given already
+    breakpoint_limit_hint_shown: bool,
+    /// Service metrics shown by `monitor status`
+    pub(crate) metrics: Option<Arc<Metrics>>,
+    /// Keeps the polled core states from flooding the debug log
+    state_log: state_log::StateLogTracker,
+    /// Favors the core resumed alone when querying the core states
+    poll_schedule: poll_schedule::PollSchedule,
+    /// Monitor commands registered by an embedding application
+    external_commands: HashMap<String, MonitorCommand>,
+    /// Exit code asked for by `monitor exit`, ends the session
+    exit_requested: Option<u8>,
+    /// STM readings and run totals, see `monitor runstats`
+    target_time: target_time::TargetTime,
+    /// Flash operation in progress, client requests are refused meanwhile
+    busy: Option<BusyReason>,
+    /// Writes read back with `verify-writes`
+    write_journal: write_journal::WriteJournal,
+    /// Data triggers over address ranges, see `monitor wp range`
+    range_watches: watch_range::RangeWatches,
+    /// Cores whose triggers changed since their trigger list was last downloaded
+    dirty_triggers: BTreeSet<usize>,
+    /// PC samples of the running cores, see `trap-loop-detect`
+    trap_loop: trap_loop::TrapLoopDetector,
+    /// Host files opened by the target through semihosting
+    semihost: semihost::Semihost,
+    /// Cores found running and halted by `--halt-on-connect`, resumed when GDB detaches
+    halted_on_connect: Vec<usize>,
 }
 
-pub type StaticTricoreTarget = TricoreTarget<'static>;
+/// PC and A10 of a core read when its stop is detected, for the stop classification, the
+/// stop events and the register read GDB follows the stop with. The stop reply carries no
+/// registers, the gdbstub fork in use has no expedited registers, so this saves no round
+/// trip: GDB still sends `g` after every stop and only two of its register reads are spared.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StopSnapshot {
+    pub pc: u32,
+    pub a10: u32,
+}
 
-impl TricoreTarget<'static> {
-    pub fn new(program_elf: Option<&PathBuf>) -> DynResult<TricoreTarget<'static>> {
-        let mut command_server = chip_communication::ChipCommunication::new()?;
-        let scanned_devices = command_server.list_devices()?;
+/// Device selected and connected through DAS, before any core is claimed
+pub(crate) struct ConnectedDevice {
+    pub command_server: chip_communication::ChipCommunication,
+    pub arch: ArchVariant,
+    /// Server description as reported by the scan
+    pub server: String,
+    pub probe: ProbeIdentity,
+}
 
-        if scanned_devices.is_empty() {
-            return Err("No devices found".into());
+/// Starts DAS and connects to the chosen device
+pub(crate) fn connect_device(device: &DeviceChoice) -> anyhow::Result<ConnectedDevice> {
+    let mut command_server = chip_communication::ChipCommunication::new()?;
+    let scanned_devices = command_server.list_devices()?;
+
+    if scanned_devices.is_empty() {
+        bail!("No devices found");
+    }
+
+    pretty_print_devices(&scanned_devices);
+
+    let selected = match device {
+        DeviceChoice::Index(index) => match scanned_devices.get(*index) {
+            Some(selected) => selected,
+            None => bail!(
+                "No device {}, found {} devices",
+                index,
+                scanned_devices.len()
+            ),
+        },
+        DeviceChoice::Serial(serial) => {
+            let found = scanned_devices
+                .iter()
+                .find(|device| ProbeIdentity::of(device).serial.as_ref() == Some(serial));
+            match found {
+                Some(selected) => selected,
+                None => {
+                    let serials: Vec<String> = scanned_devices
+                        .iter()
+                        .filter_map(|device| ProbeIdentity::of(device).serial)
+                        .collect();
+                    bail!(
+                        "No probe with serial {}, available: {}",
+                        serial,
+                        if serials.is_empty() {
+                            "none".to_owned()
+                        } else {
+                            serials.join(", ")
+                        }
+                    );
+                }
+            }
         }
+    };
+    command_server.connect(Some(selected))?;
+    let probe = ProbeIdentity::of(selected);
+    info!("Connected to {} on port {}", probe, probe.port);
+    let arch = ArchVariant::of_device(selected);
+    info!("Core architecture: {}", arch);
 
-        pretty_print_devices(&scanned_devices);
+    Ok(ConnectedDevice {
+        command_server,
+        arch,
+        server: format!("{:?}", selected.info.acc_hw()),
+        probe,
+    })
+}
+
+/// Programs the elfs into the connected device, publishing the flash events
+pub(crate) fn flash_device(
+    device: &mut ConnectedDevice,
+    program_elfs: &[&ElfImage],
+    flash_options: &FlashOptions,
+    publish: &dyn Fn(TargetEvent),
+) -> anyhow::Result<()> {
+    let program_elfs = bootable_elfs(program_elfs);
+    let paths: Vec<&Path> = program_elfs.iter().map(|elf| elf.path.as_path()).collect();
+    if program_elfs.is_empty() {
+        info!("Nothing to program, the device keeps its flash content");
+        return Ok(());
+    }
+    info!("Programming via elf: {:?}", paths);
+    publish(TargetEvent::FlashStarted);
+    let flashed = device
+        .command_server
+        .flash_elf(&program_elfs, device.arch, flash_options);
+    match &flashed {
+        Err(e) if e.downcast_ref::<flash::ProjectChange>().is_some() => {
+            publish(TargetEvent::FlashRefused)
+        }
+        _ => publish(TargetEvent::FlashFinished {
+            success: flashed.is_ok(),
+            failure: flashed
+                .as_ref()
+                .err()
+                .and_then(|e| e.downcast_ref::<FlashError>())
+                .map(FlashError::failure),
+        }),
+    }
+    flashed.context("Cannot flash elf")?;
+
+    info!("Sucessfully flashed {:?}", paths);
+    Ok(())
+}
 
-        command_server.connect(Some(&scanned_devices[0]))?;
+/// Device connected through DAS and optionally programmed. It owns the MCD system, the
+/// [TricoreTarget] attached to it borrows its cores.
+pub struct Device {
+    system: rust_mcd::system::System,
+    /// Handed to the target on attaching
+    prepared: Option<PreparedSession>,
+}
+
+/// Session state set up while connecting, before any core is claimed
+struct PreparedSession {
+    arch: ArchVariant,
+    das_capabilities: capabilities::Capabilities,
+    elfs: elf_set::ElfSet,
+    session_log: Option<session_log::SessionLog>,
+    settings: Settings,
+    events: Option<EventBroadcaster>,
+}
 
-        match program_elf {
-            Some(program_elf) => {
-                println!("Programming via elf: {:?}", program_elf);
-                command_server
-                    .flash_elf(program_elf)
-                    .context("Cannot flash elf")?;
+impl Device {
+    /// Connects to the first device found, see [Device::connect]
+    pub fn new(
+        program_elfs: &[ElfSpec],
+        flash_options: &FlashOptions,
+        settings: Settings,
+        events: Option<EventBroadcaster>,
+    ) -> DynResult<Device> {
+        Self::connect(
+            DeviceChoice::Index(0),
+            program_elfs,
+            flash_options,
+            settings,
+            events,
+        )
+    }
 
-                println!("Sucessfully flashed {:?} ", program_elf);
+    /// Connects to the device chosen by scan index or probe serial and optionally
+    /// programs the elfs. The cores are reset or halted by [TricoreTarget::attach].
+    pub fn connect(
+        device: impl Into<DeviceChoice>,
+        program_elfs: &[ElfSpec],
+        flash_options: &FlashOptions,
+        settings: Settings,
+        events: Option<EventBroadcaster>,
+    ) -> DynResult<Device> {
+        // Opened first, so the flash operations are logged as well
+        let session_log = RefCell::new(settings.session_log.as_ref().and_then(|path| {
+            session_log::SessionLog::open(
+                path,
+                settings.session_log_size,
+                settings.session_id.as_deref(),
+            )
+            .map_err(|e| warn!("Cannot open session log {}: {}", path.display(), e))
+            .ok()
+        }));
+        let publish = |event: TargetEvent| {
+            if let Some(log) = session_log.borrow_mut().as_mut() {
+                log.line(event.core(), format_args!("{}", event));
             }
-            None => println!("No elf provided..."),
+            if let Some(events) = &events {
+                events.publish(event)
+            }
+        };
+
+        let mut device = connect_device(&device.into())?;
+        if let Some(events) = &events {
+            events.set_probe(device.probe.serial.clone());
+        }
+        let arch = device.arch;
+        // Before programming and the core resets, either may reset the device
+        if settings.log_reset_cause {
+            reset_cause::log_before_reset(&mut device);
         }
+        let das_capabilities = capabilities::Capabilities {
+            server: device.server.clone(),
+            probe: Some(device.probe.clone()),
+            // rust_mcd gives no access to the power switch of the access hardware
+            power_control: false,
+            ..Default::default()
+        };
+
+        // Parsed once, programming and the session borrow the segments from the mapping
+        let elfs = elf_set::ElfSet::load(program_elfs)?;
+        if program_elfs.is_empty() {
+            info!("No elf provided, device is not programmed");
+        } else {
+            flash_device(&mut device, &elfs.distinct(), flash_options, &publish)?;
+        }
+        let mut command_server = device.command_server;
 
         sleep(Duration::from_secs(2));
 
         let system = command_server.get_system()?;
+        Ok(Device {
+            system,
+            prepared: Some(PreparedSession {
+                arch,
+                das_capabilities,
+                elfs,
+                session_log,
+                settings,
+                events,
+            }),
+        })
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Claims the cores of a connected device and resets or, with [ConnectMode::Halt],
+    /// halts them. A device is attached once.
+    pub fn attach(device: &'a mut Device) -> DynResult<TricoreTarget<'a>> {
+        let Some(PreparedSession {
+            arch,
+            das_capabilities,
+            elfs,
+            session_log,
+            settings,
+            events,
+        }) = device.prepared.take()
+        else {
+            return Err("The device is attached already".into());
+        };
+        let system = &device.system;
 
         let core_count = system.core_count();
         debug!("Detected {:?} core", core_count);
 
-        let mut cores: Vec<Core<'static>> = Vec::with_capacity(core_count);
-        let mut resume_actions: Vec<ResumeAction> = Vec::with_capacity(core_count);
+        let mut halted_on_connect = Vec::new();
 
-        for core_index in 0..core_count {
-            let core = system.get_core(core_index)?;
-            let system_reset = ResetClass::construct_reset_class(&core, 0);
-            core.reset(system_reset, false)?;
-            let static_core: Core<'static> =
-                unsafe { std::mem::transmute::<Core<'_>, Core<'static>>(core) };
-            cores.push(static_core);
-            resume_actions.push(ResumeAction::Unchanged);
+        let cores = init_cores(core_count, |core_index| {
+            let mut core = system.get_core(core_index).map_err(|e| e.to_string())?;
+            match settings.connect_mode {
+                ConnectMode::Reset => {
+                    let system_reset = ResetClass::construct_reset_class(&core, 0);
+                    core.reset(system_reset, false).map_err(|e| e.to_string())?;
+                }
+                ConnectMode::Halt => {
+                    halt_core(&mut core)?;
+                    halted_on_connect.push(core_index);
+                }
+            }
+            Ok(core)
+        })?;
+        let slots: Vec<_> = cores
+            .into_iter()
+            .map(|core| match core {
+                Ok(core) => {
+                    let capabilities = registers::CoreCapabilities::probe(&core);
+                    CoreSlot::present(core, capabilities)
+                }
+                Err(e) => CoreSlot::absent(e),
+            })
+            .collect();
+        if let Some(cpu_id) = settings.single_core {
+            let index = usize::from(cpu_id);
+            match slots.get(index) {
+                None => {
+                    return Err(format!(
+                        "--single-core {}: the device has {} cores",
+                        index, core_count
+                    )
+                    .into())
+                }
+                Some(CoreSlot {
+                    absent: Some(reason),
+                    ..
+                }) => return Err(format!("--single-core {}: {}", index, reason).into()),
+                Some(_) => info!("Debugging core {} only", index),
+            }
         }
+        let pause = pause::PauseTriggers::new(settings.pause_file.clone());
+        let mut stats = McdStats::default();
+        stats.record_read_allocation(READ_CHUNK_SIZE);
+        let usage_report = if elfs.is_empty() {
+            Vec::new()
+        } else {
+            flash::flash_usage_report(
+                &flash::flash_usage(&elfs.segments(), arch.regions()),
+                settings.flash_fill_warn,
+            )
+        };
+        let state_log = state_log::StateLogTracker::new(slots.len());
+        let semihost = semihost::Semihost::new(settings.semihost_root.clone());
 
-        Ok(TricoreTarget {
+        let mut target = TricoreTarget {
             breakpoints: HashMap::new(),
+            pending_breakpoints: Vec::new(),
+            deferred: HashMap::new(),
+            overlays: Vec::new(),
+            last_guard_poll: None,
             system,
-            cores,
-            resume_actions,
-        })
+            slots,
+            arch,
+            das_capabilities,
+            pending_events: VecDeque::new(),
+            events,
+            session_log,
+            settings,
+            stats,
+            read_buffer: vec![0; READ_CHUNK_SIZE],
+            trigger_budget: trigger_budget::TriggerBudget::default(),
+            pending_write: None,
+            heartbeat: heartbeat::Heartbeat::default(),
+            initial_stop: None,
+            left_running: false,
+            link_health: link_health::LinkHealth::default(),
+            code_cache: code_cache::CodeCache::default(),
+            saved_irq_enable: HashMap::new(),
+            step_overs: HashMap::new(),
+            soft_steps: HashMap::new(),
+            pause,
+            stop_snapshots: HashMap::new(),
+            peripherals: None,
+            metrics: None,
+            gdb_notes: VecDeque::new(),
+            flash_write_hint_shown: false,
+            breakpoint_limit_hint_shown: false,
+            state_log,
+            poll_schedule: poll_schedule::PollSchedule::new(),
+            flashed_build_ids: elfs
+                .assignments()
+                .into_iter()
+                .filter_map(|(_, image)| image.build_id.clone())
+                .collect(),
+            elfs,
+            external_commands: HashMap::new(),
+            exit_requested: None,
+            target_time: target_time::TargetTime::default(),
+            busy: None,
+            write_journal: write_journal::WriteJournal::default(),
+            range_watches: watch_range::RangeWatches::default(),
+            dirty_triggers: BTreeSet::new(),
+            trap_loop: trap_loop::TrapLoopDetector::new(),
+            semihost,
+            halted_on_connect,
+        };
+        match target.settings.connect_mode {
+            ConnectMode::Reset => target.publish(TargetEvent::Reset),
+            ConnectMode::Halt => {
+                for core_index in target.halted_on_connect.clone() {
+                    target.publish(TargetEvent::Halted {
+                        cpu: CpuId::try_from(core_index).expect("Unexpected core index"),
+                        pc: None,
+                        cause: "connect",
+                    });
+                }
+            }
+        }
+        target.probe_trigger_limits();
+        match target.halted_on_connect.first() {
+            Some(&index) => target.set_initial_stop(index, StartupCause::HaltOnConnect),
+            None => target.set_initial_stop_first(StartupCause::Reset),
+        }
+        target.settle_other_cores();
+        for line in usage_report.into_iter().chain(target.bootcheck_report()) {
+            target.console(None, format_args!("{}", line));
+        }
+
+        Ok(target)
+    }
+
+    /// Executable address ranges of the elfs, empty without one
+    pub(crate) fn exec_ranges(&self) -> Vec<Range<u32>> {
+        self.elfs.exec_ranges()
+    }
+
+    /// Shares the service metrics with the target and records the connected device
+    pub fn attach_metrics(&mut self, metrics: Arc<Metrics>) {
+        metrics.record_device_connected(format!("{} {}", self.das_capabilities.server, self.arch));
+        self.metrics = Some(metrics);
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Exit code of the stub process asked for by `monitor exit`
+    pub fn exit_requested(&self) -> Option<u8> {
+        self.exit_requested
+    }
+
+    /// Resources of the DAS server and probe in use
+    pub fn das_capabilities(&self) -> &Capabilities {
+        &self.das_capabilities
+    }
+
+    pub(crate) fn publish(&mut self, event: TargetEvent) {
+        if let Some(log) = &mut self.session_log {
+            log.line(event.core(), format_args!("{}", event));
+        }
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+    }
+
+    /// Logs a line of the stub's console and appends it to the session log.
+    ///
+    /// GDB only shows console output sent in reply to a monitor command, which the monitor
+    /// commands mirror into the session log themselves. Notes the user must see go through
+    /// [TricoreTarget::notify].
+    pub(crate) fn console(&mut self, core: Option<CpuId>, msg: std::fmt::Arguments) {
+        log_context::with_core(core, || info!("{}", msg));
+        if let Some(log) = &mut self.session_log {
+            log.line(core, msg);
+        }
+    }
+
+    /// Logs a note for the user of GDB as [TricoreTarget::console] does and shows it on the
+    /// GDB console ahead of the output of the next monitor command. The protocol has no
+    /// console output of its own while the target is halted.
+    pub(crate) fn notify(&mut self, core: Option<CpuId>, msg: std::fmt::Arguments) {
+        self.console(core, msg);
+        if self.gdb_notes.len() == MAX_GDB_NOTES {
+            self.gdb_notes.pop_front();
+        }
+        self.gdb_notes.push_back(match core {
+            Some(core) => format!("CPU{}: {}", usize::from(core), msg),
+            None => msg.to_string(),
+        });
+    }
+
+    /// Notes the ID of an accepted GDB connection in the log and the session log
+    pub fn connection_accepted(&mut self, connection_id: &str) {
+        self.console(None, format_args!("GDB session {} started", connection_id));
     }
 
     pub fn restart(&mut self) {
-        for core in &mut self.cores.iter_mut() {
+        self.left_running = false;
+        self.flush_writes_noted();
+        self.clear_register_cache();
+        self.code_cache.invalidate();
+        for core in self.present_cores_mut() {
             let system_reset = ResetClass::construct_reset_class(core, 0);
             _ = core.reset(system_reset, true);
         }
+        for breakpoint in self.breakpoints.values_mut() {
+            breakpoint.stale = true;
+        }
+        self.apply_boot_args();
+        self.replant_monitor_breakpoints();
+        self.replant_range_watches();
+        self.settle_other_cores();
+        self.set_initial_stop_first(StartupCause::Reset);
+        self.publish(TargetEvent::Reset);
     }
 
     // run till event
-    pub fn run(&mut self, mut poll_incoming_data: impl FnMut() -> bool) -> tricore::RunEvent {
+    pub fn run(
+        &mut self,
+        mut poll_incoming_data: impl FnMut() -> bool,
+        budget: Duration,
+    ) -> tricore::RunEvent {
+        if let Some((event, cpu_id)) = self.pending_events.pop_front() {
+            debug!("Reporting queued stop of core {:?}", cpu_id);
+            return tricore::RunEvent::Event(event, cpu_id);
+        }
+
+        let started = Instant::now();
         loop {
+            if started.elapsed() > budget {
+                break tricore::RunEvent::Yield;
+            }
             if poll_incoming_data() {
                 break tricore::RunEvent::IncomingData;
             }
-            for (index, core) in &mut self.cores.iter_mut().enumerate() {
-                match core.query_state() {
-                    Ok(core_info) => match core_info.state {
-                        CoreState::Debug => {
-                            let cpu_id = CpuId::try_from(index).expect("Unexpected core index");
-                            debug!("Core {:?} in Debug state", index);
-                            return tricore::RunEvent::Event(tricore::Event::Break, cpu_id);
-                        }
-                        CoreState::Custom => todo!(),
-                        CoreState::Halted => {
-                            // Halted state is one in which core is not under debugger control
-                            let cpu_id = CpuId::try_from(index).expect("Unexpected core index");
-                            debug!("Core: {:?} in halted state", cpu_id);
-                            return tricore::RunEvent::Event(tricore::Event::Break, cpu_id);
-                        }
-                        CoreState::Running => {
-                            debug!("Core {:?} Running", index);
+
+            if let Some(trigger) = self.pause.poll() {
+                info!("Halting target, requested by {}", trigger);
+                self.interrupt();
+                break tricore::RunEvent::Event(
+                    tricore::Event::HaltedByStub(tricore::HaltCause::Pause),
+                    self.stop_report_core(),
+                );
+            }
+
+            if !self.deferred.is_empty() {
+                self.plant_ready_breakpoints();
+            }
+
+            let stopped = self.stopped_cores();
+            // The STM keeps counting while halted, it is read before anything else
+            self.note_stop_time(&stopped);
+            let stopped: Vec<CpuId> = stopped
+                .into_iter()
+                .filter(|&cpu_id| !self.serve_semihost(cpu_id))
+                .filter(|&cpu_id| !self.resume_if_breakpoint(cpu_id, Breakpoint::take_ignored_hit))
+                .collect();
+            self.target_time.keep_stops(&stopped);
+            if !stopped.is_empty() {
+                // All-stop: once one core stops, the others are brought to a halt as well.
+                // Cores that stopped on their own in the same poll are reported afterwards.
+                self.halt();
+                self.apply_pending_breakpoints();
+                self.restore_step_irq_masks();
+                self.stats.record_stop();
+                self.end_target_time(&stopped);
+                for &cpu_id in &stopped {
+                    log_context::with_core(Some(cpu_id), || {
+                        self.snapshot_stop(cpu_id);
+                        if self.settings.cdc_watch {
+                            self.check_call_depth(cpu_id);
                         }
-                        CoreState::Unknown => todo!(),
-                    },
-                    Err(_) => {
-                        debug!("What is this weird undocumented state!")
-                    }
+                        let stepped_over = self.finish_step_over(cpu_id);
+                        let soft_stepped = self.finish_soft_step(cpu_id);
+                        let event = if stepped_over || soft_stepped {
+                            tricore::Event::DoneStep
+                        } else {
+                            let pc = self.stop_snapshots.get(&cpu_id).map(|stop| stop.pc);
+                            self.range_watch_hit(cpu_id, pc)
+                                .unwrap_or(tricore::Event::Break)
+                        };
+                        self.publish_halted(cpu_id, "breakpoint");
+                        self.pending_events.push_back((event, cpu_id));
+                    });
+                }
+                self.clear_step_overs();
+                self.remove_oneshot_hits(&stopped);
+                let (event, cpu_id) = self.pending_events.pop_front().unwrap();
+                return tricore::RunEvent::Event(event, cpu_id);
+            }
+
+            if self.settings.trap_loop_detect {
+                if let Some(cpu_id) = self.detect_trap_loop() {
+                    self.note_stop_time(&[cpu_id]);
+                    self.halt();
+                    self.apply_pending_breakpoints();
+                    self.restore_step_irq_masks();
+                    self.stats.record_stop();
+                    self.end_target_time(&[cpu_id]);
+                    let event = log_context::with_core(Some(cpu_id), || {
+                        self.snapshot_stop(cpu_id);
+                        self.publish_halted(cpu_id, "trap loop");
+                        self.report_trap_loop(cpu_id)
+                    });
+                    self.clear_step_overs();
+                    break tricore::RunEvent::Event(event, cpu_id);
                 }
             }
         }
     }
 
+    /// Reads PC and A10 of a stopped core. A failing read only loses the snapshot, the
+    /// stop is reported regardless.
+    fn snapshot_stop(&mut self, cpu_id: CpuId) {
+        let Some(core) = self.core(usize::from(cpu_id)) else {
+            return;
+        };
+        if let (Some(pc), Some(a10)) = (read_register(core, "PC"), read_register(core, "A10")) {
+            self.stop_snapshots.insert(cpu_id, StopSnapshot { pc, a10 });
+        }
+    }
+
+    /// Warns when the call depth counter of a stopped core is about to raise the CDO trap,
+    /// while the frames leading there can still be inspected
+    fn check_call_depth(&self, cpu_id: CpuId) {
+        let Some(psw) = self
+            .core(usize::from(cpu_id))
+            .and_then(|core| read_register(core, "PSW"))
+        else {
+            return;
+        };
+        if !decode::call_depth_enabled(psw) {
+            return;
+        }
+        let depth = decode::CallDepth::from_psw(psw);
+        let Some(headroom) = depth
+            .headroom()
+            .filter(|&headroom| headroom < self.settings.cdc_headroom)
+        else {
+            return;
+        };
+        let pc = self.stop_snapshots.get(&cpu_id).map(|snapshot| snapshot.pc);
+        warn!(
+            "Core {:?} at PC {} is {} calls away from a call depth overflow, {}",
+            cpu_id,
+            pc.map_or("unknown".to_owned(), |pc| format!("{:#010x}", pc)),
+            headroom,
+            depth
+        );
+    }
+
+    fn publish_halted(&mut self, cpu_id: CpuId, cause: &'static str) {
+        if self.events.is_some() || self.session_log.is_some() {
+            let pc = match self.stop_snapshots.get(&cpu_id) {
+                Some(snapshot) => Some(snapshot.pc),
+                None => self
+                    .core(usize::from(cpu_id))
+                    .and_then(|core| read_register(core, "PC")),
+            };
+            self.publish(TargetEvent::Halted {
+                cpu: cpu_id,
+                pc,
+                cause,
+            });
+        }
+    }
+
+    /// Masks interrupts of a core about to be stepped, remembering the previous state
+    fn mask_step_irq(&mut self, index: usize) {
+        let Some(core) = self.core(index) else {
+            return;
+        };
+        let Some(icr) = read_register(core, "ICR") else {
+            warn!(
+                "Cannot read ICR of core {}, stepping with interrupts unchanged",
+                index
+            );
+            return;
+        };
+        // An instruction setting ICR.IE itself decides it after the step
+        let sets_ie = read_register(core, "PC")
+            .and_then(|pc| core.read_bytes(pc as u64, 4).ok())
+            .is_some_and(|code| decode::writes_icr(&code));
+        self.invalidate_registers(index);
+        if write_register(core, "ICR", icr & !ICR_IE).is_some() && !sets_ie {
+            let cpu_id = CpuId::try_from(index).expect("Unexpected core index");
+            self.saved_irq_enable.insert(cpu_id, icr & ICR_IE != 0);
+        }
+    }
+
+    /// Restores the interrupt enable bit of cores stepped with interrupts masked.
+    ///
+    /// Only ICR.IE is restored, everything else the step changed in ICR is kept. A set IE
+    /// was written by the stepped code, e.g. an instruction not decoded, and is kept too.
+    fn restore_step_irq_masks(&mut self) {
+        for (cpu_id, enabled) in self.saved_irq_enable.drain() {
+            let Some(slot) = self.slot_mut(cpu_id) else {
+                continue;
+            };
+            slot.register_cache = None;
+            let Some(core) = &slot.core else {
+                continue;
+            };
+            let restored = read_register(core, "ICR").and_then(|icr| {
+                if icr & ICR_IE != 0 || !enabled {
+                    return Some(());
+                }
+                write_register(core, "ICR", icr | ICR_IE)
+            });
+            if restored.is_none() {
+                warn!("Cannot restore interrupt enable of core {:?}", cpu_id);
+            }
+        }
+    }
+
+    /// Polls the cores due in this iteration and returns the ones that are no longer running
+    fn stopped_cores(&mut self) -> Vec<CpuId> {
+        let mut stopped = Vec::new();
+        let due = self
+            .poll_schedule
+            .due(self.slots.len(), self.settings.background_poll);
+        for index in due {
+            if !self.in_session(index) {
+                continue;
+            }
+            let Some(core) = self.slots.get(index).and_then(|slot| slot.core.as_ref()) else {
+                continue;
+            };
+            match core.query_state() {
+                Ok(core_info) => match core_info.state {
+                    CoreState::Debug => {
+                        self.state_log.state(index, "in Debug state");
+                        stopped.push(CpuId::try_from(index).expect("Unexpected core index"));
+                    }
+                    CoreState::Custom => self.state_log.unexpected_state(index, "a custom state"),
+                    CoreState::Halted => {
+                        // Halted state is one in which core is not under debugger control
+                        self.state_log.state(index, "in halted state");
+                        stopped.push(CpuId::try_from(index).expect("Unexpected core index"));
+                    }
+                    CoreState::Running => self.state_log.state(index, "Running"),
+                    CoreState::Unknown => {
+                        self.state_log.unexpected_state(index, "an unknown state")
+                    }
+                },
+                Err(_) => self.state_log.query_failed(index),
+            }
+        }
+        self.state_log.tick();
+        stopped
+    }
+
+    /// Transparently resumes a core which stopped on a breakpoint for which `skip_hit` decides
+    /// the hit is not to be reported. Returns whether the core was resumed.
+    fn resume_if_breakpoint(
+        &mut self,
+        cpu_id: CpuId,
+        skip_hit: impl FnOnce(&mut Breakpoint) -> bool,
+    ) -> bool {
+        let index = usize::from(cpu_id);
+        let Some(pc) = self.core(index).and_then(|core| read_register(core, "PC")) else {
+            return false;
+        };
+
+        let stepping = matches!(
+            self.slot_at(index).map(|slot| slot.resume_action),
+            Some(ResumeAction::Step)
+        );
+        let hardware_step = self.hardware_stepping(index);
+        let key = self.breakpoint_key(pc);
+        let Some(breakpoint) = self.breakpoints.get_mut(&key) else {
+            return false;
+        };
+        // A step ending on the address is no hit
+        if !stepping {
+            breakpoint.timing.record(Instant::now());
+        }
+
+        // A hardware step ending on the address is reported as the step it is, it takes
+        // no ignored hit
+        if hardware_step || !skip_hit(breakpoint) {
+            return false;
+        }
+
+        debug!("Silently resuming core {:?} at {:#010x}", cpu_id, pc);
+        match self.step_off_breakpoint(index) {
+            // Reported where the core is now, as the run would have stopped there
+            step_off::StepOff::Stopped(_) => false,
+            step_off::StepOff::Stepped | step_off::StepOff::NotOnBreakpoint => {
+                self.restart_core(index)
+            }
+        }
+    }
+
+    /// Human readable execution state of a core, as shown in `info threads`
+    pub(crate) fn core_state_name(&self, tid: Tid) -> &'static str {
+        let Ok(core) = self.get_core(tid) else {
+            return "absent";
+        };
+        match core.query_state() {
+            Ok(core_info) => match core_info.state {
+                CoreState::Debug => "halted",
+                CoreState::Halted => "halted (no debug control)",
+                CoreState::Running => "running",
+                CoreState::Custom | CoreState::Unknown => "unknown",
+            },
+            Err(_) => "unknown",
+        }
+    }
+
+    /// Lets every present core run, e.g. after loading when no debugger is attached yet
+    pub fn run_all(&mut self) {
+        self.initial_stop = None;
+        self.left_running = true;
+        self.clear_register_cache();
+        self.flush_triggers();
+        let cores = self.all_cores();
+        for (position, index) in self.resume_sequence(&cores).into_iter().enumerate() {
+            self.stagger_start(position);
+            let cpu = CpuId::try_from(index).expect("Unexpected core index");
+            match self.core_mut(index).map(|core| core.run()) {
+                Some(Ok(_)) => self.publish(TargetEvent::Resumed { cpu }),
+                _ => warn!("Cannot run core {}", index),
+            }
+        }
+    }
+
     pub fn halt(&mut self) {
-        for core in &mut self.cores.iter_mut() {
-            _ = core.stop();
+        self.left_running = false;
+        for index in self.all_cores() {
+            if let Some(core) = self.core_mut(index) {
+                _ = core.stop();
+            }
         }
     }
 
-    fn get_core(&self, tid: Tid) -> Result<&Core<'static>, TricoreTargetError> {
-        let core_id = tid_to_cpuid(tid).map_err(|_|TricoreTargetError::Fatal(format!("tid_to_cpuid failed")))?;
-        let index = usize::from(core_id);
-        self.cores
-            .get(index)
-            .ok_or_else(|| TricoreTargetError::Fatal(format!("Invalid core index")))
+    /// Lets the cores halted by `--halt-on-connect` run again, once GDB detached. Cores
+    /// the session left running, or which were absent, are not touched.
+    pub(crate) fn resume_halted_on_connect(&mut self) {
+        self.clear_register_cache();
+        self.flush_triggers();
+        for index in std::mem::take(&mut self.halted_on_connect) {
+            let cpu = CpuId::try_from(index).expect("Unexpected core index");
+            let Some(core) = self.core_mut(index) else {
+                continue;
+            };
+            if !matches!(
+                core.query_state().map(|info| info.state),
+                Ok(CoreState::Debug)
+            ) {
+                continue;
+            }
+            match core.run() {
+                Ok(_) => self.publish(TargetEvent::Resumed { cpu }),
+                Err(_) => warn!("Cannot resume core {}", index),
+            }
+        }
+    }
+
+    /// Core a halt of the whole target is reported on: the first core the last resume
+    /// started, the first present core otherwise
+    pub(crate) fn stop_report_core(&self) -> CpuId {
+        self.slots()
+            .find(|(index, slot)| {
+                slot.core.is_some()
+                    && self.in_session(*index)
+                    && !matches!(slot.resume_action, ResumeAction::Unchanged)
+            })
+            .map(|(index, _)| index)
+            .or_else(|| self.all_cores().first().copied())
+            .and_then(|index| CpuId::try_from(index).ok())
+            .unwrap_or(CpuId::Cpu0)
+    }
+
+    /// Halts every core on behalf of the GDB user
+    pub fn interrupt(&mut self) {
+        self.halt();
+        self.apply_pending_breakpoints();
+        self.restore_step_irq_masks();
+        self.clear_step_overs();
+        let cores: Vec<CpuId> = self
+            .all_cores()
+            .into_iter()
+            .map(|index| CpuId::try_from(index).expect("Unexpected core index"))
+            .collect();
+        self.end_target_time(&cores);
+        for cpu_id in cores {
+            self.publish_halted(cpu_id, "interrupt");
+        }
+    }
+
+    /// Reads target memory, program flash through the code cache
+    pub(crate) fn read_memory(
+        &mut self,
+        tid: Tid,
+        start_addr: u32,
+        data: &mut [u8],
+    ) -> Result<(), TricoreTargetError> {
+        self.flush_writes()?;
+        if self.read_cached(tid, start_addr, data)? {
+            return Ok(());
+        }
+        self.read_through(tid, start_addr, data)
+    }
+
+    /// Reads target memory in bounded chunks, so large requests never allocate more
+    /// than [READ_CHUNK_SIZE] bytes at a time.
+    pub(crate) fn read_through(
+        &mut self,
+        tid: Tid,
+        start_addr: u32,
+        data: &mut [u8],
+    ) -> Result<(), TricoreTargetError> {
+        let index = self.route_memory(tid, start_addr)?;
+        // Borrow the core through its field so the statistics can be updated alongside
+        let core = present_core(&self.slots, index)?;
+
+        let link_health = &mut self.link_health;
+        read_in_chunks(start_addr, data, &mut self.stats, |chunk_addr, len| {
+            core.read_bytes(chunk_addr as u64, len).map_err(|_| {
+                link_health.record_error();
+                debug!(
+                    "Cannot read from requested address range {:0x} - {:0x}",
+                    chunk_addr,
+                    chunk_addr as u64 + len as u64
+                );
+                TricoreTargetError::MemoryAccess(format!("read at {:#010x}", chunk_addr))
+            })
+        })
+    }
+
+    /// Rewrites a core local scratchpad address into the global alias of the core behind
+    /// `tid`, so the access does not depend on the core the probe routes it through. A
+    /// range only partly inside a local window is refused rather than accessed untranslated.
+    pub(crate) fn translate_local(
+        &self,
+        tid: Tid,
+        addr: u32,
+        len: usize,
+    ) -> Result<u32, TricoreTargetError> {
+        let Some(map) = self.arch.local_map() else {
+            return Ok(addr);
+        };
+        let index = self.core_index(tid)?;
+        match map.to_global(index, addr, len) {
+            Ok(Some(global)) => {
+                trace!("Core {} local {:#010x} -> {:#010x}", index, addr, global);
+                Ok(global)
+            }
+            Ok(None) => Ok(addr),
+            Err(e) => Err(TricoreTargetError::MemoryAccess(format!(
+                "{} bytes at {:#010x}: {}",
+                len, addr, e
+            ))),
+        }
+    }
+
+    pub(crate) fn write_memory(
+        &mut self,
+        tid: Tid,
+        start_addr: u32,
+        data: &[u8],
+    ) -> Result<(), TricoreTargetError> {
+        self.flush_writes()?;
+        // CSFR writes can change any register, e.g. PC or PSW
+        for index in memory_map::csfr_cores(start_addr, data.len()) {
+            self.invalidate_registers(index);
+        }
+        let index = self.route_memory(tid, start_addr)?;
+        let core = present_core(&self.slots, index)?;
+        core.write(start_addr as u64, data.to_vec()).map_err(|_| {
+            self.link_health.record_error();
+            debug!("Cannot write to addr {:0x} ", start_addr);
+            TricoreTargetError::MemoryAccess(format!("write at {:#010x}", start_addr))
+        })?;
+        self.stats.record_write(data.len());
+        if memory_map::flash_region_in(self.arch.regions(), start_addr, data.len()).is_some() {
+            self.code_cache.invalidate();
+        }
+        if self.settings.verify_writes {
+            self.verify_write(tid, start_addr, data)?;
+        }
+        Ok(())
+    }
+
+    /// Refuses a GDB write touching flash unless `allow-flash-writes` is on. The probe
+    /// cannot program flash with plain writes, so the first refusal explains why.
+    pub(crate) fn refuse_flash_write(&mut self, start_addr: u32, len: usize) -> bool {
+        if self.settings.allow_flash_writes {
+            return false;
+        }
+        let Some(region) = memory_map::flash_region_in(self.arch.regions(), start_addr, len) else {
+            return false;
+        };
+
+        debug!(
+            "Refusing write of {} bytes at {:#010x} in {}",
+            len, start_addr, region.name
+        );
+        if !self.flash_write_hint_shown {
+            self.flash_write_hint_shown = true;
+            warn!(
+                "{:#010x} is in flash ({}) which cannot be written like RAM. Change the elf \
+                 and program it with --elf_file, or `monitor set allow-flash-writes on` if the \
+                 device supports programming erased cells this way",
+                start_addr, region.name
+            );
+        }
+        true
+    }
+
+    /// Physical core index of a thread. Threads keep their index for the whole session,
+    /// a Tid GDB still holds for a core that is gone fails without ending the session.
+    fn core_index(&self, tid: Tid) -> Result<usize, TricoreTargetError> {
+        // The single thread of `--single-core` is the selected core
+        let tid = match self.settings.single_core {
+            Some(cpu_id) if tid.get() == 1 => cpuid_to_tid(cpu_id),
+            _ => tid,
+        };
+        let index = tid.get() - 1;
+        match tid_to_cpuid(tid) {
+            Ok(core_id) if self.slot(core_id).is_some() => Ok(index),
+            _ => Err(TricoreTargetError::NotPresent(index)),
+        }
+    }
+
+    fn get_core(&self, tid: Tid) -> Result<&Core<'a>, TricoreTargetError> {
+        present_core(&self.slots, self.core_index(tid)?)
+    }
+
+    /// Core at a physical index, `None` if there is no such core or it is absent
+    pub(crate) fn core(&self, index: usize) -> Option<&Core<'a>> {
+        self.slot_at(index).and_then(|slot| slot.core.as_ref())
+    }
+
+    pub(crate) fn core_mut(&mut self, index: usize) -> Option<&mut Core<'a>> {
+        self.slot_at_mut(index).and_then(|slot| slot.core.as_mut())
     }
 }
 
-impl Target for StaticTricoreTarget {
-    type Arch = TricoreV1_6;
+/// Reads a single register from the first register group of a core
+fn read_register(core: &Core<'_>, name: &str) -> Option<u32> {
+    let groups = core.register_groups().ok()?;
+    let group = groups.get_group(0).ok()?;
+    group.register(name)?.read().ok()
+}
+
+/// Halts a core where it is and waits for it to report the halt
+fn halt_core(core: &mut Core<'_>) -> Result<(), String> {
+    core.stop().map_err(|e| e.to_string())?;
+    let started = Instant::now();
+    while !matches!(
+        core.query_state().map(|info| info.state),
+        Ok(CoreState::Debug)
+    ) {
+        if started.elapsed() > CONNECT_HALT_TIMEOUT {
+            return Err(format!(
+                "did not halt within {} ms",
+                CONNECT_HALT_TIMEOUT.as_millis()
+            ));
+        }
+        sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+/// Writes a single register of the first register group of a core
+fn write_register(core: &Core<'_>, name: &str, value: u32) -> Option<()> {
+    let groups = core.register_groups().ok()?;
+    let group = groups.get_group(0).ok()?;
+    group.register(name)?.write(value).ok()
+}
+
+impl<'a> Target for TricoreTarget<'a> {
+    type Arch = TricoreArch;
     type Error = &'static str;
 
     #[inline(always)]
     fn base_ops(&mut self) -> target::ext::base::BaseOps<'_, Self::Arch, Self::Error> {
-        target::ext::base::BaseOps::MultiThread(self)
+        if self.settings.single_core.is_some() {
+            target::ext::base::BaseOps::SingleThread(self)
+        } else {
+            target::ext::base::BaseOps::MultiThread(self)
+        }
     }
 
     #[inline(always)]
@@ -254,6 +1471,20 @@ impl Target for StaticTricoreTarget {
         Some(self)
     }
 
+    #[inline(always)]
+    fn support_target_description_xml_override(
+        &mut self,
+    ) -> Option<
+        target::ext::target_description_xml_override::TargetDescriptionXmlOverrideOps<'_, Self>,
+    > {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_exec_file(&mut self) -> Option<target::ext::exec_file::ExecFileOps<'_, Self>> {
+        Some(self)
+    }
+
     #[inline(always)]
     fn support_extended_mode(
         &mut self,
@@ -261,3 +1492,70 @@ impl Target for StaticTricoreTarget {
         Some(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addr_len_pairs_are_parsed() {
+        assert_eq!(parse_addr_len("0x70000000:64"), Ok((0x7000_0000, 64)));
+        assert_eq!(parse_addr_len("1024:0X100"), Ok((1024, 0x100)));
+        assert_eq!(parse_addr_len("0xfffffff0:16"), Ok((0xffff_fff0, 16)));
+        assert!(parse_addr_len("0x70000000").is_err());
+        assert!(parse_addr_len("0x70000000:0").is_err());
+        assert!(parse_addr_len("0xfffffff0:17").is_err());
+        assert!(parse_addr_len("ram:64").is_err());
+        assert!(parse_addr_len("0x1000:-4").is_err());
+    }
+
+    #[test]
+    fn large_read_stays_within_a_chunk_buffer() {
+        const LEN: usize = 16 * 1024 * 1024;
+        let mut data = vec![0u8; LEN];
+        let mut stats = McdStats::default();
+        let mut requested = 0;
+        read_in_chunks(0x9000_0000, &mut data, &mut stats, |addr, len| {
+            assert_eq!(addr, 0x9000_0000 + requested as u32);
+            requested += len;
+            Ok::<_, ()>((0..len).map(|i| (addr as usize + i) as u8).collect())
+        })
+        .unwrap();
+
+        assert_eq!(requested, LEN);
+        assert!(data.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+        assert_eq!(stats.reads, (LEN / READ_CHUNK_SIZE) as u64);
+        assert_eq!(stats.read_bytes, LEN as u64);
+        assert_eq!(stats.read_allocations, stats.reads);
+        assert_eq!(stats.read_allocated_bytes, LEN as u64);
+        assert_eq!(stats.peak_read_buffer, READ_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn failed_chunk_ends_the_read() {
+        let mut data = vec![0u8; 3 * READ_CHUNK_SIZE];
+        let mut stats = McdStats::default();
+        let result = read_in_chunks(0x7000_0000, &mut data, &mut stats, |addr, len| {
+            if addr == 0x7000_0000 + READ_CHUNK_SIZE as u32 {
+                Err(addr)
+            } else {
+                Ok(vec![0xAA; len])
+            }
+        });
+        assert_eq!(result, Err(0x7000_1000));
+        assert_eq!(stats.reads, 1);
+        assert!(data[READ_CHUNK_SIZE..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("reset", "reset"), 0);
+        assert_eq!(edit_distance("", "halt"), 4);
+        assert_eq!(edit_distance("halt", ""), 4);
+        assert_eq!(edit_distance("rset", "reset"), 1);
+        assert_eq!(edit_distance("resets", "reset"), 1);
+        assert_eq!(edit_distance("reaet", "reset"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
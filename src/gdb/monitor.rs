@@ -1,8 +1,480 @@
+use std::fmt;
+
 use gdbstub::{outputln, target::ext::monitor_cmd::ConsoleOutput};
+use log::info;
+
+use super::breakpoints::PendingChange;
+use super::monitor_registry::{self, Lookup, BUILTIN_COMMANDS};
+use super::settings::format_core_list;
+use super::{decode, flash, parse_u32, read_register, TricoreTarget};
+
+/// Exit code of `exit [code]`, 0 without one
+fn exit_code(allow_exit: bool, code: Option<&str>) -> Result<u8, &'static str> {
+    if !allow_exit {
+        return Err("Refused, allow-exit is off");
+    }
+    match code.map(str::parse::<u8>) {
+        None => Ok(0),
+        Some(Ok(code)) => Ok(code),
+        Some(Err(_)) => Err("usage: exit [code], a code of 0 to 255"),
+    }
+}
+
+/// Output of a monitor command to the GDB console, also kept for the session log
+pub struct MonitorOutput<'a> {
+    /// `None` for commands not sent by GDB, e.g. of `--init-commands`
+    gdb: Option<ConsoleOutput<'a>>,
+    /// Output so far, `None` without a session log
+    mirror: Option<String>,
+    /// The command was refused or not understood
+    failed: bool,
+}
+
+impl<'a> MonitorOutput<'a> {
+    fn new(gdb: ConsoleOutput<'a>, mirror: bool) -> Self {
+        MonitorOutput {
+            gdb: Some(gdb),
+            mirror: mirror.then(String::new),
+            failed: false,
+        }
+    }
+
+    /// Output kept in memory only, see [MonitorOutput::into_text]
+    pub(crate) fn captured() -> Self {
+        MonitorOutput {
+            gdb: None,
+            mirror: Some(String::new()),
+            failed: false,
+        }
+    }
+
+    /// Marks the command as failed, stops a script of monitor commands
+    pub(crate) fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    pub(crate) fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// The output kept so far
+    pub(crate) fn into_text(self) -> String {
+        self.mirror.unwrap_or_default()
+    }
+}
+
+impl fmt::Write for MonitorOutput<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Some(mirror) = &mut self.mirror {
+            mirror.push_str(s);
+        }
+        match &mut self.gdb {
+            Some(gdb) => fmt::Write::write_str(gdb, s),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Handler of a monitor command registered by an embedding application, called with the
+/// arguments following the command name
+pub type MonitorCommand = Box<dyn FnMut(&mut TricoreTarget<'_>, &[&str], &mut MonitorOutput<'_>)>;
+
+impl<'a> TricoreTarget<'a> {
+    /// Adds `monitor <name> ...` to the commands of the stub. Built-in commands take
+    /// precedence, a later registration under the same name replaces the earlier one.
+    pub fn register_monitor_command(&mut self, name: &str, handler: MonitorCommand) {
+        self.external_commands.insert(name.to_owned(), handler);
+    }
+
+    fn run_external_command(&mut self, name: &str, args: &[&str], out: &mut MonitorOutput<'_>) {
+        // The handler is taken out while it runs, so it can borrow the target mutably
+        let Some(mut handler) = self.external_commands.remove(name) else {
+            return;
+        };
+        handler(self, args, out);
+        self.external_commands
+            .entry(name.to_owned())
+            .or_insert(handler);
+    }
+
+    pub(crate) fn print_breakpoints(&self, out: &mut MonitorOutput<'_>) {
+        if self.breakpoints.is_empty()
+            && self.deferred.is_empty()
+            && self.pending_breakpoints.is_empty()
+        {
+            outputln!(out, "No breakpoints");
+            return;
+        }
+
+        let mut addresses: Vec<&u32> = self.breakpoints.keys().collect();
+        addresses.sort();
+        for addr in addresses {
+            let breakpoint = &self.breakpoints[addr];
+            outputln!(
+                out,
+                "{:#010x}{} kind: {} cores: {} ignore: {} skipped: {}{}{}{}{}",
+                addr,
+                breakpoint.aliases(*addr),
+                breakpoint.kind,
+                breakpoint.planted_cores(),
+                breakpoint.ignore_count,
+                breakpoint.skipped,
+                if breakpoint.oneshot { " oneshot" } else { "" },
+                if breakpoint.from_monitor {
+                    " (monitor)"
+                } else {
+                    ""
+                },
+                if breakpoint.suspect { " (suspect)" } else { "" },
+                breakpoint.timing.columns()
+            );
+        }
+        self.print_timing_caveat(out);
+
+        let mut deferred: Vec<_> = self.deferred.iter().collect();
+        deferred.sort_by_key(|(addr, _)| **addr);
+        for (addr, guard) in deferred {
+            outputln!(out, "{:#010x} deferred until {}", addr, guard);
+        }
+
+        for change in &self.pending_breakpoints {
+            let action = match change {
+                PendingChange::Add { .. } => "add",
+                PendingChange::Remove { .. } => "remove",
+            };
+            outputln!(
+                out,
+                "{:#010x} pending {}, applied at the next stop",
+                change.address(),
+                action
+            );
+        }
+
+        for index in self.all_cores() {
+            let limit = self
+                .das_capabilities
+                .trigger_limits
+                .get(index)
+                .copied()
+                .flatten()
+                .map_or("?".to_owned(), |limit| limit.to_string());
+            outputln!(
+                out,
+                "{}/{} used on CPU{}",
+                self.triggers_in_use(index),
+                limit,
+                index
+            );
+        }
+    }
 
-use super::TricoreTarget;
+    pub(crate) fn psw_info(&mut self, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let index = match core.map(parse_u32) {
+            None => 0,
+            Some(Some(index)) => index as usize,
+            Some(None) => {
+                outputln!(out, "usage: psw [core]");
+                return;
+            }
+        };
+        let Some(core) = self.core(index) else {
+            outputln!(out, "No core {}", index);
+            return;
+        };
+        let Some(psw) = read_register(core, "PSW") else {
+            outputln!(out, "Cannot read PSW of core {}", index);
+            return;
+        };
 
-impl gdbstub::target::ext::monitor_cmd::MonitorCmd for TricoreTarget<'static> {
+        outputln!(out, "{:<20} {:#010x}", "PSW", psw);
+        for (key, value) in decode::psw_fields(psw) {
+            outputln!(out, "{:<20} {}", key, value);
+        }
+    }
+
+    pub(crate) fn print_cores(&self, out: &mut MonitorOutput<'_>) {
+        for (index, slot) in self.slots() {
+            let capabilities = &slot.capabilities;
+            if let Some(error) = &slot.absent {
+                outputln!(out, "CPU{} absent: {}", index, error);
+                continue;
+            }
+            outputln!(
+                out,
+                "CPU{} fpu: {} system: {}",
+                index,
+                if capabilities.fpu { "yes" } else { "no" },
+                capabilities.system_registers.join(" ")
+            );
+            let missing = capabilities.missing();
+            if !missing.is_empty() {
+                outputln!(out, "     unavailable: {}", missing.join(" "));
+            }
+        }
+    }
+
+    pub(crate) fn print_addrmap(&self, out: &mut MonitorOutput<'_>) {
+        let Some(map) = self.arch.local_map() else {
+            outputln!(
+                out,
+                "No translation of core local addresses for {}",
+                self.arch
+            );
+            return;
+        };
+        for window in map.windows {
+            for (index, segment) in map.core_segments.iter().enumerate() {
+                let global = (segment << 28) + window.segment_offset;
+                outputln!(
+                    out,
+                    "{} CPU{}: {:#010x}..{:#010x} -> {:#010x}",
+                    window.name,
+                    index,
+                    window.start,
+                    window.start as u64 + window.len as u64,
+                    global
+                );
+            }
+        }
+    }
+
+    pub(crate) fn set_oneshot(&mut self, addr: &str, out: &mut MonitorOutput<'_>) {
+        let Some(addr) = parse_u32(addr) else {
+            outputln!(out, "usage: bp oneshot <addr>");
+            return;
+        };
+
+        let addr = self.breakpoint_address(addr);
+        let key = self.breakpoint_key(addr);
+        match self.breakpoints.get_mut(&key) {
+            Some(breakpoint) => {
+                breakpoint.oneshot = true;
+                outputln!(out, "Removing {:#010x} on its first hit", addr);
+            }
+            None => outputln!(out, "No breakpoint at {:#010x}", addr),
+        }
+    }
+
+    pub(crate) fn set_ignore_count(
+        &mut self,
+        addr: &str,
+        count: &str,
+        out: &mut MonitorOutput<'_>,
+    ) {
+        let (Some(addr), Some(count)) = (parse_u32(addr), parse_u32(count)) else {
+            outputln!(out, "usage: bp ignore <addr> <count>");
+            return;
+        };
+
+        let addr = self.breakpoint_address(addr);
+        let key = self.breakpoint_key(addr);
+        match self.breakpoints.get_mut(&key) {
+            Some(breakpoint) => {
+                breakpoint.ignore_count = count;
+                outputln!(out, "Ignoring the next {} hits at {:#010x}", count, addr);
+            }
+            None => outputln!(out, "No breakpoint at {:#010x}", addr),
+        }
+    }
+
+    /// Appends a monitor command and its output to the session log
+    pub(crate) fn log_monitor_command(&mut self, cmd: &str, output: &str) {
+        if let Some(log) = &mut self.session_log {
+            log.line(None, format_args!("monitor {}", cmd));
+            for line in output.lines() {
+                log.line(None, format_args!("{}", line));
+            }
+        }
+    }
+
+    /// Whether `word` runs an application command. Those only give way to built-in
+    /// commands of exactly that name, not to abbreviations.
+    fn is_external_command(&self, word: &str) -> bool {
+        let builtin = BUILTIN_COMMANDS
+            .iter()
+            .any(|command| command.name.split_whitespace().next() == Some(word));
+        !builtin && self.external_commands.contains_key(word)
+    }
+
+    /// Whether `cmd` runs a built-in command which only inspects state, the commands
+    /// offered to observers
+    pub(crate) fn is_read_only_command(&self, cmd: &str) -> bool {
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+        match args.first() {
+            Some(word) if !self.is_external_command(word) => matches!(
+                monitor_registry::lookup(&args),
+                Lookup::Found(command, _) if command.read_only
+            ),
+            _ => false,
+        }
+    }
+
+    /// Runs a monitor command, whether sent by GDB or read from a script
+    pub(crate) fn monitor_command(&mut self, cmd: &str, out: &mut MonitorOutput<'_>) {
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+
+        if let Err(e) = self.flush_writes() {
+            outputln!(out, "Cannot write GDB's memory changes: {}", e);
+        }
+        if args.is_empty() {
+            outputln!(out, "Sorry, didn't catch that. Try `monitor help`!");
+            return;
+        }
+
+        if self.is_external_command(args[0]) {
+            if let Some(busy) = &self.busy {
+                outputln!(out, "Refused, {}", busy);
+                out.fail();
+                return;
+            }
+            self.run_external_command(args[0], &args[1..], out);
+            return;
+        }
+
+        let (command, rest) = match monitor_registry::lookup(&args) {
+            Lookup::Found(command, rest) => (command, rest),
+            Lookup::Ambiguous(commands) => {
+                outputln!(out, "'{}' may be:", cmd.trim());
+                for command in commands {
+                    outputln!(out, "  {}", command.usage());
+                }
+                out.fail();
+                return;
+            }
+            Lookup::Unknown => {
+                outputln!(out, "I don't know how to handle '{}'", cmd);
+                let external = self.external_commands.keys().map(String::as_str);
+                if let Some(name) = monitor_registry::closest(&args, external) {
+                    outputln!(out, "Did you mean `{}`?", name);
+                }
+                out.fail();
+                return;
+            }
+        };
+        if let Some(busy) = &self.busy {
+            if !command.while_busy {
+                outputln!(out, "Refused, {}", busy);
+                out.fail();
+                return;
+            }
+        }
+        if !command.accepts(rest.len()) {
+            outputln!(out, "usage: {}", command.usage());
+            out.fail();
+            return;
+        }
+        (command.handler)(self, rest, out);
+    }
+
+    /// `info`
+    pub(crate) fn print_info(&self, out: &mut MonitorOutput<'_>) {
+        if let Some(session_id) = &self.settings.session_id {
+            outputln!(out, "{:<20} {}", "session id", session_id);
+        }
+        outputln!(out, "{:<20} {}", "architecture", self.arch);
+        outputln!(out, "{:<20} {}", "cores", self.all_cores().len());
+        for (key, value) in self.das_capabilities.entries() {
+            outputln!(out, "{:<20} {}", key, value);
+        }
+        let absent = self
+            .slots()
+            .filter(|(_, slot)| slot.absent.is_some())
+            .count();
+        if absent > 0 {
+            outputln!(out, "{:<20} {}", "absent cores", absent);
+        }
+        if let Some(peripherals) = &self.peripherals {
+            outputln!(out, "{:<20} {}", "peripheral registers", peripherals.len());
+        }
+    }
+
+    /// `status`
+    pub(crate) fn print_status(&self, out: &mut MonitorOutput<'_>) {
+        match &self.metrics {
+            Some(metrics) => {
+                for (key, value) in metrics.entries() {
+                    outputln!(out, "{:<20} {}", key, value);
+                }
+            }
+            None => outputln!(out, "No metrics available"),
+        }
+    }
+
+    /// `mcd-stats`
+    pub(crate) fn print_mcd_stats(&self, out: &mut MonitorOutput<'_>) {
+        for (key, value) in self.stats.entries() {
+            outputln!(out, "{:<20} {}", key, value);
+        }
+    }
+
+    /// `set <key> <value>`
+    pub(crate) fn set_command(&mut self, key: &str, value: &str, out: &mut MonitorOutput<'_>) {
+        match self.settings.set(key, value) {
+            Ok(()) => outputln!(out, "{} = {}", key, value),
+            Err(e) => {
+                outputln!(out, "{}", e);
+                out.fail();
+            }
+        }
+    }
+
+    /// `show`
+    pub(crate) fn print_settings(&self, out: &mut MonitorOutput<'_>) {
+        let cores: Vec<usize> = (0..self.core_count()).collect();
+        for (key, value) in self.settings.entries() {
+            // The order the detected cores are started in, listed or not
+            let value = match key {
+                "resume-order" => format!(
+                    "{} ({})",
+                    format_core_list(&self.resume_sequence(&cores)),
+                    value
+                ),
+                _ => value,
+            };
+            outputln!(out, "{:<20} {}", key, value);
+        }
+    }
+
+    /// `flash usage`
+    pub(crate) fn print_flash_usage(&self, out: &mut MonitorOutput<'_>) {
+        if self.elfs.is_empty() {
+            outputln!(out, "No elf provided");
+            return;
+        }
+        let usage = flash::flash_usage(&self.elfs.segments(), self.arch.regions());
+        for line in flash::flash_usage_report(&usage, self.settings.flash_fill_warn) {
+            outputln!(out, "{}", line);
+        }
+    }
+
+    /// `exit [code]`, the session ends once the reply is sent
+    pub(crate) fn exit_command(&mut self, code: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let code = match exit_code(self.settings.allow_exit, code) {
+            Ok(code) => code,
+            Err(e) => {
+                outputln!(out, "{}", e);
+                out.fail();
+                return;
+            }
+        };
+        info!("monitor exit, the stub ends with code {}", code);
+        outputln!(out, "Ending the session, the stub exits with code {}", code);
+        self.exit_requested = Some(code);
+    }
+
+    /// `flash abort`
+    pub(crate) fn flash_abort(&mut self, out: &mut MonitorOutput<'_>) {
+        match &mut self.busy {
+            Some(busy) => {
+                busy.abort_requested = true;
+                outputln!(out, "Aborting {}", busy.operation);
+            }
+            None => outputln!(out, "No flash operation in progress"),
+        }
+    }
+}
+
+impl<'a> gdbstub::target::ext::monitor_cmd::MonitorCmd for TricoreTarget<'a> {
     fn handle_monitor_cmd(
         &mut self,
         cmd: &[u8],
@@ -16,12 +488,44 @@ impl gdbstub::target::ext::monitor_cmd::MonitorCmd for TricoreTarget<'static> {
             }
         };
 
-        match cmd {
-            "" => outputln!(out, "Sorry, didn't catch that. Try `monitor ping`!"),
-            "ping" => outputln!(out, "pong!"),
-            _ => outputln!(out, "I don't know how to handle '{}'", cmd),
-        };
+        // Logged already when they were made
+        for note in self.gdb_notes.drain(..) {
+            outputln!(out, "{}", note);
+        }
+        let mut out = MonitorOutput::new(out, self.session_log.is_some());
+        self.monitor_command(cmd, &mut out);
+        if let Some(output) = out.mirror {
+            self.log_monitor_command(cmd, &output);
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_defaults_to_code_zero() {
+        assert_eq!(exit_code(true, None), Ok(0));
+        assert_eq!(exit_code(true, Some("3")), Ok(3));
+        assert_eq!(exit_code(true, Some("255")), Ok(255));
+    }
+
+    #[test]
+    fn exit_codes_out_of_range_are_refused() {
+        assert!(exit_code(true, Some("256")).is_err());
+        assert!(exit_code(true, Some("-1")).is_err());
+        assert!(exit_code(true, Some("ok")).is_err());
+    }
+
+    #[test]
+    fn exit_is_refused_while_allow_exit_is_off() {
+        assert_eq!(exit_code(false, None), Err("Refused, allow-exit is off"));
+        assert_eq!(
+            exit_code(false, Some("1")),
+            Err("Refused, allow-exit is off")
+        );
+    }
+}
@@ -0,0 +1,831 @@
+//! Table of the built-in monitor commands, the single source of dispatch and `monitor help`.
+//!
+//! A command is named by one or more words, e.g. `bp sync`, followed by its arguments. The
+//! synopsis of the arguments also gives their count: `<arg>` is required, `[arg]` optional
+//! and a trailing `...` takes any number more. Every word of a name may be abbreviated as
+//! long as it is unambiguous among the commands sharing the words before it, an exact word
+//! always wins. Where no command continues the words, the command named by the words so
+//! far takes the rest as arguments, so `fpu 2` runs `fpu [core]` while `fpu rm ...` runs
+//! `fpu rm`.
+
+use gdbstub::outputln;
+
+use super::{edit_distance, MonitorOutput, TricoreTarget};
+
+/// Group a command is listed under by `monitor help`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Category {
+    General,
+    Breakpoints,
+    Cores,
+    Memory,
+    Flash,
+}
+
+impl Category {
+    const ALL: [Category; 5] = [
+        Category::General,
+        Category::Breakpoints,
+        Category::Cores,
+        Category::Memory,
+        Category::Flash,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Category::General => "General",
+            Category::Breakpoints => "Breakpoints and watches",
+            Category::Cores => "Cores",
+            Category::Memory => "Memory and registers",
+            Category::Flash => "Flash and elf",
+        }
+    }
+}
+
+/// Handler of a built-in command, called with the arguments following its name, their
+/// count already checked against the synopsis
+type Handler = fn(&mut TricoreTarget<'_>, &[&str], &mut MonitorOutput<'_>);
+
+/// Flag of a command accepted while a flash operation holds the target
+const WHILE_BUSY: u8 = 1 << 0;
+/// Flag of a command which only inspects state
+const READ_ONLY: u8 = 1 << 1;
+
+/// A built-in monitor command
+pub(crate) struct BuiltinCommand {
+    /// Words naming the command
+    pub name: &'static str,
+    /// Synopsis of the arguments
+    pub args: &'static str,
+    pub description: &'static str,
+    pub category: Category,
+    /// Accepted while a flash operation holds the target
+    pub while_busy: bool,
+    /// Only inspects the target and the session, offered to observers
+    pub read_only: bool,
+    pub handler: Handler,
+}
+
+impl BuiltinCommand {
+    fn words(&self) -> impl Iterator<Item = &'static str> {
+        self.name.split_whitespace()
+    }
+
+    fn word_count(&self) -> usize {
+        self.words().count()
+    }
+
+    /// Name and argument synopsis
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            self.name.to_owned()
+        } else {
+            format!("{} {}", self.name, self.args)
+        }
+    }
+
+    /// Whether `count` arguments fit the synopsis
+    pub fn accepts(&self, count: usize) -> bool {
+        let mut required = 0;
+        let mut optional = 0;
+        for arg in self.args.split_whitespace() {
+            if arg.ends_with("...") {
+                return count >= required;
+            }
+            if arg.starts_with('[') {
+                optional += 1;
+            } else {
+                required += 1;
+            }
+        }
+        (required..=required + optional).contains(&count)
+    }
+}
+
+/// Outcome of looking up the words of a command line
+pub(crate) enum Lookup<'a> {
+    /// The command and the words following its name
+    Found(&'static BuiltinCommand, &'a [&'a str]),
+    /// A word abbreviates several commands or the words only start their names
+    Ambiguous(Vec<&'static BuiltinCommand>),
+    Unknown,
+}
+
+macro_rules! command {
+    ($category:ident, $name:literal, $args:literal, $description:literal, $handler:expr) => {
+        command!($category, $name, $args, $description, 0, $handler)
+    };
+    ($category:ident, $name:literal, $args:literal, $description:literal, $flags:expr, $handler:expr) => {
+        BuiltinCommand {
+            name: $name,
+            args: $args,
+            description: $description,
+            category: Category::$category,
+            while_busy: $flags & WHILE_BUSY != 0,
+            read_only: $flags & READ_ONLY != 0,
+            handler: $handler,
+        }
+    };
+}
+
+/// Every built-in monitor command, in the order `monitor help` lists them
+pub(crate) static BUILTIN_COMMANDS: &[BuiltinCommand] = &[
+    command!(
+        General,
+        "help",
+        "[command...]",
+        "List the commands or describe one",
+        WHILE_BUSY | READ_ONLY,
+        |target, args, out| target.help_command(args, out)
+    ),
+    command!(
+        General,
+        "ping",
+        "",
+        "Check the stub answers",
+        READ_ONLY,
+        |_, _, out| outputln!(out, "pong!")
+    ),
+    command!(
+        General,
+        "info",
+        "",
+        "Architecture, probe and capabilities of the session",
+        READ_ONLY,
+        |target, _, out| target.print_info(out)
+    ),
+    command!(
+        General,
+        "status",
+        "",
+        "Device and session status as in the status file",
+        READ_ONLY,
+        |target, _, out| target.print_status(out)
+    ),
+    command!(
+        General,
+        "show",
+        "",
+        "List the settings with their values",
+        READ_ONLY,
+        |target, _, out| target.print_settings(out)
+    ),
+    command!(
+        General,
+        "set",
+        "<key> <value>",
+        "Change a setting, see `show` for the keys",
+        |target, args, out| target.set_command(args[0], args[1], out)
+    ),
+    command!(
+        General,
+        "source",
+        "<file>",
+        "Run the monitor commands of a file",
+        |target, args, out| target.source_command(args[0], out)
+    ),
+    command!(
+        General,
+        "exit",
+        "[code]",
+        "End the session and the stub, leaving the target as on-disconnect says",
+        |target, args, out| target.exit_command(args.first().copied(), out)
+    ),
+    command!(
+        General,
+        "mcd-stats",
+        "",
+        "Count the MCD transactions of the session",
+        READ_ONLY,
+        |target, _, out| target.print_mcd_stats(out)
+    ),
+    command!(
+        General,
+        "cache-stats",
+        "",
+        "Hit rate of the program flash read cache",
+        READ_ONLY,
+        |target, _, out| target.print_cache_stats(out)
+    ),
+    command!(
+        General,
+        "dasclock show",
+        "",
+        "Failed transactions and bursts of the session",
+        READ_ONLY,
+        |target, _, out| target.das_clock_show(out)
+    ),
+    command!(
+        General,
+        "dasclock set",
+        "<khz>",
+        "How to change the debug interface frequency",
+        |target, args, out| target.das_clock_set(args[0], out)
+    ),
+    command!(
+        Breakpoints,
+        "breakpoints",
+        "",
+        "List breakpoints with hit counts and timing",
+        READ_ONLY,
+        |target, _, out| target.print_breakpoints(out)
+    ),
+    command!(
+        Breakpoints,
+        "triggers",
+        "",
+        "Trigger budget and owners of every core",
+        READ_ONLY,
+        |target, _, out| target.print_triggers(out)
+    ),
+    command!(
+        Breakpoints,
+        "b",
+        "<symbol>[+offset][@cpuN]",
+        "Set a breakpoint on a function of the elf",
+        |target, args, out| target.symbol_breakpoint(args[0], out)
+    ),
+    command!(
+        Breakpoints,
+        "bd",
+        "<symbol>[+offset][@cpuN]",
+        "Delete a breakpoint set with `b`",
+        |target, args, out| target.symbol_breakpoint_delete(args[0], out)
+    ),
+    command!(
+        Breakpoints,
+        "bp ignore",
+        "<addr> <count>",
+        "Skip the next hits of a breakpoint",
+        |target, args, out| target.set_ignore_count(args[0], args[1], out)
+    ),
+    command!(
+        Breakpoints,
+        "bp oneshot",
+        "<addr>",
+        "Remove a breakpoint on its first hit",
+        |target, args, out| target.set_oneshot(args[0], out)
+    ),
+    command!(
+        Breakpoints,
+        "bp timing",
+        "<addr>",
+        "Kept hits of a breakpoint with their intervals",
+        READ_ONLY,
+        |target, args, out| target.timing_show(args[0], out)
+    ),
+    command!(
+        Breakpoints,
+        "bp timing reset",
+        "[addr]",
+        "Forget the hit timing of one or every breakpoint",
+        |target, args, out| target.timing_reset(args.first().copied(), out)
+    ),
+    command!(
+        Breakpoints,
+        "bp defer",
+        "<addr> <guard-addr> <guard-value>",
+        "Plant a breakpoint in RAM code once a word holds a value",
+        |target, args, out| target.defer_command(args[0], args[1], args[2], out)
+    ),
+    command!(
+        Breakpoints,
+        "bp check",
+        "",
+        "Report breakpoints and triggers out of step",
+        READ_ONLY,
+        |target, _, out| target.breakpoint_check(out)
+    ),
+    command!(
+        Breakpoints,
+        "bp sync",
+        "",
+        "Remove every trigger and forget the breakpoints",
+        |target, _, out| target.breakpoint_sync(out)
+    ),
+    command!(
+        Breakpoints,
+        "wp range",
+        "<start> <end> [cpuN] [r|w|rw]",
+        "Watch an address range with a data trigger",
+        |target, args, out| target.range_watch_add(args, out)
+    ),
+    command!(
+        Breakpoints,
+        "wp delete",
+        "<number>",
+        "Remove a range watch",
+        |target, args, out| target.range_watch_delete(args[0], out)
+    ),
+    command!(
+        Breakpoints,
+        "wp list",
+        "",
+        "List the range watches with their hits",
+        READ_ONLY,
+        |target, _, out| target.range_watch_list(out)
+    ),
+    command!(
+        Breakpoints,
+        "overlay add",
+        "<flash> <ram> <len> <ready-addr> <ready-value>",
+        "Declare flash code the application copies into RAM and its ready flag",
+        |target, args, out| target.add_overlay(args, out)
+    ),
+    command!(
+        Cores,
+        "cores",
+        "",
+        "Present cores and their registers",
+        READ_ONLY,
+        |target, _, out| target.print_cores(out)
+    ),
+    command!(
+        Cores,
+        "csa info",
+        "[core]",
+        "Free and previous context lists of a core",
+        READ_ONLY,
+        |target, args, out| target.csa_info(args.first().copied(), out)
+    ),
+    command!(
+        Cores,
+        "psw",
+        "[core]",
+        "Decode the PSW of a core",
+        READ_ONLY,
+        |target, args, out| target.psw_info(args.first().copied(), out)
+    ),
+    command!(
+        Cores,
+        "fpu",
+        "[core]",
+        "Rounding mode, flags and trap enables of a core's FPU",
+        READ_ONLY,
+        |target, args, out| target.fpu_info(args.first().copied(), out)
+    ),
+    command!(
+        Cores,
+        "fpu rm",
+        "<core> nearest|+inf|-inf|zero",
+        "Change the rounding mode of a halted core",
+        |target, args, out| target.fpu_set_rounding(args[0], args[1], out)
+    ),
+    command!(
+        Cores,
+        "cache status",
+        "[core]",
+        "Whether the caches of a core are enabled",
+        READ_ONLY,
+        |target, args, out| target.cache_status(args.first().copied(), out)
+    ),
+    command!(
+        Cores,
+        "cache flush",
+        "[core]",
+        "Write back the dirty data cache lines of a core",
+        |target, args, out| target.cache_maintain(args.first().copied(), false, out)
+    ),
+    command!(
+        Cores,
+        "cache invalidate",
+        "[core]",
+        "Write back and invalidate the data cache of a core",
+        |target, args, out| target.cache_maintain(args.first().copied(), true, out)
+    ),
+    command!(
+        Cores,
+        "resetcause",
+        "",
+        "Decode the resets flagged in SCU RSTSTAT",
+        READ_ONLY,
+        |target, _, out| target.reset_cause_command(out)
+    ),
+    command!(
+        Cores,
+        "resetcause clear",
+        "",
+        "Clear SCU RSTSTAT",
+        |target, _, out| target.reset_cause_clear(out)
+    ),
+    command!(
+        Cores,
+        "runstats",
+        "",
+        "Target time the cores ran, measured with their STM",
+        READ_ONLY,
+        |target, _, out| target.print_run_stats(out)
+    ),
+    command!(
+        Cores,
+        "runstats reset",
+        "",
+        "Clear the run totals of every core",
+        |target, _, out| target.reset_run_stats(out)
+    ),
+    command!(
+        Cores,
+        "reggroups",
+        "[core]",
+        "MCD register groups of a core with their register count",
+        READ_ONLY,
+        |target, args, out| target.reg_groups_command(args.first().copied(), out)
+    ),
+    command!(
+        Cores,
+        "reg list",
+        "<group> [core]",
+        "Registers of a group, marking reads with side effects",
+        READ_ONLY,
+        |target, args, out| target.reg_list(args[0], args.get(1).copied(), out)
+    ),
+    command!(
+        Cores,
+        "reg read",
+        "<group>/<name>[!] [core]",
+        "Read any register the DAS server exposes",
+        |target, args, out| target.reg_read(args[0], args.get(1).copied(), out)
+    ),
+    command!(
+        Cores,
+        "reg write",
+        "<group>/<name> <value> [core]",
+        "Write any register the DAS server exposes",
+        |target, args, out| target.reg_write(args[0], args[1], args.get(2).copied(), out)
+    ),
+    command!(
+        Cores,
+        "bootargs set",
+        "<string>...",
+        "Replace the boot args and write them",
+        |target, args, out| target.boot_args_set(args, out)
+    ),
+    command!(
+        Cores,
+        "bootargs show",
+        "",
+        "Read back and decode the boot args block",
+        READ_ONLY,
+        |target, _, out| target.boot_args_show(out)
+    ),
+    command!(
+        Memory,
+        "addrmap",
+        "",
+        "Core local address windows and their global addresses",
+        READ_ONLY,
+        |target, _, out| target.print_addrmap(out)
+    ),
+    command!(
+        Memory,
+        "rd8",
+        "<addr>",
+        "Read a byte in a single transaction",
+        |target, args, out| target.bus_read(args[0], 1, out)
+    ),
+    command!(
+        Memory,
+        "rd16",
+        "<addr>",
+        "Read a half word in a single transaction",
+        |target, args, out| target.bus_read(args[0], 2, out)
+    ),
+    command!(
+        Memory,
+        "rd32",
+        "<addr>",
+        "Read a word in a single transaction",
+        |target, args, out| target.bus_read(args[0], 4, out)
+    ),
+    command!(
+        Memory,
+        "wr8",
+        "<addr> <value>",
+        "Write a byte in a single transaction",
+        |target, args, out| target.bus_write(args[0], args[1], 1, out)
+    ),
+    command!(
+        Memory,
+        "wr16",
+        "<addr> <value>",
+        "Write a half word in a single transaction",
+        |target, args, out| target.bus_write(args[0], args[1], 2, out)
+    ),
+    command!(
+        Memory,
+        "wr32",
+        "<addr> <value>",
+        "Write a word in a single transaction",
+        |target, args, out| target.bus_write(args[0], args[1], 4, out)
+    ),
+    command!(
+        Memory,
+        "sfr read",
+        "<PERIPH.REG[.FIELD]>",
+        "Read a peripheral register by name",
+        |target, args, out| target.sfr_read(args[0], out)
+    ),
+    command!(
+        Memory,
+        "sfr write",
+        "<PERIPH.REG[.FIELD]> <value>",
+        "Write a peripheral register by name",
+        |target, args, out| target.sfr_write(args[0], args[1], out)
+    ),
+    command!(
+        Memory,
+        "sfr decode",
+        "<PERIPH.REG>",
+        "Read a peripheral register and decode its fields",
+        |target, args, out| target.sfr_decode(args[0], out)
+    ),
+    command!(
+        Memory,
+        "find",
+        "<start> <len> <hex-pattern>",
+        "Search memory for a byte pattern",
+        READ_ONLY,
+        |target, args, out| target.find_command(args[0], args[1], args[2], out)
+    ),
+    command!(
+        Memory,
+        "dump",
+        "<addr> <len> <file> [--gz] [cpuN]",
+        "Stream memory into a host file",
+        |target, args, out| target.dump_memory(args[0], args[1], args[2], &args[3..], out)
+    ),
+    command!(
+        Memory,
+        "restore",
+        "<file> <addr>",
+        "Write a host file, plain or gzip, to memory",
+        |target, args, out| target.restore_memory(args[0], args[1], out)
+    ),
+    command!(
+        Memory,
+        "writelog",
+        "",
+        "List the journaled memory writes",
+        READ_ONLY,
+        |target, _, out| target.print_write_journal(out)
+    ),
+    command!(
+        Flash,
+        "flash usage",
+        "",
+        "Bytes used per flash sector and bank",
+        READ_ONLY,
+        |target, _, out| target.print_flash_usage(out)
+    ),
+    command!(
+        Flash,
+        "flash abort",
+        "",
+        "Stop the flash operation in progress",
+        WHILE_BUSY,
+        |target, _, out| target.flash_abort(out)
+    ),
+    command!(
+        Flash,
+        "bootcheck",
+        "",
+        "Check the boot mode headers in the UCB",
+        READ_ONLY,
+        |target, _, out| target.bootcheck_command(out)
+    ),
+    command!(
+        Flash,
+        "elf load",
+        "[cpuN:]<elf>",
+        "Replace the elf of the session or of a core",
+        |target, args, out| target.elf_load(args[0], out)
+    ),
+    command!(
+        Flash,
+        "elf info",
+        "",
+        "List the elfs and their cores",
+        READ_ONLY,
+        |target, _, out| target.elf_info(out)
+    ),
+    command!(
+        Flash,
+        "exec-file",
+        "[core]",
+        "Elf of a core, or the assignments",
+        READ_ONLY,
+        |target, args, out| target.exec_file_command(args.first().copied(), out)
+    ),
+];
+
+/// Narrows `commands` to those whose word at `depth` is `word` or, without such, starts
+/// with it
+fn narrow(
+    commands: Vec<&'static BuiltinCommand>,
+    depth: usize,
+    word: &str,
+) -> Vec<&'static BuiltinCommand> {
+    let at = |command: &BuiltinCommand| command.words().nth(depth);
+    let exact: Vec<_> = commands
+        .iter()
+        .copied()
+        .filter(|command| at(command) == Some(word))
+        .collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+    commands
+        .into_iter()
+        .filter(|command| at(command).is_some_and(|name| name.starts_with(word)))
+        .collect()
+}
+
+/// Finds the command `args` name, the words following the name are its arguments
+pub(crate) fn lookup<'a>(args: &'a [&'a str]) -> Lookup<'a> {
+    let mut candidates: Vec<_> = BUILTIN_COMMANDS.iter().collect();
+    let mut depth = 0;
+    loop {
+        let complete = candidates
+            .iter()
+            .copied()
+            .find(|command| command.word_count() == depth);
+        let Some(word) = args.get(depth) else {
+            return match complete {
+                Some(command) => Lookup::Found(command, &args[depth..]),
+                None if depth == 0 => Lookup::Unknown,
+                None => Lookup::Ambiguous(candidates),
+            };
+        };
+        let narrowed = narrow(candidates.clone(), depth, word);
+        let next_words = |commands: &[&'static BuiltinCommand]| {
+            let mut words: Vec<_> = commands
+                .iter()
+                .filter_map(|command| command.words().nth(depth))
+                .collect();
+            words.sort_unstable();
+            words.dedup();
+            words.len()
+        };
+        match (narrowed.is_empty(), complete) {
+            (false, _) if next_words(&narrowed) == 1 => {
+                candidates = narrowed;
+                depth += 1;
+            }
+            (_, Some(command)) => return Lookup::Found(command, &args[depth..]),
+            (false, None) => return Lookup::Ambiguous(narrowed),
+            (true, None) if depth == 0 => return Lookup::Unknown,
+            (true, None) => return Lookup::Ambiguous(candidates),
+        }
+    }
+}
+
+/// Commands `words` name or start the name of, abbreviations allowed as for [lookup]
+fn matching(words: &[&str]) -> Vec<&'static BuiltinCommand> {
+    let mut commands: Vec<_> = BUILTIN_COMMANDS.iter().collect();
+    for (depth, word) in words.iter().enumerate() {
+        commands = narrow(commands, depth, word);
+    }
+    commands
+}
+
+/// Name closest to the words typed among the commands and `others`, if close enough to be
+/// a typo
+pub(crate) fn closest<'a>(args: &[&str], others: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    BUILTIN_COMMANDS
+        .iter()
+        .map(|command| command.name)
+        .chain(others)
+        .map(|name| {
+            let words = name.split_whitespace().count().min(args.len());
+            (edit_distance(&args[..words].join(" "), name), name)
+        })
+        .filter(|&(distance, name)| distance <= name.len() / 2)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, name)| name)
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// `help [command...]`
+    fn help_command(&self, args: &[&str], out: &mut MonitorOutput<'_>) {
+        if !args.is_empty() {
+            let commands = matching(args);
+            if commands.is_empty() {
+                outputln!(out, "No command '{}'", args.join(" "));
+                out.fail();
+            }
+            for command in commands {
+                outputln!(out, "usage: {}", command.usage());
+                outputln!(out, "  {}", command.description);
+            }
+            return;
+        }
+
+        for category in Category::ALL {
+            outputln!(out, "{}:", category.title());
+            for command in BUILTIN_COMMANDS
+                .iter()
+                .filter(|command| command.category == category)
+            {
+                outputln!(out, "  {:<44} {}", command.usage(), command.description);
+            }
+        }
+        if !self.external_commands.is_empty() {
+            let mut names: Vec<&String> = self.external_commands.keys().collect();
+            names.sort();
+            outputln!(out, "Application:");
+            for name in names {
+                outputln!(out, "  {}", name);
+            }
+        }
+        outputln!(
+            out,
+            "Words of a command may be abbreviated while unambiguous, e.g. `bp ch` for `bp check`"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_names_are_unique() {
+        let mut names: Vec<_> = BUILTIN_COMMANDS
+            .iter()
+            .map(|command| command.name)
+            .collect();
+        names.sort_unstable();
+        for pair in names.windows(2) {
+            assert_ne!(
+                pair[0], pair[1],
+                "command '{}' is registered twice",
+                pair[0]
+            );
+        }
+    }
+
+    #[test]
+    fn commands_are_described() {
+        for command in BUILTIN_COMMANDS {
+            assert!(!command.name.trim().is_empty());
+            assert!(
+                !command.description.trim().is_empty(),
+                "command '{}' has no description",
+                command.name
+            );
+        }
+    }
+
+    #[test]
+    fn every_command_is_found_by_its_name() {
+        for command in BUILTIN_COMMANDS {
+            let words: Vec<_> = command.words().collect();
+            match lookup(&words) {
+                Lookup::Found(found, rest) => {
+                    assert_eq!(found.name, command.name);
+                    assert!(rest.is_empty());
+                }
+                _ => panic!("command '{}' is not found by its name", command.name),
+            }
+        }
+    }
+
+    #[test]
+    fn read_only_commands_change_nothing() {
+        let read_only = |name: &str| {
+            let words: Vec<_> = name.split_whitespace().collect();
+            match lookup(&words) {
+                Lookup::Found(command, _) => command.read_only,
+                _ => panic!("no command '{}'", name),
+            }
+        };
+        for name in [
+            "ping", "show", "cores", "psw 1", "wp list", "bp check", "sh",
+        ] {
+            assert!(read_only(name), "'{}' should be read-only", name);
+        }
+        for name in [
+            "set",
+            "exit",
+            "source",
+            "bp sync",
+            "wr32",
+            "reg write",
+            "restore",
+            "dump",
+            "elf load",
+            "flash abort",
+            "bp timing reset",
+            "resetcause clear",
+        ] {
+            assert!(!read_only(name), "'{}' changes state", name);
+        }
+    }
+
+    #[test]
+    fn closest_suggests_a_typo_fix() {
+        assert_eq!(closest(&["hepl"], std::iter::empty()), Some("help"));
+        assert_eq!(closest(&["sfrs"], ["sfr"].into_iter()), Some("sfr"));
+        assert_eq!(closest(&["zzzzzzzz"], std::iter::empty()), None);
+    }
+}
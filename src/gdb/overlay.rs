@@ -0,0 +1,246 @@
+//! Deferred breakpoints for code which is copied into RAM at runtime.
+//!
+//! An IP trigger in RAM which does not hold the code yet behaves unpredictably, so such
+//! breakpoints are only recorded together with a guard. The guards are polled while the
+//! target runs and the trigger is planted once the guard holds.
+//!
+//! The guard of an overlay is a ready flag the application sets once the copy is done.
+//! Comparing the RAM code with its flash source would take a partly copied or stale
+//! overlay for a copied one.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+use log::debug;
+
+use super::events::TargetEvent;
+use super::{parse_u32, MonitorOutput, TricoreTarget};
+
+/// Breakpoint kind of deferred breakpoints, a 32 bit instruction as GDB would request
+const DEFERRED_KIND: usize = 4;
+
+/// Interval between guard polls, each poll reads target memory over the debug link
+const GUARD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Code copied from flash into RAM by the application
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Overlay {
+    pub flash: u32,
+    pub ram: u32,
+    pub len: u32,
+    /// Word the application sets to `ready_value` once the copy is complete
+    pub ready_addr: u32,
+    pub ready_value: u32,
+}
+
+impl Overlay {
+    fn contains_ram(&self, addr: u32) -> bool {
+        addr >= self.ram && ((addr - self.ram) as u64) < self.len as u64
+    }
+}
+
+/// Condition under which a deferred breakpoint is planted, the word at `addr` reads `value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Guard {
+    pub addr: u32,
+    pub value: u32,
+}
+
+impl fmt::Display for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:#010x}] == {:#010x}", self.addr, self.value)
+    }
+}
+
+/// Whether the guards are due for another poll
+fn poll_due(last: Option<Instant>, now: Instant) -> bool {
+    last.map_or(true, |last| now.duration_since(last) >= GUARD_POLL_INTERVAL)
+}
+
+/// Addresses to read for the pending breakpoints, each once however many wait on it
+fn guard_words<'g>(guards: impl Iterator<Item = &'g Guard>) -> BTreeMap<u32, Option<u32>> {
+    guards.map(|guard| (guard.addr, None)).collect()
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Guard for a breakpoint inside a configured overlay, `None` outside of overlays
+    pub(crate) fn overlay_guard(&self, addr: u32) -> Option<Guard> {
+        let overlay = self.overlays.iter().find(|o| o.contains_ram(addr))?;
+        Some(Guard {
+            addr: overlay.ready_addr,
+            value: overlay.ready_value,
+        })
+    }
+
+    pub(crate) fn defer_breakpoint(&mut self, addr: u32, guard: Guard) {
+        self.deferred.insert(addr, guard);
+    }
+
+    fn read_word(&mut self, addr: u32) -> Option<u32> {
+        let mut bytes = [0u8; 4];
+        self.read_memory(Tid::new(1).unwrap(), addr, &mut bytes)
+            .ok()?;
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    /// Plants the deferred breakpoints whose guard holds by now. The guards are polled at
+    /// most every [GUARD_POLL_INTERVAL], reading each guard word once per poll.
+    pub(crate) fn plant_ready_breakpoints(&mut self) {
+        let now = Instant::now();
+        if !poll_due(self.last_guard_poll, now) {
+            return;
+        }
+        self.last_guard_poll = Some(now);
+
+        let mut words = guard_words(self.deferred.values());
+        for (&addr, word) in words.iter_mut() {
+            *word = self.read_word(addr);
+        }
+        let mut ready: Vec<(u32, Guard)> = self
+            .deferred
+            .iter()
+            .filter(|(_, guard)| words.get(&guard.addr) == Some(&Some(guard.value)))
+            .map(|(&addr, &guard)| (addr, guard))
+            .collect();
+        if ready.is_empty() {
+            return;
+        }
+        ready.sort_unstable_by_key(|&(addr, _)| addr);
+
+        for (addr, guard) in ready {
+            self.deferred.remove(&addr);
+
+            let cores = self.all_cores();
+            match self.plant_breakpoint(addr, &cores, DEFERRED_KIND) {
+                Ok(()) => {
+                    self.publish(TargetEvent::BreakpointAdded { addr });
+                    self.notify(
+                        None,
+                        format_args!("Planted deferred breakpoint at {:#010x} ({})", addr, guard),
+                    );
+                }
+                Err(e) => self.notify(
+                    None,
+                    format_args!("Cannot plant deferred breakpoint at {:#010x}: {}", addr, e),
+                ),
+            }
+        }
+        debug!("{} breakpoints still deferred", self.deferred.len());
+        // The cores are running, the breakpoint has to take effect right away
+        self.flush_triggers();
+    }
+
+    pub(crate) fn defer_command(
+        &mut self,
+        addr: &str,
+        guard_addr: &str,
+        guard_value: &str,
+        out: &mut MonitorOutput<'_>,
+    ) {
+        let (Some(addr), Some(guard_addr), Some(value)) = (
+            parse_u32(addr),
+            parse_u32(guard_addr),
+            parse_u32(guard_value),
+        ) else {
+            outputln!(out, "usage: bp defer <addr> <guard-addr> <guard-value>");
+            return;
+        };
+
+        let addr = self.breakpoint_address(addr);
+        if self.breakpoints.contains_key(&self.breakpoint_key(addr)) {
+            outputln!(out, "Breakpoint at {:#010x} is already planted", addr);
+            return;
+        }
+
+        let guard = Guard {
+            addr: guard_addr,
+            value,
+        };
+        self.defer_breakpoint(addr, guard);
+        outputln!(out, "Breakpoint at {:#010x} deferred until {}", addr, guard);
+    }
+
+    pub(crate) fn add_overlay(&mut self, args: &[&str], out: &mut MonitorOutput<'_>) {
+        let values: Vec<Option<u32>> = args.iter().map(|arg| parse_u32(arg)).collect();
+        let [Some(flash), Some(ram), Some(len), Some(ready_addr), Some(ready_value)] = values[..]
+        else {
+            outputln!(
+                out,
+                "usage: overlay add <flash-start> <ram-start> <len> <ready-addr> <ready-value>"
+            );
+            return;
+        };
+
+        self.overlays.push(Overlay {
+            flash,
+            ram,
+            len,
+            ready_addr,
+            ready_value,
+        });
+        outputln!(
+            out,
+            "Breakpoints in {:#010x}..{:#010x} wait for the copy from {:#010x} until [{:#010x}] == {:#010x}",
+            ram,
+            ram as u64 + len as u64,
+            flash,
+            ready_addr,
+            ready_value
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_covers_its_ram_window_only() {
+        let overlay = Overlay {
+            flash: 0x8000_0000,
+            ram: 0x7000_0000,
+            len: 0x100,
+            ready_addr: 0x7000_1000,
+            ready_value: 1,
+        };
+        assert!(overlay.contains_ram(0x7000_0000));
+        assert!(overlay.contains_ram(0x7000_00ff));
+        assert!(!overlay.contains_ram(0x7000_0100));
+        assert!(!overlay.contains_ram(0x6fff_ffff));
+    }
+
+    #[test]
+    fn shared_guard_words_are_read_once() {
+        let guards = [
+            Guard {
+                addr: 0x7000_1000,
+                value: 1,
+            },
+            Guard {
+                addr: 0x7000_1000,
+                value: 1,
+            },
+            Guard {
+                addr: 0x7000_2000,
+                value: 0xa5a5,
+            },
+        ];
+        let words = guard_words(guards.iter());
+        assert_eq!(
+            words.keys().copied().collect::<Vec<_>>(),
+            [0x7000_1000, 0x7000_2000]
+        );
+    }
+
+    #[test]
+    fn guards_are_polled_at_the_interval() {
+        let start = Instant::now();
+        assert!(poll_due(None, start));
+        assert!(!poll_due(Some(start), start));
+        assert!(!poll_due(Some(start), start + GUARD_POLL_INTERVAL / 2));
+        assert!(poll_due(Some(start), start + GUARD_POLL_INTERVAL));
+    }
+}
@@ -0,0 +1,89 @@
+//! Asynchronous halt requests from outside of GDB: SIGUSR1 and a pause file.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Minimum time between two checks of the pause file
+const FILE_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A file whose creation or modification requests a halt
+struct PauseFile {
+    path: PathBuf,
+    last_check: Instant,
+    /// Modification time seen last, `None` while the file doesn't exist
+    last_seen: Option<SystemTime>,
+}
+
+impl PauseFile {
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+
+    /// Whether the file appeared or changed since the last check
+    fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < FILE_CHECK_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = self.modified();
+        let changed = modified.is_some() && modified != self.last_seen;
+        self.last_seen = modified;
+        changed
+    }
+}
+
+/// Sources of asynchronous halt requests, polled while the target runs
+pub(crate) struct PauseTriggers {
+    /// Set from the SIGUSR1 handler
+    signal: Arc<AtomicBool>,
+    file: Option<PauseFile>,
+}
+
+impl PauseTriggers {
+    pub(crate) fn new(pause_file: Option<PathBuf>) -> Self {
+        let signal = Arc::new(AtomicBool::new(false));
+
+        // The handler only stores to the flag, which is async-signal-safe
+        #[cfg(unix)]
+        if let Err(e) =
+            signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&signal))
+        {
+            log::warn!("Cannot install SIGUSR1 handler: {}", e);
+        }
+
+        let mut triggers = PauseTriggers {
+            signal,
+            file: pause_file.map(|path| PauseFile {
+                path,
+                last_check: Instant::now(),
+                last_seen: None,
+            }),
+        };
+        triggers.discard();
+        triggers
+    }
+
+    /// Returns the trigger which requested a halt since the last poll, if any
+    pub(crate) fn poll(&mut self) -> Option<&'static str> {
+        if self.signal.swap(false, Ordering::Relaxed) {
+            return Some("SIGUSR1");
+        }
+        match &mut self.file {
+            Some(file) if file.changed() => Some("pause file"),
+            _ => None,
+        }
+    }
+
+    /// Forgets requests made while the target was halted anyway
+    pub(crate) fn discard(&mut self) {
+        self.signal.store(false, Ordering::Relaxed);
+        if let Some(file) = &mut self.file {
+            file.last_seen = file.modified();
+        }
+    }
+}
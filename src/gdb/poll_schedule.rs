@@ -0,0 +1,110 @@
+//! Order and frequency in which the run loop queries the core states.
+//!
+//! While GDB steps or continues a single core, that core is queried on every iteration
+//! and first, the others only every `background_every` iterations. This keeps the step
+//! latency of the focused core independent of the core count, while a stop of another
+//! core is still found within `background_every` iterations. Without a focused core every
+//! core is queried on every iteration.
+
+pub(crate) struct PollSchedule {
+    /// Core resumed alone by the last resume, `None` if several were resumed
+    focus: Option<usize>,
+    /// Iterations since the background cores were last queried
+    iteration: u32,
+}
+
+impl PollSchedule {
+    pub fn new() -> Self {
+        PollSchedule {
+            focus: None,
+            iteration: 0,
+        }
+    }
+
+    /// Sets the core to favor from now on. The first iteration queries every core, so
+    /// a background core which stopped meanwhile isn't delayed.
+    pub fn set_focus(&mut self, focus: Option<usize>) {
+        self.focus = focus;
+        self.iteration = 0;
+    }
+
+    /// Indices of the cores to query in this iteration of the run loop, in query order
+    pub fn due(&mut self, core_count: usize, background_every: u32) -> Vec<usize> {
+        let Some(focus) = self.focus.filter(|&focus| focus < core_count) else {
+            return (0..core_count).collect();
+        };
+
+        let every = background_every.max(1);
+        let background = self.iteration % every == 0;
+        self.iteration = (self.iteration + 1) % every;
+
+        let mut due = vec![focus];
+        if background {
+            due.extend((0..core_count).filter(|&index| index != focus));
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Iterations of the run loop until `stopped` is queried, the stop happening right
+    /// before the first one
+    fn detected_after(
+        schedule: &mut PollSchedule,
+        cores: usize,
+        every: u32,
+        stopped: usize,
+    ) -> u32 {
+        (1..)
+            .find(|_| schedule.due(cores, every).contains(&stopped))
+            .unwrap()
+    }
+
+    #[test]
+    fn without_focus_every_core_is_queried() {
+        let mut schedule = PollSchedule::new();
+        for _ in 0..3 {
+            assert_eq!(schedule.due(4, 5), vec![0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn focused_core_is_queried_first_every_iteration() {
+        let mut schedule = PollSchedule::new();
+        schedule.set_focus(Some(2));
+        assert_eq!(schedule.due(4, 3), vec![2, 0, 1, 3]);
+        assert_eq!(schedule.due(4, 3), vec![2]);
+        assert_eq!(schedule.due(4, 3), vec![2]);
+        assert_eq!(schedule.due(4, 3), vec![2, 0, 1, 3]);
+    }
+
+    #[test]
+    fn stops_are_detected_within_the_bound() {
+        let mut schedule = PollSchedule::new();
+        schedule.set_focus(Some(1));
+        for _ in 0..20 {
+            assert_eq!(detected_after(&mut schedule, 6, 4, 1), 1);
+        }
+        for skipped in 0..8 {
+            let mut schedule = PollSchedule::new();
+            schedule.set_focus(Some(1));
+            for _ in 0..skipped {
+                schedule.due(6, 4);
+            }
+            assert!(detected_after(&mut schedule, 6, 4, 5) <= 4);
+        }
+    }
+
+    #[test]
+    fn focus_beyond_the_cores_falls_back_to_all() {
+        let mut schedule = PollSchedule::new();
+        schedule.set_focus(Some(7));
+        assert_eq!(schedule.due(3, 4), vec![0, 1, 2]);
+        schedule.set_focus(Some(0));
+        assert_eq!(schedule.due(3, 0), vec![0, 1, 2]);
+        assert_eq!(schedule.due(3, 0), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,287 @@
+//! `monitor reggroups` and `monitor reg`, raw access to every register the DAS server
+//! exposes, beyond the GDB register set of the first group.
+//!
+//! The groups and their registers are enumerated once per core when the target is set up
+//! and kept with the core's capabilities. Registers are named `<group>/<name>`, the group
+//! by its index or its name with `_` for a space, e.g. `0/PSW`. A register whose read has
+//! side effects, e.g. clearing a status flag, is only read with a `!` appended to its name.
+
+use gdbstub::outputln;
+
+use super::registers::GroupInfo;
+use super::{parse_u32, CpuId, MonitorOutput, TricoreTarget};
+
+/// `<group>/<name>[!]`, a register of any group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RegisterPath<'a> {
+    pub group: &'a str,
+    pub name: &'a str,
+    /// `!` given, a read with side effects is accepted
+    pub force: bool,
+}
+
+impl<'a> RegisterPath<'a> {
+    pub fn parse(text: &'a str) -> Result<RegisterPath<'a>, String> {
+        let (path, force) = match text.strip_suffix('!') {
+            Some(path) => (path, true),
+            None => (text, false),
+        };
+        match path.rsplit_once('/') {
+            Some((group, name)) if !group.is_empty() && !name.is_empty() => {
+                Ok(RegisterPath { group, name, force })
+            }
+            _ => Err(format!("'{}' is not of the form <group>/<name>", text)),
+        }
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Core index of the optional `[core]` argument, core 0 by default
+    fn reg_core(&self, core: Option<&str>, out: &mut MonitorOutput<'_>) -> Option<usize> {
+        let index = match core.map(parse_u32) {
+            None => 0,
+            Some(Some(index)) => index as usize,
+            Some(None) => {
+                outputln!(out, "Invalid core '{}'", core.unwrap_or_default());
+                return None;
+            }
+        };
+        if self.core(index).is_none() {
+            outputln!(out, "No core {}", index);
+            return None;
+        }
+        Some(index)
+    }
+
+    /// The enumerated group `selector` names on core `index`
+    fn reg_group(
+        &self,
+        index: usize,
+        selector: &str,
+        out: &mut MonitorOutput<'_>,
+    ) -> Option<&GroupInfo> {
+        let group = self
+            .slot_at(index)
+            .and_then(|slot| slot.capabilities.group(selector));
+        if group.is_none() {
+            outputln!(
+                out,
+                "Core {} has no register group '{}', see `monitor reggroups {}`",
+                index,
+                selector,
+                index
+            );
+        }
+        group
+    }
+
+    /// `reggroups [core]`
+    pub(crate) fn reg_groups_command(&self, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let Some(index) = self.reg_core(core, out) else {
+            return;
+        };
+        let Some(slot) = self.slot_at(index) else {
+            return;
+        };
+        if slot.capabilities.groups.is_empty() {
+            outputln!(out, "Core {} reports no register groups", index);
+        }
+        for group in &slot.capabilities.groups {
+            outputln!(
+                out,
+                "{:>2} {:<32} {} registers",
+                group.index,
+                group.name,
+                group.registers.len()
+            );
+        }
+    }
+
+    /// `reg list <group> [core]`
+    pub(crate) fn reg_list(&self, group: &str, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let Some(index) = self.reg_core(core, out) else {
+            return;
+        };
+        let Some(group) = self.reg_group(index, group, out) else {
+            return;
+        };
+        for register in &group.registers {
+            if register.side_effects {
+                outputln!(out, "{} (read has side effects)", register.name);
+            } else {
+                outputln!(out, "{}", register.name);
+            }
+        }
+    }
+
+    /// `reg read <group>/<name>[!] [core]`
+    pub(crate) fn reg_read(&mut self, path: &str, core: Option<&str>, out: &mut MonitorOutput<'_>) {
+        let path = match RegisterPath::parse(path) {
+            Ok(path) => path,
+            Err(e) => {
+                outputln!(out, "{}", e);
+                out.fail();
+                return;
+            }
+        };
+        let Some(index) = self.reg_core(core, out) else {
+            return;
+        };
+        let Some(group) = self.reg_group(index, path.group, out) else {
+            return;
+        };
+        let Some(register) = group.register(path.name) else {
+            outputln!(out, "No register {} in group {}", path.name, group.name);
+            return;
+        };
+        if register.side_effects && !path.force {
+            outputln!(
+                out,
+                "Reading {} has side effects, append ! to read it anyway",
+                register.name
+            );
+            out.fail();
+            return;
+        }
+
+        let group_index = group.index;
+        let value = self.core(index).and_then(|core| {
+            let groups = core.register_groups().ok()?;
+            let group = groups.get_group(group_index).ok()?;
+            group.register(path.name)?.read().ok()
+        });
+        self.stats.record_register_read();
+        match value {
+            Some(value) => outputln!(out, "{}/{} = {:#010x}", group_index, path.name, value),
+            None => {
+                outputln!(out, "Cannot read {}/{}", group_index, path.name);
+                out.fail();
+            }
+        }
+    }
+
+    /// `reg write <group>/<name> <value> [core]`
+    pub(crate) fn reg_write(
+        &mut self,
+        path: &str,
+        value: &str,
+        core: Option<&str>,
+        out: &mut MonitorOutput<'_>,
+    ) {
+        let (path, Some(value)) = (RegisterPath::parse(path), parse_u32(value)) else {
+            outputln!(out, "usage: reg write <group>/<name> <value> [core]");
+            out.fail();
+            return;
+        };
+        let path = match path {
+            Ok(path) => path,
+            Err(e) => {
+                outputln!(out, "{}", e);
+                out.fail();
+                return;
+            }
+        };
+        let Some(index) = self.reg_core(core, out) else {
+            return;
+        };
+        let Some(group) = self.reg_group(index, path.group, out) else {
+            return;
+        };
+        if group.register(path.name).is_none() {
+            outputln!(out, "No register {} in group {}", path.name, group.name);
+            return;
+        }
+
+        let group_index = group.index;
+        let written = self.core(index).and_then(|core| {
+            let groups = core.register_groups().ok()?;
+            let group = groups.get_group(group_index).ok()?;
+            group.register(path.name)?.write(value).ok()
+        });
+        // The register may be one of the GDB register set
+        self.invalidate_registers(index);
+        if let Ok(cpu_id) = CpuId::try_from(index) {
+            self.stop_snapshots.remove(&cpu_id);
+        }
+        match written {
+            Some(()) => outputln!(out, "{}/{} = {:#010x}", group_index, path.name, value),
+            None => {
+                outputln!(out, "Cannot write {}/{}", group_index, path.name);
+                out.fail();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gdb::registers::{CoreCapabilities, McdRegister};
+
+    #[test]
+    fn path_is_parsed() {
+        assert_eq!(
+            RegisterPath::parse("0/PSW"),
+            Ok(RegisterPath {
+                group: "0",
+                name: "PSW",
+                force: false
+            })
+        );
+        assert_eq!(
+            RegisterPath::parse("Core_Registers/DBGSR!"),
+            Ok(RegisterPath {
+                group: "Core_Registers",
+                name: "DBGSR",
+                force: true
+            })
+        );
+    }
+
+    #[test]
+    fn name_follows_the_last_slash() {
+        let path = RegisterPath::parse("SFR/CPU0/PCXI").unwrap();
+        assert_eq!((path.group, path.name), ("SFR/CPU0", "PCXI"));
+    }
+
+    #[test]
+    fn incomplete_paths_are_refused() {
+        for text in ["PSW", "0/", "/PSW", "/", "!", "0/!", ""] {
+            assert!(RegisterPath::parse(text).is_err(), "{:?}", text);
+        }
+        assert_eq!(
+            RegisterPath::parse("PSW!"),
+            Err("'PSW!' is not of the form <group>/<name>".to_owned())
+        );
+    }
+
+    #[test]
+    fn group_is_selected_by_index_or_name() {
+        let register = |name: &str| McdRegister {
+            name: name.to_owned(),
+            side_effects: false,
+        };
+        let mut capabilities = CoreCapabilities::default();
+        capabilities.groups = vec![
+            GroupInfo {
+                index: 0,
+                name: "Core Registers".to_owned(),
+                registers: vec![register("A10"), register("PC"), register("PSW")],
+            },
+            GroupInfo {
+                index: 1,
+                name: "FPU".to_owned(),
+                registers: vec![register("FPU_TRAP_CON")],
+            },
+        ];
+        let index = |selector| capabilities.group(selector).map(|group| group.index);
+        assert_eq!(index("0"), Some(0));
+        assert_eq!(index("core_registers"), Some(0));
+        assert_eq!(index("fpu"), Some(1));
+        assert_eq!(index("2"), None);
+        assert_eq!(index("Core Registers"), None);
+        let core = capabilities.group("0").unwrap();
+        assert!(core.register("PSW").is_some());
+        assert!(core.register("D15").is_none());
+    }
+}
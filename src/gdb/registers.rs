@@ -0,0 +1,506 @@
+//! The single table of registers exchanged with GDB.
+//!
+//! Register reads and writes, single ones included, the register set on the wire as well
+//! as the target description sent to GDB are derived from [REGISTERS], so a register is
+//! added in exactly one place. Registers a core lacks are sent to GDB as unavailable.
+
+use std::num::NonZeroUsize;
+
+use gdbstub::arch::{Arch, RegId, Registers, SingleStepGdbBehavior};
+use gdbstub::target::ext::target_description_xml_override::TargetDescriptionXmlOverride;
+use gdbstub::target::TargetResult;
+use gdbstub_arch::tricore::TricoreV1_6;
+use rust_mcd::core::Core;
+
+use super::TricoreTarget;
+
+/// A core register as seen by GDB and by MCD
+pub(crate) struct RegisterDef {
+    /// GDB register number
+    pub regnum: usize,
+    /// Register name in the target description
+    pub name: &'static str,
+    /// Size in bytes
+    pub size: usize,
+    /// Register name in the MCD register group
+    pub mcd: &'static str,
+}
+
+const fn reg(regnum: usize, name: &'static str, mcd: &'static str) -> RegisterDef {
+    RegisterDef {
+        regnum,
+        name,
+        size: 4,
+        mcd,
+    }
+}
+
+pub(crate) const REGISTERS: &[RegisterDef] = &[
+    reg(0, "a10", "A10"),
+    reg(1, "a11", "A11"),
+    reg(2, "a12", "A12"),
+    reg(3, "a13", "A13"),
+    reg(4, "a14", "A14"),
+    reg(5, "a15", "A15"),
+    reg(6, "d8", "D8"),
+    reg(7, "d9", "D9"),
+    reg(8, "d10", "D10"),
+    reg(9, "d11", "D11"),
+    reg(10, "d12", "D12"),
+    reg(11, "d13", "D13"),
+    reg(12, "d14", "D14"),
+    reg(13, "d15", "D15"),
+    reg(14, "pc", "PC"),
+    reg(15, "pcxi", "PCXI"),
+    reg(16, "psw", "PSW"),
+];
+
+/// Table index of the program counter
+const PC_REGNUM: usize = 14;
+
+// Register numbers follow the table order, the wire offsets are derived from it
+const _: () = {
+    let mut i = 0;
+    while i < REGISTERS.len() {
+        assert!(
+            REGISTERS[i].regnum == i,
+            "register numbers must follow table order"
+        );
+        i += 1;
+    }
+    assert!(
+        REGISTERS.len() <= u32::BITS as usize,
+        "one unavailable bit per register"
+    );
+};
+
+/// The register set exchanged with GDB in `g` and `G` packets, the values of [REGISTERS]
+/// in table order. Registers the core lacks are marked unavailable and sent as `x`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoreRegisters {
+    values: [u32; REGISTERS.len()],
+    /// Bit `regnum` set for a register the core lacks
+    unavailable: u32,
+}
+
+impl CoreRegisters {
+    /// A register set with the registers the core lacks marked unavailable
+    pub(crate) fn for_core(capabilities: &CoreCapabilities) -> Self {
+        let mut regs = CoreRegisters::default();
+        for def in REGISTERS.iter().filter(|def| !capabilities.has(def)) {
+            regs.unavailable |= 1 << def.regnum;
+        }
+        regs
+    }
+
+    pub(crate) fn get(&self, def: &RegisterDef) -> u32 {
+        self.values[def.regnum]
+    }
+
+    pub(crate) fn set(&mut self, def: &RegisterDef, value: u32) {
+        self.values[def.regnum] = value;
+    }
+
+    pub(crate) fn is_available(&self, def: &RegisterDef) -> bool {
+        self.unavailable & (1 << def.regnum) == 0
+    }
+}
+
+impl Registers for CoreRegisters {
+    type ProgramCounter = u32;
+
+    fn pc(&self) -> u32 {
+        self.values[PC_REGNUM]
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for def in REGISTERS {
+            let bytes = self.get(def).to_le_bytes();
+            for &byte in &bytes[..def.size] {
+                write_byte(self.is_available(def).then_some(byte));
+            }
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let mut rest = bytes;
+        for def in REGISTERS {
+            if rest.len() < def.size {
+                return Err(());
+            }
+            let (value, tail) = rest.split_at(def.size);
+            let mut le = [0u8; 4];
+            le[..def.size].copy_from_slice(value);
+            self.values[def.regnum] = u32::from_le_bytes(le);
+            rest = tail;
+        }
+        self.unavailable = 0;
+        Ok(())
+    }
+}
+
+/// Register number of an entry of [REGISTERS], as GDB sends it in `p` and `P` packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterId(usize);
+
+impl RegisterId {
+    pub(crate) fn def(self) -> &'static RegisterDef {
+        &REGISTERS[self.0]
+    }
+}
+
+impl RegId for RegisterId {
+    fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+        let def = REGISTERS.get(id)?;
+        Some((RegisterId(id), NonZeroUsize::new(def.size)))
+    }
+}
+
+/// The TriCore architecture of gdbstub_arch, with the register set and the single register
+/// numbers of [REGISTERS]
+pub enum TricoreArch {}
+
+impl Arch for TricoreArch {
+    type Usize = u32;
+    type Registers = CoreRegisters;
+    type BreakpointKind = <TricoreV1_6 as Arch>::BreakpointKind;
+    type RegId = RegisterId;
+
+    fn target_description_xml() -> Option<&'static str> {
+        TricoreV1_6::target_description_xml()
+    }
+
+    fn single_step_gdb_behavior() -> SingleStepGdbBehavior {
+        TricoreV1_6::single_step_gdb_behavior()
+    }
+}
+
+/// FPU trap registers, only present on cores with an FPU
+const FPU_REGISTERS: &[&str] = &[
+    "FPU_TRAP_CON",
+    "FPU_TRAP_PC",
+    "FPU_TRAP_OPC",
+    "FPU_TRAP_SRC1",
+    "FPU_TRAP_SRC2",
+    "FPU_TRAP_SRC3",
+];
+
+/// Core special function registers whose presence differs between core types
+const SYSTEM_REGISTERS: &[&str] = &[
+    "ICR", "ISP", "BTV", "BIV", "SYSCON", "FCX", "LCX", "CORE_ID", "DBGSR", "COMPAT",
+];
+
+/// Upper bound of MCD register groups enumerated per core, rust_mcd reports no count
+const MAX_REGISTER_GROUPS: usize = 64;
+
+/// A register of an MCD register group
+#[derive(Debug, Clone)]
+pub(crate) struct McdRegister {
+    pub name: String,
+    /// Reading the register changes the device state, e.g. clears a status flag
+    pub side_effects: bool,
+}
+
+/// An MCD register group of a core, see `monitor reggroups`
+#[derive(Debug, Clone)]
+pub(crate) struct GroupInfo {
+    /// Index of the group with rust_mcd
+    pub index: usize,
+    pub name: String,
+    /// Registers of the group, sorted by name
+    pub registers: Vec<McdRegister>,
+}
+
+impl GroupInfo {
+    pub(crate) fn register(&self, name: &str) -> Option<&McdRegister> {
+        self.registers
+            .binary_search_by(|register| register.name.as_str().cmp(name))
+            .ok()
+            .map(|position| &self.registers[position])
+    }
+
+    /// Whether `selector`, a group index or name, names this group. Names match
+    /// regardless of case and with `_` for a space.
+    fn selected_by(&self, selector: &str) -> bool {
+        match selector.parse::<usize>() {
+            Ok(index) => index == self.index,
+            Err(_) => self.name.replace(' ', "_").eq_ignore_ascii_case(selector),
+        }
+    }
+}
+
+/// Enumerates the register groups of a core and their registers
+fn enumerate_groups(core: &Core<'_>) -> Vec<GroupInfo> {
+    let Ok(groups) = core.register_groups() else {
+        return Vec::new();
+    };
+    (0..MAX_REGISTER_GROUPS)
+        .map_while(|index| groups.get_group(index).ok().map(|group| (index, group)))
+        .map(|(index, group)| {
+            let mut registers: Vec<McdRegister> = group
+                .registers()
+                .iter()
+                .map(|register| McdRegister {
+                    name: register.name().to_owned(),
+                    side_effects: register.has_side_effects_read(),
+                })
+                .collect();
+            registers.sort_by(|a, b| a.name.cmp(&b.name));
+            GroupInfo {
+                index,
+                name: group.name().to_owned(),
+                registers,
+            }
+        })
+        .collect()
+}
+
+/// Registers a core exposes, enumerated once at startup. The GDB register set is read
+/// from the first MCD register group.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CoreCapabilities {
+    /// Availability of each entry of [REGISTERS], by table index
+    available: Vec<bool>,
+    pub fpu: bool,
+    pub system_registers: Vec<&'static str>,
+    /// Every register group of the core, by index
+    pub groups: Vec<GroupInfo>,
+}
+
+impl CoreCapabilities {
+    pub(crate) fn probe(core: &Core<'_>) -> Self {
+        Self::from_groups(enumerate_groups(core))
+    }
+
+    /// Capabilities of a core with the register groups `groups`
+    fn from_groups(groups: Vec<GroupInfo>) -> Self {
+        let Some(first) = groups.first() else {
+            return CoreCapabilities::default();
+        };
+        let has = |name: &str| first.register(name).is_some();
+
+        CoreCapabilities {
+            available: REGISTERS.iter().map(|def| has(def.mcd)).collect(),
+            fpu: FPU_REGISTERS.iter().any(|&name| has(name)),
+            system_registers: SYSTEM_REGISTERS
+                .iter()
+                .copied()
+                .filter(|&name| has(name))
+                .collect(),
+            groups,
+        }
+    }
+
+    /// The group `selector`, an index or a name, refers to
+    pub(crate) fn group(&self, selector: &str) -> Option<&GroupInfo> {
+        self.groups.iter().find(|group| group.selected_by(selector))
+    }
+
+    /// Whether the core has the register of the table entry
+    pub(crate) fn has(&self, def: &RegisterDef) -> bool {
+        self.available.get(def.regnum).copied().unwrap_or(false)
+    }
+
+    /// Table registers the core does not expose
+    pub(crate) fn missing(&self) -> Vec<&'static str> {
+        REGISTERS
+            .iter()
+            .filter(|def| !self.has(def))
+            .map(|def| def.name)
+            .collect()
+    }
+}
+
+/// Target description listing exactly the registers of [REGISTERS]
+fn target_xml() -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0"><architecture>tricore</architecture><feature name="org.gnu.gdb.tricore.core">"#,
+    );
+    for def in REGISTERS {
+        let kind = match def.name {
+            "pc" => "code_ptr",
+            name if name.starts_with('a') => "data_ptr",
+            _ => "uint32",
+        };
+        xml.push_str(&format!(
+            r#"<reg name="{}" bitsize="{}" regnum="{}" type="{}"/>"#,
+            def.name,
+            def.size * 8,
+            def.regnum,
+            kind
+        ));
+    }
+    xml.push_str("</feature></target>");
+    xml
+}
+
+/// Copies the part of the target description GDB asked for into `buf`
+pub(crate) fn read_target_xml(offset: u64, length: usize, buf: &mut [u8]) -> usize {
+    let xml = target_xml();
+    let xml = xml.as_bytes();
+    let start = (offset as usize).min(xml.len());
+    let len = length.min(buf.len()).min(xml.len() - start);
+    buf[..len].copy_from_slice(&xml[start..start + len]);
+    len
+}
+
+impl<'a> TargetDescriptionXmlOverride for TricoreTarget<'a> {
+    fn target_description_xml(
+        &self,
+        _annex: &[u8],
+        offset: u64,
+        length: usize,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        Ok(read_target_xml(offset, length, buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Byte offset of a register within the `g` packet payload
+    fn wire_offset(def: &RegisterDef) -> usize {
+        REGISTERS[..def.regnum].iter().map(|def| def.size).sum()
+    }
+
+    /// A register set with a value unique per register and byte, so shifted fields show
+    fn synthetic() -> CoreRegisters {
+        let mut regs = CoreRegisters::default();
+        for def in REGISTERS {
+            regs.set(
+                def,
+                u32::from_le_bytes([def.regnum as u8, 0xA5, 0x5A, 0xC3]),
+            );
+        }
+        regs
+    }
+
+    fn serialize(regs: &CoreRegisters) -> Vec<u8> {
+        let mut wire = Vec::new();
+        regs.gdb_serialize(|byte| wire.push(byte.expect("register marked unavailable")));
+        wire
+    }
+
+    fn group(index: usize, name: &str, registers: &[&str]) -> GroupInfo {
+        let mut registers: Vec<McdRegister> = registers
+            .iter()
+            .map(|&name| McdRegister {
+                name: name.to_owned(),
+                side_effects: false,
+            })
+            .collect();
+        registers.sort_by(|a, b| a.name.cmp(&b.name));
+        GroupInfo {
+            index,
+            name: name.to_owned(),
+            registers,
+        }
+    }
+
+    #[test]
+    fn every_register_is_serialized_at_its_regnum() {
+        let regs = synthetic();
+        let wire = serialize(&regs);
+        assert_eq!(
+            wire.len(),
+            REGISTERS.iter().map(|def| def.size).sum::<usize>()
+        );
+        for def in REGISTERS {
+            let offset = wire_offset(def);
+            assert_eq!(
+                wire[offset..offset + def.size],
+                regs.get(def).to_le_bytes()[..def.size],
+                "{} is not at regnum {}",
+                def.name,
+                def.regnum
+            );
+        }
+    }
+
+    #[test]
+    fn wire_layout_round_trips() {
+        let regs = synthetic();
+        let mut parsed = CoreRegisters::default();
+        parsed.gdb_deserialize(&serialize(&regs)).unwrap();
+        assert_eq!(parsed, regs);
+        assert_eq!(parsed.pc(), u32::from_le_bytes([14, 0xA5, 0x5A, 0xC3]));
+    }
+
+    #[test]
+    fn registers_the_group_lacks_are_sent_unavailable() {
+        // A core without FPU whose group also lacks D15
+        let names: Vec<&str> = REGISTERS
+            .iter()
+            .map(|def| def.mcd)
+            .filter(|&name| name != "D15")
+            .chain(["ICR", "SYSCON"])
+            .collect();
+        let capabilities = CoreCapabilities::from_groups(vec![group(0, "Core", &names)]);
+        assert!(!capabilities.fpu);
+        assert_eq!(capabilities.missing(), ["d15"]);
+        assert_eq!(capabilities.system_registers, ["ICR", "SYSCON"]);
+
+        let mut regs = CoreRegisters::for_core(&capabilities);
+        for def in REGISTERS.iter().filter(|def| capabilities.has(def)) {
+            regs.set(def, 0x1234_5678);
+        }
+        let mut wire = Vec::new();
+        regs.gdb_serialize(|byte| wire.push(byte));
+        for def in REGISTERS {
+            let offset = wire_offset(def);
+            let bytes = &wire[offset..offset + def.size];
+            if def.mcd == "D15" {
+                assert!(bytes.iter().all(Option::is_none));
+            } else {
+                assert_eq!(bytes, [Some(0x78), Some(0x56), Some(0x34), Some(0x12)]);
+            }
+        }
+    }
+
+    #[test]
+    fn a_core_without_groups_has_no_registers() {
+        let capabilities = CoreCapabilities::from_groups(Vec::new());
+        assert_eq!(capabilities.missing().len(), REGISTERS.len());
+        let mut wire = Vec::new();
+        CoreRegisters::for_core(&capabilities).gdb_serialize(|byte| wire.push(byte));
+        assert!(wire.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn short_register_writes_are_refused() {
+        let mut regs = CoreRegisters::default();
+        let wire = serialize(&synthetic());
+        assert!(regs.gdb_deserialize(&wire[..wire.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn pc_is_the_pc_entry() {
+        assert_eq!(REGISTERS[PC_REGNUM].name, "pc");
+    }
+
+    #[test]
+    fn single_registers_are_numbered_by_the_table() {
+        for def in REGISTERS {
+            let (id, size) = RegisterId::from_raw_id(def.regnum).unwrap();
+            assert_eq!(id.def().name, def.name);
+            assert_eq!(size.map(NonZeroUsize::get), Some(def.size));
+        }
+        assert!(RegisterId::from_raw_id(REGISTERS.len()).is_none());
+    }
+
+    #[test]
+    fn target_description_lists_the_table() {
+        let xml = target_xml();
+        for def in REGISTERS {
+            assert!(xml.contains(&format!(
+                r#"name="{}" bitsize="{}" regnum="{}""#,
+                def.name,
+                def.size * 8,
+                def.regnum
+            )));
+        }
+        assert_eq!(xml.matches("<reg ").count(), REGISTERS.len());
+    }
+}
@@ -0,0 +1,245 @@
+//! Reset cause of the device, `monitor resetcause` and `--log-reset-cause`.
+//!
+//! SCU RSTSTAT flags every reset since its last clear, RSTCON2.CLRC clears it. The
+//! startup software or the stub's own reset at startup may already have cleared it, so
+//! `--log-reset-cause` reads it right after connecting, before anything resets the device.
+//! Devices whose family is not recognized are refused, their SCU layout is unknown.
+
+use gdbstub::outputln;
+use log::info;
+
+use super::arch::ArchVariant;
+use super::{ConnectedDevice, MonitorOutput, TricoreTarget};
+
+/// A reset cause flag of RSTSTAT
+pub(crate) struct ResetCauseBit {
+    pub bit: u32,
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// SCU registers holding and clearing the reset cause
+pub struct ResetRegisters {
+    pub rststat: u32,
+    pub rstcon2: u32,
+    pub(crate) bits: &'static [ResetCauseBit],
+}
+
+/// RSTCON2.CLRC, clears RSTSTAT
+const RSTCON2_CLRC: u32 = 1 << 1;
+
+const fn cause(bit: u32, name: &'static str, description: &'static str) -> ResetCauseBit {
+    ResetCauseBit {
+        bit,
+        name,
+        description,
+    }
+}
+
+const TC2XX_CAUSES: &[ResetCauseBit] = &[
+    cause(0, "ESR0", "external reset request on ESR0"),
+    cause(1, "ESR1", "external reset request on ESR1"),
+    cause(3, "SMU", "SMU alarm, e.g. watchdog or lockstep"),
+    cause(4, "SW", "software reset"),
+    cause(5, "STM0", "STM0 compare match"),
+    cause(6, "STM1", "STM1 compare match"),
+    cause(7, "STM2", "STM2 compare match"),
+    cause(16, "PORST", "power-on reset, PORST pin"),
+    cause(18, "CB0", "system reset requested by the debugger"),
+    cause(19, "CB1", "debug reset requested by the debugger"),
+    cause(20, "CB3", "application reset requested by the debugger"),
+    cause(23, "EVR13", "EVR13 supply watchdog"),
+    cause(24, "EVR33", "EVR33 supply watchdog"),
+    cause(25, "SWD", "supply watchdog"),
+    cause(28, "STBYR", "standby regulator watchdog"),
+];
+
+const TC3XX_CAUSES: &[ResetCauseBit] = &[
+    cause(0, "ESR0", "external reset request on ESR0"),
+    cause(1, "ESR1", "external reset request on ESR1"),
+    cause(3, "SMU", "SMU alarm, e.g. watchdog or lockstep"),
+    cause(4, "SW", "software reset"),
+    cause(5, "STM0", "STM0 compare match"),
+    cause(6, "STM1", "STM1 compare match"),
+    cause(7, "STM2", "STM2 compare match"),
+    cause(8, "STM3", "STM3 compare match"),
+    cause(9, "STM4", "STM4 compare match"),
+    cause(10, "STM5", "STM5 compare match"),
+    cause(16, "PORST", "power-on reset, PORST pin"),
+    cause(18, "CB0", "system reset requested by the debugger"),
+    cause(19, "CB1", "debug reset requested by the debugger"),
+    cause(20, "CB3", "application reset requested by the debugger"),
+    cause(23, "EVRC", "EVRC supply monitor"),
+    cause(24, "EVR33", "EVR33 supply monitor"),
+    cause(25, "SWD", "supply watchdog"),
+    cause(26, "HSMT", "HSM triggered reset"),
+    cause(27, "HSMA", "HSM application reset"),
+    cause(28, "STBYR", "standby regulator watchdog"),
+    cause(29, "LBPORST", "LVD based power-on reset"),
+    cause(30, "LBTERM", "LVD based reset on leaving standby"),
+];
+
+pub(crate) const TC2XX_RESET: ResetRegisters = ResetRegisters {
+    rststat: 0xF003_6050,
+    rstcon2: 0xF003_6064,
+    bits: TC2XX_CAUSES,
+};
+
+pub(crate) const TC3XX_RESET: ResetRegisters = ResetRegisters {
+    rststat: 0xF003_6050,
+    rstcon2: 0xF003_6064,
+    bits: TC3XX_CAUSES,
+};
+
+/// Reset registers of the named device, `None` for devices of an unknown family
+fn reset_registers(device_name: &str) -> Option<&'static ResetRegisters> {
+    ArchVariant::detect(device_name).map(ArchVariant::reset_registers)
+}
+
+/// Labeled line per RSTSTAT flag set in `rststat`, reserved bits included
+pub(crate) fn decode_rststat(registers: &ResetRegisters, rststat: u32) -> Vec<String> {
+    let mut lines = vec![format!("RSTSTAT {:#010x}", rststat)];
+    for bit in (0..32).filter(|bit| rststat & (1 << bit) != 0) {
+        lines.push(match registers.bits.iter().find(|cause| cause.bit == bit) {
+            Some(cause) => format!("  {:<8} {}", cause.name, cause.description),
+            None => format!("  bit {:<4} reserved", bit),
+        });
+    }
+    if rststat == 0 {
+        lines.push("  no reset cause recorded since the last clear".to_owned());
+    }
+    lines
+}
+
+/// Reads and logs the reset cause through a connection made before the first reset
+pub(crate) fn log_before_reset(device: &mut ConnectedDevice) {
+    let Some(registers) = reset_registers(&device.server) else {
+        info!(
+            "Reset cause not read, the SCU layout of {} is unknown",
+            device.server
+        );
+        return;
+    };
+    let rststat = device.command_server.get_system().and_then(|system| {
+        Ok(system
+            .get_core(0)?
+            .read_bytes(registers.rststat as u64, 4)?)
+    });
+    match rststat {
+        Ok(bytes) if bytes.len() == 4 => {
+            let rststat = u32::from_le_bytes(bytes.try_into().unwrap());
+            for line in decode_rststat(registers, rststat) {
+                info!("Reset cause: {}", line.trim_start());
+            }
+        }
+        Ok(_) => info!("Cannot read the reset cause: short read of RSTSTAT"),
+        Err(e) => info!("Cannot read the reset cause: {:#}", e),
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    fn scu_reset_registers(&self, out: &mut MonitorOutput<'_>) -> Option<&'static ResetRegisters> {
+        let registers = reset_registers(&self.das_capabilities.server);
+        if registers.is_none() {
+            outputln!(
+                out,
+                "Refused, the SCU layout of {} is unknown",
+                self.das_capabilities.server
+            );
+        }
+        registers
+    }
+
+    pub(crate) fn reset_cause_command(&mut self, out: &mut MonitorOutput<'_>) {
+        let Some(registers) = self.scu_reset_registers(out) else {
+            return;
+        };
+        match self.read_sized(registers.rststat, 4) {
+            Ok(rststat) => {
+                for line in decode_rststat(registers, rststat) {
+                    outputln!(out, "{}", line);
+                }
+            }
+            Err(e) => outputln!(out, "Cannot read RSTSTAT: {}", e),
+        }
+    }
+
+    pub(crate) fn reset_cause_clear(&mut self, out: &mut MonitorOutput<'_>) {
+        let Some(registers) = self.scu_reset_registers(out) else {
+            return;
+        };
+        let cleared = self
+            .read_sized(registers.rstcon2, 4)
+            .and_then(|rstcon2| self.write_sized(registers.rstcon2, 4, rstcon2 | RSTCON2_CLRC))
+            .and_then(|()| self.read_sized(registers.rststat, 4));
+        match cleared {
+            Ok(0) => outputln!(out, "Reset cause cleared"),
+            // RSTCON2 is ENDINIT protected on some devices, the write is then dropped
+            Ok(rststat) => {
+                outputln!(
+                    out,
+                    "RSTSTAT did not clear, RSTCON2 may be ENDINIT protected"
+                );
+                for line in decode_rststat(registers, rststat) {
+                    outputln!(out, "{}", line);
+                }
+            }
+            Err(e) => outputln!(out, "Cannot clear the reset cause: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cause_tables_follow_the_register_layout() {
+        for causes in [TC2XX_CAUSES, TC3XX_CAUSES] {
+            for pair in causes.windows(2) {
+                assert!(pair[0].bit < pair[1].bit, "{} out of order", pair[1].name);
+                assert_ne!(pair[0].name, pair[1].name);
+            }
+            assert!(causes.iter().all(|cause| cause.bit < 32));
+            let bit = |name| causes.iter().find(|cause| cause.name == name).unwrap().bit;
+            assert_eq!(bit("ESR0"), 0);
+            assert_eq!(bit("SW"), 4);
+            assert_eq!(bit("PORST"), 16);
+            assert_eq!(bit("CB0"), 18);
+        }
+    }
+
+    #[test]
+    fn set_flags_are_decoded_in_bit_order() {
+        let lines = decode_rststat(&TC3XX_RESET, 1 << 16 | 1 << 4 | 1 << 2);
+        assert_eq!(
+            lines,
+            vec![
+                "RSTSTAT 0x00010014".to_owned(),
+                "  bit 2    reserved".to_owned(),
+                "  SW       software reset".to_owned(),
+                "  PORST    power-on reset, PORST pin".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn cleared_register_says_so() {
+        let lines = decode_rststat(&TC2XX_RESET, 0);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("no reset cause"));
+    }
+
+    #[test]
+    fn tc3xx_bits_are_reserved_on_tc2xx() {
+        assert!(decode_rststat(&TC2XX_RESET, 1 << 8)[1].contains("reserved"));
+        assert!(decode_rststat(&TC3XX_RESET, 1 << 8)[1].contains("STM3"));
+    }
+
+    #[test]
+    fn unknown_devices_are_refused() {
+        assert!(reset_registers("TC397XP").is_some());
+        assert!(reset_registers("TC277TF").is_some());
+        assert!(reset_registers("XMC4500").is_none());
+    }
+}
@@ -1,35 +1,322 @@
 use gdbstub::{
     common::{Signal, Tid},
-    target::{ext::base::multithread::MultiThreadResume, TargetError},
+    target::ext::base::multithread::MultiThreadResume,
 };
-use log::trace;
+use log::{debug, trace, warn};
+use std::thread::sleep;
 
-use super::{tid_to_cpuid, ResumeAction, StaticTricoreTarget};
+use super::decode::call_len;
+use super::events::TargetEvent;
+use super::step_off::StepOff;
+use super::trigger_budget::TriggerOwner;
+use super::{present_core, read_register, tricore, CpuId, ResumeAction, TricoreTarget};
 
-impl MultiThreadResume for StaticTricoreTarget {
-    fn resume(&mut self) -> Result<(), Self::Error> {
-        _ = self.cores[1].query_state();
-
-        // iterate through each recoreded resume action and run or step
-        for (iter, resume_action) in self.resume_actions.iter().enumerate() {
-            let core = &mut self.cores[iter];
-
-            match resume_action {
-                ResumeAction::Resume => {
-                    trace!("Resumed core {:?}", iter);
-                    _ = core
-                        .run()
-                        .map_err(|_| format!("failed to run core: {}", iter));
+/// The cores of `cores` in the order they are started: the ones listed in `order` first,
+/// as listed, the others after them in the order given
+pub(crate) fn start_order(cores: &[usize], order: &[usize]) -> Vec<usize> {
+    let mut sequence: Vec<usize> = order
+        .iter()
+        .copied()
+        .filter(|index| cores.contains(index))
+        .collect();
+    sequence.extend(cores.iter().copied().filter(|index| !order.contains(index)));
+    sequence
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// `cores` in the order of the `resume-order` setting
+    pub(crate) fn resume_sequence(&self, cores: &[usize]) -> Vec<usize> {
+        start_order(cores, &self.settings.resume_order)
+    }
+
+    /// Waits `resume-stagger-ms` before the core at `position` of a resume sequence is
+    /// started
+    pub(crate) fn stagger_start(&self, position: usize) {
+        if position > 0 && !self.settings.resume_stagger.is_zero() {
+            sleep(self.settings.resume_stagger);
+        }
+    }
+
+    /// Arms a trigger at the return address of a core sitting on a call instruction, so
+    /// the core can be run over the callee instead of stepping into it. Returns false if
+    /// the core is to be stepped normally.
+    fn arm_step_over(&mut self, index: usize) -> bool {
+        let Some(core) = self.core(index) else {
+            return false;
+        };
+        let Some(pc) = read_register(core, "PC") else {
+            return false;
+        };
+        let Some(len) = core
+            .read_bytes(pc as u64, 4)
+            .ok()
+            .and_then(|code| call_len(&code))
+        else {
+            return false;
+        };
+
+        let return_addr = pc.wrapping_add(len);
+        let Some(trigger) = self.create_core_trigger(index, return_addr, TriggerOwner::StepOver)
+        else {
+            return false;
+        };
+
+        debug!(
+            "Core {} stepping over call at {:#010x} to {:#010x}",
+            index, pc, return_addr
+        );
+        let cpu_id = CpuId::try_from(index).expect("Unexpected core index");
+        self.step_overs.insert(cpu_id, (return_addr, trigger));
+        true
+    }
+
+    /// Removes the step over trigger of a stopped core, returns whether the core stopped
+    /// at the return address, i.e. the step over completed.
+    pub(crate) fn finish_step_over(&mut self, cpu_id: CpuId) -> bool {
+        let Some((return_addr, trigger)) = self.step_overs.remove(&cpu_id) else {
+            return false;
+        };
+        self.remove_core_trigger(usize::from(cpu_id), trigger, TriggerOwner::StepOver);
+        self.core(usize::from(cpu_id))
+            .and_then(|core| read_register(core, "PC"))
+            == Some(return_addr)
+    }
+
+    /// Whether core `index` was started with a hardware step, rather than run for a
+    /// continue, a step over or a soft step
+    pub(crate) fn hardware_stepping(&self, index: usize) -> bool {
+        let Ok(cpu_id) = CpuId::try_from(index) else {
+            return false;
+        };
+        matches!(
+            self.slot_at(index).map(|slot| slot.resume_action),
+            Some(ResumeAction::Step)
+        ) && !self.step_overs.contains_key(&cpu_id)
+            && !self.soft_steps.contains_key(&cpu_id)
+    }
+
+    /// Starts a core halted behind GDB's back again the way the last resume started it,
+    /// so a step stays a step
+    pub(crate) fn restart_core(&mut self, index: usize) -> bool {
+        let step = self.hardware_stepping(index);
+        self.core_mut(index).is_some_and(|core| {
+            if step {
+                core.step().is_ok()
+            } else {
+                core.run().is_ok()
+            }
+        })
+    }
+
+    /// Records the action of a thread. Threads without a present core are skipped rather
+    /// than failing the whole resume request.
+    fn set_resume_action(&mut self, tid: Tid, action: ResumeAction) {
+        match self
+            .core_index(tid)
+            .and_then(|index| present_core(&self.slots, index).map(|_| index))
+        {
+            Ok(index) => {
+                if let Some(slot) = self.slot_at_mut(index) {
+                    slot.resume_action = action;
                 }
-                ResumeAction::Step => {
-                    trace!("Stepped core {:?}", iter);
+            }
+            Err(e) => warn!("Not resuming thread {}: {}", tid, e),
+        }
+    }
+
+    /// Drops the step over and soft step triggers of cores halted before completing
+    /// their step
+    pub(crate) fn clear_step_overs(&mut self) {
+        for (cpu_id, (_, trigger)) in std::mem::take(&mut self.step_overs) {
+            self.remove_core_trigger(usize::from(cpu_id), trigger, TriggerOwner::StepOver);
+        }
+        self.clear_soft_steps();
+    }
+}
+
+impl<'a> MultiThreadResume for TricoreTarget<'a> {
+    fn resume(&mut self) -> Result<(), Self::Error> {
+        if !self.pending_events.is_empty() {
+            // A stop is still queued: leave every core halted so it is reported right away
+            trace!("Stop pending, not resuming cores");
+            return Ok(());
+        }
+        if self.refuse_busy("resume") {
+            // Report the cores as stopped right away, GDB would wait for a stop otherwise
+            let cpu_id = self.stop_report_core();
+            self.pending_events.push_back((
+                tricore::Event::HaltedByStub(tricore::HaltCause::Busy),
+                cpu_id,
+            ));
+            return Ok(());
+        }
+        // vCont has no error reply: a failed write of GDB's is noted and reported as a stop
+        if let Err(e) = self.flush_writes() {
+            self.notify(None, format_args!("Not resuming: {}", e));
+            let cpu_id = self.stop_report_core();
+            self.pending_events.push_back((
+                tricore::Event::HaltedByStub(tricore::HaltCause::WriteFailed),
+                cpu_id,
+            ));
+            return Ok(());
+        }
+        self.initial_stop = None;
+        // Cores running since GDB attached are where the resume takes them already
+        let left_running = if std::mem::take(&mut self.left_running) {
+            self.running_cores()
+        } else {
+            Vec::new()
+        };
 
-                    _ = core
-                        .step()
-                        .map_err(|_| TargetError::Fatal(format!("failed to run core: {}", iter)));
+        if self.arch.query_state_before_register_read() {
+            if let Some(core) = self.core(1) {
+                _ = core.query_state();
+            }
+        }
+
+        // Halt requests made while stopped have been served by being stopped already
+        self.pause.discard();
+        self.link_health.note_run();
+        self.stop_snapshots.clear();
+        self.clear_register_cache();
+
+        // A `vCont;c` without a thread names no core, with `scheduler-lock` it still
+        // resumes every core
+        if self.settings.scheduler_lock
+            && self
+                .slots()
+                .all(|(_, slot)| matches!(slot.resume_action, ResumeAction::Unchanged))
+        {
+            for (_, slot) in self.slots_mut() {
+                slot.resume_action = ResumeAction::Resume;
+            }
+        }
+
+        // Prepare every core first, so the cores are started back to back afterwards and
+        // none can stop before the last one was started
+        let mut starts = Vec::new();
+        let actions: Vec<(usize, ResumeAction)> = self
+            .slots()
+            .filter(|(_, slot)| slot.core.is_some())
+            .map(|(index, slot)| (index, slot.resume_action))
+            .collect();
+        for (index, resume_action) in actions {
+            if matches!(resume_action, ResumeAction::Unchanged) {
+                // Cores GDB gave no action stay as they are
+                continue;
+            }
+            let step = matches!(resume_action, ResumeAction::Step);
+            if step && self.settings.step_mask_irq {
+                self.mask_step_irq(index);
+            }
+            let stepped_over = step && self.settings.step_over_calls && self.arm_step_over(index);
+            let soft_stepped =
+                step && !stepped_over && self.settings.soft_step && self.arm_soft_step(index);
+            let hardware_step = step && !stepped_over && !soft_stepped;
+            if !left_running.contains(&index) {
+                let cpu_id = CpuId::try_from(index).expect("Unexpected core index");
+                match self.step_off_breakpoint(index) {
+                    StepOff::NotOnBreakpoint => {}
+                    // The step off the breakpoint was the step, a step over continues into
+                    // the callee
+                    StepOff::Stepped if hardware_step || soft_stepped => {
+                        self.pending_events
+                            .push_back((tricore::Event::DoneStep, cpu_id));
+                        continue;
+                    }
+                    StepOff::Stepped => {}
+                    StepOff::Stopped(event) => {
+                        self.pending_events.push_back((event, cpu_id));
+                        continue;
+                    }
                 }
-                ResumeAction::Unchanged => {}
             }
+            starts.push((index, hardware_step));
+        }
+        if !self.pending_events.is_empty() {
+            // A step off a breakpoint ended the resume, no core is started and the stop is
+            // reported right away
+            self.restore_step_irq_masks();
+            self.clear_step_overs();
+            return Ok(());
+        }
+
+        // A single core started, or a single core stepped while others run, is the one
+        // the user waits on
+        let stepped: Vec<usize> = starts
+            .iter()
+            .filter(|(_, step)| *step)
+            .map(|&(index, _)| index)
+            .collect();
+        self.poll_schedule
+            .set_focus(match (starts.as_slice(), stepped.as_slice()) {
+                ([(index, _)], _) | (_, [index]) => Some(*index),
+                _ => None,
+            });
+
+        // Steps end on their own, only cores left running can end up in a trap loop
+        self.trap_loop.start(
+            starts
+                .iter()
+                .filter(|(_, step)| !*step)
+                .map(|&(index, _)| index),
+        );
+
+        // Breakpoint changes made while halted are downloaded once per core, here
+        self.flush_triggers();
+        self.snapshot_range_watches();
+
+        // Started in the configured order, e.g. a boot core polling the others last
+        let indices: Vec<usize> = starts.iter().map(|&(index, _)| index).collect();
+        let starts: Vec<(usize, bool)> = self
+            .resume_sequence(&indices)
+            .into_iter()
+            .filter_map(|index| starts.iter().find(|&&(start, _)| start == index).copied())
+            .collect();
+
+        // The STM is read before the first core starts, so no run is measured short
+        self.start_target_time(&indices);
+
+        let mut failed = Vec::new();
+        for (position, &(index, step)) in starts.iter().enumerate() {
+            self.stagger_start(position);
+            if left_running.contains(&index) {
+                continue;
+            }
+            let Some(core) = self.core_mut(index) else {
+                failed.push(index);
+                continue;
+            };
+            let started = if step {
+                core.step().is_ok()
+            } else {
+                core.run().is_ok()
+            };
+            if !started {
+                failed.push(index);
+            }
+        }
+
+        let running: Vec<usize> = starts
+            .iter()
+            .filter(|&&(index, step)| !step && !failed.contains(&index))
+            .map(|&(index, _)| index)
+            .collect();
+        self.calibrate_stm_once(&running);
+
+        for (index, step) in starts {
+            if failed.contains(&index) {
+                warn!("Failed to start core {}", index);
+                self.target_time.abandon(index);
+                continue;
+            }
+            trace!(
+                "{} core {}",
+                if step { "Stepped" } else { "Resumed" },
+                index
+            );
+            let cpu = CpuId::try_from(index).expect("Unexpected core index");
+            self.publish(TargetEvent::Resumed { cpu });
         }
 
         Ok(())
@@ -50,22 +337,27 @@ impl MultiThreadResume for StaticTricoreTarget {
         if signal.is_some() {
             return Err("no support for continuing with signal");
         }
-        let core_id = tid_to_cpuid(tid)?;
-        let index = usize::from(core_id);
-        self.resume_actions[index] = ResumeAction::Resume;
-
+        self.set_resume_action(tid, ResumeAction::Resume);
         Ok(())
     }
 
+    /// gdbstub only reports explicit per-thread actions, the default action for every
+    /// core not mentioned in a vCont packet is therefore to continue. With
+    /// `scheduler-lock` such cores keep their current state instead.
     fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
-        for resume_action in self.resume_actions.iter_mut() {
-            *resume_action = ResumeAction::Resume;
+        let action = if self.settings.scheduler_lock {
+            ResumeAction::Unchanged
+        } else {
+            ResumeAction::Resume
+        };
+        for (_, slot) in self.slots_mut() {
+            slot.resume_action = action;
         }
         Ok(())
     }
 }
 
-impl gdbstub::target::ext::base::multithread::MultiThreadSingleStep for StaticTricoreTarget {
+impl<'a> gdbstub::target::ext::base::multithread::MultiThreadSingleStep for TricoreTarget<'a> {
     fn set_resume_action_step(
         &mut self,
         tid: Tid,
@@ -75,11 +367,37 @@ impl gdbstub::target::ext::base::multithread::MultiThreadSingleStep for StaticTr
             return Err("no support for stepping with signal");
         }
 
-        let core_id = tid_to_cpuid(tid)?;
-        let index = usize::from(core_id);
+        self.set_resume_action(tid, ResumeAction::Step);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        self.resume_actions[index] = ResumeAction::Step;
+    #[test]
+    fn listed_cores_start_first() {
+        assert_eq!(start_order(&[0, 1, 2], &[2, 1, 0]), vec![2, 1, 0]);
+        assert_eq!(start_order(&[0, 1, 2, 3], &[2]), vec![2, 0, 1, 3]);
+    }
 
-        Ok(())
+    #[test]
+    fn boot_core_last() {
+        assert_eq!(
+            start_order(&[0, 1, 2, 3, 4, 5], &[1, 2, 3, 4, 5, 0]).last(),
+            Some(&0)
+        );
+    }
+
+    #[test]
+    fn only_resumed_cores_are_started() {
+        assert_eq!(start_order(&[1, 3], &[3, 2, 0]), vec![3, 1]);
+        assert_eq!(start_order(&[], &[2, 1, 0]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn without_order_the_cores_start_as_given() {
+        assert_eq!(start_order(&[0, 2, 4], &[]), vec![0, 2, 4]);
     }
 }
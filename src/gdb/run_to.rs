@@ -0,0 +1,146 @@
+//! `--break-at`, runs the boot core to a function or address before GDB attaches, so the
+//! client finds the target parked there, e.g. at `main` with the startup code done.
+//!
+//! The device is reset, a trigger only this run knows of is planted on the boot core and
+//! the boot core is let run. The other cores stay halted from the reset. Once the boot
+//! core stops, or the time is up or the run is aborted, every core is halted and the
+//! trigger removed.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rust_mcd::core::CoreState;
+
+use super::initial_stop::StartupCause;
+use super::trigger_budget::TriggerOwner;
+use super::{read_register, CpuId, TricoreTarget};
+
+/// The core running after a reset
+const BOOT_CORE: CpuId = CpuId::Cpu0;
+
+/// Pause between polls of the boot core
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How a run to the `--break-at` location ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunToOutcome {
+    /// The boot core stopped at the location
+    Reached,
+    /// The boot core stopped elsewhere, e.g. in a trap, at the PC
+    StoppedAt(Option<u32>),
+    TimedOut,
+    Aborted,
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Resets the device and runs the boot core to `location`, `<symbol>[+offset]` or an
+    /// address, for at most `timeout`. `abort` is polled meanwhile. The target is halted
+    /// afterwards however the run ended, only a location which cannot be resolved or
+    /// planted fails.
+    pub fn run_to(
+        &mut self,
+        location: &str,
+        timeout: Duration,
+        mut abort: impl FnMut() -> bool,
+    ) -> Result<RunToOutcome, String> {
+        let addr = self.resolve_address(location)?;
+        let addr = self.breakpoint_address(addr);
+        let index = usize::from(BOOT_CORE);
+        if self.core(index).is_none() {
+            return Err(format!("The boot core {:?} is absent", BOOT_CORE));
+        }
+
+        self.restart();
+        let trigger = self
+            .create_core_trigger(index, addr, TriggerOwner::RunTo)
+            .ok_or_else(|| format!("Cannot plant a breakpoint at {:#010x}", addr))?;
+        self.flush_triggers();
+
+        info!("Running to {} at {:#010x}", location, addr);
+        let outcome = self.run_busy("running to the --break-at location", |target| {
+            target.run_boot_core(timeout, &mut abort)
+        });
+
+        self.halt();
+        self.remove_core_trigger(index, trigger, TriggerOwner::RunTo);
+        self.flush_triggers();
+        self.clear_register_cache();
+
+        let outcome = match outcome {
+            RunToOutcome::StoppedAt(Some(pc)) if pc == addr => RunToOutcome::Reached,
+            outcome => outcome,
+        };
+        match outcome {
+            RunToOutcome::Reached => self.console(
+                Some(BOOT_CORE),
+                format_args!("Halted at {} ({:#010x})", location, addr),
+            ),
+            RunToOutcome::StoppedAt(pc) => self.console(
+                Some(BOOT_CORE),
+                format_args!(
+                    "Stopped at {} before reaching {}, the target is halted",
+                    pc.map_or("an unknown PC".to_owned(), |pc| format!("{:#010x}", pc)),
+                    location
+                ),
+            ),
+            RunToOutcome::TimedOut => self.console(
+                Some(BOOT_CORE),
+                format_args!(
+                    "{} not reached within {:?}, the target is halted where it was",
+                    location, timeout
+                ),
+            ),
+            RunToOutcome::Aborted => self.console(
+                Some(BOOT_CORE),
+                format_args!("Run to {} aborted, the target is halted", location),
+            ),
+        }
+        let cause = match outcome {
+            RunToOutcome::Reached => StartupCause::BreakAt(addr),
+            _ => StartupCause::BreakAtMissed,
+        };
+        self.set_initial_stop(index, cause);
+        for index in self.all_cores() {
+            let cpu_id = CpuId::try_from(index).expect("Unexpected core index");
+            self.publish_halted(cpu_id, "break-at");
+        }
+        Ok(outcome)
+    }
+
+    /// Lets the boot core run until it stops, the time is up or `abort` returns true
+    fn run_boot_core(
+        &mut self,
+        timeout: Duration,
+        abort: &mut dyn FnMut() -> bool,
+    ) -> RunToOutcome {
+        let index = usize::from(BOOT_CORE);
+        let Some(core) = self.core_mut(index) else {
+            return RunToOutcome::StoppedAt(None);
+        };
+        if core.run().is_err() {
+            warn!("Cannot run the boot core");
+            return RunToOutcome::StoppedAt(None);
+        }
+
+        let started = Instant::now();
+        loop {
+            if abort() {
+                return RunToOutcome::Aborted;
+            }
+            if started.elapsed() > timeout {
+                return RunToOutcome::TimedOut;
+            }
+            let Some(core) = self.core(index) else {
+                return RunToOutcome::StoppedAt(None);
+            };
+            if matches!(
+                core.query_state().map(|info| info.state),
+                Ok(CoreState::Debug | CoreState::Halted)
+            ) {
+                return RunToOutcome::StoppedAt(read_register(core, "PC"));
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
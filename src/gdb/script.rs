@@ -0,0 +1,112 @@
+//! Scripts of monitor commands, `--init-commands` at startup and `monitor source`.
+//!
+//! A script holds one monitor command per line, without the `monitor` prefix. Empty lines
+//! and lines starting with `#` are skipped. The script stops at the first command which
+//! is refused or not understood, a `-k` line before the first command makes it go on
+//! instead. Most commands report a bad argument as text only, which doesn't stop it.
+
+use std::fs;
+use std::path::Path;
+
+use gdbstub::outputln;
+use log::info;
+
+use super::{MonitorOutput, TricoreTarget};
+
+/// Line making a script go on after a failed command
+const KEEP_GOING: &str = "-k";
+
+struct Script {
+    /// Commands with their line number
+    commands: Vec<(usize, String)>,
+    keep_going: bool,
+}
+
+fn parse_script(text: &str) -> Script {
+    let mut script = Script {
+        commands: Vec::new(),
+        keep_going: false,
+    };
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == KEEP_GOING && script.commands.is_empty() {
+            script.keep_going = true;
+            continue;
+        }
+        script.commands.push((index + 1, line.to_owned()));
+    }
+    script
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Runs the commands of the script at `path`, `transcript` receives every command
+    /// followed by its output. Fails if the script cannot be read or stopped early.
+    fn run_script(&mut self, path: &Path, transcript: &mut dyn FnMut(&str)) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let script = parse_script(&text);
+
+        let mut failed = 0;
+        for (line, cmd) in &script.commands {
+            transcript(&format!("> {}", cmd));
+            let mut out = MonitorOutput::captured();
+            if cmd.split_whitespace().next() == Some("source") {
+                outputln!(out, "Scripts cannot source other scripts");
+                out.fail();
+            } else {
+                self.monitor_command(cmd, &mut out);
+            }
+            let failed_now = out.failed();
+            for output in out.into_text().lines() {
+                transcript(&format!("  {}", output));
+            }
+            if !failed_now {
+                continue;
+            }
+            if !script.keep_going {
+                return Err(format!(
+                    "{}:{}: `{}` failed, the remaining commands were skipped",
+                    path.display(),
+                    line,
+                    cmd
+                ));
+            }
+            failed += 1;
+        }
+        if failed > 0 {
+            transcript(&format!(
+                "{} of {} commands failed",
+                failed,
+                script.commands.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs the commands of `--init-commands`, the transcript goes to the log
+    pub fn run_init_commands(&mut self, path: &Path) -> Result<(), String> {
+        info!("Running the monitor commands of {}", path.display());
+        let mut lines = Vec::new();
+        let result = self.run_script(path, &mut |line| {
+            info!("init: {}", line);
+            lines.push(line.to_owned());
+        });
+        if let Some(log) = &mut self.session_log {
+            for line in lines {
+                log.line(None, format_args!("init: {}", line));
+            }
+        }
+        result
+    }
+
+    pub(crate) fn source_command(&mut self, path: &str, out: &mut MonitorOutput<'_>) {
+        let result = self.run_script(Path::new(path), &mut |line| outputln!(out, "{}", line));
+        if let Err(e) = result {
+            outputln!(out, "{}", e);
+            out.fail();
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Memory search for `monitor find`, done stub side so the memory does not have to cross
+//! the remote protocol.
+//!
+//! gdbstub does not implement `qSearch:memory`, GDB's `find` therefore still reads the
+//! memory itself.
+
+use std::ops::Range;
+
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+
+use super::{parse_u32, MonitorOutput, TricoreTarget};
+
+/// Bytes searched per read, consecutive reads overlap by the pattern length minus one
+const SEARCH_CHUNK: u64 = 64 * 1024;
+
+/// Matches reported by `monitor find` before the search stops
+const MAX_MATCHES: usize = 64;
+
+struct SearchResult {
+    matches: Vec<u32>,
+    /// Ranges which could not be read and were not searched
+    skipped: Vec<Range<u64>>,
+}
+
+/// Parses a pattern given as hex string, e.g. `deadbeef`
+fn parse_hex_pattern(text: &str) -> Option<Vec<u8>> {
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    if text.is_empty() || text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Finds up to `limit` occurrences of `pattern` in `[start, start + len)`, reading memory
+/// with `read`, which tells whether the read succeeded
+fn search_memory(
+    start: u32,
+    len: u64,
+    pattern: &[u8],
+    limit: usize,
+    mut read: impl FnMut(u32, &mut [u8]) -> bool,
+) -> SearchResult {
+    let mut result = SearchResult {
+        matches: Vec::new(),
+        skipped: Vec::new(),
+    };
+    let end = (start as u64 + len).min(1 << 32);
+    let overlap = pattern.len() as u64 - 1;
+
+    let mut pos = start as u64;
+    let mut window = Vec::new();
+    while pos < end && result.matches.len() < limit {
+        let window_end = (pos + SEARCH_CHUNK + overlap).min(end);
+        window.resize((window_end - pos) as usize, 0);
+        if !read(pos as u32, &mut window) {
+            let skipped_end = (pos + SEARCH_CHUNK).min(end);
+            match result.skipped.last_mut() {
+                Some(last) if last.end == pos => last.end = skipped_end,
+                _ => result.skipped.push(pos..skipped_end),
+            }
+            pos = skipped_end;
+            continue;
+        }
+
+        // Matches starting in the overlap are found by the next window
+        let starts = (SEARCH_CHUNK as usize).min(window.len());
+        for offset in 0..starts {
+            if window[offset..].starts_with(pattern) {
+                result.matches.push((pos + offset as u64) as u32);
+                if result.matches.len() >= limit {
+                    break;
+                }
+            }
+        }
+        pos += SEARCH_CHUNK;
+    }
+
+    result
+}
+
+impl<'a> TricoreTarget<'a> {
+    pub(crate) fn find_command(
+        &mut self,
+        start: &str,
+        len: &str,
+        pattern: &str,
+        out: &mut MonitorOutput<'_>,
+    ) {
+        let (Some(start), Some(len), Some(pattern)) =
+            (parse_u32(start), parse_u32(len), parse_hex_pattern(pattern))
+        else {
+            outputln!(out, "usage: find <start> <len> <hex-pattern>");
+            return;
+        };
+
+        let result = search_memory(start, len as u64, &pattern, MAX_MATCHES, |addr, data| {
+            self.read_memory(Tid::new(1).unwrap(), addr, data).is_ok()
+        });
+        for range in &result.skipped {
+            outputln!(
+                out,
+                "Skipped unreadable {:#010x}..{:#010x}",
+                range.start,
+                range.end
+            );
+        }
+        for addr in &result.matches {
+            outputln!(out, "{:#010x}", addr);
+        }
+        match result.matches.len() {
+            0 => outputln!(out, "Pattern not found"),
+            MAX_MATCHES => outputln!(out, "Stopped after {} matches", MAX_MATCHES),
+            count => outputln!(out, "{} matches", count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Memory of `len` bytes at `base` holding `pattern` at each of `at`, unreadable in
+    /// `holes`
+    fn memory(
+        base: u32,
+        len: usize,
+        pattern: &'static [u8],
+        at: &'static [u32],
+        holes: &'static [Range<u32>],
+    ) -> impl FnMut(u32, &mut [u8]) -> bool {
+        let mut bytes = vec![0; len];
+        for &addr in at {
+            let offset = (addr - base) as usize;
+            bytes[offset..offset + pattern.len()].copy_from_slice(pattern);
+        }
+        move |addr, data| {
+            let end = addr + data.len() as u32;
+            if holes.iter().any(|hole| hole.start < end && addr < hole.end) {
+                return false;
+            }
+            let offset = (addr - base) as usize;
+            data.copy_from_slice(&bytes[offset..offset + data.len()]);
+            true
+        }
+    }
+
+    #[test]
+    fn hex_pattern_is_parsed() {
+        assert_eq!(
+            parse_hex_pattern("deadBEEF"),
+            Some(vec![0xDE, 0xAD, 0xBE, 0xEF])
+        );
+        assert_eq!(parse_hex_pattern("0x01"), Some(vec![0x01]));
+        assert_eq!(parse_hex_pattern(""), None);
+        assert_eq!(parse_hex_pattern("abc"), None);
+        assert_eq!(parse_hex_pattern("zz"), None);
+    }
+
+    #[test]
+    fn match_across_chunks_is_found_once() {
+        let base = 0x7000_0000;
+        let len = 3 * SEARCH_CHUNK as usize;
+        let across = base + SEARCH_CHUNK as u32 - 2;
+        let found = search_memory(
+            base,
+            len as u64,
+            b"\xde\xad\xbe\xef",
+            MAX_MATCHES,
+            memory(
+                base,
+                len,
+                b"\xde\xad\xbe\xef",
+                &[0x7000_0010, 0x7000_fffe],
+                &[],
+            ),
+        );
+        assert_eq!(across, 0x7000_fffe);
+        assert_eq!(found.matches, vec![0x7000_0010, across]);
+        assert!(found.skipped.is_empty());
+    }
+
+    #[test]
+    fn unreadable_chunks_are_skipped_together() {
+        let base = 0x7000_0000;
+        let len = 4 * SEARCH_CHUNK as usize;
+        let found = search_memory(
+            base,
+            len as u64,
+            b"\x55",
+            MAX_MATCHES,
+            memory(
+                base,
+                len,
+                b"\x55",
+                &[0x7003_0000],
+                &[0x7001_0000..0x7003_0000],
+            ),
+        );
+        assert_eq!(found.matches, vec![0x7003_0000]);
+        assert_eq!(found.skipped, vec![0x7001_0000..0x7003_0000]);
+    }
+
+    #[test]
+    fn search_stops_at_the_limit() {
+        let base = 0x7000_0000;
+        let found = search_memory(
+            base,
+            0x100,
+            b"\x00",
+            3,
+            memory(base, 0x100, b"\x00", &[], &[]),
+        );
+        assert_eq!(found.matches, vec![base, base + 1, base + 2]);
+    }
+}
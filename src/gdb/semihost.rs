@@ -0,0 +1,401 @@
+//! Semihosting, file access on the host for code running on the target, e.g. a test
+//! harness writing its results.
+//!
+//! Bare-metal TriCore has no system call trap the stub could catch, so a breakpoint
+//! convention is used instead. The firmware provides
+//!
+//! ```c
+//! int __semihost_call(struct semihost_args *args); /* body: debug; ret */
+//! struct semihost_args { uint32_t op, arg0, arg1, arg2; };
+//! ```
+//!
+//! The core halts on the `debug` instruction at `__semihost_call`. The stub reads the
+//! argument block A4 points to, performs the call, places the result in D2, moves PC past
+//! the `debug` and resumes the core, without GDB noticing the stop. Results are the ones
+//! of the POSIX calls, errors are returned as negative errno values.
+//!
+//! | op | call    | arg0         | arg1          | arg2         |
+//! |----|---------|--------------|---------------|--------------|
+//! | 1  | open    | path address | path length   | [OpenMode]   |
+//! | 2  | close   | fd           |               |              |
+//! | 3  | read    | fd           | buffer        | length       |
+//! | 4  | write   | fd           | buffer        | length       |
+//! | 5  | lseek   | fd           | offset (i32)  | whence       |
+//! | 6  | putchar | character    |               |              |
+//!
+//! Files are only opened below `--semihost-root`, without it every file call fails with
+//! EACCES while putchar still reaches the console. Transfers are capped at
+//! [MAX_TRANSFER] bytes per call, a longer read or write is done partially as POSIX
+//! allows.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use log::{debug, warn};
+
+use super::{cpuid_to_tid, decode, read_register, write_register, CpuId, TricoreTarget};
+
+/// Symbol of the function whose `debug` instruction requests a call
+pub(crate) const SEMIHOST_SYMBOL: &str = "__semihost_call";
+
+/// Largest read or write served in a single call
+const MAX_TRANSFER: u32 = 64 * 1024;
+/// Longest path accepted from the target
+const MAX_PATH: u32 = 1024;
+/// Files the target may hold open at the same time
+const MAX_OPEN_FILES: usize = 16;
+
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+const EFAULT: i32 = 14;
+const EINVAL: i32 = 22;
+const EMFILE: i32 = 24;
+const ENAMETOOLONG: i32 = 36;
+const ENOSYS: i32 = 38;
+
+/// How `open` opens a file, arg2 of the call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpenMode {
+    Read,
+    /// Created or truncated
+    Write,
+    /// Created if missing, written at the end
+    Append,
+    ReadWrite,
+}
+
+impl OpenMode {
+    fn from_arg(arg: u32) -> Option<Self> {
+        match arg {
+            0 => Some(OpenMode::Read),
+            1 => Some(OpenMode::Write),
+            2 => Some(OpenMode::Append),
+            3 => Some(OpenMode::ReadWrite),
+            _ => None,
+        }
+    }
+
+    fn options(self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        match self {
+            OpenMode::Read => options.read(true),
+            OpenMode::Write => options.write(true).create(true).truncate(true),
+            OpenMode::Append => options.append(true).create(true),
+            OpenMode::ReadWrite => options.read(true).write(true),
+        };
+        options
+    }
+}
+
+/// Negative errno of a failed host operation
+fn errno(e: &io::Error) -> i32 {
+    -e.raw_os_error().unwrap_or(EIO)
+}
+
+/// Host files opened by the target and the console lines being assembled
+#[derive(Default)]
+pub(crate) struct Semihost {
+    root: Option<PathBuf>,
+    files: HashMap<i32, File>,
+    next_fd: i32,
+    /// putchar output per core, logged line by line
+    lines: HashMap<CpuId, String>,
+}
+
+impl Semihost {
+    pub fn new(root: Option<PathBuf>) -> Self {
+        Semihost {
+            root,
+            next_fd: 3,
+            ..Default::default()
+        }
+    }
+
+    /// Resolves a target path below the root. Only plain relative paths are accepted,
+    /// and neither their directory nor the file itself may lead out of the root through
+    /// a symlink.
+    fn resolve(&self, path: &str) -> Result<PathBuf, i32> {
+        let root = self.root.as_ref().ok_or(-EACCES)?;
+        let path = Path::new(path);
+        let plain = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        let Some(name) = path.file_name().filter(|_| plain) else {
+            return Err(-EACCES);
+        };
+        let root = root.canonicalize().map_err(|e| errno(&e))?;
+        let parent = root
+            .join(path)
+            .parent()
+            .ok_or(-EINVAL)?
+            .canonicalize()
+            .map_err(|e| errno(&e))?;
+        let full = parent.join(name);
+        let full = match full.canonicalize() {
+            Ok(target) => target,
+            // A dangling symlink, opening it would create its target wherever it points
+            Err(_) if full.symlink_metadata().is_ok() => return Err(-EACCES),
+            Err(_) => full,
+        };
+        if !full.starts_with(&root) {
+            return Err(-EACCES);
+        }
+        Ok(full)
+    }
+
+    fn open(&mut self, path: &str, mode: u32) -> i32 {
+        let Some(mode) = OpenMode::from_arg(mode) else {
+            return -EINVAL;
+        };
+        if self.files.len() >= MAX_OPEN_FILES {
+            return -EMFILE;
+        }
+        let full = match self.resolve(path) {
+            Ok(full) => full,
+            Err(e) => {
+                warn!("Semihosting: refused to open {:?}", path);
+                return e;
+            }
+        };
+        match mode.options().open(&full) {
+            Ok(file) => {
+                let fd = self.next_fd;
+                self.next_fd += 1;
+                self.files.insert(fd, file);
+                debug!("Semihosting: opened {:?} as {}", full, fd);
+                fd
+            }
+            Err(e) => errno(&e),
+        }
+    }
+
+    fn close(&mut self, fd: i32) -> i32 {
+        match self.files.remove(&fd) {
+            Some(_) => 0,
+            None => -EBADF,
+        }
+    }
+
+    fn read(&mut self, fd: i32, len: u32) -> Result<Vec<u8>, i32> {
+        let file = self.files.get_mut(&fd).ok_or(-EBADF)?;
+        let mut data = vec![0; len.min(MAX_TRANSFER) as usize];
+        let read = file.read(&mut data).map_err(|e| errno(&e))?;
+        data.truncate(read);
+        Ok(data)
+    }
+
+    fn write(&mut self, fd: i32, data: &[u8]) -> i32 {
+        let Some(file) = self.files.get_mut(&fd) else {
+            return -EBADF;
+        };
+        match file.write(data) {
+            Ok(written) => written as i32,
+            Err(e) => errno(&e),
+        }
+    }
+
+    fn lseek(&mut self, fd: i32, offset: i32, whence: u32) -> i32 {
+        let Some(file) = self.files.get_mut(&fd) else {
+            return -EBADF;
+        };
+        let position = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset as i64),
+            2 => SeekFrom::End(offset as i64),
+            _ => return -EINVAL,
+        };
+        match file.seek(position) {
+            Ok(position) => i32::try_from(position).unwrap_or(-EINVAL),
+            Err(e) => errno(&e),
+        }
+    }
+
+    /// Adds a character to the core's console line, returns the line once complete
+    fn putchar(&mut self, cpu_id: CpuId, c: u8) -> Option<String> {
+        let line = self.lines.entry(cpu_id).or_default();
+        if c == b'\n' {
+            return Some(std::mem::take(line));
+        }
+        line.push(char::from(c));
+        None
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Target memory of the core's address space, local addresses included
+    fn semihost_read(&mut self, cpu_id: CpuId, addr: u32, len: u32) -> Result<Vec<u8>, i32> {
+        let tid = cpuid_to_tid(cpu_id);
+        let addr = self
+            .translate_local(tid, addr, len as usize)
+            .map_err(|_| -EFAULT)?;
+        let mut data = vec![0; len as usize];
+        self.read_memory(tid, addr, &mut data)
+            .map_err(|_| -EFAULT)?;
+        Ok(data)
+    }
+
+    fn semihost_write(&mut self, cpu_id: CpuId, addr: u32, data: &[u8]) -> Result<(), i32> {
+        let tid = cpuid_to_tid(cpu_id);
+        let addr = self
+            .translate_local(tid, addr, data.len())
+            .map_err(|_| -EFAULT)?;
+        self.write_memory(tid, addr, data).map_err(|_| -EFAULT)
+    }
+
+    /// Performs the call described by the argument block, returns the value for D2
+    fn semihost_call(&mut self, cpu_id: CpuId, op: u32, args: [u32; 3]) -> i32 {
+        let [arg0, arg1, arg2] = args;
+        match op {
+            1 => {
+                if arg1 > MAX_PATH {
+                    return -ENAMETOOLONG;
+                }
+                let path = match self.semihost_read(cpu_id, arg0, arg1) {
+                    Ok(path) => path,
+                    Err(e) => return e,
+                };
+                match String::from_utf8(path) {
+                    Ok(path) => self.semihost.open(&path, arg2),
+                    Err(_) => -EINVAL,
+                }
+            }
+            2 => self.semihost.close(arg0 as i32),
+            3 => match self.semihost.read(arg0 as i32, arg2) {
+                Ok(data) => match self.semihost_write(cpu_id, arg1, &data) {
+                    Ok(()) => data.len() as i32,
+                    Err(e) => e,
+                },
+                Err(e) => e,
+            },
+            4 => match self.semihost_read(cpu_id, arg1, arg2.min(MAX_TRANSFER)) {
+                Ok(data) => self.semihost.write(arg0 as i32, &data),
+                Err(e) => e,
+            },
+            5 => self.semihost.lseek(arg0 as i32, arg1 as i32, arg2),
+            6 => {
+                if let Some(line) = self.semihost.putchar(cpu_id, arg0 as u8) {
+                    self.console(Some(cpu_id), format_args!("{}", line));
+                }
+                0
+            }
+            _ => -ENOSYS,
+        }
+    }
+
+    /// Serves a core stopped at [SEMIHOST_SYMBOL] and resumes it. Returns false for any
+    /// other stop, and for a request which cannot be served, which GDB then sees.
+    pub(crate) fn serve_semihost(&mut self, cpu_id: CpuId) -> bool {
+        let Some(call_addr) = self
+            .elfs
+            .for_core(cpu_id)
+            .and_then(|image| image.semihost_call)
+        else {
+            return false;
+        };
+        let index = usize::from(cpu_id);
+        let Some(core) = self.core(index) else {
+            return false;
+        };
+        let (Some(pc), Some(block)) = (read_register(core, "PC"), read_register(core, "A4")) else {
+            return false;
+        };
+        if pc != call_addr {
+            return false;
+        }
+
+        let words = match self.semihost_read(cpu_id, block, 16) {
+            Ok(words) => words,
+            Err(_) => {
+                warn!(
+                    "Semihosting: cannot read the argument block at {:#010x}",
+                    block
+                );
+                return false;
+            }
+        };
+        let word = |index: usize| u32::from_le_bytes(words[index * 4..][..4].try_into().unwrap());
+        let result = self.semihost_call(cpu_id, word(0), [word(1), word(2), word(3)]);
+        debug!("Semihosting: op {} returned {}", word(0), result);
+
+        let Ok(debug) = self.semihost_read(cpu_id, pc, 1) else {
+            return false;
+        };
+        self.invalidate_registers(index);
+        let Some(core) = self.core(index) else {
+            return false;
+        };
+        let resumed = write_register(core, "D2", result as u32)
+            .and_then(|()| write_register(core, "PC", pc + decode::instruction_len(debug[0])));
+        if resumed.is_none() {
+            warn!("Semihosting: cannot return the result to core {:?}", cpu_id);
+            return false;
+        }
+        // A core stepped onto the call is stepped on, not run
+        self.restart_core(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Fresh root below the temporary directory, with a sibling directory outside of it
+    fn sandbox(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("semihost-{}-{}", name, std::process::id()));
+        _ = fs::remove_dir_all(&base);
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        (root, outside)
+    }
+
+    #[test]
+    fn resolves_plain_paths_below_the_root() {
+        let (root, _) = sandbox("plain");
+        let semihost = Semihost::new(Some(root.clone()));
+        let root = root.canonicalize().unwrap();
+        assert_eq!(semihost.resolve("out.txt"), Ok(root.join("out.txt")));
+        assert_eq!(
+            semihost.resolve("./logs/run.txt"),
+            Ok(root.join("logs/run.txt"))
+        );
+    }
+
+    #[test]
+    fn refuses_paths_leaving_the_root() {
+        let (root, _) = sandbox("escape");
+        let semihost = Semihost::new(Some(root));
+        assert_eq!(semihost.resolve("../outside/file"), Err(-EACCES));
+        assert_eq!(semihost.resolve("/etc/passwd"), Err(-EACCES));
+        assert_eq!(semihost.resolve("logs/.."), Err(-EACCES));
+        assert_eq!(Semihost::new(None).resolve("out.txt"), Err(-EACCES));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_leaving_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let (root, outside) = sandbox("symlink");
+        fs::write(outside.join("secret"), b"secret").unwrap();
+        symlink(outside.join("secret"), root.join("file")).unwrap();
+        symlink(outside.join("missing"), root.join("dangling")).unwrap();
+        symlink(&outside, root.join("dir")).unwrap();
+        fs::write(root.join("logs/run.txt"), b"run").unwrap();
+        symlink(root.join("logs/run.txt"), root.join("inside")).unwrap();
+
+        let semihost = Semihost::new(Some(root.clone()));
+        assert_eq!(semihost.resolve("file"), Err(-EACCES));
+        assert_eq!(semihost.resolve("dangling"), Err(-EACCES));
+        assert_eq!(semihost.resolve("dir/secret"), Err(-EACCES));
+        assert_eq!(
+            semihost.resolve("inside"),
+            Ok(root.canonicalize().unwrap().join("logs/run.txt"))
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! `--session-log`: monitor output and target events appended to a file on the host, so
+//! they outlive the GDB console and are kept while no client is connected.
+//!
+//! Each line carries a Unix timestamp with milliseconds and the core it concerns. Once the
+//! file would grow beyond its size limit it is renamed to `<path>.old`, replacing an
+//! earlier one, and a new file is started. Every file opened starts with a header line
+//! naming the session ID of the stub process.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use super::CpuId;
+
+pub(crate) struct SessionLog {
+    path: PathBuf,
+    file: File,
+    /// Bytes in the current file
+    size: u64,
+    max_size: u64,
+    /// Written whenever a file is opened
+    header: String,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl SessionLog {
+    pub fn open(path: &Path, max_size: u64, session_id: Option<&str>) -> io::Result<Self> {
+        let file = open_append(path)?;
+        let mut log = SessionLog {
+            path: path.to_owned(),
+            size: file.metadata()?.len(),
+            file,
+            max_size,
+            header: format!(
+                "tricore-gdb-das {} session {}",
+                env!("CARGO_PKG_VERSION"),
+                session_id.unwrap_or("unknown")
+            ),
+        };
+        log.write_header();
+        Ok(log)
+    }
+
+    fn write_header(&mut self) {
+        let header = self.header.clone();
+        self.line(None, format_args!("{}", header));
+    }
+
+    /// Appends a line, a failing write is logged and the line lost
+    pub fn line(&mut self, core: Option<CpuId>, msg: fmt::Arguments) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let core = core.map_or("-".to_owned(), |cpu| format!("{:?}", cpu));
+        let line = format!(
+            "{}.{:03} [{}] {}\n",
+            now.as_secs(),
+            now.subsec_millis(),
+            core,
+            msg
+        );
+        if let Err(e) = self.append(line.as_bytes()) {
+            warn!("Cannot write session log {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn append(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut old: OsString = self.path.as_os_str().to_owned();
+        old.push(".old");
+        std::fs::rename(&self.path, &old)?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.write_header();
+        Ok(())
+    }
+}
@@ -0,0 +1,574 @@
+//! Session settings, adjustable from the command line and via `monitor set`.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::boot_args::{BootArgsBlock, BootArgsFormat};
+use super::{parse_u32, CpuId};
+
+/// What a GDB `kill` does to the device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KillAction {
+    /// Leave the device untouched
+    #[default]
+    None,
+    /// System reset of the device
+    Reset,
+    /// Switch the target power off and on via the access hardware
+    PowerCycle,
+}
+
+impl KillAction {
+    pub fn parse(value: &str) -> Result<KillAction, String> {
+        match value {
+            "none" => Ok(KillAction::None),
+            "reset" => Ok(KillAction::Reset),
+            "power-cycle" => Ok(KillAction::PowerCycle),
+            _ => Err(format!(
+                "'{}' is none of 'none', 'reset' and 'power-cycle'",
+                value
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            KillAction::None => "none",
+            KillAction::Reset => "reset",
+            KillAction::PowerCycle => "power-cycle",
+        }
+    }
+}
+
+/// What is done to the device when GDB disconnects or its connection is lost
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisconnectAction {
+    /// Remove every trigger and let all cores run
+    Run,
+    /// Halt all cores, their state is kept for the next session
+    Halt,
+    /// Undo what the session set up: triggers and interrupt masks are removed, the cores
+    /// halted on connecting resume, the others stay as GDB left them
+    #[default]
+    Detach,
+}
+
+impl DisconnectAction {
+    pub fn parse(value: &str) -> Result<DisconnectAction, String> {
+        match value {
+            "run" => Ok(DisconnectAction::Run),
+            "halt" => Ok(DisconnectAction::Halt),
+            "detach" => Ok(DisconnectAction::Detach),
+            _ => Err(format!("'{}' is none of 'run', 'halt' and 'detach'", value)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DisconnectAction::Run => "run",
+            DisconnectAction::Halt => "halt",
+            DisconnectAction::Detach => "detach",
+        }
+    }
+}
+
+/// How the stub takes control of the cores when it connects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectMode {
+    /// Reset every core, the target starts from the reset vector
+    #[default]
+    Reset,
+    /// Halt every core where it is, nothing is reset
+    Halt,
+}
+
+impl ConnectMode {
+    fn name(self) -> &'static str {
+        match self {
+            ConnectMode::Reset => "reset",
+            ConnectMode::Halt => "halt",
+        }
+    }
+}
+
+/// What `--single-core` does with the cores GDB doesn't see
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtherCores {
+    /// Keep them halted, at the reset vector once the device was reset
+    #[default]
+    Hold,
+    /// Let them run, the stub never halts them
+    Run,
+}
+
+impl OtherCores {
+    pub fn parse(value: &str) -> Result<OtherCores, String> {
+        match value {
+            "hold" => Ok(OtherCores::Hold),
+            "run" => Ok(OtherCores::Run),
+            _ => Err(format!("'{}' is none of 'hold' and 'run'", value)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OtherCores::Hold => "hold",
+            OtherCores::Run => "run",
+        }
+    }
+}
+
+/// Which core memory accesses are issued through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemRoute {
+    /// Scratchpads and CSFRs through their own core, system memories and peripherals
+    /// through the designated core
+    #[default]
+    Auto,
+    /// Through the core of the thread GDB asks for
+    Tid,
+    /// Everything through the designated core
+    Core0,
+}
+
+impl MemRoute {
+    pub fn parse(value: &str) -> Result<MemRoute, String> {
+        match value {
+            "auto" => Ok(MemRoute::Auto),
+            "tid" => Ok(MemRoute::Tid),
+            "core0" => Ok(MemRoute::Core0),
+            _ => Err(format!("'{}' is none of 'auto', 'tid' and 'core0'", value)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            MemRoute::Auto => "auto",
+            MemRoute::Tid => "tid",
+            MemRoute::Core0 => "core0",
+        }
+    }
+}
+
+/// Tunables of the debug session
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Idle time before TCP keepalive probes are sent on the GDB connection
+    pub keepalive: Duration,
+    /// Idle time of the GDB connection after which the socket is checked for errors
+    pub idle_probe: Duration,
+    /// Interval of the liveness check of the halted device, zero disables it
+    pub heartbeat: Duration,
+    /// Largest memory read accepted from GDB in a single request
+    pub max_read: usize,
+    /// Mask interrupts while single stepping so steps don't end up in an ISR
+    pub step_mask_irq: bool,
+    /// Step over call instructions in the stub instead of into the callee
+    pub step_over_calls: bool,
+    /// Cores a vCont packet doesn't name keep their state instead of continuing, so a
+    /// single-thread `continue` resumes only that core
+    pub scheduler_lock: bool,
+    /// Step conditional jumps and loops by running to triggers on their possible next
+    /// instructions, for silicon whose hardware step skips instructions
+    pub soft_step: bool,
+    /// File whose creation or modification halts the target, fixed at startup
+    pub pause_file: Option<PathBuf>,
+    /// Pass GDB writes to flash addresses on to the probe instead of refusing them
+    pub allow_flash_writes: bool,
+    /// Serve repeated register reads of a halted core from a cache
+    pub reg_cache: bool,
+    /// Sector fill level in percent above which the flash usage report flags a sector
+    pub flash_fill_warn: u32,
+    /// Action taken on a GDB `kill`, fixed at startup
+    pub kill_action: KillAction,
+    /// Action taken once GDB disconnected or its connection was lost
+    pub on_disconnect: DisconnectAction,
+    /// Whether the cores are reset or only halted on connecting, fixed at startup
+    pub connect_mode: ConnectMode,
+    /// Only core exposed to GDB, as a single thread, fixed at startup
+    pub single_core: Option<CpuId>,
+    /// What is done with the other cores with `single_core`, fixed at startup
+    pub other_cores: OtherCores,
+    /// Which core memory accesses are issued through
+    pub mem_route: MemRoute,
+    /// Core the system memory accesses are issued through, see `mem_route`
+    pub mem_route_core: usize,
+    /// Apply breakpoint changes made while running right away, halting the running cores
+    /// briefly, instead of at the next stop
+    pub live_bp: bool,
+    /// Warn on stops of a core whose call depth counter is close to overflowing
+    pub cdc_watch: bool,
+    /// Calls left before the CDO trap below which `cdc_watch` warns
+    pub cdc_headroom: u32,
+    /// While a single core runs, the other cores are queried every this many polls
+    pub background_poll: u32,
+    /// Cores started first when several are resumed together, in this order. The cores
+    /// not listed follow in index order.
+    pub resume_order: Vec<usize>,
+    /// Pause between starting two cores of a resume
+    pub resume_stagger: Duration,
+    /// Halt cores whose PC stays within the trap vectors, see `trap_loop_window`
+    pub trap_loop_detect: bool,
+    /// Address span in bytes the sampled PCs of a core caught in a trap loop stay within
+    pub trap_loop_window: u32,
+    /// Triggers of each core kept from user breakpoints and range watches for step overs,
+    /// soft steps and snippets
+    pub reserved_triggers: usize,
+    /// Bytes of program flash kept by the code cache, 0 disables it
+    pub code_cache_size: usize,
+    /// Merge small adjacent GDB writes into one transaction, see `write_coalesce`
+    pub write_coalesce: bool,
+    /// Accept `monitor exit`, once turned off it stays off for the session
+    pub allow_exit: bool,
+    /// STM frequency in Hz run times are converted with, measured after the first resume
+    /// when `None`
+    pub stm_freq: Option<u32>,
+    /// Read back and journal every memory write of GDB and the monitor write commands
+    pub verify_writes: bool,
+    /// Log the reset cause right after connecting, before the stub resets the device,
+    /// fixed at startup
+    pub log_reset_cause: bool,
+    /// Directory the target may access files in through semihosting, fixed at startup
+    pub semihost_root: Option<PathBuf>,
+    /// File monitor output and target events are appended to, fixed at startup
+    pub session_log: Option<PathBuf>,
+    /// Size in bytes at which the session log is rotated, fixed at startup
+    pub session_log_size: u64,
+    /// Arguments written to `boot_args_block` after every reset, changed by
+    /// `monitor bootargs set`
+    pub boot_args: Option<String>,
+    /// RAM block the firmware reads its boot arguments from, fixed at startup
+    pub boot_args_block: Option<BootArgsBlock>,
+    /// Layout of the boot arguments in their block, fixed at startup
+    pub boot_args_format: BootArgsFormat,
+    /// ID of the stub process, written at the top of every session log file, fixed at
+    /// startup
+    pub session_id: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            keepalive: Duration::from_secs(10),
+            idle_probe: Duration::from_secs(30),
+            heartbeat: Duration::from_secs(5),
+            max_read: 1024 * 1024,
+            step_mask_irq: false,
+            step_over_calls: false,
+            scheduler_lock: false,
+            soft_step: false,
+            pause_file: None,
+            allow_flash_writes: false,
+            reg_cache: true,
+            flash_fill_warn: 90,
+            kill_action: KillAction::None,
+            on_disconnect: DisconnectAction::Detach,
+            connect_mode: ConnectMode::Reset,
+            single_core: None,
+            other_cores: OtherCores::Hold,
+            mem_route: MemRoute::Auto,
+            mem_route_core: 0,
+            live_bp: false,
+            cdc_watch: false,
+            cdc_headroom: 4,
+            background_poll: 4,
+            resume_order: Vec::new(),
+            resume_stagger: Duration::ZERO,
+            trap_loop_detect: true,
+            trap_loop_window: 64,
+            reserved_triggers: 0,
+            code_cache_size: 1024 * 1024,
+            write_coalesce: false,
+            allow_exit: true,
+            stm_freq: None,
+            verify_writes: false,
+            log_reset_cause: false,
+            semihost_root: None,
+            session_log: None,
+            session_log_size: 10 * 1024 * 1024,
+            boot_args: None,
+            boot_args_block: None,
+            boot_args_format: BootArgsFormat::Blob,
+            session_id: None,
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("'{}' is neither 'on' nor 'off'", value)),
+    }
+}
+
+fn on_off(value: bool) -> String {
+    if value { "on" } else { "off" }.to_owned()
+}
+
+fn parse_secs(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| format!("'{}' is not a number of seconds", value))
+}
+
+/// `2,1,0`, or `index` for the index order
+fn parse_resume_order(value: &str) -> Result<Vec<usize>, String> {
+    if value == "index" {
+        return Ok(Vec::new());
+    }
+    let mut order = Vec::new();
+    for core in value.split(',') {
+        let index = core
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|&index| CpuId::try_from(index).is_ok())
+            .ok_or_else(|| format!("'{}' is not a core number", core))?;
+        if order.contains(&index) {
+            return Err(format!("Core {} is listed twice", index));
+        }
+        order.push(index);
+    }
+    Ok(order)
+}
+
+/// The cores as started, e.g. `2,1,0`
+pub(crate) fn format_core_list(cores: &[usize]) -> String {
+    cores
+        .iter()
+        .map(|index| index.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Settings {
+    /// Updates the setting named `key` from its textual representation
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "keepalive" => self.keepalive = parse_secs(value)?,
+            "idle-probe" => self.idle_probe = parse_secs(value)?,
+            "heartbeat" => self.heartbeat = parse_secs(value)?,
+            "max-read" => {
+                self.max_read = parse_u32(value)
+                    .ok_or_else(|| format!("'{}' is not a number of bytes", value))?
+                    as usize
+            }
+            "reserved-triggers" => {
+                self.reserved_triggers = value
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a number of triggers", value))?
+            }
+            "code-cache-size" => {
+                self.code_cache_size = parse_u32(value)
+                    .ok_or_else(|| format!("'{}' is not a number of bytes", value))?
+                    as usize
+            }
+            "step-mask-irq" => self.step_mask_irq = parse_bool(value)?,
+            "step-over-calls" => self.step_over_calls = parse_bool(value)?,
+            "scheduler-lock" => self.scheduler_lock = parse_bool(value)?,
+            "soft-step" => self.soft_step = parse_bool(value)?,
+            "allow-flash-writes" => self.allow_flash_writes = parse_bool(value)?,
+            "reg-cache" => self.reg_cache = parse_bool(value)?,
+            "live-bp" => self.live_bp = parse_bool(value)?,
+            "cdc-watch" => self.cdc_watch = parse_bool(value)?,
+            "verify-writes" => self.verify_writes = parse_bool(value)?,
+            "write-coalesce" => self.write_coalesce = parse_bool(value)?,
+            "allow-exit" => match parse_bool(value)? {
+                true if !self.allow_exit => {
+                    return Err("allow-exit stays off for the session".to_owned())
+                }
+                allow => self.allow_exit = allow,
+            },
+            "stm-freq" => {
+                self.stm_freq = match value {
+                    "auto" => None,
+                    _ => Some(parse_u32(value).filter(|hz| *hz > 0).ok_or_else(|| {
+                        format!("'{}' is neither 'auto' nor a frequency in Hz", value)
+                    })?),
+                }
+            }
+            "trap-loop-detect" => self.trap_loop_detect = parse_bool(value)?,
+            "on-disconnect" => self.on_disconnect = DisconnectAction::parse(value)?,
+            "mem-route" => self.mem_route = MemRoute::parse(value)?,
+            "mem-route-core" => {
+                self.mem_route_core = value
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a core index", value))?
+            }
+            "resume-order" => self.resume_order = parse_resume_order(value)?,
+            "resume-stagger-ms" => {
+                self.resume_stagger = value
+                    .parse()
+                    .map(Duration::from_millis)
+                    .map_err(|_| format!("'{}' is not a number of milliseconds", value))?
+            }
+            "trap-loop-window" => {
+                self.trap_loop_window = parse_u32(value)
+                    .filter(|bytes| *bytes > 0)
+                    .ok_or_else(|| format!("'{}' is not a positive number of bytes", value))?
+            }
+            "cdc-headroom" => {
+                self.cdc_headroom = value
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a number of calls", value))?
+            }
+            "flash-fill-warn" => {
+                self.flash_fill_warn = value
+                    .parse()
+                    .ok()
+                    .filter(|percent| *percent <= 100)
+                    .ok_or_else(|| format!("'{}' is not a percentage", value))?
+            }
+            "background-poll" => {
+                self.background_poll = value
+                    .parse()
+                    .ok()
+                    .filter(|polls| *polls > 0)
+                    .ok_or_else(|| format!("'{}' is not a positive number of polls", value))?
+            }
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// All settings with their current value, in display order
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("keepalive", format!("{}s", self.keepalive.as_secs())),
+            ("idle-probe", format!("{}s", self.idle_probe.as_secs())),
+            ("heartbeat", format!("{}s", self.heartbeat.as_secs())),
+            ("max-read", format!("{} bytes", self.max_read)),
+            ("reserved-triggers", self.reserved_triggers.to_string()),
+            ("code-cache-size", format!("{} bytes", self.code_cache_size)),
+            ("step-mask-irq", on_off(self.step_mask_irq)),
+            ("step-over-calls", on_off(self.step_over_calls)),
+            ("scheduler-lock", on_off(self.scheduler_lock)),
+            ("soft-step", on_off(self.soft_step)),
+            ("allow-flash-writes", on_off(self.allow_flash_writes)),
+            ("reg-cache", on_off(self.reg_cache)),
+            ("live-bp", on_off(self.live_bp)),
+            ("cdc-watch", on_off(self.cdc_watch)),
+            ("cdc-headroom", format!("{} calls", self.cdc_headroom)),
+            ("background-poll", format!("{} polls", self.background_poll)),
+            (
+                "resume-order",
+                if self.resume_order.is_empty() {
+                    "index".to_owned()
+                } else {
+                    format_core_list(&self.resume_order)
+                },
+            ),
+            (
+                "resume-stagger-ms",
+                format!("{} ms", self.resume_stagger.as_millis()),
+            ),
+            ("verify-writes", on_off(self.verify_writes)),
+            ("write-coalesce", on_off(self.write_coalesce)),
+            ("allow-exit", on_off(self.allow_exit)),
+            (
+                "stm-freq",
+                self.stm_freq
+                    .map_or("auto".to_owned(), |hz| format!("{} Hz", hz)),
+            ),
+            ("trap-loop-detect", on_off(self.trap_loop_detect)),
+            (
+                "trap-loop-window",
+                format!("{} bytes", self.trap_loop_window),
+            ),
+            ("flash-fill-warn", format!("{}%", self.flash_fill_warn)),
+            ("kill-action", self.kill_action.name().to_owned()),
+            ("on-disconnect", self.on_disconnect.name().to_owned()),
+            ("connect-mode", self.connect_mode.name().to_owned()),
+            (
+                "single-core",
+                self.single_core.map_or("off".to_owned(), |cpu_id| {
+                    format!("CPU{}", usize::from(cpu_id))
+                }),
+            ),
+            ("other-cores", self.other_cores.name().to_owned()),
+            ("mem-route", self.mem_route.name().to_owned()),
+            ("mem-route-core", self.mem_route_core.to_string()),
+            ("log-reset-cause", on_off(self.log_reset_cause)),
+            (
+                "pause-file",
+                self.pause_file
+                    .as_ref()
+                    .map_or("none".to_owned(), |path| path.display().to_string()),
+            ),
+            (
+                "semihost-root",
+                self.semihost_root
+                    .as_ref()
+                    .map_or("none".to_owned(), |path| path.display().to_string()),
+            ),
+            (
+                "session-log",
+                self.session_log
+                    .as_ref()
+                    .map_or("none".to_owned(), |path| path.display().to_string()),
+            ),
+            (
+                "boot-args",
+                self.boot_args.clone().unwrap_or("none".to_owned()),
+            ),
+            (
+                "boot-args-addr",
+                self.boot_args_block.map_or("none".to_owned(), |block| {
+                    format!("{:#010x}:{}", block.addr, block.len)
+                }),
+            ),
+            ("boot-args-format", self.boot_args_format.name().to_owned()),
+            (
+                "session-id",
+                self.session_id.clone().unwrap_or("none".to_owned()),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_order_is_parsed() {
+        assert_eq!(parse_resume_order("2,1,0"), Ok(vec![2, 1, 0]));
+        assert_eq!(parse_resume_order(" 2, 0 "), Ok(vec![2, 0]));
+        assert_eq!(parse_resume_order("index"), Ok(Vec::new()));
+        assert!(parse_resume_order("2,6").is_err());
+        assert!(parse_resume_order("2,x").is_err());
+        assert!(parse_resume_order("1,1").is_err());
+        assert!(parse_resume_order("").is_err());
+    }
+
+    #[test]
+    fn resume_settings_are_set() {
+        let mut settings = Settings::default();
+        assert!(settings.set("resume-order", "1,0").is_ok());
+        assert!(settings.set("resume-stagger-ms", "5").is_ok());
+        assert_eq!(settings.resume_order, vec![1, 0]);
+        assert_eq!(settings.resume_stagger, Duration::from_millis(5));
+        assert_eq!(format_core_list(&settings.resume_order), "1,0");
+
+        assert!(settings.set("resume-stagger-ms", "soon").is_err());
+        assert_eq!(settings.resume_stagger, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn allow_exit_stays_off_once_turned_off() {
+        let mut settings = Settings::default();
+        assert!(settings.allow_exit);
+        assert!(settings.set("allow-exit", "on").is_ok());
+        assert!(settings.set("allow-exit", "off").is_ok());
+        assert!(!settings.allow_exit);
+
+        assert_eq!(
+            settings.set("allow-exit", "on"),
+            Err("allow-exit stays off for the session".to_owned())
+        );
+        assert!(!settings.allow_exit);
+        assert!(settings.set("allow-exit", "off").is_ok());
+    }
+}
@@ -0,0 +1,428 @@
+//! Peripheral registers by name, described by a CMSIS-SVD file given with `--svd`.
+//!
+//! The SVD is parsed once at startup into an index of upper-cased `PERIPH.REG` names.
+//! `monitor sfr read|write|decode` resolve names through it and access the register with
+//! its own width through the regular memory path.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use gdbstub::outputln;
+
+use super::{edit_distance, parse_u32, MonitorOutput, TricoreTarget};
+
+/// Register width used when neither the register nor its peripheral or device specify one
+const DEFAULT_SIZE: u32 = 32;
+
+/// Number of close matches suggested for an unknown name
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A bit-field of a peripheral register
+#[derive(Debug, Clone)]
+pub struct SfrField {
+    pub name: String,
+    pub lsb: u32,
+    pub width: u32,
+}
+
+impl SfrField {
+    fn mask(&self) -> u32 {
+        let bits = if self.width >= 32 {
+            u32::MAX
+        } else {
+            (1 << self.width) - 1
+        };
+        bits << self.lsb
+    }
+
+    fn extract(&self, value: u32) -> u32 {
+        (value & self.mask()) >> self.lsb
+    }
+}
+
+/// A peripheral register at its absolute address
+#[derive(Debug, Clone)]
+pub struct SfrRegister {
+    /// `PERIPH.REG` as written in the SVD
+    pub name: String,
+    pub address: u32,
+    /// Width of the register in bits, 8, 16 or 32
+    pub size: u32,
+    pub fields: Vec<SfrField>,
+}
+
+impl SfrRegister {
+    fn field(&self, name: &str) -> Option<&SfrField> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Peripheral registers of the device, indexed by upper-cased `PERIPH.REG`
+#[derive(Debug, Default)]
+pub struct PeripheralMap {
+    registers: HashMap<String, SfrRegister>,
+}
+
+/// Register as found in a peripheral, relative to its base address
+struct RegisterDecl {
+    name: String,
+    offset: u32,
+    size: u32,
+    fields: Vec<SfrField>,
+}
+
+struct PeripheralDecl {
+    name: String,
+    base: u32,
+    derived_from: Option<String>,
+    registers: Vec<RegisterDecl>,
+}
+
+/// Parses an SVD scaled integer: decimal, `0x` hexadecimal or `#` binary
+fn svd_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix('#') {
+        Some(binary) => u32::from_str_radix(binary, 2).ok(),
+        None => parse_u32(text),
+    }
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+fn child_number(node: roxmltree::Node<'_, '_>, name: &str) -> Option<u32> {
+    child_text(node, name).and_then(svd_number)
+}
+
+/// Bit position of a field, given as offset and width, as lsb and msb or as bit range
+fn parse_field(node: roxmltree::Node<'_, '_>) -> Option<SfrField> {
+    let name = child_text(node, "name")?.to_string();
+    let (lsb, width) = if let Some(lsb) = child_number(node, "bitOffset") {
+        (lsb, child_number(node, "bitWidth").unwrap_or(1))
+    } else if let (Some(lsb), Some(msb)) = (child_number(node, "lsb"), child_number(node, "msb")) {
+        (lsb, msb.checked_sub(lsb)? + 1)
+    } else {
+        let range = child_text(node, "bitRange")?;
+        let (msb, lsb) = range
+            .strip_prefix('[')?
+            .strip_suffix(']')?
+            .split_once(':')?;
+        let (msb, lsb) = (svd_number(msb)?, svd_number(lsb)?);
+        (lsb, msb.checked_sub(lsb)? + 1)
+    };
+    Some(SfrField { name, lsb, width })
+}
+
+/// Expands a register declaration, which may be an array of `dim` registers
+fn parse_register(node: roxmltree::Node<'_, '_>, default_size: u32) -> Vec<RegisterDecl> {
+    let (Some(name), Some(offset)) = (
+        child_text(node, "name"),
+        child_number(node, "addressOffset"),
+    ) else {
+        return Vec::new();
+    };
+    let size = child_number(node, "size").unwrap_or(default_size);
+    let fields: Vec<SfrField> = node
+        .children()
+        .find(|child| child.has_tag_name("fields"))
+        .map(|fields| {
+            fields
+                .children()
+                .filter(|child| child.has_tag_name("field"))
+                .filter_map(parse_field)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    match child_number(node, "dim") {
+        Some(dim) if name.contains("%s") => {
+            let increment = child_number(node, "dimIncrement").unwrap_or(size / 8);
+            (0..dim)
+                .map(|index| RegisterDecl {
+                    name: name
+                        .replace("[%s]", &index.to_string())
+                        .replace("%s", &index.to_string()),
+                    offset: offset.wrapping_add(index * increment),
+                    size,
+                    fields: fields.clone(),
+                })
+                .collect()
+        }
+        _ => vec![RegisterDecl {
+            name: name.to_string(),
+            offset,
+            size,
+            fields,
+        }],
+    }
+}
+
+fn parse_peripheral(node: roxmltree::Node<'_, '_>, default_size: u32) -> Option<PeripheralDecl> {
+    let name = child_text(node, "name")?.to_string();
+    let base = child_number(node, "baseAddress")?;
+    let default_size = child_number(node, "size").unwrap_or(default_size);
+    let registers = node
+        .children()
+        .find(|child| child.has_tag_name("registers"))
+        .map(|registers| {
+            registers
+                .children()
+                .filter(|child| child.has_tag_name("register"))
+                .flat_map(|register| parse_register(register, default_size))
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(PeripheralDecl {
+        name,
+        base,
+        derived_from: node.attribute("derivedFrom").map(str::to_string),
+        registers,
+    })
+}
+
+impl PeripheralMap {
+    /// Parses an SVD file. Register clusters are not supported and skipped.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Cannot parse {}", path.display()))
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let document = roxmltree::Document::parse(text)?;
+        let device = document.root_element();
+        if !device.has_tag_name("device") {
+            bail!(
+                "not an SVD file, root element is <{}>",
+                device.tag_name().name()
+            );
+        }
+        let default_size = child_number(device, "size").unwrap_or(DEFAULT_SIZE);
+
+        let peripherals: Vec<PeripheralDecl> = device
+            .children()
+            .find(|child| child.has_tag_name("peripherals"))
+            .ok_or_else(|| anyhow!("no peripherals"))?
+            .children()
+            .filter(|child| child.has_tag_name("peripheral"))
+            .filter_map(|peripheral| parse_peripheral(peripheral, default_size))
+            .collect();
+
+        let mut registers = HashMap::new();
+        for peripheral in &peripherals {
+            // A derived peripheral reuses the registers of another one at its own base
+            let declared = match &peripheral.derived_from {
+                Some(parent) if peripheral.registers.is_empty() => peripherals
+                    .iter()
+                    .find(|p| &p.name == parent)
+                    .map(|p| &p.registers)
+                    .unwrap_or(&peripheral.registers),
+                _ => &peripheral.registers,
+            };
+            for register in declared {
+                let name = format!("{}.{}", peripheral.name, register.name);
+                registers.insert(
+                    name.to_ascii_uppercase(),
+                    SfrRegister {
+                        name,
+                        address: peripheral.base.wrapping_add(register.offset),
+                        size: register.size,
+                        fields: register.fields.clone(),
+                    },
+                );
+            }
+        }
+
+        Ok(PeripheralMap { registers })
+    }
+
+    pub fn len(&self) -> usize {
+        self.registers.len()
+    }
+
+    /// Resolves `PERIPH.REG` or `PERIPH.REG.FIELD`. Unknown names return close matches.
+    fn lookup(&self, path: &str) -> Result<(&SfrRegister, Option<&SfrField>), Vec<&str>> {
+        let upper = path.to_ascii_uppercase();
+        if let Some(register) = self.registers.get(&upper) {
+            return Ok((register, None));
+        }
+        if let Some((register, field)) = upper.rsplit_once('.') {
+            if let Some(register) = self.registers.get(register) {
+                return match register.field(field) {
+                    Some(field) => Ok((register, Some(field))),
+                    None => Err(register.fields.iter().map(|f| f.name.as_str()).collect()),
+                };
+            }
+        }
+
+        let mut candidates: Vec<(usize, &str)> = self
+            .registers
+            .iter()
+            .map(|(key, register)| (edit_distance(&upper, key), register.name.as_str()))
+            .filter(|&(distance, _)| distance <= upper.len() / 2)
+            .collect();
+        candidates.sort();
+        Err(candidates
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, name)| name)
+            .collect())
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    pub fn set_peripherals(&mut self, peripherals: PeripheralMap) {
+        self.peripherals = Some(peripherals);
+    }
+
+    /// Resolves a name for a monitor command, printing why it cannot be resolved
+    fn resolve_sfr(
+        &self,
+        path: &str,
+        out: &mut MonitorOutput<'_>,
+    ) -> Option<(SfrRegister, Option<SfrField>)> {
+        let Some(peripherals) = &self.peripherals else {
+            outputln!(out, "No peripheral description, start with --svd <file>");
+            return None;
+        };
+        match peripherals.lookup(path) {
+            Ok((register, field)) => Some((register.clone(), field.cloned())),
+            Err(suggestions) if suggestions.is_empty() => {
+                outputln!(out, "Unknown register {}", path);
+                None
+            }
+            Err(suggestions) => {
+                outputln!(
+                    out,
+                    "Unknown register {}, did you mean {}?",
+                    path,
+                    suggestions.join(", ")
+                );
+                None
+            }
+        }
+    }
+
+    fn read_sfr(&mut self, register: &SfrRegister) -> anyhow::Result<u32> {
+        let width = sfr_bytes(register)?;
+        Ok(self.read_sized(register.address, width)?)
+    }
+
+    fn write_sfr(&mut self, register: &SfrRegister, value: u32) -> anyhow::Result<()> {
+        let width = sfr_bytes(register)?;
+        Ok(self.write_sized(register.address, width, value)?)
+    }
+
+    pub(crate) fn sfr_read(&mut self, path: &str, out: &mut MonitorOutput<'_>) {
+        let Some((register, field)) = self.resolve_sfr(path, out) else {
+            return;
+        };
+        match self.read_sfr(&register) {
+            Ok(value) => match field {
+                Some(field) => outputln!(
+                    out,
+                    "{}.{} = {:#x}",
+                    register.name,
+                    field.name,
+                    field.extract(value)
+                ),
+                None => outputln!(
+                    out,
+                    "{} @ {:#010x} = {:#x}",
+                    register.name,
+                    register.address,
+                    value
+                ),
+            },
+            Err(e) => outputln!(out, "Cannot read {}: {}", register.name, e),
+        }
+    }
+
+    pub(crate) fn sfr_decode(&mut self, path: &str, out: &mut MonitorOutput<'_>) {
+        let Some((register, _)) = self.resolve_sfr(path, out) else {
+            return;
+        };
+        let value = match self.read_sfr(&register) {
+            Ok(value) => value,
+            Err(e) => {
+                outputln!(out, "Cannot read {}: {}", register.name, e);
+                return;
+            }
+        };
+        outputln!(
+            out,
+            "{} @ {:#010x} = {:#x}",
+            register.name,
+            register.address,
+            value
+        );
+        let mut fields: Vec<&SfrField> = register.fields.iter().collect();
+        fields.sort_by_key(|field| std::cmp::Reverse(field.lsb));
+        for field in fields {
+            let bits = if field.width == 1 {
+                format!("[{}]", field.lsb)
+            } else {
+                format!("[{}:{}]", field.lsb + field.width - 1, field.lsb)
+            };
+            outputln!(
+                out,
+                "  {:<16} {:<8} {:#x}",
+                field.name,
+                bits,
+                field.extract(value)
+            );
+        }
+    }
+
+    /// Writes a whole register, or a single field by read-modify-write
+    pub(crate) fn sfr_write(&mut self, path: &str, value: &str, out: &mut MonitorOutput<'_>) {
+        let Some(value) = parse_u32(value) else {
+            outputln!(out, "usage: sfr write <PERIPH.REG[.FIELD]> <value>");
+            return;
+        };
+        let Some((register, field)) = self.resolve_sfr(path, out) else {
+            return;
+        };
+
+        let written = match &field {
+            None => self.write_sfr(&register, value).map(|()| value),
+            Some(field) => {
+                if value > field.mask() >> field.lsb {
+                    outputln!(
+                        out,
+                        "{:#x} does not fit the {} bit field {}",
+                        value,
+                        field.width,
+                        field.name
+                    );
+                    return;
+                }
+                self.read_sfr(&register).and_then(|current| {
+                    let updated = (current & !field.mask()) | (value << field.lsb);
+                    self.write_sfr(&register, updated).map(|()| updated)
+                })
+            }
+        };
+        match written {
+            Ok(written) => outputln!(out, "{} = {:#x}", register.name, written),
+            Err(e) => outputln!(out, "Cannot write {}: {}", register.name, e),
+        }
+    }
+}
+
+/// Access width of a register in bytes
+fn sfr_bytes(register: &SfrRegister) -> anyhow::Result<usize> {
+    match register.size {
+        8 => Ok(1),
+        16 => Ok(2),
+        32 => Ok(4),
+        size => bail!("unsupported register size of {} bits", size),
+    }
+}
@@ -0,0 +1,148 @@
+//! `--single-core`, the target as a single thread for GDB builds which mishandle the
+//! multi-thread extensions, e.g. older vendor builds which never send `Hg` or `vCont`
+//! with a thread id and get confused by a thread list of several cores.
+//!
+//! GDB sees the selected core only. Its requests are served by the multi-thread
+//! implementation with the thread of that core, breakpoints are planted on it alone and
+//! only it is polled for stops. The other cores are held halted or left running per
+//! `--other-cores`, the stub doesn't touch them otherwise.
+
+use gdbstub::common::{Signal, Tid};
+use gdbstub::target::ext::base::multithread::{MultiThreadBase, MultiThreadResume};
+use gdbstub::target::ext::base::single_register_access::{
+    SingleRegisterAccess, SingleRegisterAccessOps,
+};
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep,
+    SingleThreadSingleStepOps,
+};
+use gdbstub::target::TargetResult;
+use log::warn;
+
+use super::registers::{CoreRegisters, RegisterId};
+use super::settings::OtherCores;
+use super::{ResumeAction, TricoreTarget};
+
+/// The thread GDB knows the selected core as
+fn single_thread() -> Tid {
+    Tid::new(1).unwrap()
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Whether the core at `index` is debugged, all are unless `--single-core` is given
+    pub(crate) fn in_session(&self, index: usize) -> bool {
+        self.settings
+            .single_core
+            .map_or(true, |cpu_id| usize::from(cpu_id) == index)
+    }
+
+    /// Holds or starts the cores outside the session, after connecting and after a reset
+    pub(crate) fn settle_other_cores(&mut self) {
+        if self.settings.single_core.is_none() {
+            return;
+        }
+        let others: Vec<usize> = self
+            .slots()
+            .filter(|(_, slot)| slot.core.is_some())
+            .map(|(index, _)| index)
+            .filter(|&index| !self.in_session(index))
+            .collect();
+        let action = self.settings.other_cores;
+        for index in others {
+            let Some(core) = self.core_mut(index) else {
+                continue;
+            };
+            let (done, verb) = match action {
+                OtherCores::Hold => (core.stop().is_ok(), "halt"),
+                OtherCores::Run => (core.run().is_ok(), "run"),
+            };
+            if !done {
+                warn!("Cannot {} core {} outside the session", verb, index);
+            }
+        }
+    }
+
+    /// Resumes the selected core alone with `action`
+    fn resume_single(&mut self, action: ResumeAction) -> Result<(), &'static str> {
+        let selected = self.settings.single_core.map_or(0, usize::from);
+        for (index, slot) in self.slots_mut() {
+            slot.resume_action = if index == selected {
+                action
+            } else {
+                ResumeAction::Unchanged
+            };
+        }
+        MultiThreadResume::resume(self)
+    }
+}
+
+impl<'a> SingleThreadBase for TricoreTarget<'a> {
+    fn read_registers(&mut self, regs: &mut CoreRegisters) -> TargetResult<(), Self> {
+        MultiThreadBase::read_registers(self, regs, single_thread())
+    }
+
+    fn write_registers(&mut self, regs: &CoreRegisters) -> TargetResult<(), Self> {
+        MultiThreadBase::write_registers(self, regs, single_thread())
+    }
+
+    fn read_addrs(&mut self, start_addr: u32, data: &mut [u8]) -> TargetResult<usize, Self> {
+        MultiThreadBase::read_addrs(self, start_addr, data, single_thread())
+    }
+
+    fn write_addrs(&mut self, start_addr: u32, data: &[u8]) -> TargetResult<(), Self> {
+        MultiThreadBase::write_addrs(self, start_addr, data, single_thread())
+    }
+
+    #[inline(always)]
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<'_, (), Self>> {
+        Some(self)
+    }
+}
+
+impl<'a> SingleRegisterAccess<()> for TricoreTarget<'a> {
+    fn read_register(
+        &mut self,
+        _tid: (),
+        reg_id: RegisterId,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        SingleRegisterAccess::<Tid>::read_register(self, single_thread(), reg_id, buf)
+    }
+
+    fn write_register(
+        &mut self,
+        _tid: (),
+        reg_id: RegisterId,
+        val: &[u8],
+    ) -> TargetResult<(), Self> {
+        SingleRegisterAccess::<Tid>::write_register(self, single_thread(), reg_id, val)
+    }
+}
+
+impl<'a> SingleThreadResume for TricoreTarget<'a> {
+    fn resume(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+        if signal.is_some() {
+            return Err("no support for continuing with signal");
+        }
+        self.resume_single(ResumeAction::Resume)
+    }
+
+    #[inline(always)]
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl<'a> SingleThreadSingleStep for TricoreTarget<'a> {
+    fn step(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+        if signal.is_some() {
+            return Err("no support for stepping with signal");
+        }
+        self.resume_single(ResumeAction::Step)
+    }
+}
@@ -0,0 +1,85 @@
+//! `soft-step`, stepping conditional jumps and loops without the hardware single step.
+//!
+//! On some silicon steps the hardware step skips an instruction when IP triggers are
+//! involved. With `soft-step` on, a core sitting on a conditional jump or loop instead
+//! runs to a one-shot trigger on each instruction it can continue at, the next one and
+//! the jump target. Reaching either completes the step. Every other instruction is still
+//! stepped by the hardware.
+
+use log::debug;
+
+use super::breakpoints::TriggerId;
+use super::decode::{conditional_jump_target, instruction_len};
+use super::trigger_budget::TriggerOwner;
+use super::{read_register, CpuId, TricoreTarget};
+
+impl<'a> TricoreTarget<'a> {
+    /// Arms a trigger on each possible next instruction of a core sitting on a
+    /// conditional jump or loop. Returns false if the core is to be stepped by the
+    /// hardware.
+    pub(crate) fn arm_soft_step(&mut self, index: usize) -> bool {
+        let Some(core) = self.core(index) else {
+            return false;
+        };
+        let Some(pc) = read_register(core, "PC") else {
+            return false;
+        };
+        let Ok(code) = core.read_bytes(pc as u64, 4) else {
+            return false;
+        };
+        let Some(target) = conditional_jump_target(pc, &code) else {
+            return false;
+        };
+        let next = pc.wrapping_add(instruction_len(code[0]));
+
+        let mut triggers: Vec<(u32, TriggerId)> = Vec::new();
+        for addr in [next, target] {
+            if triggers.iter().any(|&(planted, _)| planted == addr) {
+                continue;
+            }
+            match self.create_core_trigger(index, addr, TriggerOwner::SoftStep) {
+                Some(trigger) => triggers.push((addr, trigger)),
+                None => {
+                    for (_, trigger) in triggers {
+                        self.remove_core_trigger(index, trigger, TriggerOwner::SoftStep);
+                    }
+                    return false;
+                }
+            }
+        }
+
+        debug!(
+            "Core {} soft stepping {:#010x} to {:#010x} or {:#010x}",
+            index, pc, next, target
+        );
+        let cpu_id = CpuId::try_from(index).expect("Unexpected core index");
+        self.soft_steps.insert(cpu_id, triggers);
+        true
+    }
+
+    /// Removes the soft step triggers of a stopped core, returns whether the core stopped
+    /// on one of them, i.e. the step completed
+    pub(crate) fn finish_soft_step(&mut self, cpu_id: CpuId) -> bool {
+        let Some(triggers) = self.soft_steps.remove(&cpu_id) else {
+            return false;
+        };
+        let index = usize::from(cpu_id);
+        let pc = self.core(index).and_then(|core| read_register(core, "PC"));
+        let mut reached = false;
+        for (addr, trigger) in triggers {
+            reached |= pc == Some(addr);
+            self.remove_core_trigger(index, trigger, TriggerOwner::SoftStep);
+        }
+        reached
+    }
+
+    /// Drops the soft step triggers of cores halted before completing the step, see
+    /// [Self::clear_step_overs]
+    pub(crate) fn clear_soft_steps(&mut self) {
+        for (cpu_id, triggers) in std::mem::take(&mut self.soft_steps) {
+            for (_, trigger) in triggers {
+                self.remove_core_trigger(usize::from(cpu_id), trigger, TriggerOwner::SoftStep);
+            }
+        }
+    }
+}
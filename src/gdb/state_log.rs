@@ -0,0 +1,147 @@
+//! Coalesced logging of the core states polled by the run loop.
+//!
+//! The run loop queries every core thousands of times per second. At debug level only
+//! changes of the state are logged, together with a periodic summary of all cores. Trace
+//! level still logs every single poll.
+
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+
+/// Interval of the one line summary of all core states
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) struct StateLogTracker {
+    /// State last logged per core, `None` before the first poll
+    last: Vec<Option<&'static str>>,
+    /// Failed state queries per core since the last successful one
+    failures: Vec<u64>,
+    last_summary: Instant,
+}
+
+impl StateLogTracker {
+    pub fn new(core_count: usize) -> Self {
+        StateLogTracker {
+            last: vec![None; core_count],
+            failures: vec![0; core_count],
+            last_summary: Instant::now(),
+        }
+    }
+
+    /// Records the state of a core, logging it only when it differs from the last one
+    pub fn state(&mut self, index: usize, state: &'static str) {
+        trace!("Core {} {}", index, state);
+        self.ensure_core(index);
+        self.flush_failures(index, false);
+        if self.last[index] != Some(state) {
+            debug!("Core {} {}", index, state);
+            self.last[index] = Some(state);
+        }
+    }
+
+    /// Records a state the run loop cannot act on, warning once when a core enters it
+    pub fn unexpected_state(&mut self, index: usize, state: &'static str) {
+        trace!("Core {} {}", index, state);
+        self.ensure_core(index);
+        self.flush_failures(index, false);
+        if self.last[index] != Some(state) {
+            warn!("Core {} reports {}, it is treated as running", index, state);
+            self.last[index] = Some(state);
+        }
+    }
+
+    /// Records a failed state query. Only the first of a series is logged right away, the
+    /// rest is counted and reported once the query succeeds again or with the summary.
+    pub fn query_failed(&mut self, index: usize) {
+        trace!("Cannot query the state of core {}", index);
+        self.ensure_core(index);
+        if self.failures[index] == 0 {
+            debug!("What is this weird undocumented state! (core {})", index);
+        }
+        self.failures[index] += 1;
+    }
+
+    /// Logs the summary of all core states once [SUMMARY_INTERVAL] passed since the last one
+    pub fn tick(&mut self) {
+        if self.last_summary.elapsed() < SUMMARY_INTERVAL {
+            return;
+        }
+        self.last_summary = Instant::now();
+        for index in 0..self.failures.len() {
+            self.flush_failures(index, true);
+        }
+
+        let states: Vec<String> = self
+            .last
+            .iter()
+            .enumerate()
+            .map(|(index, state)| format!("core {}: {}", index, state.unwrap_or("unknown")))
+            .collect();
+        debug!("Core states: {}", states.join(", "));
+    }
+
+    fn ensure_core(&mut self, index: usize) {
+        if index >= self.last.len() {
+            self.last.resize(index + 1, None);
+            self.failures.resize(index + 1, 0);
+        }
+    }
+
+    /// Logs how often the failure of a core was suppressed. A series still `ongoing` is
+    /// counted anew, without logging its next failure on its own again.
+    fn flush_failures(&mut self, index: usize, ongoing: bool) {
+        let failures = self.failures[index];
+        if failures > 1 {
+            debug!(
+                "What is this weird undocumented state! (core {}) ...repeated {} times",
+                index,
+                failures - 1
+            );
+        }
+        self.failures[index] = if ongoing { failures.min(1) } else { 0 };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_states_are_remembered_once() {
+        let mut tracker = StateLogTracker::new(2);
+        tracker.state(0, "running");
+        tracker.state(0, "running");
+        tracker.unexpected_state(1, "custom");
+        assert_eq!(tracker.last, vec![Some("running"), Some("custom")]);
+    }
+
+    #[test]
+    fn failures_are_counted_until_a_query_succeeds() {
+        let mut tracker = StateLogTracker::new(1);
+        for _ in 0..3 {
+            tracker.query_failed(0);
+        }
+        assert_eq!(tracker.failures, vec![3]);
+        tracker.state(0, "halted");
+        assert_eq!(tracker.failures, vec![0]);
+    }
+
+    #[test]
+    fn summary_keeps_an_ongoing_failure_series() {
+        let mut tracker = StateLogTracker::new(1);
+        tracker.query_failed(0);
+        tracker.query_failed(0);
+        tracker.last_summary -= SUMMARY_INTERVAL;
+        tracker.tick();
+        assert_eq!(tracker.failures, vec![1]);
+    }
+
+    #[test]
+    fn cores_beyond_the_initial_count_are_tracked() {
+        let mut tracker = StateLogTracker::new(1);
+        tracker.query_failed(2);
+        tracker.state(1, "running");
+        assert_eq!(tracker.last, vec![None, Some("running"), None]);
+        assert_eq!(tracker.failures, vec![0, 0, 1]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Counters of the traffic exchanged with the probe, shown by `monitor mcd-stats`.
+
+use std::time::{Duration, Instant};
+
+use super::mem_route::Route;
+
+/// Probe transaction statistics of the session
+#[derive(Debug, Default)]
+pub struct McdStats {
+    /// Memory read transactions issued
+    pub reads: u64,
+    /// Bytes read from target memory
+    pub read_bytes: u64,
+    /// Buffers allocated on the read path, one per transaction as rust_mcd returns the
+    /// bytes read in a buffer of its own
+    pub read_allocations: u64,
+    /// Bytes of the buffers allocated on the read path
+    pub read_allocated_bytes: u64,
+    /// Largest buffer allocated on the read path
+    pub peak_read_buffer: usize,
+    /// Memory write transactions issued
+    pub writes: u64,
+    /// Bytes written to target memory
+    pub write_bytes: u64,
+    /// Memory accesses issued through the core of the requesting thread
+    pub routed_tid: u64,
+    /// Memory accesses issued through the core owning the address
+    pub routed_owner: u64,
+    /// Memory accesses issued through the designated core of system accesses
+    pub routed_designated: u64,
+    /// GDB writes merged into a larger transaction by `write-coalesce`
+    pub coalesced_writes: u64,
+    /// Trigger lists downloaded to a core
+    pub trigger_downloads: u64,
+    /// Reads of the liveness check, retries included
+    pub heartbeats: u64,
+    /// Reads of the liveness check which failed or read an unexpected value
+    pub heartbeat_failures: u64,
+    /// Register reads served from the register cache
+    pub register_cache_hits: u64,
+    /// Register reads which went to the probe while the cache was enabled
+    pub register_cache_misses: u64,
+    /// When the last stop was detected, cleared once GDB read the registers
+    last_stop: Option<Instant>,
+    /// Time from the last stop until GDB read the registers
+    pub stop_latency: Option<Duration>,
+    /// Longest time from a stop until GDB read the registers
+    pub peak_stop_latency: Duration,
+}
+
+impl McdStats {
+    /// Counts a read transaction of `len` bytes and the buffer it returned
+    pub fn record_read(&mut self, len: usize) {
+        self.reads += 1;
+        self.read_bytes += len as u64;
+        self.record_read_allocation(len);
+    }
+
+    pub fn record_read_allocation(&mut self, len: usize) {
+        self.read_allocations += 1;
+        self.read_allocated_bytes += len as u64;
+        self.peak_read_buffer = self.peak_read_buffer.max(len);
+    }
+
+    pub fn record_write(&mut self, len: usize) {
+        self.writes += 1;
+        self.write_bytes += len as u64;
+    }
+
+    pub fn record_route(&mut self, route: Route) {
+        match route {
+            Route::Tid => self.routed_tid += 1,
+            Route::Owner(_) => self.routed_owner += 1,
+            Route::Designated => self.routed_designated += 1,
+        }
+    }
+
+    pub fn record_trigger_download(&mut self) {
+        self.trigger_downloads += 1;
+    }
+
+    pub fn record_stop(&mut self) {
+        self.last_stop = Some(Instant::now());
+    }
+
+    /// Completes the stop latency measurement on the first register read after a stop
+    pub fn record_register_read(&mut self) {
+        if let Some(stop) = self.last_stop.take() {
+            let latency = stop.elapsed();
+            self.stop_latency = Some(latency);
+            self.peak_stop_latency = self.peak_stop_latency.max(latency);
+        }
+    }
+
+    /// All counters with their current value, in display order
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("reads", self.reads.to_string()),
+            ("read bytes", self.read_bytes.to_string()),
+            ("read allocations", self.read_allocations.to_string()),
+            (
+                "read allocated bytes",
+                self.read_allocated_bytes.to_string(),
+            ),
+            ("peak read buffer", self.peak_read_buffer.to_string()),
+            ("writes", self.writes.to_string()),
+            ("write bytes", self.write_bytes.to_string()),
+            ("routed via tid", self.routed_tid.to_string()),
+            ("routed via owner", self.routed_owner.to_string()),
+            ("routed via designated", self.routed_designated.to_string()),
+            ("coalesced writes", self.coalesced_writes.to_string()),
+            ("trigger downloads", self.trigger_downloads.to_string()),
+            ("heartbeats", self.heartbeats.to_string()),
+            ("heartbeat failures", self.heartbeat_failures.to_string()),
+            ("register cache hits", self.register_cache_hits.to_string()),
+            (
+                "register cache misses",
+                self.register_cache_misses.to_string(),
+            ),
+            (
+                "stop latency",
+                self.stop_latency
+                    .map_or("-".to_owned(), |latency| format!("{:.1?}", latency)),
+            ),
+            (
+                "peak stop latency",
+                format!("{:.1?}", self.peak_stop_latency),
+            ),
+        ]
+    }
+}
@@ -0,0 +1,126 @@
+//! Stepping a core off the breakpoint it is halted on, before it is resumed.
+//!
+//! A core continued from a breakpoint hit sits on the address of a trigger that is still
+//! planted. Rather than rely on the debug hardware not to fire the trigger again before
+//! the instruction retires, the trigger of that core is removed, the core steps over the
+//! instruction and the trigger is planted again, without a stop reported. The requested
+//! action follows: a continue runs the core from the next instruction, a step is complete
+//! with the step off. A step off which does not complete, or ends on another breakpoint,
+//! is reported as a stop instead.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use rust_mcd::core::CoreState;
+
+use super::trigger_budget::TriggerOwner;
+use super::{halt_core, read_register, tricore, TricoreTarget};
+
+/// Time a core has to complete the step off a breakpoint
+const STEP_OFF_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Outcome of [TricoreTarget::step_off_breakpoint]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StepOff {
+    /// The core doesn't sit on a breakpoint planted on it
+    NotOnBreakpoint,
+    /// The core executed the instruction and halted on the next one
+    Stepped,
+    /// To be reported in place of the requested action
+    Stopped(tricore::Event),
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Key of the breakpoint planted on core `index` at `addr`
+    fn planted_breakpoint_at(&self, index: usize, addr: u32) -> Option<u32> {
+        let key = self.breakpoint_key(addr);
+        self.breakpoints
+            .get(&key)
+            .filter(|breakpoint| {
+                breakpoint.address == addr
+                    && !breakpoint.stale
+                    && breakpoint.planted_core_indices().contains(&index)
+            })
+            .map(|_| key)
+    }
+
+    /// Waits for core `index` to halt after a step, halts it if it doesn't in time
+    fn await_step(&mut self, index: usize) -> bool {
+        let started = Instant::now();
+        loop {
+            let Some(core) = self.core_mut(index) else {
+                return false;
+            };
+            if matches!(
+                core.query_state().map(|info| info.state),
+                Ok(CoreState::Debug)
+            ) {
+                return true;
+            }
+            if started.elapsed() > STEP_OFF_TIMEOUT {
+                _ = halt_core(core);
+                return false;
+            }
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Steps core `index` over the instruction at its PC if a breakpoint is planted there
+    /// on the core, with the core's trigger removed meanwhile
+    pub(crate) fn step_off_breakpoint(&mut self, index: usize) -> StepOff {
+        let Some(pc) = self.core(index).and_then(|core| read_register(core, "PC")) else {
+            return StepOff::NotOnBreakpoint;
+        };
+        let Some(key) = self.planted_breakpoint_at(index, pc) else {
+            return StepOff::NotOnBreakpoint;
+        };
+        let Some(trigger) = self
+            .breakpoints
+            .get_mut(&key)
+            .and_then(|breakpoint| breakpoint.take_trigger(index))
+        else {
+            return StepOff::NotOnBreakpoint;
+        };
+        let owner = TriggerOwner::Breakpoint(pc);
+        self.remove_core_trigger(index, trigger, owner);
+        self.flush_triggers();
+
+        let stepped =
+            self.core_mut(index).is_some_and(|core| core.step().is_ok()) && self.await_step(index);
+
+        // Planted again however the step went
+        match self.create_core_trigger(index, pc, owner) {
+            Some(trigger) => {
+                if let Some(breakpoint) = self.breakpoints.get_mut(&key) {
+                    breakpoint.restore_trigger(index, trigger);
+                }
+            }
+            None => warn!(
+                "Core {} lost the breakpoint at {:#010x}, no trigger left to plant it again",
+                index, pc
+            ),
+        }
+        self.flush_triggers();
+
+        if !stepped {
+            warn!(
+                "Core {} did not step off the breakpoint at {:#010x}",
+                index, pc
+            );
+            return StepOff::Stopped(tricore::Event::HaltedByStub(tricore::HaltCause::StepOff));
+        }
+        let Some(next) = self.core(index).and_then(|core| read_register(core, "PC")) else {
+            return StepOff::Stepped;
+        };
+        debug!(
+            "Core {} stepped off the breakpoint at {:#010x} to {:#010x}",
+            index, pc, next
+        );
+        if self.planted_breakpoint_at(index, next).is_some() {
+            StepOff::Stopped(tricore::Event::Break)
+        } else {
+            StepOff::Stepped
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! `monitor b` and `monitor bd`, breakpoints by function name for clients without the
+//! elf. The name is resolved in the elf of the stub.
+//!
+//! `<symbol>[+offset][@cpuN]`, the core suffix plants the breakpoint on that core only
+//! and resolves the name in the elf of that core. The breakpoints take the same path as
+//! the ones of GDB and are marked as set by the monitor, so a GDB removal at the same
+//! address leaves them in place.
+
+use gdbstub::outputln;
+
+use super::breakpoints::{place_breakpoint, Owner, PendingChange, Placement};
+use super::elf::{find_functions, FunctionSymbol};
+use super::elf_set::parse_core;
+use super::{parse_u32, CpuId, MonitorOutput, TricoreTarget};
+
+/// Kind recorded for breakpoints of `monitor b`, GDB never sees them
+const MONITOR_BP_KIND: usize = 4;
+
+/// Breakpoint location given to `monitor b`
+struct Location<'a> {
+    symbol: &'a str,
+    offset: u32,
+    core: Option<CpuId>,
+}
+
+fn parse_location(text: &str) -> Result<Location<'_>, String> {
+    let (rest, core) = match text.rsplit_once('@') {
+        Some((rest, core)) => (
+            rest,
+            Some(parse_core(core).ok_or_else(|| format!("Unknown core '{}'", core))?),
+        ),
+        None => (text, None),
+    };
+    let (symbol, offset) = match rest.rsplit_once('+') {
+        Some((symbol, offset)) => (
+            symbol,
+            parse_u32(offset).ok_or_else(|| format!("'{}' is not an offset", offset))?,
+        ),
+        None => (rest, 0),
+    };
+    if symbol.is_empty() {
+        return Err("usage: b <symbol>[+offset][@cpuN]".to_owned());
+    }
+    Ok(Location {
+        symbol,
+        offset,
+        core,
+    })
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Address of the location, candidates are listed if the name is ambiguous
+    fn resolve_location(&self, location: &Location, out: &mut MonitorOutput<'_>) -> Option<u32> {
+        let image = match location.core {
+            Some(core) => self.elfs.for_core(core),
+            None => self.elfs.primary(),
+        };
+        let Some(image) = image else {
+            outputln!(out, "No elf provided, symbols cannot be resolved");
+            return None;
+        };
+        let found = find_functions(image.data(), location.symbol);
+        let mut found: Vec<FunctionSymbol> = match found {
+            Ok(found) => found,
+            Err(e) => {
+                outputln!(out, "Cannot read symbols of {:?}: {:#}", image.path, e);
+                return None;
+            }
+        };
+        found.sort_by_key(|symbol| symbol.address);
+        found.dedup_by_key(|symbol| symbol.address);
+
+        match found.as_slice() {
+            [] => {
+                outputln!(out, "No function '{}' in {:?}", location.symbol, image.path);
+                None
+            }
+            [symbol] => Some(symbol.address.wrapping_add(location.offset)),
+            candidates => {
+                outputln!(out, "'{}' is ambiguous:", location.symbol);
+                for symbol in candidates {
+                    outputln!(out, "  {:#010x} {}", symbol.address, symbol.name);
+                }
+                None
+            }
+        }
+    }
+
+    /// Address of `<symbol>[+offset]` or of a plain address, outside of a monitor command.
+    /// The error holds what the monitor command would print.
+    pub(crate) fn resolve_address(&self, text: &str) -> Result<u32, String> {
+        if let Some(addr) = parse_u32(text) {
+            return Ok(addr);
+        }
+        let location = parse_location(text)?;
+        let mut out = MonitorOutput::captured();
+        self.resolve_location(&location, &mut out)
+            .ok_or_else(|| out.into_text().trim_end().to_owned())
+    }
+
+    pub(crate) fn symbol_breakpoint(&mut self, text: &str, out: &mut MonitorOutput<'_>) {
+        let location = match parse_location(text) {
+            Ok(location) => location,
+            Err(e) => {
+                outputln!(out, "{}", e);
+                return;
+            }
+        };
+        let Some(addr) = self.resolve_location(&location, out) else {
+            return;
+        };
+        let core = location.core.map(usize::from);
+        if let Some(index) = core.filter(|&index| self.core(index).is_none()) {
+            outputln!(out, "No core {}", index);
+            return;
+        }
+
+        let placement = place_breakpoint(addr, &self.exec_ranges());
+        let change = PendingChange::Add {
+            addr: placement.address(),
+            requested: addr,
+            kind: MONITOR_BP_KIND,
+            suspect: matches!(placement, Placement::Suspect(_)),
+            owner: Owner::Monitor,
+            core,
+        };
+        let addr = placement.address();
+        match self.change_breakpoint(change) {
+            Ok(()) if self.has_pending_change(addr) => outputln!(
+                out,
+                "Breakpoint at {:#010x} ({}) is planted at the next stop",
+                addr,
+                text
+            ),
+            Ok(()) => outputln!(out, "Breakpoint at {:#010x} ({})", addr, text),
+            Err(e) => outputln!(out, "Cannot set breakpoint at {:#010x}: {}", addr, e),
+        }
+    }
+
+    pub(crate) fn symbol_breakpoint_delete(&mut self, text: &str, out: &mut MonitorOutput<'_>) {
+        let location = match parse_location(text) {
+            Ok(location) => location,
+            Err(e) => {
+                outputln!(out, "{}", e);
+                return;
+            }
+        };
+        let Some(addr) = self.resolve_location(&location, out) else {
+            return;
+        };
+        let addr = self.breakpoint_address(addr);
+        if !self
+            .breakpoints
+            .get(&self.breakpoint_key(addr))
+            .is_some_and(|breakpoint| breakpoint.from_monitor)
+        {
+            outputln!(out, "No breakpoint of `monitor b` at {:#010x}", addr);
+            return;
+        }
+        match self.change_breakpoint(PendingChange::Remove {
+            addr,
+            owner: Owner::Monitor,
+        }) {
+            Ok(()) => outputln!(out, "Removed breakpoint at {:#010x} ({})", addr, text),
+            Err(e) => outputln!(out, "Cannot remove breakpoint at {:#010x}: {}", addr, e),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Target time of each run, measured with the system timer (STM) of the core, and the
+//! totals of `monitor runstats`.
+//!
+//! The STM of a core is read when the core is resumed and again as soon as the run loop
+//! sees it stopped, before the other cores are halted and the stop is handled. The STM
+//! keeps counting while a core is halted unless the firmware has its OCDS suspend enabled,
+//! so the time of a run is overstated by the time from the halt to the poll noticing it:
+//! at most one pass of the run loop, the state queries of the cores polled. Cores halted
+//! by the stub, e.g. on an interrupt or for all-stop, are read after they were halted.
+//! The 64-bit counter is read as TIM0 followed by CAP, reading TIM0 captures the upper word
+//! into CAP, so a carry between the two reads cannot tear the value.
+//!
+//! Ticks are converted with `--stm-freq`. Without it the frequency is measured once after
+//! the first resume, from the ticks counted over [CALIBRATION_DELAY] of host time.
+
+use std::collections::BTreeMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use gdbstub::outputln;
+use log::{info, warn};
+
+use super::{CpuId, MonitorOutput, TricoreTarget};
+
+/// Host time the STM is measured over when its frequency is not given
+const CALIBRATION_DELAY: Duration = Duration::from_millis(50);
+
+/// STM instances of a device, one per core
+pub struct StmRegisters {
+    /// Base address of the STM of core 0
+    pub base: u32,
+    /// Address distance between the STMs of two consecutive cores
+    pub stride: u32,
+}
+
+/// TIM0, bits 0 to 31 of the counter, reading it captures bits 32 to 63 into CAP
+const STM_TIM0: u32 = 0x10;
+/// CAP, bits 32 to 63 of the counter as of the last read of TIM0
+const STM_CAP: u32 = 0x2C;
+
+pub(crate) const TC2XX_STM: StmRegisters = StmRegisters {
+    base: 0xF000_0000,
+    stride: 0x100,
+};
+
+pub(crate) const TC3XX_STM: StmRegisters = StmRegisters {
+    base: 0xF000_1000,
+    stride: 0x100,
+};
+
+impl StmRegisters {
+    fn base_of(&self, index: usize) -> u32 {
+        self.base + self.stride * index as u32
+    }
+}
+
+/// Runs of a single core
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct CoreRunTime {
+    pub runs: u64,
+    /// STM ticks of all runs
+    pub ticks: u64,
+    /// STM ticks of the last run
+    pub last: u64,
+}
+
+/// STM readings of the running cores and the totals of the finished runs
+#[derive(Debug, Default)]
+pub(crate) struct TargetTime {
+    /// STM value at the resume, per physical core index
+    started: BTreeMap<usize, u64>,
+    /// STM value when the core was seen stopped, per physical core index
+    stopped_at: BTreeMap<usize, u64>,
+    /// Finished runs per physical core index
+    totals: BTreeMap<usize, CoreRunTime>,
+    /// Frequency measured after the first resume, `None` if not measured (yet)
+    measured_hz: Option<u64>,
+    /// Whether the measurement was made, it is not repeated after a failure
+    calibrated: bool,
+}
+
+impl TargetTime {
+    pub fn reset(&mut self) {
+        self.totals.clear();
+    }
+
+    /// Forgets the run of a core which failed to start
+    pub fn abandon(&mut self, index: usize) {
+        self.started.remove(&index);
+    }
+
+    /// Drops the stop readings of cores resumed right away, e.g. after serving semihosting
+    pub fn keep_stops(&mut self, stopped: &[CpuId]) {
+        self.stopped_at
+            .retain(|&index, _| stopped.iter().any(|&cpu_id| usize::from(cpu_id) == index));
+    }
+}
+
+/// `ticks` in the unit best suited, ticks as such when the frequency is unknown
+pub(crate) fn format_ticks(ticks: u64, hz: Option<u64>) -> String {
+    let Some(hz) = hz.filter(|hz| *hz > 0) else {
+        return format!("{} STM ticks", ticks);
+    };
+    let us = ticks as f64 * 1_000_000.0 / hz as f64;
+    if us >= 1_000_000.0 {
+        format!("{:.3} s", us / 1_000_000.0)
+    } else if us >= 1000.0 {
+        format!("{:.1} ms", us / 1000.0)
+    } else {
+        format!("{:.1} us", us)
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// The 64-bit STM counter of core `index`
+    fn read_stm(&mut self, index: usize) -> Option<u64> {
+        let base = self.arch.stm_registers().base_of(index);
+        let low = self.read_sized(base + STM_TIM0, 4).ok()?;
+        let high = self.read_sized(base + STM_CAP, 4).ok()?;
+        Some(u64::from(high) << 32 | u64::from(low))
+    }
+
+    /// STM frequency in Hz, the one given or the one measured
+    pub(crate) fn stm_hz(&self) -> Option<u64> {
+        self.settings
+            .stm_freq
+            .map(u64::from)
+            .or(self.target_time.measured_hz)
+    }
+
+    /// Reads the STM of the cores about to be started. Runs ended by a halt which did not
+    /// go through the run loop, e.g. a restart, are dropped.
+    pub(crate) fn start_target_time(&mut self, cores: &[usize]) {
+        self.target_time.started.clear();
+        self.target_time.stopped_at.clear();
+        for &index in cores {
+            if let Some(stm) = self.read_stm(index) {
+                self.target_time.started.insert(index, stm);
+            }
+        }
+    }
+
+    /// Measures the STM frequency on the first resume of a running core, unless it is
+    /// given by `stm-freq`
+    pub(crate) fn calibrate_stm_once(&mut self, running: &[usize]) {
+        if self.stm_hz().is_some() || self.target_time.calibrated {
+            return;
+        }
+        if let Some(&index) = running.first() {
+            self.calibrate_stm(index);
+        }
+    }
+
+    /// Measures the STM frequency of a running core against the host clock
+    fn calibrate_stm(&mut self, index: usize) {
+        self.target_time.calibrated = true;
+        let first = self.read_stm(index).map(|stm| (stm, Instant::now()));
+        sleep(CALIBRATION_DELAY);
+        let second = self.read_stm(index).map(|stm| (stm, Instant::now()));
+        let hz = match (first, second) {
+            (Some((first, at)), Some((second, later))) => {
+                let elapsed = later.duration_since(at).as_secs_f64();
+                Some((second.wrapping_sub(first) as f64 / elapsed).round() as u64)
+            }
+            _ => None,
+        };
+        match hz.filter(|hz| *hz > 0) {
+            Some(hz) => {
+                info!("STM of core {} measured at {} Hz", index, hz);
+                self.target_time.measured_hz = Some(hz);
+            }
+            None => warn!(
+                "Cannot measure the STM frequency, target time is given in ticks, see --stm-freq"
+            ),
+        }
+    }
+
+    /// Reads the STM of cores which stopped on their own, right as the stop is seen
+    pub(crate) fn note_stop_time(&mut self, stopped: &[CpuId]) {
+        for &cpu_id in stopped {
+            let index = usize::from(cpu_id);
+            if !self.target_time.started.contains_key(&index) {
+                continue;
+            }
+            if let Some(stm) = self.read_stm(index) {
+                self.target_time.stopped_at.insert(index, stm);
+            }
+        }
+    }
+
+    /// Ends the runs of every core started, logging the time of those in `stopped`. Their
+    /// STM reading of [TricoreTarget::note_stop_time] is used where there is one.
+    pub(crate) fn end_target_time(&mut self, stopped: &[CpuId]) {
+        let hz = self.stm_hz();
+        let started = std::mem::take(&mut self.target_time.started);
+        let mut stopped_at = std::mem::take(&mut self.target_time.stopped_at);
+        for (index, start) in started {
+            let stopped_cpu = stopped
+                .iter()
+                .copied()
+                .find(|&cpu_id| usize::from(cpu_id) == index);
+            let noted = stopped_cpu.and_then(|_| stopped_at.remove(&index));
+            let Some(stm) = noted.or_else(|| self.read_stm(index)) else {
+                continue;
+            };
+            let ticks = stm.wrapping_sub(start);
+            let total = self.target_time.totals.entry(index).or_default();
+            total.runs += 1;
+            total.ticks += ticks;
+            total.last = ticks;
+            if let Some(cpu_id) = stopped_cpu {
+                self.console(
+                    Some(cpu_id),
+                    format_args!(
+                        "CPU{} stopped after {} of execution",
+                        index,
+                        format_ticks(ticks, hz)
+                    ),
+                );
+            }
+        }
+    }
+
+    /// `runstats`
+    pub(crate) fn print_run_stats(&self, out: &mut MonitorOutput<'_>) {
+        let hz = self.stm_hz();
+        match (self.settings.stm_freq, self.target_time.measured_hz) {
+            (Some(hz), _) => outputln!(out, "STM frequency: {} Hz (--stm-freq)", hz),
+            (None, Some(hz)) => outputln!(out, "STM frequency: {} Hz (measured)", hz),
+            (None, None) => outputln!(out, "STM frequency: unknown, times in ticks"),
+        }
+        if self.target_time.totals.is_empty() {
+            outputln!(out, "No run finished yet");
+        }
+        for (index, total) in &self.target_time.totals {
+            outputln!(
+                out,
+                "CPU{}: {} runs, {} in total, last {}",
+                index,
+                total.runs,
+                format_ticks(total.ticks, hz),
+                format_ticks(total.last, hz)
+            );
+        }
+    }
+
+    /// `runstats reset`
+    pub(crate) fn reset_run_stats(&mut self, out: &mut MonitorOutput<'_>) {
+        self.target_time.reset();
+        outputln!(out, "Run statistics reset");
+    }
+}
@@ -8,12 +8,18 @@ use gdbstub::target::TargetError;
 pub enum TricoreTargetError {
     // ...
     Fatal(String),
+    /// A memory access failed, the session can continue
+    MemoryAccess(String),
+    /// The thread refers to a core slot without a usable core, e.g. a stale Tid
+    NotPresent(usize),
 }
 
 impl fmt::Display for TricoreTargetError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             TricoreTargetError::Fatal(msg) => write!(f, "Fatal error: {}", msg),
+            TricoreTargetError::MemoryAccess(msg) => write!(f, "Memory access failed: {}", msg),
+            TricoreTargetError::NotPresent(index) => write!(f, "Core {} is not present", index),
         }
     }
 }
@@ -22,6 +28,8 @@ impl From<TricoreTargetError> for TargetError<&'static str> {
     fn from(error: TricoreTargetError) -> Self {
         match error {
             TricoreTargetError::Fatal(_s) => TargetError::Fatal("Fatal error"),
+            TricoreTargetError::MemoryAccess(_s) => TargetError::NonFatal,
+            TricoreTargetError::NotPresent(_index) => TargetError::NonFatal,
         }
     }
 }
@@ -0,0 +1,126 @@
+//! Detection of cores caught in an endless trap loop, e.g. a trap handler which traps
+//! itself. Such a core keeps running, so without the detector GDB just waits.
+//!
+//! While cores run, the PC of each resumed core is sampled at most every
+//! [SAMPLE_INTERVAL]. Once [SAMPLES] consecutive samples stay within `trap-loop-window`
+//! bytes and that window overlaps the trap vector table at BTV, the core is considered
+//! stuck. Tight loops elsewhere never fire.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use super::decode::{trap_class, TRAP_TABLE_LEN};
+use super::tricore::{Event, HaltCause};
+use super::{read_register, CpuId, TricoreTarget};
+
+/// Minimum time between two PC samples of the running cores
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+/// Consecutive confined samples before the detector fires
+const SAMPLES: usize = 8;
+
+pub(crate) struct TrapLoopDetector {
+    /// Last PCs of the cores resumed by the last resume
+    samples: HashMap<usize, VecDeque<u32>>,
+    last_sample: Option<Instant>,
+}
+
+impl TrapLoopDetector {
+    pub fn new() -> Self {
+        TrapLoopDetector {
+            samples: HashMap::new(),
+            last_sample: None,
+        }
+    }
+
+    /// Starts over with the cores just resumed
+    pub fn start(&mut self, cores: impl IntoIterator<Item = usize>) {
+        self.samples = cores
+            .into_iter()
+            .map(|index| (index, VecDeque::new()))
+            .collect();
+        self.last_sample = None;
+    }
+
+    /// Whether the running cores are to be sampled now
+    fn due(&mut self) -> bool {
+        if self.samples.is_empty()
+            || self
+                .last_sample
+                .is_some_and(|last| last.elapsed() < SAMPLE_INTERVAL)
+        {
+            return false;
+        }
+        self.last_sample = Some(Instant::now());
+        true
+    }
+
+    /// Records a sample, returns the address range of the last samples once they are
+    /// all confined to `window` bytes
+    fn record(&mut self, index: usize, pc: u32, window: u32) -> Option<(u32, u32)> {
+        let samples = self.samples.get_mut(&index)?;
+        if samples.len() == SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(pc);
+        if samples.len() < SAMPLES {
+            return None;
+        }
+        let low = *samples.iter().min()?;
+        let high = *samples.iter().max()?;
+        (high - low < window).then_some((low, high))
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Samples the resumed cores if due, returns a core stuck in a trap loop
+    pub(crate) fn detect_trap_loop(&mut self) -> Option<CpuId> {
+        if !self.trap_loop.due() {
+            return None;
+        }
+        let mut indices: Vec<usize> = self.trap_loop.samples.keys().copied().collect();
+        indices.sort_unstable();
+        for index in indices {
+            let Some(pc) = self.core(index).and_then(|core| read_register(core, "PC")) else {
+                continue;
+            };
+            let Some((low, high)) =
+                self.trap_loop
+                    .record(index, pc, self.settings.trap_loop_window)
+            else {
+                continue;
+            };
+            let Some(btv) = self.core(index).and_then(|core| read_register(core, "BTV")) else {
+                continue;
+            };
+            if low < btv.saturating_add(TRAP_TABLE_LEN) && btv <= high {
+                return CpuId::try_from(index).ok();
+            }
+        }
+        None
+    }
+
+    /// Explains the stop of a core the trap loop detector halted, as a fault of the trap
+    /// class whose vector the core is in. TriCore passes the TIN in D15 on trap entry.
+    pub(crate) fn report_trap_loop(&mut self, cpu_id: CpuId) -> Event {
+        warn!(
+            "Core {:?} kept executing in its trap vectors, halted by the trap loop detector \
+             (`monitor set trap-loop-detect off` disables it)",
+            cpu_id
+        );
+        let core = self.core(usize::from(cpu_id));
+        let read = |name: &str| core.and_then(|core| read_register(core, name));
+        match (read("PC"), read("BTV"), read("D15")) {
+            (Some(pc), Some(btv), Some(d15)) => match trap_class(btv, pc) {
+                Some((class, _)) => Event::Fault {
+                    class: class as u8,
+                    tin: d15 as u8,
+                    pc,
+                },
+                None => Event::HaltedByStub(HaltCause::TrapLoop),
+            },
+            _ => Event::HaltedByStub(HaltCause::TrapLoop),
+        }
+    }
+}
@@ -1,15 +1,73 @@
+use std::fmt;
+
 use super::CpuId;
 
+/// Why the stub halted a core on its own
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HaltCause {
+    /// Requested by an external trigger, e.g. the pause file
+    Pause,
+    /// A resume refused while a flash operation is in progress
+    Busy,
+    /// The trap loop detector, the trap class could not be told
+    TrapLoop,
+    /// Stepping off a breakpoint before a resume did not complete
+    StepOff,
+    /// A resume refused as a memory write already acknowledged to GDB failed
+    WriteFailed,
+}
+
+impl fmt::Display for HaltCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HaltCause::Pause => "halted by an external trigger",
+            HaltCause::Busy => "not resumed, a flash operation is in progress",
+            HaltCause::TrapLoop => "halted by the trap loop detector",
+            HaltCause::StepOff => "halted, it did not step off its breakpoint",
+            HaltCause::WriteFailed => "not resumed, an acknowledged memory write failed",
+        })
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Event {
     DoneStep,
     Halted,
     Break,
+    /// Stopped in the trap vector of `class` with the trap identification number `tin`
+    Fault {
+        class: u8,
+        tin: u8,
+        pc: u32,
+    },
+    /// The program ended with the exit code
+    Exited(u8),
+    HaltedByStub(HaltCause),
     WatchWrite(u32),
     WatchRead(u32),
 }
 
+impl Event {
+    /// Explanation of the stop for the console, for stops GDB can only show as a signal
+    pub fn detail(&self) -> Option<String> {
+        match self {
+            Event::Fault { class, tin, pc } => Some(format!(
+                "Class {} ({}) trap, TIN {} at {:#010x}",
+                class,
+                super::decode::trap_class_name(*class),
+                tin,
+                pc
+            )),
+            Event::Exited(code) => Some(format!("Program exited with code {}", code)),
+            Event::HaltedByStub(cause) => Some(format!("Core {}", cause)),
+            _ => None,
+        }
+    }
+}
+
 pub enum RunEvent {
     Event(Event, CpuId),
     IncomingData,
+    /// No event within the time budget, poll again
+    Yield,
 }
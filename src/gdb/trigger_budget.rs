@@ -0,0 +1,313 @@
+//! Accounting of the hardware triggers of each core by owner, so the stub keeps triggers
+//! for its own use when the user sets many breakpoints.
+//!
+//! Every trigger is acquired from [TriggerBudget] before it is created and released when
+//! it is removed. `reserved-triggers` of the probed limit of a core are kept for the stub:
+//! user breakpoints and range watches are refused once the rest is in use, while step
+//! overs, soft steps, snippets and `--break-at` may use the reserve. The trigger event
+//! units of a core serve IP and data triggers alike, so every trigger of the core counts
+//! against the limit of the type requested. Without a known limit nothing is refused and
+//! the probe has the last word.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use gdbstub::outputln;
+
+use super::{MonitorOutput, TricoreTarget};
+
+/// What a trigger is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TriggerOwner {
+    /// Breakpoint of GDB or the monitor planted at the address
+    Breakpoint(u32),
+    /// `monitor wp range` starting at the address
+    RangeWatch(u32),
+    /// Return address of a call stepped over
+    StepOver,
+    /// Possible next instruction of a soft step
+    SoftStep,
+    /// End of a code snippet run on a core
+    Snippet,
+    /// `--break-at` location
+    RunTo,
+    /// Counting the triggers of a core after connecting
+    Probe,
+}
+
+impl TriggerOwner {
+    /// Whether the trigger serves the stub rather than the user, these may use the
+    /// reserved triggers
+    pub fn internal(self) -> bool {
+        !matches!(
+            self,
+            TriggerOwner::Breakpoint(_) | TriggerOwner::RangeWatch(_)
+        )
+    }
+
+    /// Data trigger rather than an IP trigger
+    pub fn data(self) -> bool {
+        matches!(self, TriggerOwner::RangeWatch(_))
+    }
+}
+
+impl fmt::Display for TriggerOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerOwner::Breakpoint(addr) => write!(f, "breakpoint at {:#010x}", addr),
+            TriggerOwner::RangeWatch(addr) => write!(f, "range watch at {:#010x}", addr),
+            TriggerOwner::StepOver => f.write_str("internal step over"),
+            TriggerOwner::SoftStep => f.write_str("internal soft step"),
+            TriggerOwner::Snippet => f.write_str("internal snippet"),
+            TriggerOwner::RunTo => f.write_str("internal break-at"),
+            TriggerOwner::Probe => f.write_str("internal probe"),
+        }
+    }
+}
+
+/// Why a trigger was not granted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Refusal {
+    /// The triggers left to the user are in use
+    Budget {
+        in_use: usize,
+        limit: usize,
+        reserved: usize,
+    },
+    /// Even the reserved triggers are in use
+    Reserve { in_use: usize, limit: usize },
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Budget {
+                in_use,
+                limit,
+                reserved,
+            } => write!(
+                f,
+                "{}/{} triggers in use, {} reserved for the stub",
+                in_use, limit, reserved
+            ),
+            Refusal::Reserve { in_use, limit } => write!(
+                f,
+                "{}/{} triggers in use, none left for the stub",
+                in_use, limit
+            ),
+        }
+    }
+}
+
+/// Owners of the triggers in use, by core index
+#[derive(Debug, Default)]
+pub(crate) struct TriggerBudget {
+    owners: HashMap<usize, Vec<TriggerOwner>>,
+}
+
+impl TriggerBudget {
+    /// Grants core `index` a trigger for `owner` if its `limit` allows, keeping
+    /// `reserved` triggers from user owners
+    pub fn acquire(
+        &mut self,
+        index: usize,
+        owner: TriggerOwner,
+        limit: Option<usize>,
+        reserved: usize,
+    ) -> Result<(), Refusal> {
+        let in_use = self.in_use(index);
+        if let Some(limit) = limit {
+            if in_use >= limit {
+                return Err(Refusal::Reserve { in_use, limit });
+            }
+            if !owner.internal() && in_use + reserved >= limit {
+                return Err(Refusal::Budget {
+                    in_use,
+                    limit,
+                    reserved,
+                });
+            }
+        }
+        self.owners.entry(index).or_default().push(owner);
+        Ok(())
+    }
+
+    /// Gives back a trigger of `owner` on core `index`
+    pub fn release(&mut self, index: usize, owner: TriggerOwner) {
+        let Some(owners) = self.owners.get_mut(&index) else {
+            return;
+        };
+        if let Some(position) = owners.iter().position(|&used| used == owner) {
+            owners.remove(position);
+        }
+    }
+
+    pub fn in_use(&self, index: usize) -> usize {
+        self.owners.get(&index).map_or(0, Vec::len)
+    }
+
+    /// Owners of the triggers of core `index`, in the order they were acquired
+    pub fn owners(&self, index: usize) -> &[TriggerOwner] {
+        self.owners.get(&index).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Trigger limit of core `index` for the type `owner` uses
+    pub(crate) fn trigger_limit(&self, index: usize, owner: TriggerOwner) -> Option<usize> {
+        self.das_capabilities.trigger_limit(index, owner.data())
+    }
+
+    /// Acquires a trigger on core `index` for `owner` from the budget
+    pub(crate) fn acquire_trigger(
+        &mut self,
+        index: usize,
+        owner: TriggerOwner,
+    ) -> Result<(), Refusal> {
+        let limit = self.trigger_limit(index, owner);
+        self.trigger_budget
+            .acquire(index, owner, limit, self.settings.reserved_triggers)
+    }
+
+    /// `triggers`: budget, owners and free triggers of every core
+    pub(crate) fn print_triggers(&self, out: &mut MonitorOutput<'_>) {
+        let reserved = self.settings.reserved_triggers;
+        for index in self.all_cores() {
+            let in_use = self.trigger_budget.in_use(index);
+            let limit = |owner| {
+                self.trigger_limit(index, owner)
+                    .map_or("?".to_owned(), |limit| limit.to_string())
+            };
+            outputln!(
+                out,
+                "CPU{}: {} IP / {} data triggers, {} reserved, {} in use",
+                index,
+                limit(TriggerOwner::Breakpoint(0)),
+                limit(TriggerOwner::RangeWatch(0)),
+                reserved,
+                in_use
+            );
+            for owner in self.trigger_budget.owners(index) {
+                outputln!(out, "  {}", owner);
+            }
+            if let Some(limit) = self.trigger_limit(index, TriggerOwner::Breakpoint(0)) {
+                outputln!(
+                    out,
+                    "  {} free for the user, {} for the stub",
+                    limit.saturating_sub(in_use + reserved),
+                    limit.saturating_sub(in_use)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_owners_leave_the_reserve() {
+        let mut budget = TriggerBudget::default();
+        for addr in [0x100, 0x200, 0x300] {
+            budget
+                .acquire(0, TriggerOwner::Breakpoint(addr), Some(4), 1)
+                .unwrap();
+        }
+        assert_eq!(
+            budget.acquire(0, TriggerOwner::Breakpoint(0x400), Some(4), 1),
+            Err(Refusal::Budget {
+                in_use: 3,
+                limit: 4,
+                reserved: 1
+            })
+        );
+        assert_eq!(
+            budget.acquire(0, TriggerOwner::StepOver, Some(4), 1),
+            Ok(())
+        );
+        assert_eq!(
+            budget.acquire(0, TriggerOwner::SoftStep, Some(4), 1),
+            Err(Refusal::Reserve {
+                in_use: 4,
+                limit: 4
+            })
+        );
+        assert_eq!(budget.in_use(0), 4);
+    }
+
+    #[test]
+    fn cores_are_accounted_separately() {
+        let mut budget = TriggerBudget::default();
+        budget.acquire(0, TriggerOwner::RunTo, Some(1), 0).unwrap();
+        assert!(budget.acquire(0, TriggerOwner::RunTo, Some(1), 0).is_err());
+        assert_eq!(budget.acquire(1, TriggerOwner::RunTo, Some(1), 0), Ok(()));
+        assert_eq!(budget.owners(1), [TriggerOwner::RunTo]);
+        assert!(budget.owners(2).is_empty());
+    }
+
+    #[test]
+    fn release_frees_a_trigger_of_the_owner() {
+        let mut budget = TriggerBudget::default();
+        let owner = TriggerOwner::Breakpoint(0x8000_0000);
+        budget.acquire(0, owner, Some(2), 0).unwrap();
+        budget
+            .acquire(0, TriggerOwner::Snippet, Some(2), 0)
+            .unwrap();
+        budget.release(0, TriggerOwner::Breakpoint(0x8000_0004));
+        assert_eq!(budget.in_use(0), 2);
+        budget.release(0, owner);
+        assert_eq!(budget.owners(0), [TriggerOwner::Snippet]);
+        budget.release(3, owner);
+        assert_eq!(budget.acquire(0, owner, Some(2), 0), Ok(()));
+    }
+
+    #[test]
+    fn unknown_limit_refuses_nothing() {
+        let mut budget = TriggerBudget::default();
+        for addr in 0..32 {
+            budget
+                .acquire(0, TriggerOwner::Breakpoint(addr), None, 4)
+                .unwrap();
+        }
+        assert_eq!(budget.in_use(0), 32);
+    }
+
+    #[test]
+    fn only_user_owners_are_not_internal() {
+        assert!(!TriggerOwner::Breakpoint(0).internal());
+        assert!(!TriggerOwner::RangeWatch(0).internal());
+        for owner in [
+            TriggerOwner::StepOver,
+            TriggerOwner::SoftStep,
+            TriggerOwner::Snippet,
+            TriggerOwner::RunTo,
+            TriggerOwner::Probe,
+        ] {
+            assert!(owner.internal(), "{}", owner);
+            assert!(!owner.data());
+        }
+        assert!(TriggerOwner::RangeWatch(0).data());
+    }
+
+    #[test]
+    fn refusals_read_well() {
+        let budget = Refusal::Budget {
+            in_use: 7,
+            limit: 8,
+            reserved: 1,
+        };
+        assert_eq!(
+            budget.to_string(),
+            "7/8 triggers in use, 1 reserved for the stub"
+        );
+        let reserve = Refusal::Reserve {
+            in_use: 8,
+            limit: 8,
+        };
+        assert_eq!(
+            reserve.to_string(),
+            "8/8 triggers in use, none left for the stub"
+        );
+    }
+}
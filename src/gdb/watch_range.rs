@@ -0,0 +1,385 @@
+//! `monitor wp range`, data triggers over an address range to catch the core writing
+//! into memory it doesn't own, e.g. CPU1 corrupting a buffer of CPU0.
+//!
+//! GDB watchpoints cover a few bytes and every core alike, a range watch covers up to
+//! [MAX_RANGE_LEN] bytes with a single trigger on the chosen cores. rust_mcd reports no
+//! trigger status, so a stop of a watching core which isn't on a breakpoint and wasn't
+//! stepping is taken as a hit. The range content is kept from the last resume where it
+//! is at most [SNAPSHOT_LEN] bytes, the first byte changed since gives the address and
+//! the value written.
+
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use gdbstub::outputln;
+use log::{debug, warn};
+use rust_mcd::breakpoint::TriggerType;
+
+use super::breakpoints::TriggerId;
+use super::elf_set::parse_core;
+use super::hit_timing::{self, HitTiming};
+use super::tricore::Event;
+use super::trigger_budget::TriggerOwner;
+use super::{parse_u32, CpuId, MonitorOutput, ResumeAction, TricoreTarget};
+
+/// Largest range a single data trigger covers
+const MAX_RANGE_LEN: u32 = 0x1_0000;
+/// Largest range whose content is kept to tell the address written
+const SNAPSHOT_LEN: u32 = 4096;
+
+/// Accesses a range watch stops on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WatchAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchAccess {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "r" => Some(WatchAccess::Read),
+            "w" => Some(WatchAccess::Write),
+            "rw" => Some(WatchAccess::ReadWrite),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            WatchAccess::Read => "r",
+            WatchAccess::Write => "w",
+            WatchAccess::ReadWrite => "rw",
+        }
+    }
+
+    fn trigger_type(self) -> TriggerType {
+        match self {
+            WatchAccess::Read => TriggerType::Read,
+            WatchAccess::Write => TriggerType::Write,
+            WatchAccess::ReadWrite => TriggerType::ReadWrite,
+        }
+    }
+}
+
+/// A range watched on a set of cores
+pub(crate) struct RangeWatch {
+    start: u32,
+    /// Exclusive
+    end: u32,
+    access: WatchAccess,
+    /// Trigger per watching core
+    triggers: Vec<(usize, TriggerId)>,
+    /// Range content at the last resume, `None` for ranges beyond [SNAPSHOT_LEN]
+    snapshot: Option<Vec<u8>>,
+    timing: HitTiming,
+}
+
+impl RangeWatch {
+    fn len(&self) -> u32 {
+        self.end - self.start
+    }
+
+    fn cores(&self) -> Vec<usize> {
+        self.triggers.iter().map(|&(index, _)| index).collect()
+    }
+}
+
+/// The range watches of the session by their number
+#[derive(Default)]
+pub(crate) struct RangeWatches {
+    watches: BTreeMap<u32, RangeWatch>,
+    next_id: u32,
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Creates a data trigger over `len` bytes at `addr` on a single core for `owner`, see
+    /// [Self::create_core_trigger]
+    pub(crate) fn create_data_trigger(
+        &mut self,
+        index: usize,
+        addr: u32,
+        len: u32,
+        access: WatchAccess,
+        owner: TriggerOwner,
+    ) -> Option<TriggerId> {
+        self.core(index)?;
+        if let Err(refusal) = self.acquire_trigger(index, owner) {
+            debug!("Core {} refused the {}: {}", index, owner, refusal);
+            return None;
+        }
+        let Ok(trigger) =
+            self.core_mut(index)?
+                .create_breakpoint(access.trigger_type(), addr as u64, len as _)
+        else {
+            self.trigger_budget.release(index, owner);
+            return None;
+        };
+        let trigger = TriggerId::of(&trigger);
+        self.dirty_triggers.insert(index);
+        Some(trigger)
+    }
+
+    /// Creates the triggers of a watch on `cores`, none are left behind if one fails
+    fn plant_range_watch(
+        &mut self,
+        start: u32,
+        len: u32,
+        access: WatchAccess,
+        cores: &[usize],
+    ) -> Result<Vec<(usize, TriggerId)>, usize> {
+        let owner = TriggerOwner::RangeWatch(start);
+        let mut triggers = Vec::new();
+        for &index in cores {
+            match self.create_data_trigger(index, start, len, access, owner) {
+                Some(trigger) => triggers.push((index, trigger)),
+                None => {
+                    for (index, trigger) in triggers {
+                        self.remove_core_trigger(index, trigger, owner);
+                    }
+                    return Err(index);
+                }
+            }
+        }
+        Ok(triggers)
+    }
+
+    pub(crate) fn range_watch_add(&mut self, args: &[&str], out: &mut MonitorOutput<'_>) {
+        const USAGE: &str = "usage: wp range <start> <end> [cpuN] [r|w|rw]";
+        let (Some(start), Some(end)) = (
+            args.first().and_then(|arg| parse_u32(arg)),
+            args.get(1).and_then(|arg| parse_u32(arg)),
+        ) else {
+            outputln!(out, "{}", USAGE);
+            return;
+        };
+        let mut core = None;
+        let mut access = WatchAccess::Write;
+        for arg in &args[2..] {
+            if let Some(parsed) = WatchAccess::parse(arg) {
+                access = parsed;
+            } else if let Some(parsed) = parse_core(arg) {
+                core = Some(parsed);
+            } else {
+                outputln!(out, "{}", USAGE);
+                return;
+            }
+        }
+        if end <= start {
+            outputln!(out, "The end {:#010x} must lie above the start", end);
+            return;
+        }
+        let len = end - start;
+        if len > MAX_RANGE_LEN {
+            outputln!(
+                out,
+                "A data trigger covers at most {:#x} bytes, {:#x} requested",
+                MAX_RANGE_LEN,
+                len
+            );
+            return;
+        }
+        let cores = match core {
+            Some(core) if self.core(usize::from(core)).is_none() => {
+                outputln!(out, "No core {:?}", core);
+                return;
+            }
+            Some(core) => vec![usize::from(core)],
+            None => self.all_cores(),
+        };
+        if !self.running_cores().is_empty() {
+            outputln!(out, "Refused while cores run, halt the target first");
+            return;
+        }
+
+        let triggers = match self.plant_range_watch(start, len, access, &cores) {
+            Ok(triggers) => triggers,
+            Err(index) => {
+                outputln!(out, "Core {} refused a data trigger over the range", index);
+                return;
+            }
+        };
+        let id = self.range_watches.next_id;
+        self.range_watches.next_id += 1;
+        let mut watch = RangeWatch {
+            start,
+            end,
+            access,
+            triggers,
+            snapshot: None,
+            timing: HitTiming::default(),
+        };
+        self.refresh_snapshot(&mut watch);
+        outputln!(
+            out,
+            "Watch {}: [{:#010x}, {:#010x}) {} on {}",
+            id,
+            start,
+            end,
+            access.name(),
+            core.map_or("all cores".to_owned(), |core| format!("{:?}", core))
+        );
+        self.range_watches.watches.insert(id, watch);
+    }
+
+    pub(crate) fn range_watch_delete(&mut self, id: &str, out: &mut MonitorOutput<'_>) {
+        let Some(id) = parse_u32(id) else {
+            outputln!(out, "usage: wp delete <number>");
+            return;
+        };
+        let Some(watch) = self.range_watches.watches.remove(&id) else {
+            outputln!(out, "No watch {}", id);
+            return;
+        };
+        for (index, trigger) in watch.triggers {
+            self.remove_core_trigger(index, trigger, TriggerOwner::RangeWatch(watch.start));
+        }
+        outputln!(out, "Watch {} removed", id);
+    }
+
+    pub(crate) fn range_watch_list(&self, out: &mut MonitorOutput<'_>) {
+        if self.range_watches.watches.is_empty() {
+            outputln!(out, "No range watches");
+            return;
+        }
+        for (id, watch) in &self.range_watches.watches {
+            let cores: Vec<String> = watch
+                .cores()
+                .iter()
+                .map(|index| format!("CPU{}", index))
+                .collect();
+            outputln!(
+                out,
+                "{}: [{:#010x}, {:#010x}) {:#x} bytes {} on {}{}",
+                id,
+                watch.start,
+                watch.end,
+                watch.len(),
+                watch.access.name(),
+                cores.join(" "),
+                watch.timing.columns()
+            );
+        }
+        if self
+            .range_watches
+            .watches
+            .values()
+            .any(|watch| watch.timing.count() > 0)
+        {
+            outputln!(out, "{}", hit_timing::CAVEAT);
+        }
+    }
+
+    /// Reads the content of a watched range through its first core
+    fn refresh_snapshot(&self, watch: &mut RangeWatch) {
+        watch.snapshot = None;
+        if watch.len() > SNAPSHOT_LEN || watch.access == WatchAccess::Read {
+            return;
+        }
+        let Some(&(index, _)) = watch.triggers.first() else {
+            return;
+        };
+        watch.snapshot = self.core(index).and_then(|core| {
+            core.read_bytes(watch.start as u64, watch.len() as usize)
+                .ok()
+        });
+    }
+
+    /// Keeps the content of the watched ranges before the cores run
+    pub(crate) fn snapshot_range_watches(&mut self) {
+        let mut watches = std::mem::take(&mut self.range_watches.watches);
+        for watch in watches.values_mut() {
+            self.refresh_snapshot(watch);
+        }
+        self.range_watches.watches = watches;
+    }
+
+    /// Removes every range watch with its triggers
+    pub(crate) fn remove_range_watches(&mut self) {
+        for (_, watch) in std::mem::take(&mut self.range_watches.watches) {
+            for (index, trigger) in watch.triggers {
+                self.remove_core_trigger(index, trigger, TriggerOwner::RangeWatch(watch.start));
+            }
+        }
+    }
+
+    /// Plants the range watches again after a reset dropped the triggers
+    pub(crate) fn replant_range_watches(&mut self) {
+        let watches = std::mem::take(&mut self.range_watches.watches);
+        for (id, watch) in watches {
+            let cores = watch.cores();
+            for (index, trigger) in watch.triggers {
+                self.remove_core_trigger(index, trigger, TriggerOwner::RangeWatch(watch.start));
+            }
+            match self.plant_range_watch(watch.start, watch.len(), watch.access, &cores) {
+                Ok(triggers) => {
+                    self.range_watches
+                        .watches
+                        .insert(id, RangeWatch { triggers, ..watch });
+                }
+                Err(index) => warn!(
+                    "Watch {} dropped, core {} refused its trigger after the reset",
+                    id, index
+                ),
+            }
+        }
+    }
+
+    /// Forgets the hits of every range watch, for `bp timing reset`
+    pub(crate) fn reset_range_watch_timing(&mut self) {
+        for watch in self.range_watches.watches.values_mut() {
+            watch.timing.reset();
+        }
+    }
+
+    /// Takes the stop of a core as a range watch hit if the core watches a range and
+    /// neither sits on a breakpoint nor was stepping. Reports the hit on the console and
+    /// returns the stop event, with the address written where it can be told.
+    pub(crate) fn range_watch_hit(&mut self, cpu_id: CpuId, pc: Option<u32>) -> Option<Event> {
+        let index = usize::from(cpu_id);
+        if matches!(
+            self.slot_at(index).map(|slot| slot.resume_action),
+            Some(ResumeAction::Step)
+        ) || pc.is_some_and(|pc| self.breakpoints.contains_key(&self.breakpoint_key(pc)))
+        {
+            return None;
+        }
+        let (&id, watch) = self
+            .range_watches
+            .watches
+            .iter_mut()
+            .find(|(_, watch)| watch.cores().contains(&index))?;
+        watch.timing.record(Instant::now());
+        let (start, end, access) = (watch.start, watch.end, watch.access);
+
+        let changed = watch.snapshot.as_ref().and_then(|before| {
+            let after = self
+                .core(index)?
+                .read_bytes(start as u64, before.len())
+                .ok()?;
+            let offset = before.iter().zip(&after).position(|(a, b)| a != b)?;
+            Some((start + offset as u32, after[offset]))
+        });
+        // Without a changed byte a read or rewrite of the same value hit, reported at the
+        // start of the range
+        let (event, detail) = match (changed, access) {
+            (Some((addr, value)), _) => (
+                Event::WatchWrite(addr),
+                format!("byte at {:#010x} now {:#04x}", addr, value),
+            ),
+            (None, WatchAccess::Write) => {
+                (Event::WatchWrite(start), "address not known".to_owned())
+            }
+            (None, _) => (Event::WatchRead(start), "address not known".to_owned()),
+        };
+        let at = pc.map_or("?".to_owned(), |pc| format!("{:#010x}", pc));
+        self.console(
+            Some(cpu_id),
+            format_args!(
+                "Watch {} [{:#010x}, {:#010x}) hit by {:?} at PC {}, {}",
+                id, start, end, cpu_id, at, detail
+            ),
+        );
+        Some(event)
+    }
+}
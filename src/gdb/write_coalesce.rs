@@ -0,0 +1,293 @@
+//! `write-coalesce`, merging the small adjacent memory writes GDB sends for a struct
+//! assignment into a single MCD transaction.
+//!
+//! A GDB write of at most [MAX_COALESCED] bytes to RAM is acknowledged right away and kept,
+//! following writes continuing it are appended. GDB only sends the next packet once it got
+//! the reply to the last one, so merging needs the early acknowledgement.
+//!
+//! A kept write therefore only goes to the device where a failure still reaches GDB: with
+//! the next memory read or write, which fails, at a resume, which is refused with an
+//! immediate stop and a console note, and before a monitor command, which prints the
+//! failure. To name the packet at fault, the packets are written again one by one after a
+//! failure, the first one failing again is reported with its address. Flash and CSFR
+//! writes are never kept.
+
+use std::fmt;
+
+use gdbstub::common::Tid;
+use log::debug;
+
+use super::memory_map;
+use super::traits::TricoreTargetError;
+use super::TricoreTarget;
+
+/// Largest GDB write kept for merging
+const MAX_COALESCED: usize = 16;
+/// Largest merged write
+const MAX_MERGED: usize = 1024;
+
+/// GDB writes acknowledged but not yet written to the device
+#[derive(Debug)]
+pub(crate) struct PendingWrite {
+    tid: Tid,
+    start: u32,
+    data: Vec<u8>,
+    /// Address and length of every GDB write merged
+    packets: Vec<(u32, usize)>,
+}
+
+/// An acknowledged GDB write which failed when it was written to the device
+#[derive(Debug)]
+pub(crate) struct FailedWrite<E> {
+    pub addr: u32,
+    pub len: usize,
+    pub error: E,
+}
+
+impl<E: fmt::Display> fmt::Display for FailedWrite<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GDB's write of {} bytes at {:#010x} failed after it was acknowledged: {}",
+            self.len, self.addr, self.error
+        )
+    }
+}
+
+impl PendingWrite {
+    fn new(tid: Tid, addr: u32, data: &[u8]) -> Self {
+        PendingWrite {
+            tid,
+            start: addr,
+            data: data.to_vec(),
+            packets: vec![(addr, data.len())],
+        }
+    }
+
+    fn continued_by(&self, tid: Tid, addr: u32, len: usize) -> bool {
+        self.tid == tid
+            && self.start as u64 + self.data.len() as u64 == addr as u64
+            && self.data.len() + len <= MAX_MERGED
+    }
+
+    /// Writes the merged packets with `write`, one by one after the merged write failed.
+    /// The first packet failing again is returned.
+    fn write_to<E>(
+        self,
+        mut write: impl FnMut(Tid, u32, &[u8]) -> Result<(), E>,
+    ) -> Result<(), FailedWrite<E>> {
+        if write(self.tid, self.start, &self.data).is_ok() {
+            return Ok(());
+        }
+        let mut offset = 0;
+        for (addr, len) in self.packets {
+            let bytes = &self.data[offset..offset + len];
+            offset += len;
+            write(self.tid, addr, bytes).map_err(|error| FailedWrite { addr, len, error })?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the write in `pending`, appended to the kept one if it continues it. A kept write
+/// it doesn't continue is returned to be written first.
+fn keep(
+    pending: &mut Option<PendingWrite>,
+    tid: Tid,
+    addr: u32,
+    data: &[u8],
+) -> Option<PendingWrite> {
+    match pending {
+        Some(kept) if kept.continued_by(tid, addr, data.len()) => {
+            kept.data.extend_from_slice(data);
+            kept.packets.push((addr, data.len()));
+            None
+        }
+        _ => pending.replace(PendingWrite::new(tid, addr, data)),
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Keeps a GDB write for merging if `write-coalesce` is on and the write qualifies.
+    /// Returns false if the write is to be issued right away, kept writes are flushed then.
+    pub(crate) fn coalesce_write(
+        &mut self,
+        tid: Tid,
+        addr: u32,
+        data: &[u8],
+    ) -> Result<bool, TricoreTargetError> {
+        let qualifies = self.settings.write_coalesce
+            && data.len() <= MAX_COALESCED
+            && memory_map::flash_region_in(self.arch.regions(), addr, data.len()).is_none()
+            && memory_map::csfr_cores(addr, data.len()).next().is_none();
+        if !qualifies {
+            self.flush_writes()?;
+            return Ok(false);
+        }
+
+        if let Some(displaced) = keep(&mut self.pending_write, tid, addr, data) {
+            self.write_pending(displaced)?;
+        }
+        Ok(true)
+    }
+
+    /// Writes the kept GDB writes to the device
+    pub(crate) fn flush_writes(&mut self) -> Result<(), TricoreTargetError> {
+        match self.pending_write.take() {
+            Some(pending) => self.write_pending(pending),
+            None => Ok(()),
+        }
+    }
+
+    fn write_pending(&mut self, pending: PendingWrite) -> Result<(), TricoreTargetError> {
+        if pending.packets.len() > 1 {
+            debug!(
+                "Writing {} merged writes, {} bytes at {:#010x}",
+                pending.packets.len(),
+                pending.data.len(),
+                pending.start
+            );
+            self.stats.coalesced_writes += pending.packets.len() as u64;
+        }
+        pending
+            .write_to(|tid, addr, bytes| self.write_memory(tid, addr, bytes))
+            .map_err(|failed| TricoreTargetError::MemoryAccess(failed.to_string()))
+    }
+
+    /// Flushes kept writes where the caller has no reply to report a failure in. The
+    /// failure goes to GDB's console.
+    pub(crate) fn flush_writes_noted(&mut self) {
+        if let Err(e) = self.flush_writes() {
+            self.notify(None, format_args!("{}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn tid(id: usize) -> Tid {
+        Tid::new(id).unwrap()
+    }
+
+    /// RAM of a device, recording its write transactions and failing writes that touch
+    /// `bad` bytes
+    #[derive(Default)]
+    struct Device {
+        memory: HashMap<u32, u8>,
+        transactions: Vec<(u32, Vec<u8>)>,
+        bad: Vec<u32>,
+    }
+
+    impl Device {
+        fn write(&mut self, _tid: Tid, addr: u32, bytes: &[u8]) -> Result<(), &'static str> {
+            self.transactions.push((addr, bytes.to_vec()));
+            let touched = addr..addr + bytes.len() as u32;
+            if self.bad.iter().any(|bad| touched.contains(bad)) {
+                return Err("bus error");
+            }
+            for (offset, &byte) in bytes.iter().enumerate() {
+                self.memory.insert(addr + offset as u32, byte);
+            }
+            Ok(())
+        }
+
+        fn read(&self, addr: u32, len: u32) -> Vec<u8> {
+            (addr..addr + len)
+                .map(|addr| self.memory.get(&addr).copied().unwrap_or(0))
+                .collect()
+        }
+
+        /// Flushes `pending` as the target does before a read
+        fn flush(&mut self, pending: &mut Option<PendingWrite>) -> Result<(), FailedWrite<&str>> {
+            match pending.take() {
+                Some(kept) => kept.write_to(|tid, addr, bytes| self.write(tid, addr, bytes)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn adjacent_writes_merge_into_one_transaction() {
+        let mut pending = None;
+        for (index, chunk) in [[1u8, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12]]
+            .iter()
+            .enumerate()
+        {
+            let addr = 0x7000_0000 + 4 * index as u32;
+            assert!(keep(&mut pending, tid(1), addr, chunk).is_none());
+        }
+
+        let mut device = Device::default();
+        device.flush(&mut pending).unwrap();
+        assert_eq!(
+            device.transactions,
+            [(0x7000_0000, (1..=12).collect::<Vec<u8>>())]
+        );
+    }
+
+    #[test]
+    fn writes_which_do_not_continue_displace_the_kept_one() {
+        let mut pending = None;
+        assert!(keep(&mut pending, tid(1), 0x7000_0000, &[1, 2]).is_none());
+        // A gap, another core, and a write before the kept one each start over
+        let displaced = keep(&mut pending, tid(1), 0x7000_0004, &[3]).unwrap();
+        assert_eq!((displaced.start, displaced.data), (0x7000_0000, vec![1, 2]));
+        let displaced = keep(&mut pending, tid(2), 0x7000_0005, &[4]).unwrap();
+        assert_eq!(displaced.start, 0x7000_0004);
+        let displaced = keep(&mut pending, tid(2), 0x7000_0004, &[5]).unwrap();
+        assert_eq!(displaced.start, 0x7000_0005);
+    }
+
+    #[test]
+    fn merged_writes_are_bounded() {
+        let mut pending = None;
+        let chunk = [0u8; MAX_COALESCED];
+        let mut addr = 0x7000_0000;
+        while addr < 0x7000_0000 + MAX_MERGED as u32 {
+            assert!(keep(&mut pending, tid(1), addr, &chunk).is_none());
+            addr += chunk.len() as u32;
+        }
+        let displaced = keep(&mut pending, tid(1), addr, &chunk).unwrap();
+        assert_eq!(displaced.data.len(), MAX_MERGED);
+    }
+
+    #[test]
+    fn a_read_sees_the_kept_writes() {
+        let mut device = Device::default();
+        let mut pending = None;
+        keep(&mut pending, tid(1), 0x7000_0010, &[0xaa, 0xbb]);
+        keep(&mut pending, tid(1), 0x7000_0012, &[0xcc]);
+        assert!(device.transactions.is_empty());
+
+        // The target flushes before every read
+        device.flush(&mut pending).unwrap();
+        assert_eq!(device.read(0x7000_0010, 4), [0xaa, 0xbb, 0xcc, 0]);
+        assert_eq!(device.transactions.len(), 1);
+        assert!(pending.is_none());
+    }
+
+    #[test]
+    fn a_failed_flush_names_the_packet_at_fault() {
+        let mut device = Device {
+            bad: vec![0x7000_0006],
+            ..Default::default()
+        };
+        let mut pending = None;
+        keep(&mut pending, tid(1), 0x7000_0000, &[1, 2, 3, 4]);
+        keep(&mut pending, tid(1), 0x7000_0004, &[5, 6, 7, 8]);
+        keep(&mut pending, tid(1), 0x7000_0008, &[9]);
+
+        let failed = device.flush(&mut pending).unwrap_err();
+        assert_eq!((failed.addr, failed.len), (0x7000_0004, 4));
+        assert!(failed.to_string().contains("0x70000004"));
+        // The merged write, then the packets up to the failing one
+        let starts: Vec<u32> = device.transactions.iter().map(|(addr, _)| *addr).collect();
+        assert_eq!(starts, [0x7000_0000, 0x7000_0000, 0x7000_0004]);
+        assert_eq!(device.read(0x7000_0000, 4), [1, 2, 3, 4]);
+        assert!(pending.is_none());
+    }
+}
@@ -0,0 +1,166 @@
+//! `monitor set verify-writes on`: every memory write of GDB and of the monitor write
+//! commands is read back, retried once on a mismatch and journaled.
+//!
+//! Only writes completely inside a memory region of the device table are read back.
+//! Peripheral and CSFR space may change on read or hold write-only registers, writes
+//! there are journaled as unverified. `monitor writelog` lists the journal, with a
+//! session log the entries are appended to it as well.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gdbstub::common::Tid;
+use gdbstub::outputln;
+
+use super::flash::crc32;
+use super::memory_map::{csfr_cores, find_region};
+use super::traits::TricoreTargetError;
+use super::{CpuId, MonitorOutput, TricoreTarget};
+
+/// Entries kept, the oldest are dropped beyond
+const JOURNAL_LEN: usize = 1000;
+
+/// Outcome of reading a write back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WriteCheck {
+    Verified,
+    /// The first read back differed, the repeated write matched
+    VerifiedAfterRetry,
+    /// The repeated write differed as well, or could not be read back
+    Failed,
+    /// Outside of the memory regions, not read back
+    Unverified,
+}
+
+impl fmt::Display for WriteCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WriteCheck::Verified => "verified",
+            WriteCheck::VerifiedAfterRetry => "verified after retry",
+            WriteCheck::Failed => "FAILED",
+            WriteCheck::Unverified => "unverified",
+        })
+    }
+}
+
+pub(crate) struct JournalEntry {
+    time: SystemTime,
+    core: Option<CpuId>,
+    addr: u32,
+    len: usize,
+    crc: u32,
+    check: WriteCheck,
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(
+            f,
+            "{}.{:03} {} {:#010x} len {} crc32 {:#010x} {}",
+            time.as_secs(),
+            time.subsec_millis(),
+            self.core
+                .map_or("-".to_owned(), |core| format!("{:?}", core)),
+            self.addr,
+            self.len,
+            self.crc,
+            self.check
+        )
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct WriteJournal {
+    entries: VecDeque<JournalEntry>,
+}
+
+impl WriteJournal {
+    fn push(&mut self, entry: JournalEntry) {
+        if self.entries.len() == JOURNAL_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+impl<'a> TricoreTarget<'a> {
+    /// Whether reading `[addr, addr + len)` back has no side effects
+    fn is_verifiable(&self, addr: u32, len: usize) -> bool {
+        csfr_cores(addr, len).next().is_none()
+            && find_region(self.arch.regions(), addr)
+                .is_some_and(|region| region.contains_range(addr, len as u64))
+    }
+
+    /// Whether the range reads back as `data`, a failing read counts as a mismatch
+    fn reads_back(&mut self, tid: Tid, addr: u32, data: &[u8]) -> bool {
+        let mut actual = vec![0; data.len()];
+        self.read_memory(tid, addr, &mut actual).is_ok() && actual == data
+    }
+
+    /// Reads a write back and repeats it once on a mismatch
+    fn check_write(&mut self, tid: Tid, addr: u32, data: &[u8]) -> WriteCheck {
+        if !self.is_verifiable(addr, data.len()) {
+            return WriteCheck::Unverified;
+        }
+        if self.reads_back(tid, addr, data) {
+            return WriteCheck::Verified;
+        }
+        let rewritten = self.route_memory(tid, addr).is_ok_and(|index| {
+            self.core(index)
+                .is_some_and(|core| core.write(addr as u64, data.to_vec()).is_ok())
+        });
+        if rewritten && self.reads_back(tid, addr, data) {
+            WriteCheck::VerifiedAfterRetry
+        } else {
+            WriteCheck::Failed
+        }
+    }
+
+    /// Verifies and journals a completed write, fails if it did not read back
+    pub(crate) fn verify_write(
+        &mut self,
+        tid: Tid,
+        addr: u32,
+        data: &[u8],
+    ) -> Result<(), TricoreTargetError> {
+        let check = self.check_write(tid, addr, data);
+        let entry = JournalEntry {
+            time: SystemTime::now(),
+            core: self
+                .core_index(tid)
+                .ok()
+                .and_then(|index| CpuId::try_from(index).ok()),
+            addr,
+            len: data.len(),
+            crc: crc32(data),
+            check,
+        };
+        if let Some(log) = &mut self.session_log {
+            log.line(entry.core, format_args!("write {}", entry));
+        }
+        self.write_journal.push(entry);
+
+        if check == WriteCheck::Failed {
+            return Err(TricoreTargetError::MemoryAccess(format!(
+                "write at {:#010x} does not read back",
+                addr
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn print_write_journal(&self, out: &mut MonitorOutput<'_>) {
+        if self.write_journal.entries.is_empty() {
+            outputln!(
+                out,
+                "No writes journaled, see `monitor set verify-writes on`"
+            );
+            return;
+        }
+        for entry in &self.write_journal.entries {
+            outputln!(out, "{}", entry);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! GDB stub for TriCore devices attached through DAS.
+//!
+//! The `tricore-gdb-das` binary is a thin command line frontend over this library, other
+//! tools embed the stub directly: connect with [Device::new] or [Device::connect], attach
+//! a [TricoreTarget] to the device, wrap the client's socket in a [MonitoredConnection]
+//! and hand both to [serve]. See `examples/embedded_stub.rs`.
+
+// pub mod backtrace;
+pub mod connection;
+pub mod gdb;
+pub mod metrics;
+mod observer;
+pub mod session;
+pub mod session_id;
+pub mod startup;
+
+pub use connection::{MonitoredConnection, SessionConnection, Transport};
+pub use gdb::{
+    BusyReason, Device, FlashOnlyError, FlashOptions, MonitorCommand, MonitorOutput, Settings,
+    TricoreTarget, TricoreTargetError,
+};
+pub use session::{serve, Observers, SessionError, SharedTarget};
+pub use session_id::SessionId;
+pub use startup::StartupError;
+
+pub(crate) type DynResult<T> = Result<T, Box<dyn std::error::Error>>;
@@ -0,0 +1,92 @@
+//! Logger of the command line frontend.
+//!
+//! Records go to stderr, keeping stdout free, and with `--log-file` to a file as well,
+//! both in the same format: RFC3339 timestamp, level, module and, where the stub handles
+//! a single core, that core. `RUST_LOG` selects the records as for env_logger, `info`
+//! without it. `TRICORE_LOG_FORMAT=plain` leaves out the timestamp, for sinks stamping
+//! the lines themselves.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use env_logger::filter::Filter;
+use log::{Log, Metadata, Record};
+use tricore_gdb_das::gdb;
+
+struct Logger {
+    filter: Filter,
+    timestamps: bool,
+    file: Option<Mutex<File>>,
+}
+
+impl Logger {
+    fn format(&self, record: &Record) -> String {
+        let mut line = String::new();
+        if self.timestamps {
+            _ = write!(
+                line,
+                "{} ",
+                humantime::format_rfc3339_millis(SystemTime::now())
+            );
+        }
+        _ = write!(line, "{:<5} {}", record.level(), record.target());
+        if let Some(cpu) = gdb::current_core() {
+            _ = write!(line, " [{:?}]", cpu);
+        }
+        _ = writeln!(line, ": {}", record.args());
+        line
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let line = self.format(record);
+        _ = io::stderr().write_all(line.as_bytes());
+        if let Some(file) = &self.file {
+            // A record failing to reach the file is not worth ending the session for
+            if let Ok(mut file) = file.lock() {
+                _ = file.write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                _ = file.flush();
+            }
+        }
+    }
+}
+
+/// Installs the logger, appending to `log_file` if given
+pub fn init(log_file: Option<&Path>) -> io::Result<()> {
+    let spec = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned());
+    let filter = env_logger::filter::Builder::new().parse(&spec).build();
+    let timestamps = std::env::var("TRICORE_LOG_FORMAT").map_or(true, |format| format != "plain");
+    let file = match log_file {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        None => None,
+    };
+
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(Logger {
+        filter,
+        timestamps,
+        file,
+    }))
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
@@ -1,103 +1,67 @@
 //! tricore-gdb client
 use anyhow::{anyhow, Error};
 use clap::{crate_version, value_parser};
-use clap::{Arg, Command};
-use gdb::{tricore, StaticTricoreTarget};
-use gdbstub::common::Signal;
-use gdbstub::conn::{Connection, ConnectionExt};
-use gdbstub::stub::{run_blocking, DisconnectReason, GdbStub, MultiThreadStopReason};
-use gdbstub::target::Target;
+use clap::{Arg, ArgAction, Command};
+use gdbstub::stub::DisconnectReason;
+use std::cell::RefCell;
 use std::net::{TcpListener, TcpStream};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-// pub mod backtrace;
-mod gdb;
-use crate::gdb::TricoreTarget;
+use log::{error, info, warn};
+
+use tricore_gdb_das::connection::MonitoredConnection;
+use tricore_gdb_das::gdb::events::{EventBroadcaster, TargetEvent};
+use tricore_gdb_das::gdb::{
+    self, BootArgsBlock, BootArgsFormat, ConnectMode, CpuId, DeviceChoice, DisconnectAction,
+    ElfSpec, FlashOptions, IdBlock, KillAction, OtherCores,
+};
+use tricore_gdb_das::gdb::{Device, PeripheralMap, Settings, TricoreTarget};
+use tricore_gdb_das::metrics::{self, Metrics};
+use tricore_gdb_das::session::{self, Observers, SessionError};
+use tricore_gdb_das::session_id::SessionId;
+use tricore_gdb_das::startup;
+
+mod logger;
 
 type DynResult<T> = Result<T, Box<dyn std::error::Error>>;
 
-fn wait_for_tcp(port: u16, tcp_ip: &String) -> DynResult<TcpStream> {
+fn listen_tcp(port: u16, tcp_ip: &String) -> DynResult<TcpListener> {
     let sockaddr = format!("{}:{}", tcp_ip, port);
-    println!("Waiting for a GDB connection on {:?}...", sockaddr);
+    info!("Listening for GDB on {:?}", sockaddr);
+    Ok(TcpListener::bind(sockaddr)?)
+}
 
-    let sock = TcpListener::bind(sockaddr)?;
+/// Accepts the GDB client. A client which connected while the device was initialized
+/// waits in the backlog of the listener, its packets are read from here on.
+fn wait_for_tcp(sock: &TcpListener) -> DynResult<TcpStream> {
+    info!("Waiting for a GDB connection...");
     let (stream, addr) = sock.accept()?;
-    println!("Debugger connected from {}", addr);
-
-    stream.set_nodelay(true).expect("set_nodelay call failed");
-
+    info!("Debugger connected from {}", addr);
     Ok(stream)
 }
 
-enum TricoreGdbEventLoop {}
-
-impl run_blocking::BlockingEventLoop for TricoreGdbEventLoop {
-    type Target = StaticTricoreTarget;
-    type Connection = Box<dyn ConnectionExt<Error = std::io::Error>>;
-    type StopReason = MultiThreadStopReason<u32>;
-
-    #[allow(clippy::type_complexity)]
-    fn wait_for_stop_reason(
-        target: &mut StaticTricoreTarget,
-        conn: &mut Self::Connection,
-    ) -> Result<
-        run_blocking::Event<MultiThreadStopReason<u32>>,
-        run_blocking::WaitForStopReasonError<
-            <Self::Target as Target>::Error,
-            <Self::Connection as Connection>::Error,
-        >,
-    > {
-        let poll_incoming_data = || {
-            // gdbstub takes ownership of the underlying connection, so the `borrow_conn`
-            // method is used to borrow the underlying connection back from the stub to
-            // check for incoming data.
-            conn.peek().map(|b| b.is_some()).unwrap_or(true)
-        };
-
-        match target.run(poll_incoming_data) {
-            tricore::RunEvent::IncomingData => {
-                let byte = conn
-                    .read()
-                    .map_err(run_blocking::WaitForStopReasonError::Connection)?;
-                Ok(run_blocking::Event::IncomingData(byte))
-            }
-            tricore::RunEvent::Event(event, cpuid) => {
-                use gdbstub::target::ext::breakpoints::WatchKind;
-
-                let tid = gdb::cpuid_to_tid(cpuid);
-
-                let stop_reason = match event {
-                    tricore::Event::DoneStep => MultiThreadStopReason::DoneStep,
-                    tricore::Event::Halted => MultiThreadStopReason::Terminated(Signal::SIGSTOP),
-                    tricore::Event::Break => MultiThreadStopReason::SwBreak(tid),
-                    tricore::Event::WatchWrite(addr) => MultiThreadStopReason::Watch {
-                        tid,
-                        kind: WatchKind::Write,
-                        addr,
-                    },
-                    tricore::Event::WatchRead(addr) => MultiThreadStopReason::Watch {
-                        tid,
-                        kind: WatchKind::Read,
-                        addr,
-                    },
-                };
-
-                Ok(run_blocking::Event::TargetStopped(stop_reason))
-            }
+/// Flag set by the first Ctrl-C, a second one ends the process
+fn ctrl_c_flag() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    {
+        use signal_hook::consts::SIGINT;
+        use signal_hook::flag;
+        // The check for a set flag has to be registered first, to exit on the second press
+        if let Err(e) = flag::register_conditional_shutdown(SIGINT, 130, Arc::clone(&flag))
+            .and_then(|_| flag::register(SIGINT, Arc::clone(&flag)))
+        {
+            warn!("Cannot install the Ctrl-C handler: {}", e);
         }
     }
-
-    fn on_interrupt(
-        target: &mut StaticTricoreTarget,
-    ) -> Result<Option<MultiThreadStopReason<u32>>, <StaticTricoreTarget as Target>::Error> {
-        // halt each core
-        target.halt();
-        Ok(Some(MultiThreadStopReason::Signal(Signal::SIGINT)))
-    }
+    flag
 }
 
 fn main() -> Result<(), Error> {
-    pretty_env_logger::init();
     let about = "GDB client interface via miniwiggler".to_string();
 
     let matches = Command::new("tricore-gdb-das")
@@ -106,9 +70,19 @@ fn main() -> Result<(), Error> {
         .arg(
             Arg::new("elf_file")
                 .long("elf_file")
-                .value_name("FILE")
+                .value_name("[cpuN:]FILE")
+                .help("Elf to program, repeat with cpuN: for a separate program per core")
                 .required(false)
-                .value_parser(value_parser!(PathBuf)),
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(ElfSpec)),
+        )
+        .arg(
+            Arg::new("symbols")
+                .long("symbols")
+                .value_name("[cpuN:]FILE")
+                .help("Debug elf to take the symbols from instead of the programmed elf")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(ElfSpec)),
         )
         .arg(
             Arg::new("tcp_ip")
@@ -126,61 +100,572 @@ fn main() -> Result<(), Error> {
                 .value_parser(value_parser!(u16))
                 .default_value("9001"),
         )
+        .arg(
+            Arg::new("event_socket")
+                .long("event-socket")
+                .value_name("PORT")
+                .help("Publish core and flash events as JSON lines on this port")
+                .required(false)
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("keepalive")
+                .long("keepalive")
+                .value_name("SECONDS")
+                .help("Idle time before TCP keepalive probes are sent to the GDB client")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("idle_probe")
+                .long("idle-probe")
+                .value_name("SECONDS")
+                .help("Idle time after which the GDB connection is checked while the target runs")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("heartbeat")
+                .long("heartbeat")
+                .value_name("SECONDS")
+                .help("Interval of the liveness check of the halted device, 0 disables it")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("stm_freq")
+                .long("stm-freq")
+                .value_name("HZ")
+                .help("STM frequency in Hz, measured after the first resume by default")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("pause_file")
+                .long("pause-file")
+                .value_name("PATH")
+                .help("Halt the target when this file is created or touched (SIGUSR1 works too)")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("semihost_root")
+                .long("semihost-root")
+                .value_name("DIR")
+                .help("Directory the target may read and write files in through semihosting")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("session_log")
+                .long("session-log")
+                .value_name("PATH")
+                .help("Append monitor output and target events to this file")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("session_log_size")
+                .long("session-log-size")
+                .value_name("BYTES")
+                .help("Size at which the session log is moved to <PATH>.old")
+                .default_value("10485760")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("log_file")
+                .long("log-file")
+                .value_name("PATH")
+                .help("Append the log records to this file as well")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("svd")
+                .long("svd")
+                .value_name("FILE")
+                .help("CMSIS-SVD description of the peripherals for `monitor sfr`")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("status_file")
+                .long("status-file")
+                .value_name("PATH")
+                .help("Rewrite a JSON status of the stub at this path every 10 seconds")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("observers")
+                .long("observers")
+                .value_name("N")
+                .help("Accept up to N additional read-only GDB clients on the GDB port")
+                .value_parser(value_parser!(usize))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("allow_ucb")
+                .long("allow-ucb")
+                .help("Program UCB/BMHD content of the elf, requires valid boot mode headers")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("id_block")
+                .long("id-block")
+                .value_name("ADDR:LEN")
+                .help("Build identification block compared with the device before flashing")
+                .value_parser(IdBlock::parse),
+        )
+        .arg(
+            Arg::new("force_project_change")
+                .long("force-project-change")
+                .help("Flash the elf even if the device holds another project")
+                .requires("id_block")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("kill_action")
+                .long("kill-action")
+                .value_name("ACTION")
+                .help("What a GDB kill does to the device: none, reset or power-cycle")
+                .value_parser(KillAction::parse)
+                .default_value("none"),
+        )
+        .arg(
+            Arg::new("device_serial")
+                .long("device-serial")
+                .value_name("SERIAL")
+                .help(
+                    "Connect to the probe with this serial instead of the first device found, \
+                     the scan lists the serials",
+                )
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("on_disconnect")
+                .long("on-disconnect")
+                .value_name("ACTION")
+                .help(
+                    "What is done once GDB disconnects or its connection is lost: run removes \
+                     all triggers and runs the target, halt halts it, detach removes what the \
+                     session set up",
+                )
+                .value_parser(DisconnectAction::parse)
+                .default_value("detach"),
+        )
+        .arg(
+            Arg::new("single_core")
+                .long("single-core")
+                .value_name("N")
+                .help(
+                    "Expose only core N to GDB, as a single thread, for GDB builds which \
+                     mishandle multi-thread targets",
+                )
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("other_cores")
+                .long("other-cores")
+                .value_name("ACTION")
+                .help("What --single-core does with the other cores: hold halts them, run lets them run")
+                .requires("single_core")
+                .value_parser(OtherCores::parse)
+                .default_value("hold"),
+        )
+        .arg(
+            Arg::new("boot_args")
+                .long("boot-args")
+                .value_name("ARGS")
+                .help(
+                    "Arguments for the firmware, e.g. \"key=value key2=value2\", written to \
+                     the --boot-args-addr block after every reset",
+                )
+                .requires("boot_args_addr")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("boot_args_addr")
+                .long("boot-args-addr")
+                .value_name("ADDR:LEN")
+                .help("RAM block the firmware reads its boot arguments from")
+                .value_parser(BootArgsBlock::parse),
+        )
+        .arg(
+            Arg::new("boot_args_format")
+                .long("boot-args-format")
+                .value_name("FORMAT")
+                .help("Layout of the boot arguments: blob, the string with its length, or argv")
+                .requires("boot_args_addr")
+                .value_parser(BootArgsFormat::parse)
+                .default_value("blob"),
+        )
+        .arg(
+            Arg::new("halt_on_connect")
+                .long("halt-on-connect")
+                .help(
+                    "Halt the cores where they are instead of resetting them, to inspect a \
+                     running target. The cores halted are resumed when GDB detaches",
+                )
+                .conflicts_with_all(["elf_file", "run_after_load"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("init_commands")
+                .long("init-commands")
+                .value_name("FILE")
+                .help(
+                    "Run the monitor commands in FILE, one per line, before waiting for GDB. \
+                     A failing command stops startup unless the file starts with a -k line",
+                )
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("init_commands_after_connect")
+                .long("init-commands-after-connect")
+                .help("Run the --init-commands once GDB connected and the target was reset")
+                .requires("init_commands")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("log_reset_cause")
+                .long("log-reset-cause")
+                .help(
+                    "Log the reset cause of the device right after connecting, before it is reset",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("listen_early")
+                .long("listen-early")
+                .help(
+                    "Open the GDB port before connecting to and programming the device, for \
+                     IDEs that give up on a port that opens late",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("break_at")
+                .long("break-at")
+                .value_name("SYMBOL|ADDRESS")
+                .help(
+                    "Reset and run the boot core to this function or address before waiting \
+                     for GDB, e.g. main. Ctrl-C stops the run, the target is halted either way",
+                )
+                .conflicts_with_all(["halt_on_connect", "run_after_load", "flash_only"])
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("break_at_timeout")
+                .long("break-at-timeout")
+                .value_name("SECONDS")
+                .help("Time the boot core has to reach the --break-at location")
+                .requires("break_at")
+                .value_parser(value_parser!(u64))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("run_after_load")
+                .long("run-after-load")
+                .help(
+                    "Reset and run the target before waiting for GDB. The target keeps running \
+                     when GDB attaches, a continue in GDB picks it up and Ctrl-C halts it",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("flash_only")
+                .long("flash-only")
+                .help(
+                    "Program and verify the elf, optionally run it, then exit without \
+                     starting the GDB server",
+                )
+                .requires("elf_file")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("check_elf_only")
+                .long("check-elf-only")
+                .help("Validate the elf against the device flash layout and exit")
+                .requires("elf_file")
+                .action(ArgAction::SetTrue),
+        )
         .get_matches();
 
-    let file_path = matches.get_one::<PathBuf>("elf_file");
+    logger::init(matches.get_one::<PathBuf>("log_file").map(PathBuf::as_path))
+        .map_err(|e| anyhow!("Unable to open the log file: {}", e))?;
+
+    let session_id = SessionId::generate();
+    info!(
+        "tricore-gdb-das {} session {}",
+        crate_version!(),
+        session_id
+    );
+
+    let elf_specs: Vec<ElfSpec> = matches
+        .get_many::<ElfSpec>("elf_file")
+        .map(|specs| specs.cloned().collect())
+        .unwrap_or_default();
+    let symbol_specs: Vec<ElfSpec> = matches
+        .get_many::<ElfSpec>("symbols")
+        .map(|specs| specs.cloned().collect())
+        .unwrap_or_default();
+    let flash_options = FlashOptions {
+        allow_ucb: matches.get_flag("allow_ucb"),
+        id_block: matches.get_one::<IdBlock>("id_block").copied(),
+        force_project_change: matches.get_flag("force_project_change"),
+    };
+
+    // Fail fast on a bad elf, before the probe is claimed
+    gdb::check_assignments(&elf_specs).map_err(|e| anyhow!(e))?;
+    gdb::check_assignments(&symbol_specs).map_err(|e| anyhow!(e))?;
+    for spec in elf_specs.iter().chain(&symbol_specs) {
+        if let Err(e) = startup::check_elf_path(&spec.path) {
+            error!("{}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+
+    if matches.get_flag("check_elf_only") {
+        let paths: Vec<&Path> = elf_specs.iter().map(|spec| spec.path.as_path()).collect();
+        gdb::check_elf_files(&paths, &flash_options)?;
+        info!("{:?} fits the device flash layout", paths);
+        return Ok(());
+    }
+
+    let peripherals = match matches.get_one::<PathBuf>("svd") {
+        Some(path) => {
+            let peripherals = PeripheralMap::load(path)?;
+            info!("Loaded {} peripheral registers", peripherals.len());
+            Some(peripherals)
+        }
+        None => None,
+    };
+
+    let tcp_ip = matches.get_one::<String>("tcp_ip").unwrap();
+
+    let mut settings = Settings::default();
+    if let Some(secs) = matches.get_one::<u64>("keepalive") {
+        settings.keepalive = Duration::from_secs(*secs);
+    }
+    if let Some(secs) = matches.get_one::<u64>("idle_probe") {
+        settings.idle_probe = Duration::from_secs(*secs);
+    }
+    if let Some(secs) = matches.get_one::<u64>("heartbeat") {
+        settings.heartbeat = Duration::from_secs(*secs);
+    }
+    settings.stm_freq = matches.get_one::<u32>("stm_freq").copied();
+    settings.pause_file = matches.get_one::<PathBuf>("pause_file").cloned();
+    settings.semihost_root = matches.get_one::<PathBuf>("semihost_root").cloned();
+    settings.session_log = matches.get_one::<PathBuf>("session_log").cloned();
+    settings.session_log_size = *matches.get_one::<u64>("session_log_size").unwrap();
+    settings.session_id = Some(session_id.to_string());
+    settings.kill_action = *matches.get_one::<KillAction>("kill_action").unwrap();
+    settings.on_disconnect = *matches
+        .get_one::<DisconnectAction>("on_disconnect")
+        .unwrap();
+    settings.log_reset_cause = matches.get_flag("log_reset_cause");
+    if matches.get_flag("halt_on_connect") {
+        settings.connect_mode = ConnectMode::Halt;
+    }
+    if let Some(&index) = matches.get_one::<usize>("single_core") {
+        settings.single_core = Some(
+            CpuId::try_from(index).map_err(|_| anyhow!("--single-core {}: no such core", index))?,
+        );
+    }
+    settings.other_cores = *matches.get_one::<OtherCores>("other_cores").unwrap();
+    settings.boot_args = matches.get_one::<String>("boot_args").cloned();
+    settings.boot_args_block = matches.get_one::<BootArgsBlock>("boot_args_addr").copied();
+    settings.boot_args_format = *matches
+        .get_one::<BootArgsFormat>("boot_args_format")
+        .unwrap();
+    if let (Some(args), Some(block)) = (&settings.boot_args, settings.boot_args_block) {
+        gdb::check_boot_args(args, settings.boot_args_format, block)
+            .map_err(|e| anyhow!("--boot-args: {}", e))?;
+    }
+
+    let events = match matches.get_one::<u16>("event_socket") {
+        Some(port) => Some(
+            EventBroadcaster::bind(&format!("{}:{}", tcp_ip, port))
+                .map_err(|e| anyhow!("Unable to open event socket on port {}: {}", port, e))?,
+        ),
+        None => None,
+    };
+    if let Some(events) = &events {
+        events.set_session(session_id.to_string());
+    }
+
+    let device = match matches.get_one::<String>("device_serial") {
+        Some(serial) => DeviceChoice::Serial(serial.clone()),
+        None => DeviceChoice::Index(0),
+    };
+
+    if matches.get_flag("flash_only") {
+        let run_after_load = matches.get_flag("run_after_load");
+        if let Err(e) = gdb::flash_only(
+            &device,
+            &elf_specs,
+            &flash_options,
+            run_after_load,
+            events.as_ref(),
+        ) {
+            error!("{}", e);
+            std::process::exit(e.exit_code());
+        }
+        return Ok(());
+    }
 
-    let mut target = match TricoreTarget::new(file_path) {
-        Ok(target) => target,
-        Err(_) => {
-            return Err(anyhow!(
-                "Unable to attach to tricore target, Is the board connected"
-            ))
+    let tcp_port = *matches.get_one::<u16>("tcp_port").unwrap();
+    let early_listener = if matches.get_flag("listen_early") {
+        let listener = listen_tcp(tcp_port, tcp_ip)
+            .map_err(|e| anyhow!("Unable to listen on {}:{}: {}", tcp_ip, tcp_port, e))?;
+        if let Some(events) = &events {
+            events.publish(TargetEvent::Listening { port: tcp_port });
         }
+        info!("Initializing the device, a GDB client connecting meanwhile waits until it is ready");
+        Some(listener)
+    } else {
+        None
     };
 
-    let connection: Box<dyn ConnectionExt<Error = std::io::Error>> = {
-        let tcp_port = matches.get_one::<u16>("tcp_port").unwrap();
-        let tcp_ip = matches.get_one::<String>("tcp_ip").unwrap();
-        Box::new(match wait_for_tcp(*tcp_port, tcp_ip) {
-            Ok(tc) => tc,
-            Err(_) => return Err(anyhow!("Unable to connect to {}:{:?}", tcp_ip, *tcp_port)),
-        })
+    let attach_error = |e: Box<dyn std::error::Error>| {
+        anyhow!(
+            "Unable to attach to tricore target, Is the board connected: {}",
+            e
+        )
     };
+    // Declared before the target, which borrows the cores from it
+    let mut device = Device::connect(device, &elf_specs, &flash_options, settings, events)
+        .map_err(attach_error)?;
+    let mut target = TricoreTarget::attach(&mut device).map_err(attach_error)?;
+
+    if target.settings().kill_action == KillAction::PowerCycle
+        && !target.das_capabilities().power_control
+    {
+        return Err(anyhow!(
+            "--kill-action power-cycle needs power control, which {} does not offer",
+            target.das_capabilities().server
+        ));
+    }
 
-    target.restart();
+    for spec in &symbol_specs {
+        target
+            .load_symbols(spec)
+            .map_err(|e| anyhow!("Unable to load symbols {:?}: {:#}", spec.path, e))?;
+    }
 
-    let gdb = GdbStub::new(connection);
+    if let Some(peripherals) = peripherals {
+        target.set_peripherals(peripherals);
+    }
+
+    let metrics = Arc::new(Metrics::new(session_id));
+    target.attach_metrics(Arc::clone(&metrics));
+    if let Some(path) = matches.get_one::<PathBuf>("status_file") {
+        metrics::spawn_status_writer(Arc::clone(&metrics), path.clone())
+            .map_err(|e| anyhow!("Unable to start the status file writer: {}", e))?;
+    }
 
-    match gdb.run_blocking::<TricoreGdbEventLoop>(&mut target) {
+    let run_after_load = matches.get_flag("run_after_load");
+    if run_after_load {
+        target.restart();
+        target.run_all();
+        info!("Target running");
+    }
+
+    let break_at = matches.get_one::<String>("break_at");
+    if let Some(location) = break_at {
+        let timeout = Duration::from_secs(*matches.get_one::<u64>("break_at_timeout").unwrap());
+        let interrupted = ctrl_c_flag();
+        target
+            .run_to(location, timeout, || interrupted.load(Ordering::Relaxed))
+            .map_err(|e| anyhow!("Unable to run to {}: {}", location, e))?;
+        // From here on a single Ctrl-C ends the process again
+        interrupted.store(true, Ordering::Relaxed);
+    }
+
+    let init_commands = matches.get_one::<PathBuf>("init_commands");
+    let init_after_connect = matches.get_flag("init_commands_after_connect");
+    if let Some(path) = init_commands.filter(|_| !init_after_connect) {
+        target
+            .run_init_commands(path)
+            .map_err(|e| anyhow!("Init commands failed: {}", e))?;
+    }
+
+    let (connection, listener) = {
+        let listener = match early_listener {
+            Some(listener) => listener,
+            None => listen_tcp(tcp_port, tcp_ip)
+                .map_err(|e| anyhow!("Unable to listen on {}:{}: {}", tcp_ip, tcp_port, e))?,
+        };
+        let stream = match wait_for_tcp(&listener) {
+            Ok(stream) => stream,
+            Err(_) => return Err(anyhow!("Unable to connect to {}:{:?}", tcp_ip, tcp_port)),
+        };
+        let connection = MonitoredConnection::new(stream, target.settings().keepalive)
+            .map_err(|e| anyhow!("Unable to configure GDB connection: {}", e))?
+            .with_metrics(Arc::clone(&metrics));
+        let connection_id = metrics.record_session();
+        target.connection_accepted(&connection_id);
+        metrics.set_client_attached(true);
+        (connection, listener)
+    };
+
+    let observer_limit = *matches.get_one::<usize>("observers").unwrap();
+    let mut observers = Observers::new(listener, observer_limit, Arc::clone(&metrics))
+        .map_err(|e| anyhow!("Unable to accept observers: {}", e))?;
+
+    if !run_after_load && target.settings().connect_mode == ConnectMode::Reset && break_at.is_none()
+    {
+        target.restart();
+    }
+    if let Some(path) = init_commands.filter(|_| init_after_connect) {
+        target
+            .run_init_commands(path)
+            .map_err(|e| anyhow!("Init commands failed: {}", e))?;
+    }
+
+    let target = Rc::new(RefCell::new(target));
+
+    let served = session::serve(&target, connection, &mut observers);
+    metrics.set_client_attached(false);
+
+    let exit_requested = target.borrow().exit_requested();
+    match served {
         Ok(disconnect_reason) => match disconnect_reason {
+            DisconnectReason::TargetExited(code) if exit_requested.is_some() => {
+                info!("GDB asked the stub to exit with code {}", code);
+                target.borrow_mut().apply_disconnect_action();
+            }
             DisconnectReason::Disconnect => {
-                println!("GDB client has disconnected. Running to completion...");
+                info!("GDB client has disconnected");
+                target.borrow_mut().apply_disconnect_action();
             }
             DisconnectReason::TargetExited(code) => {
-                println!("Target exited with code {}!", code)
+                info!("Target exited with code {}!", code)
             }
             DisconnectReason::TargetTerminated(sig) => {
-                println!("Target terminated with signal {}!", sig)
+                info!("Target terminated with signal {}!", sig)
             }
-            DisconnectReason::Kill => println!("GDB sent a kill command!"),
+            DisconnectReason::Kill => info!("GDB sent a kill command!"),
         },
-        Err(e) => {
+        Err(SessionError::Connection(e)) => {
+            warn!("connection error: {}", e);
+            target.borrow_mut().apply_disconnect_action();
+        }
+        Err(SessionError::Stub(e)) => {
             if e.is_target_error() {
-                println!(
+                error!(
                     "target encountered a fatal error: {}",
                     e.into_target_error().unwrap()
                 )
             } else if e.is_connection_error() {
                 let (e, kind) = e.into_connection_error().unwrap();
-                println!("connection error: {:?} - {}", kind, e,)
+                warn!("connection error: {:?} - {}", kind, e,);
+                target.borrow_mut().apply_disconnect_action();
             } else {
-                println!("gdbstub encountered a fatal error: {}", e)
+                error!("gdbstub encountered a fatal error: {}", e)
             }
         }
     }
 
-    println!("Program completed");
+    info!("Program completed");
 
+    if let Some(code) = exit_requested {
+        // Release the probe before the process ends
+        drop(observers);
+        drop(target);
+        drop(device);
+        std::process::exit(code.into());
+    }
     Ok(())
 }
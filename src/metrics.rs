@@ -0,0 +1,201 @@
+//! Service level metrics of the stub for fleet monitoring, shown by `monitor status` and
+//! optionally written to a JSON status file.
+//!
+//! All counters are atomics, so the connection wrapper can update them per byte and the
+//! status file writer thread can read them without ever blocking the event loop.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use crate::session_id::SessionId;
+
+/// Interval in which the status file is rewritten
+const STATUS_FILE_INTERVAL: Duration = Duration::from_secs(10);
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+pub struct Metrics {
+    started: Instant,
+    /// ID of this process, accepted connections are numbered under it
+    session_id: SessionId,
+    /// GDB sessions accepted, owner and observers
+    sessions: AtomicU64,
+    /// Whether the owning GDB client is connected
+    client_attached: AtomicBool,
+    /// Unix time of the last byte received from any client, 0 before the first one
+    last_activity: AtomicU64,
+    device_connected: AtomicBool,
+    /// Connections to the device made after the initial one
+    reconnects: AtomicU64,
+    /// Device and DAS server description, known once the device is connected
+    device: OnceLock<String>,
+    /// Interrupted or would-block errors of client connections which were retried
+    connection_retries: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new(SessionId::generate())
+    }
+}
+
+impl Metrics {
+    pub fn new(session_id: SessionId) -> Self {
+        Metrics {
+            started: Instant::now(),
+            session_id,
+            sessions: AtomicU64::new(0),
+            client_attached: AtomicBool::new(false),
+            last_activity: AtomicU64::new(0),
+            device_connected: AtomicBool::new(false),
+            reconnects: AtomicU64::new(0),
+            device: OnceLock::new(),
+            connection_retries: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts an accepted GDB connection, returns its ID
+    pub fn record_session(&self) -> String {
+        let number = self.sessions.fetch_add(1, Ordering::Relaxed) + 1;
+        self.session_id.connection(number)
+    }
+
+    pub fn session_id(&self) -> &SessionId {
+        &self.session_id
+    }
+
+    /// ID of the connection accepted last, `None` before the first one
+    fn last_connection(&self) -> Option<String> {
+        match self.sessions.load(Ordering::Relaxed) {
+            0 => None,
+            number => Some(self.session_id.connection(number)),
+        }
+    }
+
+    pub fn set_client_attached(&self, attached: bool) {
+        self.client_attached.store(attached, Ordering::Relaxed);
+    }
+
+    pub fn record_activity(&self) {
+        self.last_activity
+            .store(unix_secs(SystemTime::now()), Ordering::Relaxed);
+    }
+
+    pub fn record_connection_retry(&self) {
+        self.connection_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a device connection, every connection after the first counts as reconnect
+    pub fn record_device_connected(&self, device: String) {
+        if self.device_connected.swap(true, Ordering::Relaxed) || self.device.set(device).is_err() {
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the device no longer answering, the next connection counts as reconnect
+    pub fn set_device_lost(&self) {
+        self.device_connected.store(false, Ordering::Relaxed);
+    }
+
+    /// All metrics with their current value, in display order
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        let last_activity = match self.last_activity.load(Ordering::Relaxed) {
+            0 => "never".to_owned(),
+            secs => format!("{}s ago", unix_secs(SystemTime::now()).saturating_sub(secs)),
+        };
+        vec![
+            ("session id", self.session_id.to_string()),
+            ("uptime", format!("{}s", self.started.elapsed().as_secs())),
+            (
+                "sessions",
+                self.sessions.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "client attached",
+                self.client_attached.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "last connection",
+                self.last_connection().unwrap_or("none".to_owned()),
+            ),
+            ("last activity", last_activity),
+            (
+                "device connected",
+                self.device_connected.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "device reconnects",
+                self.reconnects.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "device",
+                self.device.get().cloned().unwrap_or("unknown".to_owned()),
+            ),
+            (
+                "connection retries",
+                self.connection_retries.load(Ordering::Relaxed).to_string(),
+            ),
+        ]
+    }
+
+    fn to_json(&self) -> String {
+        let last_activity = match self.last_activity.load(Ordering::Relaxed) {
+            0 => "null".to_owned(),
+            secs => secs.to_string(),
+        };
+        let device = match self.device.get() {
+            Some(device) => format!("{:?}", device),
+            None => "null".to_owned(),
+        };
+        let last_connection = match self.last_connection() {
+            Some(connection) => format!("\"{}\"", connection),
+            None => "null".to_owned(),
+        };
+        format!(
+            r#"{{"version":"{}","session":"{}","uptime_secs":{},"sessions":{},"last_connection":{},"client_attached":{},"last_activity":{},"device_connected":{},"device_reconnects":{},"device":{},"connection_retries":{}}}"#,
+            env!("CARGO_PKG_VERSION"),
+            self.session_id,
+            self.started.elapsed().as_secs(),
+            self.sessions.load(Ordering::Relaxed),
+            last_connection,
+            self.client_attached.load(Ordering::Relaxed),
+            last_activity,
+            self.device_connected.load(Ordering::Relaxed),
+            self.reconnects.load(Ordering::Relaxed),
+            device,
+            self.connection_retries.load(Ordering::Relaxed)
+        )
+    }
+
+    /// Writes the status next to `path` first and renames it over, so readers never
+    /// see a partially written file
+    fn write_status_file(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, self.to_json() + "\n")?;
+        std::fs::rename(&temporary, path)
+    }
+}
+
+/// Rewrites the status file every [STATUS_FILE_INTERVAL] from a background thread
+pub fn spawn_status_writer(metrics: Arc<Metrics>, path: PathBuf) -> io::Result<()> {
+    thread::Builder::new()
+        .name("status-file".to_owned())
+        .spawn(move || loop {
+            if let Err(e) = metrics.write_status_file(&path) {
+                warn!("Cannot write status file {}: {}", path.display(), e);
+            }
+            thread::sleep(STATUS_FILE_INTERVAL);
+        })
+        .map(|_| ())
+}
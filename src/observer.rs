@@ -0,0 +1,161 @@
+//! Read-only view of the target for GDB clients observing the owner's session.
+//!
+//! Observers may read registers and memory and list threads. Everything changing the
+//! target state is not offered to them: gdbstub answers step and breakpoint requests as
+//! unsupported, and memory/register writes as well as mutating monitor commands fail.
+//! `continue` leaves the target alone and waits for the owner's next stop, which is
+//! reported to every waiting observer.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gdbstub::common::{Signal, Tid};
+use gdbstub::outputln;
+use gdbstub::target::ext::base::multithread::{
+    MultiThreadBase, MultiThreadResume, MultiThreadResumeOps,
+};
+use gdbstub::target::ext::monitor_cmd::{ConsoleOutput, MonitorCmd, MonitorCmdOps};
+use gdbstub::target::ext::target_description_xml_override::{
+    TargetDescriptionXmlOverride, TargetDescriptionXmlOverrideOps,
+};
+use gdbstub::target::ext::thread_extra_info::{ThreadExtraInfo, ThreadExtraInfoOps};
+use gdbstub::target::{self, Target, TargetError, TargetResult};
+use log::debug;
+
+use crate::gdb::{read_target_xml, CoreRegisters, TricoreArch, TricoreTarget};
+
+/// Target handle of an observer connection
+pub struct ObserverTarget<'a> {
+    shared: Rc<RefCell<TricoreTarget<'a>>>,
+}
+
+impl<'a> ObserverTarget<'a> {
+    pub fn new(shared: Rc<RefCell<TricoreTarget<'a>>>) -> Self {
+        ObserverTarget { shared }
+    }
+}
+
+impl<'a> Target for ObserverTarget<'a> {
+    type Arch = TricoreArch;
+    type Error = &'static str;
+
+    #[inline(always)]
+    fn base_ops(&mut self) -> target::ext::base::BaseOps<'_, Self::Arch, Self::Error> {
+        target::ext::base::BaseOps::MultiThread(self)
+    }
+
+    #[inline(always)]
+    fn support_monitor_cmd(&mut self) -> Option<MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_target_description_xml_override(
+        &mut self,
+    ) -> Option<TargetDescriptionXmlOverrideOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl<'a> TargetDescriptionXmlOverride for ObserverTarget<'a> {
+    fn target_description_xml(
+        &self,
+        _annex: &[u8],
+        offset: u64,
+        length: usize,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        Ok(read_target_xml(offset, length, buf))
+    }
+}
+
+impl<'a> MultiThreadBase for ObserverTarget<'a> {
+    fn read_registers(&mut self, regs: &mut CoreRegisters, tid: Tid) -> TargetResult<(), Self> {
+        self.shared.borrow_mut().read_registers(regs, tid)
+    }
+
+    fn write_registers(&mut self, _regs: &CoreRegisters, tid: Tid) -> TargetResult<(), Self> {
+        debug!("Observer attempted to write registers of {:?}", tid);
+        Err(TargetError::NonFatal)
+    }
+
+    fn read_addrs(
+        &mut self,
+        start_addr: u32,
+        data: &mut [u8],
+        tid: Tid,
+    ) -> TargetResult<usize, Self> {
+        self.shared.borrow_mut().read_addrs(start_addr, data, tid)
+    }
+
+    fn write_addrs(&mut self, start_addr: u32, _data: &[u8], _tid: Tid) -> TargetResult<(), Self> {
+        debug!("Observer attempted to write to {:#010x}", start_addr);
+        Err(TargetError::NonFatal)
+    }
+
+    fn list_active_threads(
+        &mut self,
+        register_thread: &mut dyn FnMut(Tid),
+    ) -> Result<(), Self::Error> {
+        self.shared
+            .borrow_mut()
+            .list_active_threads(register_thread)
+    }
+
+    #[inline(always)]
+    fn support_resume(&mut self) -> Option<MultiThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_thread_extra_info(&mut self) -> Option<ThreadExtraInfoOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+/// Resuming only makes the observer wait for the owner's next stop
+impl<'a> MultiThreadResume for ObserverTarget<'a> {
+    fn resume(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_resume_action_continue(
+        &mut self,
+        tid: Tid,
+        _signal: Option<Signal>,
+    ) -> Result<(), Self::Error> {
+        debug!(
+            "Observer waits for the next stop, {:?} is left to the owner",
+            tid
+        );
+        Ok(())
+    }
+}
+
+impl<'a> ThreadExtraInfo for ObserverTarget<'a> {
+    fn thread_extra_info(&self, tid: Tid, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.shared.borrow().thread_extra_info(tid, buf)
+    }
+}
+
+impl<'a> MonitorCmd for ObserverTarget<'a> {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        let read_only = core::str::from_utf8(cmd)
+            .is_ok_and(|cmd| self.shared.borrow().is_read_only_command(cmd));
+
+        if read_only {
+            self.shared.borrow_mut().handle_monitor_cmd(cmd, out)
+        } else {
+            outputln!(out, "Observer mode: this connection is read-only");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Event loop serving the GDB client owning the target and optional read-only observers.
+//!
+//! Everything runs on a single thread, so accesses of the owner and the observers to the
+//! target, and thereby to the probe, are serialized without further locking.
+
+use std::cell::RefCell;
+use std::io;
+use std::net::TcpListener;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use gdbstub::common::{Signal, Tid};
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::state_machine::GdbStubStateMachine;
+use gdbstub::stub::{DisconnectReason, GdbStub, GdbStubError, MultiThreadStopReason};
+use gdbstub::target::ext::breakpoints::WatchKind;
+use log::{debug, info, warn};
+
+use crate::connection::{MonitoredConnection, PacketFraming, SessionConnection};
+use crate::gdb::{self, tricore, TricoreTarget};
+use crate::metrics::Metrics;
+use crate::observer::ObserverTarget;
+
+/// Pause between polls while the owner is idle and nothing arrived
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Time the target is polled for stop events before clients are serviced again
+const RUN_SLICE: Duration = Duration::from_millis(20);
+
+pub type SharedTarget<'a> = Rc<RefCell<TricoreTarget<'a>>>;
+
+type StubError = GdbStubError<&'static str, io::Error>;
+
+type ObserverStub<'a> = GdbStubStateMachine<'static, ObserverTarget<'a>, MonitoredConnection>;
+
+/// Fatal error of the owning session
+pub enum SessionError {
+    Stub(StubError),
+    Connection(io::Error),
+}
+
+impl From<StubError> for SessionError {
+    fn from(e: StubError) -> Self {
+        SessionError::Stub(e)
+    }
+}
+
+/// Ends the session once `monitor exit` asked for it, `main` exits with the code then
+fn exit_disconnect(exit_requested: Option<u8>) -> Option<DisconnectReason> {
+    exit_requested.map(DisconnectReason::TargetExited)
+}
+
+/// Signal GDB shows for a trap of the given class, following the POSIX meaning
+fn fault_signal(class: u8) -> Signal {
+    match class {
+        // MMU, internal protection and context management, e.g. a CSA list underflow
+        0 | 1 | 3 => Signal::SIGSEGV,
+        2 => Signal::SIGILL,
+        4 => Signal::SIGBUS,
+        // Arithmetic overflow assertions
+        5 => Signal::SIGFPE,
+        6 => Signal::SIGTRAP,
+        _ => Signal::SIGINT,
+    }
+}
+
+fn stop_reason(event: tricore::Event, tid: Tid) -> MultiThreadStopReason<u32> {
+    match event {
+        tricore::Event::DoneStep => MultiThreadStopReason::DoneStep,
+        tricore::Event::Halted => MultiThreadStopReason::Terminated(Signal::SIGSTOP),
+        tricore::Event::Break => MultiThreadStopReason::SwBreak(tid),
+        tricore::Event::Fault { class, .. } => MultiThreadStopReason::SignalWithThread {
+            tid,
+            signal: fault_signal(class),
+        },
+        tricore::Event::Exited(code) => MultiThreadStopReason::Exited(code),
+        tricore::Event::HaltedByStub(_) => MultiThreadStopReason::SignalWithThread {
+            tid,
+            signal: Signal::SIGSTOP,
+        },
+        tricore::Event::WatchWrite(addr) => MultiThreadStopReason::Watch {
+            tid,
+            kind: WatchKind::Write,
+            addr,
+        },
+        tricore::Event::WatchRead(addr) => MultiThreadStopReason::Watch {
+            tid,
+            kind: WatchKind::Read,
+            addr,
+        },
+    }
+}
+
+/// Serves the owning GDB client until it disconnects
+pub fn serve<'a, C: SessionConnection>(
+    target: &SharedTarget<'a>,
+    conn: C,
+    observers: &mut Observers<'a>,
+) -> Result<DisconnectReason, SessionError> {
+    target.borrow().log_initial_stop();
+    let mut gdb = GdbStub::new(conn).run_state_machine(&mut *target.borrow_mut())?;
+    // Every byte handed to gdbstub passes through here, so interrupt requests are told
+    // from packet content wherever they arrive
+    let mut framing = PacketFraming::default();
+
+    loop {
+        observers.poll(target);
+
+        gdb = match gdb {
+            GdbStubStateMachine::Idle(mut gdb) => match gdb.borrow_conn().peek() {
+                Ok(Some(_)) => {
+                    let byte = gdb.borrow_conn().read().map_err(SessionError::Connection)?;
+                    let interrupt = framing.is_interrupt(byte);
+                    framing.feed(byte);
+                    if interrupt {
+                        // Sent before GDB saw the stop reply, or repeated, while the target
+                        // is halted already. There is nothing left to interrupt.
+                        debug!("Dropping an interrupt request, the target is halted");
+                        gdb.into()
+                    } else {
+                        let gdb = gdb.incoming_data(&mut target.borrow_mut(), byte)?;
+                        // The reply of `monitor exit` is sent, the connection is closed
+                        if let Some(reason) = exit_disconnect(target.borrow().exit_requested()) {
+                            return Ok(reason);
+                        }
+                        gdb
+                    }
+                }
+                Ok(None) => {
+                    let (keepalive, idle_probe) = {
+                        let mut target = target.borrow_mut();
+                        target.heartbeat();
+                        (target.settings.keepalive, target.settings.idle_probe)
+                    };
+                    // `monitor set keepalive` takes effect on the live connection
+                    let conn = gdb.borrow_conn();
+                    conn.set_keepalive(keepalive)
+                        .map_err(SessionError::Connection)?;
+                    // A halted target leaves GDB silent until the user acts, probe the peer
+                    // so a half-open connection is found before then
+                    if conn.idle_for() > idle_probe {
+                        conn.probe().map_err(SessionError::Connection)?;
+                    }
+                    sleep(IDLE_POLL_INTERVAL);
+                    gdb.into()
+                }
+                Err(e) => return Err(SessionError::Connection(e)),
+            },
+            GdbStubStateMachine::Running(mut gdb) => {
+                let idle_probe = target.borrow().settings.idle_probe;
+                let conn = gdb.borrow_conn();
+                let mut conn_error = None;
+                let poll_incoming_data = || {
+                    // A connection idle for long might be half-open, check the socket for errors.
+                    // Reading the error clears it from the socket, so it is kept for below
+                    // rather than left to a read which might block.
+                    if conn.idle_for() > idle_probe {
+                        if let Err(e) = conn.probe() {
+                            conn_error = Some(e);
+                            return true;
+                        }
+                    }
+                    match conn.peek() {
+                        Ok(byte) => byte.is_some(),
+                        Err(e) => {
+                            conn_error = Some(e);
+                            true
+                        }
+                    }
+                };
+
+                let event = target.borrow_mut().run(poll_incoming_data, RUN_SLICE);
+                if let Some(e) = conn_error {
+                    return Err(SessionError::Connection(e));
+                }
+                match event {
+                    tricore::RunEvent::IncomingData => {
+                        // Only bytes peeked are read, a read never blocks the run loop
+                        let byte = gdb.borrow_conn().read().map_err(SessionError::Connection)?;
+                        framing.feed(byte);
+                        gdb.incoming_data(&mut target.borrow_mut(), byte)?
+                    }
+                    tricore::RunEvent::Event(event, cpuid) => {
+                        let (gdb, tid) = {
+                            let mut target = target.borrow_mut();
+                            if let Some(detail) = event.detail() {
+                                target.console(Some(cpuid), format_args!("{}", detail));
+                            }
+                            let tid = gdb::thread_of(target.settings(), cpuid);
+                            (gdb.report_stop(&mut target, stop_reason(event, tid))?, tid)
+                        };
+                        observers.report_stop(stop_reason(event, tid));
+                        gdb
+                    }
+                    tricore::RunEvent::Yield => gdb.into(),
+                }
+            }
+            GdbStubStateMachine::CtrlCInterrupt(gdb) => {
+                let gdb = {
+                    let mut target = target.borrow_mut();
+                    // halt each core
+                    target.interrupt();
+                    gdb.interrupt_handled(
+                        &mut target,
+                        Some(MultiThreadStopReason::Signal(Signal::SIGINT)),
+                    )?
+                };
+                observers.report_stop(MultiThreadStopReason::Signal(Signal::SIGINT));
+                gdb
+            }
+            GdbStubStateMachine::Disconnected(gdb) => break Ok(gdb.get_reason()),
+        };
+    }
+}
+
+/// Read-only GDB sessions accepted next to the owning one
+pub struct Observers<'a> {
+    listener: Option<TcpListener>,
+    limit: usize,
+    sessions: Vec<(ObserverTarget<'a>, ObserverStub<'a>)>,
+    metrics: Arc<Metrics>,
+}
+
+impl<'a> Observers<'a> {
+    /// Accepts up to `limit` observers on `listener`, a limit of zero disables observers
+    pub fn new(listener: TcpListener, limit: usize, metrics: Arc<Metrics>) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Observers {
+            listener: (limit > 0).then_some(listener),
+            limit,
+            sessions: Vec::new(),
+            metrics,
+        })
+    }
+
+    /// No observers, for sessions served over a connection made by the caller
+    pub fn disabled() -> Self {
+        Observers {
+            listener: None,
+            limit: 0,
+            sessions: Vec::new(),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    fn accept(&mut self, target: &SharedTarget<'a>) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+
+        let (stream, addr) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+            Err(e) => {
+                warn!("Failed to accept observer: {}", e);
+                return;
+            }
+        };
+
+        if self.sessions.len() >= self.limit {
+            warn!(
+                "Rejecting observer {}, limit of {} reached",
+                addr, self.limit
+            );
+            return;
+        }
+
+        let keepalive = target.borrow().settings.keepalive;
+        let session = stream
+            .set_nonblocking(false)
+            .and_then(|_| MonitoredConnection::new(stream, keepalive));
+        let conn = match session {
+            Ok(conn) => conn.with_metrics(Arc::clone(&self.metrics)),
+            Err(e) => {
+                warn!("Cannot set up observer connection {}: {}", addr, e);
+                return;
+            }
+        };
+
+        let mut observer = ObserverTarget::new(Rc::clone(target));
+        match GdbStub::new(conn).run_state_machine(&mut observer) {
+            Ok(gdb) => {
+                let connection_id = self.metrics.record_session();
+                info!(
+                    "Observer connected from {}, session {}",
+                    addr, connection_id
+                );
+                self.sessions.push((observer, gdb));
+            }
+            Err(e) => warn!("Cannot start observer session for {}: {}", addr, e),
+        }
+    }
+
+    /// Reports a stop of the owner's target to the observers waiting for one
+    fn report_stop(&mut self, reason: MultiThreadStopReason<u32>) {
+        for (mut observer, gdb) in std::mem::take(&mut self.sessions) {
+            let gdb = match gdb {
+                GdbStubStateMachine::Running(gdb) => gdb.report_stop(&mut observer, reason),
+                gdb => Ok(gdb),
+            };
+            match gdb {
+                Ok(gdb) => self.sessions.push((observer, gdb)),
+                Err(e) => warn!("Observer session ended: {}", e),
+            }
+        }
+    }
+
+    /// Accepts new observers and processes pending data of the connected ones
+    fn poll(&mut self, target: &SharedTarget<'a>) {
+        self.accept(target);
+
+        for (mut observer, gdb) in std::mem::take(&mut self.sessions) {
+            match step_observer(&mut observer, gdb) {
+                Ok(Some(gdb)) => self.sessions.push((observer, gdb)),
+                Ok(None) => info!("Observer disconnected"),
+                Err(e) => warn!("Observer session ended: {}", e),
+            }
+        }
+    }
+}
+
+/// Feeds at most one byte to an observer session, returns `None` once it disconnected
+fn step_observer<'a>(
+    observer: &mut ObserverTarget<'a>,
+    gdb: ObserverStub<'a>,
+) -> Result<Option<ObserverStub<'a>>, StubError> {
+    Ok(Some(match gdb {
+        GdbStubStateMachine::Idle(mut gdb) => match gdb.borrow_conn().peek() {
+            Ok(Some(_)) => match gdb.borrow_conn().read() {
+                Ok(byte) => gdb.incoming_data(observer, byte)?,
+                Err(_) => return Ok(None),
+            },
+            Ok(None) => gdb.into(),
+            Err(_) => return Ok(None),
+        },
+        // Halting the target is the owner's, an interrupt only ends the observer's wait
+        GdbStubStateMachine::CtrlCInterrupt(gdb) => gdb.interrupt_handled(
+            observer,
+            Some(MultiThreadStopReason::Signal(Signal::SIGINT)),
+        )?,
+        // Waiting for the owner's next stop, see [Observers::report_stop]
+        GdbStubStateMachine::Running(mut gdb) => match gdb.borrow_conn().peek() {
+            Ok(Some(_)) => match gdb.borrow_conn().read() {
+                Ok(byte) => gdb.incoming_data(observer, byte)?,
+                Err(_) => return Ok(None),
+            },
+            Ok(None) => gdb.into(),
+            Err(_) => return Ok(None),
+        },
+        GdbStubStateMachine::Disconnected(_) => return Ok(None),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gdb::tricore::{Event, HaltCause};
+
+    fn tid() -> Tid {
+        Tid::new(3).unwrap()
+    }
+
+    #[test]
+    fn every_event_maps_to_its_stop_reason() {
+        let signal = |signal| MultiThreadStopReason::SignalWithThread { tid: tid(), signal };
+        let table = [
+            (Event::DoneStep, MultiThreadStopReason::DoneStep),
+            (
+                Event::Halted,
+                MultiThreadStopReason::Terminated(Signal::SIGSTOP),
+            ),
+            (Event::Break, MultiThreadStopReason::SwBreak(tid())),
+            (
+                Event::Fault {
+                    class: 2,
+                    tin: 1,
+                    pc: 0x8000_0100,
+                },
+                signal(Signal::SIGILL),
+            ),
+            (Event::Exited(3), MultiThreadStopReason::Exited(3)),
+            (
+                Event::HaltedByStub(HaltCause::Pause),
+                signal(Signal::SIGSTOP),
+            ),
+            (
+                Event::WatchWrite(0x7000_0000),
+                MultiThreadStopReason::Watch {
+                    tid: tid(),
+                    kind: WatchKind::Write,
+                    addr: 0x7000_0000,
+                },
+            ),
+            (
+                Event::WatchRead(0x7000_0004),
+                MultiThreadStopReason::Watch {
+                    tid: tid(),
+                    kind: WatchKind::Read,
+                    addr: 0x7000_0004,
+                },
+            ),
+        ];
+        for (event, reason) in table {
+            assert_eq!(stop_reason(event, tid()), reason, "{:?}", event);
+            assert_eq!(stop_reason(event, tid()), stop_reason(event, tid()));
+        }
+    }
+
+    #[test]
+    fn monitor_exit_ends_the_session_with_its_code() {
+        assert!(exit_disconnect(None).is_none());
+        assert!(matches!(
+            exit_disconnect(Some(0)),
+            Some(DisconnectReason::TargetExited(0))
+        ));
+        assert!(matches!(
+            exit_disconnect(Some(42)),
+            Some(DisconnectReason::TargetExited(42))
+        ));
+    }
+
+    #[test]
+    fn trap_classes_map_to_posix_signals() {
+        let signals: Vec<_> = (0..=8).map(fault_signal).collect();
+        assert_eq!(
+            signals,
+            vec![
+                Signal::SIGSEGV,
+                Signal::SIGSEGV,
+                Signal::SIGILL,
+                Signal::SIGSEGV,
+                Signal::SIGBUS,
+                Signal::SIGFPE,
+                Signal::SIGTRAP,
+                Signal::SIGINT,
+                Signal::SIGINT,
+            ]
+        );
+    }
+
+    #[test]
+    fn signalled_stops_carry_a_detail() {
+        let fault = Event::Fault {
+            class: 1,
+            tin: 2,
+            pc: 0x8000_0100,
+        };
+        assert_eq!(
+            fault.detail().as_deref(),
+            Some("Class 1 (internal protection) trap, TIN 2 at 0x80000100")
+        );
+        assert!(Event::Exited(0).detail().is_some());
+        assert!(Event::HaltedByStub(HaltCause::TrapLoop).detail().is_some());
+        assert_eq!(Event::Break.detail(), None);
+        assert_eq!(Event::DoneStep.detail(), None);
+    }
+}
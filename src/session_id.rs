@@ -0,0 +1,82 @@
+//! Unique ID of a stub process, to match GDB transcripts, logs, events and status files
+//! of several benches collected in one place.
+//!
+//! The ID is `<unix seconds>-<4 hex digits>`, e.g. `1760523312-9f0c`, and stays the same
+//! for the lifetime of the process. Each accepted GDB connection, owner and observers,
+//! is numbered from 1 and known as `<id>.<n>`, e.g. `1760523312-9f0c.2`. External tooling
+//! parses both forms, keep them stable.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionId {
+    /// Unix time the process started at
+    started: u64,
+    suffix: u16,
+}
+
+impl SessionId {
+    /// Creates the ID of this process from the current time and a random suffix, which
+    /// tells processes started within the same second apart
+    pub fn generate() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // The std hasher is randomly keyed per process, good enough without a rand crate
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        hasher.write_u32(now.subsec_nanos());
+        SessionId {
+            started: now.as_secs(),
+            suffix: hasher.finish() as u16,
+        }
+    }
+
+    /// ID of the `number`th accepted connection, counted from 1
+    pub fn connection(&self, number: u64) -> String {
+        format!("{}.{}", self, number)
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{:04x}", self.started, self.suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_format_is_stable() {
+        let id = SessionId {
+            started: 1_760_523_312,
+            suffix: 0x9f0c,
+        };
+        assert_eq!(id.to_string(), "1760523312-9f0c");
+        assert_eq!(id.connection(2), "1760523312-9f0c.2");
+    }
+
+    #[test]
+    fn suffix_keeps_four_digits() {
+        let id = SessionId {
+            started: 0,
+            suffix: 0x000a,
+        };
+        assert_eq!(id.to_string(), "0-000a");
+    }
+
+    #[test]
+    fn generated_id_parses_back() {
+        let id = SessionId::generate();
+        let text = id.to_string();
+        let (started, suffix) = text.split_once('-').unwrap();
+        assert_eq!(started.parse::<u64>(), Ok(id.started));
+        assert_eq!(suffix.len(), 4);
+        assert_eq!(u16::from_str_radix(suffix, 16), Ok(id.suffix));
+    }
+}
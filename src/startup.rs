@@ -0,0 +1,140 @@
+//! Errors detected before the probe is claimed, each with its own process exit code.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::gdb::{validate_tricore_elf, ElfCheckError, ElfData};
+
+#[derive(Debug)]
+pub enum StartupError {
+    /// The elf file cannot be read
+    ElfUnreadable(PathBuf, io::Error),
+    /// The file is not an elf at all
+    ElfNotElf(PathBuf),
+    /// The elf headers are broken
+    ElfMalformed(PathBuf, String),
+    /// The elf targets another machine than TriCore
+    ElfWrongMachine(PathBuf, u16),
+    /// The elf carries neither content to program nor symbols
+    ElfNoLoadSegments(PathBuf),
+}
+
+impl StartupError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StartupError::ElfUnreadable(..) => 10,
+            StartupError::ElfNotElf(_) => 11,
+            StartupError::ElfMalformed(..) => 12,
+            StartupError::ElfWrongMachine(..) => 13,
+            StartupError::ElfNoLoadSegments(_) => 14,
+        }
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartupError::ElfUnreadable(path, e) => write!(f, "Cannot read {:?}: {}", path, e),
+            StartupError::ElfNotElf(path) => write!(f, "{:?} is not an elf file", path),
+            StartupError::ElfMalformed(path, e) => write!(f, "{:?} is a broken elf: {}", path, e),
+            StartupError::ElfWrongMachine(path, machine) => write!(
+                f,
+                "{:?} is built for machine {:#x}, not TriCore (0x2c). Was the host binary passed?",
+                path, machine
+            ),
+            StartupError::ElfNoLoadSegments(path) => {
+                write!(f, "{:?} has neither loadable segments nor debug info", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+/// Validates the elf given on the command line without touching the probe
+pub fn check_elf_path(path: &Path) -> Result<(), StartupError> {
+    let data =
+        ElfData::open(path).map_err(|e| StartupError::ElfUnreadable(path.to_path_buf(), e))?;
+
+    validate_tricore_elf(&data).map(|_| ()).map_err(|e| {
+        let path = path.to_path_buf();
+        match e {
+            ElfCheckError::NotElf => StartupError::ElfNotElf(path),
+            ElfCheckError::Malformed(e) => StartupError::ElfMalformed(path, e),
+            ElfCheckError::WrongMachine(machine) => StartupError::ElfWrongMachine(path, machine),
+            ElfCheckError::NoLoadSegments => StartupError::ElfNoLoadSegments(path),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Little endian ELF32 header for `machine` followed by a single `PT_NULL` program
+    /// header, without sections
+    fn elf_header(machine: u16) -> Vec<u8> {
+        let mut elf = b"\x7fELF\x01\x01\x01".to_vec();
+        elf.resize(16, 0);
+        elf.extend(2u16.to_le_bytes()); // e_type: executable
+        elf.extend(machine.to_le_bytes());
+        elf.extend(1u32.to_le_bytes()); // e_version
+        elf.extend(0u32.to_le_bytes()); // e_entry
+        elf.extend(52u32.to_le_bytes()); // e_phoff
+        elf.extend(0u32.to_le_bytes()); // e_shoff
+        elf.extend(0u32.to_le_bytes()); // e_flags
+        elf.extend(52u16.to_le_bytes()); // e_ehsize
+        elf.extend(32u16.to_le_bytes()); // e_phentsize
+        elf.extend(1u16.to_le_bytes()); // e_phnum
+        elf.extend(40u16.to_le_bytes()); // e_shentsize
+        elf.extend(0u16.to_le_bytes()); // e_shnum
+        elf.extend(0u16.to_le_bytes()); // e_shstrndx
+        elf.resize(52 + 32, 0);
+        elf
+    }
+
+    fn check(content: &[u8]) -> Result<(), StartupError> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content).unwrap();
+        check_elf_path(file.path())
+    }
+
+    #[test]
+    fn missing_file_is_unreadable() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = check_elf_path(&dir.path().join("missing.elf")).unwrap_err();
+        assert!(matches!(error, StartupError::ElfUnreadable(..)));
+        assert_eq!(error.exit_code(), 10);
+    }
+
+    #[test]
+    fn other_files_are_not_elfs() {
+        let error = check(b"MZ\x90\x00").unwrap_err();
+        assert!(matches!(error, StartupError::ElfNotElf(_)));
+        assert_eq!(error.exit_code(), 11);
+    }
+
+    #[test]
+    fn truncated_elf_is_malformed() {
+        let error = check(&elf_header(0x2c)[..20]).unwrap_err();
+        assert!(matches!(error, StartupError::ElfMalformed(..)));
+        assert_eq!(error.exit_code(), 12);
+    }
+
+    #[test]
+    fn host_elf_is_refused() {
+        let error = check(&elf_header(0x3e)).unwrap_err();
+        assert!(matches!(error, StartupError::ElfWrongMachine(_, 0x3e)));
+        assert_eq!(error.exit_code(), 13);
+    }
+
+    #[test]
+    fn empty_tricore_elf_is_refused() {
+        let error = check(&elf_header(0x2c)).unwrap_err();
+        assert!(matches!(error, StartupError::ElfNoLoadSegments(_)));
+        assert_eq!(error.exit_code(), 14);
+    }
+}